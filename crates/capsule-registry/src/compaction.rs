@@ -0,0 +1,133 @@
+use crate::{gc::GarbageCollector, CapsuleRegistry};
+use anyhow::Result;
+use nvram_sim::{CompactionStats, NvramLog};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Granularity at which the background worker re-checks policies and the stop flag.
+pub(crate) const SCHEDULER_TICK: Duration = Duration::from_millis(250);
+
+/// Result of a sweep followed by a log compaction.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompactionReport {
+    /// Segments whose metadata was dropped by the GC sweep before compacting.
+    pub swept_segments: usize,
+    pub stats: CompactionStats,
+}
+
+impl CompactionReport {
+    pub fn reclaimed_bytes(&self) -> u64 {
+        self.stats.reclaimed_bytes()
+    }
+}
+
+/// Reclaims the on-disk bytes of segments that garbage collection released.
+///
+/// GC only drops metadata; the compactor follows up by rewriting the NVRAM log
+/// so that just the live segments remain.
+pub struct Compactor<'a> {
+    registry: &'a CapsuleRegistry,
+    nvram: &'a NvramLog,
+}
+
+impl<'a> Compactor<'a> {
+    pub fn new(registry: &'a CapsuleRegistry, nvram: &'a NvramLog) -> Self {
+        Self { registry, nvram }
+    }
+
+    /// Sweep unreferenced segments, then compact the log.
+    pub fn run(&self) -> Result<CompactionReport> {
+        let swept_segments = GarbageCollector::new(self.registry, self.nvram).sweep()?;
+        let stats = self.nvram.compact()?;
+
+        info!(
+            swept_segments,
+            segments_moved = stats.segments_moved,
+            reclaimed_bytes = stats.reclaimed_bytes(),
+            "nvram log compacted"
+        );
+
+        Ok(CompactionReport {
+            swept_segments,
+            stats,
+        })
+    }
+
+    /// Shortest `compact_interval_secs` across stored capsule policies.
+    ///
+    /// Returns `None` when no capsule asks for periodic compaction.
+    pub fn interval(&self) -> Option<Duration> {
+        compaction_interval(self.registry)
+    }
+}
+
+fn compaction_interval(registry: &CapsuleRegistry) -> Option<Duration> {
+    registry
+        .index
+        .read()
+        .unwrap()
+        .min_compact_interval()
+        .map(Duration::from_secs)
+}
+
+/// Background worker that compacts the log on the cadence requested by policies.
+///
+/// The shortest interval is kept current by the registry's capsule index as
+/// policies are written, so each tick only reads it; capsules written with a
+/// tighter `compact_interval_secs` take effect without a restart.
+pub struct CompactionScheduler {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl CompactionScheduler {
+    pub fn spawn(registry: CapsuleRegistry, nvram: NvramLog) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_flag = Arc::clone(&stop);
+
+        let handle = thread::spawn(move || {
+            let mut last_run = Instant::now();
+            while !stop_flag.load(Ordering::SeqCst) {
+                thread::sleep(SCHEDULER_TICK);
+
+                let Some(interval) = compaction_interval(&registry) else {
+                    continue;
+                };
+                if last_run.elapsed() < interval {
+                    continue;
+                }
+
+                if let Err(err) = Compactor::new(&registry, &nvram).run() {
+                    warn!(error = %err, "scheduled compaction failed");
+                }
+                last_run = Instant::now();
+            }
+        });
+
+        Self {
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    /// Signal the worker to exit and wait for it.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for CompactionScheduler {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
@@ -0,0 +1,981 @@
+//! Disk-resident dedup index.
+//!
+//! The content store maps `(tenant, hash)` to the segment holding that
+//! content. It lives in a directory next to the registry metadata file:
+//!
+//! - `index` is a hash table of fixed-size pages. Page 0 is the header, pages
+//!   `1..=buckets` are the primary buckets, and buckets that fill up chain to
+//!   overflow pages appended after them. The table doubles, by rewriting it
+//!   into a fresh file, once it averages more than [`GROW_FILL`] entries per
+//!   bucket.
+//! - `filter` is a snapshot of the bloom filter checked before any page is
+//!   read, so lookups of new content rarely touch the disk.
+//! - `wal` holds the pages of a flush in progress, so a crash never leaves
+//!   the table half-written.
+//!
+//! Only pages that lookups and updates touch are held in memory, within a
+//! fixed cache budget; opening the registry reads the header and the filter
+//! snapshot and nothing else. A flush records the journal sequence number the
+//! table reflects, and content mutations journaled after it are replayed on
+//! open.
+
+use anyhow::{bail, Context, Result};
+use common::{ContentHash, SegmentId, TenantId};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+const PAGE_SIZE: usize = 4096;
+const PAGE_HEADER: usize = 20;
+const RECORD_HEADER: usize = 11;
+const INITIAL_BUCKETS: u64 = 16;
+/// Average entries per bucket past which the table doubles; a page holds
+/// about 54 entries keyed by BLAKE3 hex hashes.
+const GROW_FILL: u64 = 40;
+const DEFAULT_CACHE_PAGES: usize = 16 * 1024;
+const DEFAULT_FALSE_POSITIVE_RATE: f64 = 0.001;
+
+const INDEX_MAGIC: &[u8; 8] = b"SPCDEDUP";
+const FILTER_MAGIC: &[u8; 8] = b"SPCBLOOM";
+const FORMAT_VERSION: u32 = 1;
+
+const INDEX_FILE: &str = "index";
+const REHASH_FILE: &str = "index.rehash";
+const WAL_FILE: &str = "wal";
+const FILTER_FILE: &str = "filter";
+
+/// Size and memory use of the on-disk dedup index, as reported by
+/// [`CapsuleRegistry::content_index_stats`](crate::CapsuleRegistry::content_index_stats).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentIndexStats {
+    pub entries: u64,
+    pub buckets: u64,
+    /// Pages in the index file, including the header and overflow pages.
+    pub pages: u64,
+    pub cached_pages: usize,
+    /// Most pages kept in memory; set with `SPACE_DEDUP_CACHE_PAGES`.
+    pub cache_capacity: usize,
+    pub filter_bytes: usize,
+    /// Whether the bloom filter was loaded from its snapshot rather than
+    /// rebuilt by scanning the table.
+    pub filter_restored: bool,
+}
+
+/// A content mutation read back from the registry journal.
+#[derive(Debug, Clone)]
+pub(crate) enum ContentChange {
+    Registered {
+        tenant: Option<TenantId>,
+        hash: ContentHash,
+        segment: SegmentId,
+    },
+    Deregistered {
+        tenant: Option<TenantId>,
+        hash: ContentHash,
+    },
+}
+
+/// Where a hash lands in the table and the filter.
+struct Key {
+    bucket: u64,
+    h1: u64,
+    h2: u64,
+}
+
+impl Key {
+    fn of(hash: &ContentHash) -> Self {
+        let digest = blake3::hash(hash.as_str().as_bytes());
+        let bytes = digest.as_bytes();
+        let word = |i: usize| u64::from_le_bytes(bytes[i * 8..i * 8 + 8].try_into().unwrap());
+        Self {
+            bucket: word(0),
+            h1: word(1),
+            h2: word(2) | 1,
+        }
+    }
+}
+
+fn checksum(bytes: &[u8]) -> [u8; 8] {
+    blake3::hash(bytes).as_bytes()[..8].try_into().unwrap()
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Record {
+    tenant: Option<TenantId>,
+    hash: ContentHash,
+    segment: SegmentId,
+}
+
+impl Record {
+    fn new(tenant: Option<TenantId>, hash: ContentHash, segment: SegmentId) -> Result<Self> {
+        if hash.as_str().len() > u8::MAX as usize {
+            bail!(
+                "content hash {} is too long for the dedup index",
+                hash.as_str()
+            );
+        }
+        if let Some(tenant) = tenant
+            .as_ref()
+            .filter(|t| t.as_str().len() > u8::MAX as usize)
+        {
+            bail!("tenant id {} is too long for the dedup index", tenant);
+        }
+        Ok(Self {
+            tenant,
+            hash,
+            segment,
+        })
+    }
+
+    fn encoded_len(&self) -> usize {
+        RECORD_HEADER + self.tenant.as_ref().map_or(0, |t| t.as_str().len()) + self.hash.0.len()
+    }
+
+    fn is(&self, tenant: Option<&TenantId>, hash: &ContentHash) -> bool {
+        self.tenant.as_ref() == tenant && &self.hash == hash
+    }
+}
+
+/// One bucket or overflow page: records packed after a small header.
+///
+/// Layout: checksum (8), next overflow page or 0 (8), record count (2),
+/// reserved (2), then per record a tenant flag (1), tenant length (1), hash
+/// length (1), segment id (8), tenant bytes and hash bytes. An all-zero page
+/// is an empty one, so the file can be extended sparsely.
+#[derive(Debug, Clone, Default)]
+struct Page {
+    next: u64,
+    records: Vec<Record>,
+}
+
+impl Page {
+    fn used(&self) -> usize {
+        PAGE_HEADER + self.records.iter().map(Record::encoded_len).sum::<usize>()
+    }
+
+    fn fits(&self, record: &Record) -> bool {
+        self.used() + record.encoded_len() <= PAGE_SIZE
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![0u8; PAGE_SIZE];
+        bytes[8..16].copy_from_slice(&self.next.to_le_bytes());
+        bytes[16..18].copy_from_slice(&(self.records.len() as u16).to_le_bytes());
+        let mut at = PAGE_HEADER;
+        for record in &self.records {
+            let tenant = record.tenant.as_ref().map_or("", |t| t.as_str()).as_bytes();
+            let hash = record.hash.as_str().as_bytes();
+            bytes[at] = record.tenant.is_some() as u8;
+            bytes[at + 1] = tenant.len() as u8;
+            bytes[at + 2] = hash.len() as u8;
+            bytes[at + 3..at + 11].copy_from_slice(&record.segment.0.to_le_bytes());
+            at += RECORD_HEADER;
+            bytes[at..at + tenant.len()].copy_from_slice(tenant);
+            at += tenant.len();
+            bytes[at..at + hash.len()].copy_from_slice(hash);
+            at += hash.len();
+        }
+        let sum = checksum(&bytes[8..]);
+        bytes[..8].copy_from_slice(&sum);
+        bytes
+    }
+
+    fn decode(number: u64, bytes: &[u8]) -> Result<Self> {
+        if bytes.iter().all(|&b| b == 0) {
+            return Ok(Self::default());
+        }
+        if bytes[..8] != checksum(&bytes[8..]) {
+            bail!("dedup index page {} is corrupt", number);
+        }
+        let next = read_u64(bytes, 8);
+        let count = u16::from_le_bytes([bytes[16], bytes[17]]) as usize;
+        let mut records = Vec::with_capacity(count);
+        let mut at = PAGE_HEADER;
+        for _ in 0..count {
+            let has_tenant = bytes[at] != 0;
+            let tenant_len = bytes[at + 1] as usize;
+            let hash_len = bytes[at + 2] as usize;
+            let segment = SegmentId(read_u64(bytes, at + 3));
+            at += RECORD_HEADER;
+            let text = |range: std::ops::Range<usize>| {
+                String::from_utf8(bytes[range].to_vec())
+                    .with_context(|| format!("dedup index page {} holds invalid text", number))
+            };
+            let tenant = text(at..at + tenant_len)?;
+            at += tenant_len;
+            let hash = text(at..at + hash_len)?;
+            at += hash_len;
+            records.push(Record {
+                tenant: has_tenant.then_some(TenantId(tenant)),
+                hash: ContentHash(hash),
+                segment,
+            });
+        }
+        Ok(Self { next, records })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Header {
+    buckets: u64,
+    pages: u64,
+    entries: u64,
+    /// Journal sequence number the table reflected when it was last flushed.
+    synced_seq: u64,
+}
+
+impl Header {
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![0u8; PAGE_SIZE];
+        bytes[..8].copy_from_slice(INDEX_MAGIC);
+        bytes[8..12].copy_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes[12..20].copy_from_slice(&self.buckets.to_le_bytes());
+        bytes[20..28].copy_from_slice(&self.pages.to_le_bytes());
+        bytes[28..36].copy_from_slice(&self.entries.to_le_bytes());
+        bytes[36..44].copy_from_slice(&self.synced_seq.to_le_bytes());
+        let sum = checksum(&bytes[..44]);
+        bytes[44..52].copy_from_slice(&sum);
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        if &bytes[..8] != INDEX_MAGIC || bytes[44..52] != checksum(&bytes[..44]) {
+            bail!("dedup index header is corrupt");
+        }
+        let version = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+        if version != FORMAT_VERSION {
+            bail!("unsupported dedup index version {}", version);
+        }
+        Ok(Self {
+            buckets: read_u64(bytes, 12),
+            pages: read_u64(bytes, 20),
+            entries: read_u64(bytes, 28),
+            synced_seq: read_u64(bytes, 36),
+        })
+    }
+}
+
+struct CachedPage {
+    page: Page,
+    dirty: bool,
+    used: u64,
+}
+
+/// Least-recently-used page cache. Dirty pages are pinned until a flush
+/// writes them, so the cache can briefly exceed its capacity.
+struct PageCache {
+    capacity: usize,
+    pages: HashMap<u64, CachedPage>,
+    // Last use of each clean page, oldest first.
+    lru: BTreeMap<u64, u64>,
+    tick: u64,
+    dirty: usize,
+}
+
+impl PageCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            pages: HashMap::new(),
+            lru: BTreeMap::new(),
+            tick: 0,
+            dirty: 0,
+        }
+    }
+
+    fn get(&mut self, number: u64) -> Option<&mut Page> {
+        let cached = self.pages.get_mut(&number)?;
+        self.tick += 1;
+        if !cached.dirty {
+            self.lru.remove(&cached.used);
+            self.lru.insert(self.tick, number);
+        }
+        cached.used = self.tick;
+        Some(&mut cached.page)
+    }
+
+    fn insert(&mut self, number: u64, page: Page, dirty: bool) {
+        // Make room first so the page being added is never the one evicted.
+        while self.pages.len() >= self.capacity {
+            let Some((_, victim)) = self.lru.pop_first() else {
+                break;
+            };
+            self.pages.remove(&victim);
+        }
+        self.tick += 1;
+        if dirty {
+            self.dirty += 1;
+        } else {
+            self.lru.insert(self.tick, number);
+        }
+        self.pages.insert(
+            number,
+            CachedPage {
+                page,
+                dirty,
+                used: self.tick,
+            },
+        );
+    }
+
+    fn mark_dirty(&mut self, number: u64) {
+        if let Some(cached) = self.pages.get_mut(&number).filter(|c| !c.dirty) {
+            self.lru.remove(&cached.used);
+            cached.dirty = true;
+            self.dirty += 1;
+        }
+    }
+
+    fn peek(&self, number: u64) -> Option<&Page> {
+        self.pages.get(&number).map(|cached| &cached.page)
+    }
+
+    fn dirty_pages(&self) -> Vec<(u64, Vec<u8>)> {
+        let mut pages: Vec<_> = self
+            .pages
+            .iter()
+            .filter(|(_, cached)| cached.dirty)
+            .map(|(&number, cached)| (number, cached.page.encode()))
+            .collect();
+        pages.sort_unstable_by_key(|(number, _)| *number);
+        pages
+    }
+
+    fn mark_clean(&mut self) {
+        for (&number, cached) in self.pages.iter_mut().filter(|(_, c)| c.dirty) {
+            cached.dirty = false;
+            self.lru.insert(cached.used, number);
+        }
+        self.dirty = 0;
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.pages.len() > self.capacity {
+            let Some((_, number)) = self.lru.pop_first() else {
+                break;
+            };
+            self.pages.remove(&number);
+        }
+    }
+}
+
+/// The paged hash table in one file, without the filter in front of it.
+struct PagedTable {
+    file: File,
+    header: Header,
+    cache: PageCache,
+}
+
+impl PagedTable {
+    fn create(path: &Path, buckets: u64, synced_seq: u64, cache_pages: usize) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let header = Header {
+            buckets,
+            pages: buckets + 1,
+            entries: 0,
+            synced_seq,
+        };
+        file.write_all(&header.encode())?;
+        file.set_len(header.pages * PAGE_SIZE as u64)?;
+        file.sync_all()?;
+        Ok(Self {
+            file,
+            header,
+            cache: PageCache::new(cache_pages),
+        })
+    }
+
+    fn open(path: &Path, cache_pages: usize) -> Result<Self> {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        let mut bytes = vec![0u8; PAGE_SIZE];
+        file.read_exact(&mut bytes)?;
+        let header = Header::decode(&bytes)?;
+        Ok(Self {
+            file,
+            header,
+            cache: PageCache::new(cache_pages),
+        })
+    }
+
+    fn bucket_page(&self, key: &Key) -> u64 {
+        1 + key.bucket % self.header.buckets
+    }
+
+    /// Read a page from the file, bypassing the cache.
+    fn read_page(&mut self, number: u64) -> Result<Page> {
+        let mut bytes = vec![0u8; PAGE_SIZE];
+        self.file.seek(SeekFrom::Start(number * PAGE_SIZE as u64))?;
+        self.file.read_exact(&mut bytes)?;
+        Page::decode(number, &bytes)
+    }
+
+    fn page(&mut self, number: u64) -> Result<&mut Page> {
+        if self.cache.get(number).is_none() {
+            let page = self.read_page(number)?;
+            self.cache.insert(number, page, false);
+        }
+        Ok(self.cache.get(number).expect("page was just cached"))
+    }
+
+    /// Walk the chain of `key`'s bucket until `f` returns a value.
+    fn find<T>(
+        &mut self,
+        key: &Key,
+        mut f: impl FnMut(u64, &Page) -> Option<T>,
+    ) -> Result<Option<T>> {
+        let mut number = self.bucket_page(key);
+        loop {
+            let page = self.page(number)?;
+            if let Some(found) = f(number, page) {
+                return Ok(Some(found));
+            }
+            if page.next == 0 {
+                return Ok(None);
+            }
+            number = page.next;
+        }
+    }
+
+    fn get(
+        &mut self,
+        key: &Key,
+        tenant: Option<&TenantId>,
+        hash: &ContentHash,
+    ) -> Result<Option<SegmentId>> {
+        self.find(key, |_, page| {
+            page.records
+                .iter()
+                .find(|record| record.is(tenant, hash))
+                .map(|record| record.segment)
+        })
+    }
+
+    /// Insert or replace the mapping for the record's tenant and hash.
+    fn insert(&mut self, key: &Key, record: Record) -> Result<()> {
+        let mut free = None;
+        let mut tail = 0;
+        let existing = self.find(key, |number, page| {
+            if let Some(index) = page
+                .records
+                .iter()
+                .position(|r| r.is(record.tenant.as_ref(), &record.hash))
+            {
+                return Some((number, index));
+            }
+            if free.is_none() && page.fits(&record) {
+                free = Some(number);
+            }
+            tail = number;
+            None
+        })?;
+
+        if let Some((number, index)) = existing {
+            self.page(number)?.records[index].segment = record.segment;
+            self.cache.mark_dirty(number);
+            return Ok(());
+        }
+        match free {
+            Some(number) => {
+                self.page(number)?.records.push(record);
+                self.cache.mark_dirty(number);
+            }
+            None => {
+                let number = self.header.pages;
+                self.header.pages += 1;
+                self.page(tail)?.next = number;
+                self.cache.mark_dirty(tail);
+                let page = Page {
+                    next: 0,
+                    records: vec![record],
+                };
+                self.cache.insert(number, page, true);
+            }
+        }
+        self.header.entries += 1;
+        Ok(())
+    }
+
+    /// Remove the first record in `key`'s bucket matching `matches`.
+    fn remove(&mut self, key: &Key, matches: impl Fn(&Record) -> bool) -> Result<Option<Record>> {
+        let Some((number, index)) = self.find(key, |number, page| {
+            page.records
+                .iter()
+                .position(&matches)
+                .map(|index| (number, index))
+        })?
+        else {
+            return Ok(None);
+        };
+        let record = self.page(number)?.records.remove(index);
+        self.cache.mark_dirty(number);
+        self.header.entries -= 1;
+        Ok(Some(record))
+    }
+
+    /// Visit every record, reading uncached pages straight from the file so
+    /// a scan does not flush the cache.
+    fn scan(&mut self, mut f: impl FnMut(&Record)) -> Result<()> {
+        for number in 1..self.header.pages {
+            match self.cache.peek(number) {
+                Some(page) => page.records.iter().for_each(&mut f),
+                None => self.read_page(number)?.records.iter().for_each(&mut f),
+            }
+        }
+        Ok(())
+    }
+
+    fn write_at(&mut self, number: u64, bytes: &[u8]) -> Result<()> {
+        self.file.seek(SeekFrom::Start(number * PAGE_SIZE as u64))?;
+        self.file.write_all(bytes)?;
+        Ok(())
+    }
+
+    /// Write dirty pages and the header in place, staging them in `wal`
+    /// first so a crash part-way leaves either the old or the new table.
+    fn flush(&mut self, wal: &Path, synced_seq: u64) -> Result<()> {
+        let mut writes = self.cache.dirty_pages();
+        if writes.is_empty() && self.header.synced_seq == synced_seq {
+            return Ok(());
+        }
+        self.header.synced_seq = synced_seq;
+        writes.push((0, self.header.encode()));
+
+        let mut log = Vec::with_capacity(writes.len() * (8 + PAGE_SIZE) + 40);
+        for (number, bytes) in &writes {
+            log.extend_from_slice(&number.to_le_bytes());
+            log.extend_from_slice(bytes);
+        }
+        log.extend_from_slice(&(writes.len() as u64).to_le_bytes());
+        let digest = blake3::hash(&log);
+        log.extend_from_slice(digest.as_bytes());
+        {
+            let mut file = File::create(wal)?;
+            file.write_all(&log)?;
+            file.sync_all()?;
+        }
+
+        for (number, bytes) in &writes {
+            self.write_at(*number, bytes)?;
+        }
+        self.file.sync_all()?;
+        fs::remove_file(wal)?;
+        self.cache.mark_clean();
+        Ok(())
+    }
+
+    /// Write dirty pages and the header in place without a log, for a table
+    /// that is not live yet.
+    fn write_unlogged(&mut self) -> Result<()> {
+        for (number, bytes) in self.cache.dirty_pages() {
+            self.write_at(number, &bytes)?;
+        }
+        let header = self.header.encode();
+        self.write_at(0, &header)?;
+        self.file.sync_all()?;
+        self.cache.mark_clean();
+        Ok(())
+    }
+}
+
+/// Finish a flush that was interrupted after its log reached the disk, or
+/// drop a log that was itself cut short.
+fn recover_wal(index: &Path, wal: &Path) -> Result<()> {
+    let Ok(log) = fs::read(wal) else {
+        return Ok(());
+    };
+    let entry = 8 + PAGE_SIZE;
+    let intact = log.len() >= 40 && {
+        let (body, digest) = log.split_at(log.len() - 32);
+        let count = read_u64(body, body.len() - 8) as usize;
+        blake3::hash(body).as_bytes() == digest && body.len() == count * entry + 8
+    };
+    if intact {
+        let mut file = OpenOptions::new().write(true).open(index)?;
+        for chunk in log[..log.len() - 40].chunks(entry) {
+            file.seek(SeekFrom::Start(read_u64(chunk, 0) * PAGE_SIZE as u64))?;
+            file.write_all(&chunk[8..])?;
+        }
+        file.sync_all()?;
+    } else {
+        warn!(path = %wal.display(), "discarding incomplete dedup index log");
+    }
+    fs::remove_file(wal)?;
+    Ok(())
+}
+
+/// Bloom filter over content hashes. Entries are never removed, so removals
+/// only leave false positives behind until the table next grows and the
+/// filter is rebuilt.
+struct Filter {
+    words: Vec<u64>,
+    hashes: u32,
+}
+
+impl Filter {
+    fn for_capacity(entries: u64, false_positive_rate: f64) -> Self {
+        let entries = entries.max(1) as f64;
+        let rate = false_positive_rate.clamp(1e-9, 0.5);
+        let ln2 = std::f64::consts::LN_2;
+        let bits = ((-entries * rate.ln()) / (ln2 * ln2)).ceil().max(1024.0) as u64;
+        let hashes = ((bits as f64 / entries) * ln2).round().clamp(1.0, 16.0) as u32;
+        Self {
+            words: vec![0; bits.div_ceil(64) as usize],
+            hashes,
+        }
+    }
+
+    fn positions(&self, key: &Key) -> impl Iterator<Item = u64> + '_ {
+        let bits = self.words.len() as u64 * 64;
+        let (h1, h2) = (key.h1, key.h2);
+        (0..self.hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % bits)
+    }
+
+    fn insert(&mut self, key: &Key) {
+        let positions: Vec<u64> = self.positions(key).collect();
+        for bit in positions {
+            self.words[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    fn might_contain(&self, key: &Key) -> bool {
+        self.positions(key)
+            .all(|bit| self.words[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Layout: magic (8), journal sequence (8), hash count (4), word count
+    /// (8), the words, then a BLAKE3 digest of everything before it.
+    fn save(&self, path: &Path, seq: u64) -> Result<()> {
+        let mut bytes = Vec::with_capacity(60 + self.words.len() * 8);
+        bytes.extend_from_slice(FILTER_MAGIC);
+        bytes.extend_from_slice(&seq.to_le_bytes());
+        bytes.extend_from_slice(&self.hashes.to_le_bytes());
+        bytes.extend_from_slice(&(self.words.len() as u64).to_le_bytes());
+        for word in &self.words {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        let digest = blake3::hash(&bytes);
+        bytes.extend_from_slice(digest.as_bytes());
+
+        let tmp = path.with_extension("tmp");
+        {
+            let mut file = File::create(&tmp)?;
+            file.write_all(&bytes)?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// The snapshot at `path` and the journal sequence it reflects.
+    fn load(path: &Path) -> Result<(Self, u64)> {
+        let bytes = fs::read(path)?;
+        if bytes.len() < 60 || &bytes[..8] != FILTER_MAGIC {
+            bail!("dedup filter snapshot is corrupt");
+        }
+        let (body, digest) = bytes.split_at(bytes.len() - 32);
+        let words = read_u64(body, 20) as usize;
+        if blake3::hash(body).as_bytes() != digest || body.len() != 28 + words * 8 || words == 0 {
+            bail!("dedup filter snapshot is corrupt");
+        }
+        let filter = Self {
+            words: body[28..].chunks(8).map(|w| read_u64(w, 0)).collect(),
+            hashes: u32::from_le_bytes(body[16..20].try_into().unwrap()),
+        };
+        Ok((filter, read_u64(body, 8)))
+    }
+}
+
+/// Dedup index partitioned by tenant, with `None` as the global partition.
+///
+/// The registry keeps a second one as its similarity index, keyed on the
+/// super-features of delta bases rather than on content hashes.
+pub(crate) struct ContentStore {
+    dir: PathBuf,
+    table: PagedTable,
+    filter: Filter,
+    // Journal sequence number the filter reflects.
+    filter_seq: u64,
+    filter_restored: bool,
+    false_positive_rate: f64,
+    cache_pages: usize,
+}
+
+impl ContentStore {
+    /// Open the index in `dir`, creating an empty one if there is none.
+    pub(crate) fn open(dir: &Path) -> Result<Self> {
+        let cache_pages = std::env::var("SPACE_DEDUP_CACHE_PAGES")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(DEFAULT_CACHE_PAGES);
+        let false_positive_rate = std::env::var("SPACE_BLOOM_FPR")
+            .ok()
+            .and_then(|v| v.parse::<f64>().ok())
+            .unwrap_or(DEFAULT_FALSE_POSITIVE_RATE);
+
+        fs::create_dir_all(dir)?;
+        let _ = fs::remove_file(dir.join(REHASH_FILE));
+        let index = dir.join(INDEX_FILE);
+        let table = if fs::metadata(&index).map(|m| m.len() > 0).unwrap_or(false) {
+            recover_wal(&index, &dir.join(WAL_FILE))?;
+            PagedTable::open(&index, cache_pages)
+                .with_context(|| format!("opening dedup index {}", index.display()))?
+        } else {
+            PagedTable::create(&index, INITIAL_BUCKETS, 0, cache_pages)?
+        };
+
+        let mut store = Self {
+            dir: dir.to_path_buf(),
+            table,
+            filter: Filter::for_capacity(0, false_positive_rate),
+            filter_seq: 0,
+            filter_restored: false,
+            false_positive_rate,
+            cache_pages,
+        };
+        match Filter::load(&dir.join(FILTER_FILE)) {
+            Ok((filter, seq)) => {
+                store.filter = filter;
+                store.filter_seq = seq;
+                store.filter_restored = true;
+            }
+            Err(err) => {
+                if dir.join(FILTER_FILE).exists() {
+                    warn!(error = %err, "rebuilding dedup filter");
+                }
+                store.rebuild_filter()?;
+            }
+        }
+        Ok(store)
+    }
+
+    /// Discard every entry, leaving an empty index.
+    pub(crate) fn reset(&mut self) -> Result<()> {
+        let _ = fs::remove_file(self.dir.join(WAL_FILE));
+        let _ = fs::remove_file(self.dir.join(FILTER_FILE));
+        self.table = PagedTable::create(
+            &self.dir.join(INDEX_FILE),
+            INITIAL_BUCKETS,
+            0,
+            self.cache_pages,
+        )?;
+        self.filter = self.sized_filter();
+        self.filter_seq = 0;
+        self.filter_restored = false;
+        Ok(())
+    }
+
+    /// Load the in-memory maps that registries kept before the index moved to disk.
+    pub(crate) fn import(
+        &mut self,
+        shared: HashMap<ContentHash, SegmentId>,
+        tenants: HashMap<TenantId, HashMap<ContentHash, SegmentId>>,
+    ) -> Result<()> {
+        let partitions = tenants
+            .into_iter()
+            .map(|(tenant, entries)| (Some(tenant), entries))
+            .chain([(None, shared)]);
+        for (tenant, entries) in partitions {
+            for (hash, seg_id) in entries {
+                self.insert(tenant.clone(), hash, seg_id)?;
+                if self.needs_flush() {
+                    self.flush(self.table.header.synced_seq)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply journaled content mutations the table has not seen yet.
+    ///
+    /// `checkpoint_seq` is the sequence number of the journal checkpoint the
+    /// changes follow; anything older is no longer in the journal, so a
+    /// filter snapshot from before it can't be caught up and is rebuilt.
+    pub(crate) fn replay(
+        &mut self,
+        checkpoint_seq: u64,
+        changes: Vec<(u64, ContentChange)>,
+    ) -> Result<()> {
+        let synced = self.table.header.synced_seq;
+        if synced < checkpoint_seq {
+            warn!(
+                synced,
+                checkpoint_seq, "dedup index is behind the registry checkpoint"
+            );
+        }
+        if self.filter_seq < checkpoint_seq.min(synced) {
+            self.rebuild_filter()?;
+        }
+
+        for (seq, change) in changes {
+            match change {
+                ContentChange::Registered {
+                    tenant,
+                    hash,
+                    segment,
+                } if seq > synced => self.insert(tenant, hash, segment)?,
+                ContentChange::Registered { hash, .. } if seq > self.filter_seq => {
+                    self.filter.insert(&Key::of(&hash));
+                }
+                ContentChange::Deregistered { tenant, hash } if seq > synced => {
+                    self.remove(tenant.as_ref(), &hash)?;
+                }
+                _ => {}
+            }
+            if seq > synced && self.needs_flush() {
+                self.flush(seq)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn get(
+        &mut self,
+        tenant: Option<&TenantId>,
+        hash: &ContentHash,
+    ) -> Result<Option<SegmentId>> {
+        let key = Key::of(hash);
+        if !self.filter.might_contain(&key) {
+            return Ok(None);
+        }
+        self.table.get(&key, tenant, hash)
+    }
+
+    pub(crate) fn insert(
+        &mut self,
+        tenant: Option<TenantId>,
+        hash: ContentHash,
+        seg_id: SegmentId,
+    ) -> Result<()> {
+        let key = Key::of(&hash);
+        self.table
+            .insert(&key, Record::new(tenant, hash, seg_id)?)?;
+        self.filter.insert(&key);
+        Ok(())
+    }
+
+    pub(crate) fn remove(&mut self, tenant: Option<&TenantId>, hash: &ContentHash) -> Result<()> {
+        self.table
+            .remove(&Key::of(hash), |record| record.is(tenant, hash))?;
+        Ok(())
+    }
+
+    /// Drop `hash` from whichever partition maps it to `seg_id`, returning
+    /// that partition. Segment IDs are unique, so at most one entry matches.
+    pub(crate) fn remove_segment(
+        &mut self,
+        hash: &ContentHash,
+        seg_id: SegmentId,
+    ) -> Result<Option<Option<TenantId>>> {
+        let removed = self.table.remove(&Key::of(hash), |record| {
+            &record.hash == hash && record.segment == seg_id
+        })?;
+        Ok(removed.map(|record| record.tenant))
+    }
+
+    pub(crate) fn entries(&mut self) -> Result<Vec<(ContentHash, SegmentId)>> {
+        let mut entries = Vec::new();
+        self.table
+            .scan(|record| entries.push((record.hash.clone(), record.segment)))?;
+        Ok(entries)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.table.header.entries as usize
+    }
+
+    /// Whether enough pages are pinned dirty that they should be written out.
+    pub(crate) fn needs_flush(&self) -> bool {
+        self.table.cache.dirty * 2 >= self.table.cache.capacity
+    }
+
+    /// Make the table durable as of journal sequence `seq`, growing it if it
+    /// has become too full.
+    pub(crate) fn flush(&mut self, seq: u64) -> Result<()> {
+        self.table.flush(&self.dir.join(WAL_FILE), seq)?;
+        if self.table.header.entries > self.table.header.buckets * GROW_FILL {
+            self.grow(seq)?;
+        }
+        Ok(())
+    }
+
+    /// Flush the table and snapshot the filter, both as of `seq`.
+    pub(crate) fn checkpoint(&mut self, seq: u64) -> Result<()> {
+        self.flush(seq)?;
+        if self.filter_seq != seq {
+            self.filter.save(&self.dir.join(FILTER_FILE), seq)?;
+            self.filter_seq = seq;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn stats(&self) -> ContentIndexStats {
+        ContentIndexStats {
+            entries: self.table.header.entries,
+            buckets: self.table.header.buckets,
+            pages: self.table.header.pages,
+            cached_pages: self.table.cache.pages.len(),
+            cache_capacity: self.table.cache.capacity,
+            filter_bytes: self.filter.words.len() * 8,
+            filter_restored: self.filter_restored,
+        }
+    }
+
+    fn sized_filter(&self) -> Filter {
+        Filter::for_capacity(
+            self.table.header.buckets * GROW_FILL,
+            self.false_positive_rate,
+        )
+    }
+
+    fn rebuild_filter(&mut self) -> Result<()> {
+        let mut filter = self.sized_filter();
+        self.table
+            .scan(|record| filter.insert(&Key::of(&record.hash)))?;
+        self.filter = filter;
+        self.filter_seq = self.table.header.synced_seq;
+        Ok(())
+    }
+
+    /// Rewrite the flushed table into one with enough buckets for its
+    /// entries, streaming page by page so memory stays within the cache.
+    fn grow(&mut self, seq: u64) -> Result<()> {
+        let mut buckets = self.table.header.buckets;
+        while self.table.header.entries > buckets * GROW_FILL {
+            buckets *= 2;
+        }
+        let rehash = self.dir.join(REHASH_FILE);
+        let mut next = PagedTable::create(&rehash, buckets, seq, self.cache_pages)?;
+        let mut filter = Filter::for_capacity(buckets * GROW_FILL, self.false_positive_rate);
+        for number in 1..self.table.header.pages {
+            for record in self.table.read_page(number)?.records {
+                let key = Key::of(&record.hash);
+                filter.insert(&key);
+                next.insert(&key, record)?;
+            }
+            if next.cache.dirty >= next.cache.capacity {
+                next.write_unlogged()?;
+            }
+        }
+        next.write_unlogged()?;
+        fs::rename(&rehash, self.dir.join(INDEX_FILE))?;
+
+        self.table = next;
+        self.filter = filter;
+        self.filter.save(&self.dir.join(FILTER_FILE), seq)?;
+        self.filter_seq = seq;
+        Ok(())
+    }
+}
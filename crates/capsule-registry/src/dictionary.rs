@@ -0,0 +1,173 @@
+use crate::pipeline::WritePipeline;
+use anyhow::Result;
+use common::{
+    Capsule, CapsuleId, CompressionPolicy, DictionaryRef, EncryptionPolicy, Policy, TenantId,
+    VersioningPolicy,
+};
+use compression::dictionary::{measure_dictionary, train_dictionary, DictionaryGain};
+use tracing::info;
+
+/// Upper bound on the size of a trained dictionary.
+const DICTIONARY_SIZE: usize = 16 * 1024;
+/// Bytes sampled from the start of each segment.
+const SAMPLE_LEN: usize = 16 * 1024;
+/// Total bytes sampled per training run.
+const SAMPLE_BUDGET: usize = 4 * 1024 * 1024;
+/// Every this many samples one is held back to measure the dictionary on.
+const HOLDOUT_EVERY: usize = 4;
+/// Fewest training samples worth building a dictionary from.
+const MIN_TRAINING_SAMPLES: usize = 16;
+/// Level gains are measured at when no sampled capsule names one.
+const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+/// Outcome of one training run.
+#[derive(Debug, Clone)]
+pub struct DictionaryReport {
+    pub tenant: Option<TenantId>,
+    /// The new dictionary, which writes compress against from now on.
+    pub dictionary: DictionaryRef,
+    pub dictionary_len: usize,
+    /// Samples the dictionary was trained on.
+    pub samples: usize,
+    /// Held-out samples compressed with and without the new dictionary.
+    pub gain: DictionaryGain,
+}
+
+/// Trains the Zstd dictionaries that policies with `zstd_dictionary` set
+/// compress against.
+///
+/// Each tenant, and the shared namespace, has one dictionary capsule. Every
+/// run samples the segments of the scope's opted-in capsules, newest first,
+/// and stores the result as a new version of that capsule. Segments name the
+/// version they were compressed against, and the capsule keeps every version,
+/// so retraining never strands what was written before.
+pub struct DictionaryTrainer<'a> {
+    pipeline: &'a WritePipeline,
+}
+
+impl<'a> DictionaryTrainer<'a> {
+    pub fn new(pipeline: &'a WritePipeline) -> Self {
+        Self { pipeline }
+    }
+
+    /// Train a new dictionary for `tenant`, or for capsules without a tenant.
+    pub fn train(&self, tenant: Option<&TenantId>) -> Result<DictionaryReport> {
+        if self.pipeline.uses_modular() {
+            anyhow::bail!("zstd dictionaries are not supported by the modular pipeline");
+        }
+        let registry = self.pipeline.registry();
+        let existing = registry.dictionary(tenant);
+
+        let mut capsules: Vec<Capsule> = registry
+            .list_capsules()
+            .into_iter()
+            .filter_map(|id| registry.lookup(id).ok())
+            .filter(|capsule| capsule.tenant.as_ref() == tenant && capsule.policy.zstd_dictionary)
+            .collect();
+        capsules.sort_by_key(|capsule| std::cmp::Reverse(capsule.created_at));
+
+        let mut samples = Vec::new();
+        let mut sampled = 0;
+        'capsules: for capsule in &capsules {
+            for index in 0..capsule.segments.len() {
+                if capsule.segments[index].is_hole() {
+                    continue;
+                }
+                let mut sample = self.pipeline.read_segment(capsule, index)?;
+                sample.truncate(SAMPLE_LEN);
+                sampled += sample.len();
+                samples.push(sample);
+                if sampled >= SAMPLE_BUDGET {
+                    break 'capsules;
+                }
+            }
+        }
+
+        let (held_out, training): (Vec<_>, Vec<_>) = samples
+            .into_iter()
+            .enumerate()
+            .partition(|(index, _)| index % HOLDOUT_EVERY == HOLDOUT_EVERY - 1);
+        let held_out: Vec<Vec<u8>> = held_out.into_iter().map(|(_, sample)| sample).collect();
+        let training: Vec<Vec<u8>> = training.into_iter().map(|(_, sample)| sample).collect();
+        if training.len() < MIN_TRAINING_SAMPLES {
+            anyhow::bail!(
+                "only {} segments to sample; at least {} are needed to train a dictionary",
+                training.len() + held_out.len(),
+                MIN_TRAINING_SAMPLES * HOLDOUT_EVERY / (HOLDOUT_EVERY - 1)
+            );
+        }
+
+        let bytes = train_dictionary(&training, DICTIONARY_SIZE)?;
+        let level = capsules
+            .iter()
+            .find_map(|capsule| match capsule.policy.compression {
+                CompressionPolicy::Zstd { level } => Some(level),
+                _ => None,
+            })
+            .unwrap_or(DEFAULT_ZSTD_LEVEL);
+        let gain = measure_dictionary(&bytes, &held_out, level)?;
+
+        let dictionary = self.store(tenant, existing, &capsules, &bytes)?;
+        info!(
+            tenant = ?tenant,
+            capsule = %dictionary.capsule.as_uuid(),
+            version = dictionary.version,
+            samples = training.len(),
+            plain_ratio = gain.plain_ratio(),
+            dictionary_ratio = gain.dictionary_ratio(),
+            "zstd dictionary trained"
+        );
+        Ok(DictionaryReport {
+            tenant: tenant.cloned(),
+            dictionary,
+            dictionary_len: bytes.len(),
+            samples: training.len(),
+            gain,
+        })
+    }
+
+    /// Save `bytes` as the next version of the scope's dictionary capsule,
+    /// creating it on the first run.
+    fn store(
+        &self,
+        tenant: Option<&TenantId>,
+        existing: Option<CapsuleId>,
+        sampled: &[Capsule],
+        bytes: &[u8],
+    ) -> Result<DictionaryRef> {
+        let registry = self.pipeline.registry();
+        if let Some(capsule) = existing.filter(|id| registry.lookup(*id).is_ok()) {
+            let updated = self.pipeline.overwrite_capsule(capsule, bytes)?;
+            return Ok(DictionaryRef {
+                capsule,
+                version: updated.version,
+            });
+        }
+
+        // Made from the sampled data, so encrypted if any of it is.
+        let encryption = sampled
+            .iter()
+            .map(|capsule| capsule.policy.encryption.clone())
+            .find(EncryptionPolicy::is_enabled)
+            .unwrap_or_default();
+        let policy = Policy {
+            compression: CompressionPolicy::None,
+            dedupe: false,
+            compact_interval_secs: None,
+            encryption,
+            versioning: Some(VersioningPolicy::default()),
+            ..Policy::default()
+        };
+        let capsule = match tenant {
+            Some(tenant) => self
+                .pipeline
+                .write_capsule_for_tenant(tenant, bytes, &policy)?,
+            None => self.pipeline.write_capsule_with_policy(bytes, &policy)?,
+        };
+        registry.set_dictionary(tenant, capsule)?;
+        Ok(DictionaryRef {
+            capsule,
+            version: registry.lookup(capsule)?.version,
+        })
+    }
+}
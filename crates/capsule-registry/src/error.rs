@@ -1,4 +1,5 @@
 use anyhow::Error;
+use common::{CapsuleId, TenantId};
 use thiserror::Error;
 
 pub use compression::CompressionError;
@@ -19,6 +20,27 @@ pub enum DedupError {
     LookupFailed { hash: String, reason: String },
 }
 
+/// A delete or overwrite refused because the capsule is under retention or a
+/// legal hold.
+#[derive(Debug, Error)]
+#[error("Capsule {capsule:?} is protected from {action}: {reason}")]
+pub struct RetentionError {
+    pub capsule: CapsuleId,
+    pub action: &'static str,
+    pub reason: String,
+}
+
+/// A write refused because it would take a tenant past its hard quota.
+#[derive(Debug, Error)]
+#[error(
+    "Tenant {tenant} would use {requested_bytes} bytes, over its hard quota of {hard_limit_bytes}"
+)]
+pub struct QuotaError {
+    pub tenant: TenantId,
+    pub requested_bytes: u64,
+    pub hard_limit_bytes: u64,
+}
+
 /// Pipeline level failures aggregating subsystem errors.
 #[derive(Debug, Error)]
 pub enum PipelineError {
@@ -0,0 +1,349 @@
+use crate::dedup::hash_content;
+use crate::gc::GarbageCollector;
+use crate::CapsuleRegistry;
+use anyhow::Result;
+use common::traits::CapsuleCatalog;
+use common::{CapsuleId, ContentHash, Segment, SegmentId};
+use nvram_sim::{segment_checksum, NvramLog};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use tracing::{info, warn};
+
+/// A single inconsistency between the registry and the NVRAM log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsckIssue {
+    /// A capsule references a segment that has no NVRAM metadata.
+    DanglingSegment {
+        capsule: CapsuleId,
+        segment: SegmentId,
+    },
+    /// A segment exists in NVRAM but no capsule or delta segment references it.
+    OrphanSegment { segment: SegmentId },
+    /// The stored refcount disagrees with the number of referencing capsule
+    /// slots and delta segments.
+    RefcountMismatch {
+        segment: SegmentId,
+        recorded: u32,
+        actual: u32,
+    },
+    /// A content-store entry points at a missing segment or one with another hash.
+    ContentIndexDrift {
+        hash: ContentHash,
+        segment: SegmentId,
+    },
+    /// The stored bytes no longer match the segment's checksum (or, for segments
+    /// written before checksums, its content hash).
+    ContentHashMismatch { segment: SegmentId },
+    /// The segment's byte range extends past the end of the log file.
+    OutOfBounds {
+        segment: SegmentId,
+        offset: u64,
+        len: u32,
+        log_size: u64,
+    },
+}
+
+impl FsckIssue {
+    /// Whether `--repair` can fix this issue without losing referenced data.
+    pub fn is_repairable(&self) -> bool {
+        matches!(
+            self,
+            FsckIssue::OrphanSegment { .. }
+                | FsckIssue::RefcountMismatch { .. }
+                | FsckIssue::ContentIndexDrift { .. }
+        )
+    }
+}
+
+impl fmt::Display for FsckIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsckIssue::DanglingSegment { capsule, segment } => write!(
+                f,
+                "capsule {} references missing segment {}",
+                capsule.as_uuid(),
+                segment.0
+            ),
+            FsckIssue::OrphanSegment { segment } => {
+                write!(f, "segment {} is not referenced by any capsule", segment.0)
+            }
+            FsckIssue::RefcountMismatch {
+                segment,
+                recorded,
+                actual,
+            } => write!(
+                f,
+                "segment {} has ref_count {} but {} references",
+                segment.0, recorded, actual
+            ),
+            FsckIssue::ContentIndexDrift { hash, segment } => write!(
+                f,
+                "content hash {} maps to segment {} which does not hold it",
+                hash.as_str(),
+                segment.0
+            ),
+            FsckIssue::ContentHashMismatch { segment } => write!(
+                f,
+                "segment {} bytes do not match its content hash",
+                segment.0
+            ),
+            FsckIssue::OutOfBounds {
+                segment,
+                offset,
+                len,
+                log_size,
+            } => match offset.checked_add(*len as u64) {
+                Some(end) => write!(
+                    f,
+                    "segment {} spans {}..{} beyond log size {}",
+                    segment.0, offset, end, log_size
+                ),
+                None => write!(
+                    f,
+                    "segment {} spans {} bytes from offset {}, past any log size",
+                    segment.0, len, offset
+                ),
+            },
+        }
+    }
+}
+
+/// Findings from a consistency check, plus the number of issues fixed when repairing.
+#[derive(Debug, Clone, Default)]
+pub struct FsckReport {
+    pub capsules_checked: usize,
+    pub segments_checked: usize,
+    pub issues: Vec<FsckIssue>,
+    pub repaired: usize,
+}
+
+impl FsckReport {
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Issues still present after the run.
+    pub fn unresolved(&self) -> usize {
+        self.issues.len() - self.repaired
+    }
+}
+
+/// Cross-validates the capsule registry against the NVRAM segment map and log file.
+pub struct Fsck<'a> {
+    registry: &'a CapsuleRegistry,
+    nvram: &'a NvramLog,
+}
+
+impl<'a> Fsck<'a> {
+    pub fn new(registry: &'a CapsuleRegistry, nvram: &'a NvramLog) -> Self {
+        Self { registry, nvram }
+    }
+
+    /// Report inconsistencies without modifying anything.
+    pub fn check(&self) -> Result<FsckReport> {
+        let capsules: Vec<_> = self
+            .registry
+            .list_capsules()
+            .into_iter()
+            .filter_map(|id| self.registry.lookup(id).ok())
+            .collect();
+        let segments: HashMap<SegmentId, Segment> = self
+            .nvram
+            .list_segments()?
+            .into_iter()
+            .map(|segment| (segment.id, segment))
+            .collect();
+        let log_size = self.nvram.log_size()?;
+
+        // Segments owned by unresolved write intents are settled by pipeline recovery.
+        let in_flight: HashSet<SegmentId> = self
+            .registry
+            .pending_writes()
+            .into_iter()
+            .flat_map(|intent| intent.new_segments)
+            .collect();
+
+        let mut report = FsckReport {
+            capsules_checked: capsules.len(),
+            segments_checked: segments.len(),
+            ..FsckReport::default()
+        };
+
+        // Retained versions hold references just like live capsules.
+        let retained: Vec<_> = capsules
+            .iter()
+            .flat_map(|capsule| self.registry.version_history(capsule.id))
+            .map(|version| version.capsule)
+            .collect();
+
+        let mut references: HashMap<SegmentId, u32> = HashMap::new();
+        for capsule in capsules.iter().chain(&retained) {
+            for seg_id in capsule.segments.iter().filter(|seg_id| !seg_id.is_hole()) {
+                *references.entry(*seg_id).or_insert(0) += 1;
+                if !segments.contains_key(seg_id) {
+                    report.issues.push(FsckIssue::DanglingSegment {
+                        capsule: capsule.id,
+                        segment: *seg_id,
+                    });
+                }
+            }
+        }
+
+        // So does every delta segment on its base.
+        for base in segments.values().filter_map(|segment| segment.delta_base) {
+            *references.entry(base).or_insert(0) += 1;
+        }
+
+        let mut ids: Vec<SegmentId> = segments.keys().copied().collect();
+        ids.sort_by_key(|id| id.0);
+        for seg_id in ids {
+            let segment = &segments[&seg_id];
+            let actual = references.get(&seg_id).copied().unwrap_or(0);
+
+            if !in_flight.contains(&seg_id) {
+                if actual == 0 {
+                    report
+                        .issues
+                        .push(FsckIssue::OrphanSegment { segment: seg_id });
+                } else if segment.ref_count != actual {
+                    report.issues.push(FsckIssue::RefcountMismatch {
+                        segment: seg_id,
+                        recorded: segment.ref_count,
+                        actual,
+                    });
+                }
+            }
+
+            if segment
+                .offset
+                .checked_add(segment.len as u64)
+                .filter(|end| *end <= log_size)
+                .is_none()
+            {
+                report.issues.push(FsckIssue::OutOfBounds {
+                    segment: seg_id,
+                    offset: segment.offset,
+                    len: segment.len,
+                    log_size,
+                });
+                continue;
+            }
+
+            // Content hashes now fingerprint the plaintext, so the stored bytes are
+            // checked against their checksum. Older segments without one were keyed
+            // on their stored bytes, which is comparable when they're unencrypted.
+            let intact = match (&segment.checksum, &segment.content_hash) {
+                (Some(expected), _) => &segment_checksum(&self.nvram.read(seg_id)?) == expected,
+                (None, Some(expected)) if !segment.encrypted => {
+                    &hash_content(&self.nvram.read(seg_id)?) == expected
+                }
+                _ => true,
+            };
+            if !intact {
+                report
+                    .issues
+                    .push(FsckIssue::ContentHashMismatch { segment: seg_id });
+            }
+        }
+
+        for (hash, seg_id) in self.registry.content_entries() {
+            let holds_hash = segments
+                .get(&seg_id)
+                .map(|segment| segment.content_hash.as_ref() == Some(&hash))
+                .unwrap_or(false);
+            if !holds_hash {
+                report.issues.push(FsckIssue::ContentIndexDrift {
+                    hash,
+                    segment: seg_id,
+                });
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Check, then fix every issue that can be fixed without touching live data.
+    ///
+    /// Orphans are removed, refcounts are reset to the observed reference count
+    /// and stale content-store entries are dropped. Segments with corrupt or
+    /// missing bytes are also withdrawn from the dedup index so new writes stop
+    /// sharing them, but they stay reported since their data cannot be restored.
+    ///
+    /// Reclaiming an orphaned delta releases its base, so refcounts are fixed
+    /// last from a fresh check rather than from the counts first observed.
+    pub fn repair(&self) -> Result<FsckReport> {
+        let mut report = self.check()?;
+        let gc = GarbageCollector::new(self.registry, self.nvram);
+
+        for issue in &report.issues {
+            match issue {
+                FsckIssue::OrphanSegment { segment } => {
+                    // An earlier reclaim may already have cascaded to this one.
+                    if let Ok(meta) = self.nvram.get_segment_metadata(*segment) {
+                        gc.reclaim_segment(meta)?;
+                    }
+                    report.repaired += 1;
+                }
+                FsckIssue::RefcountMismatch { .. } => {}
+                FsckIssue::ContentIndexDrift { hash, segment } => {
+                    self.registry.deregister_content(hash, *segment)?;
+                    report.repaired += 1;
+                }
+                FsckIssue::ContentHashMismatch { segment }
+                | FsckIssue::OutOfBounds { segment, .. } => {
+                    if let Ok(meta) = self.nvram.get_segment_metadata(*segment) {
+                        if let Some(hash) = &meta.content_hash {
+                            self.registry.deregister_content(hash, *segment)?;
+                        }
+                        if let Some(sketch) = &meta.sketch {
+                            self.registry.deregister_similar(sketch, *segment)?;
+                        }
+                    }
+                    warn!(
+                        segment = segment.0,
+                        "segment data is damaged and cannot be repaired"
+                    );
+                }
+                FsckIssue::DanglingSegment { capsule, segment } => {
+                    warn!(
+                        capsule = %capsule.as_uuid(),
+                        segment = segment.0,
+                        "capsule references a missing segment; leaving it in place"
+                    );
+                }
+            }
+        }
+
+        for issue in self.check()?.issues {
+            match issue {
+                // Left behind by reclaiming the only delta that referenced it.
+                FsckIssue::OrphanSegment { segment } => {
+                    if let Ok(meta) = self.nvram.get_segment_metadata(segment) {
+                        gc.reclaim_segment(meta)?;
+                    }
+                }
+                FsckIssue::RefcountMismatch {
+                    segment, actual, ..
+                } => {
+                    let mut meta = self.nvram.get_segment_metadata(segment)?;
+                    meta.ref_count = actual;
+                    meta.deduplicated = actual > 1;
+                    self.nvram.update_segment_metadata(segment, meta)?;
+                }
+                _ => {}
+            }
+        }
+        report.repaired += report
+            .issues
+            .iter()
+            .filter(|issue| matches!(issue, FsckIssue::RefcountMismatch { .. }))
+            .count();
+
+        info!(
+            issues = report.issues.len(),
+            repaired = report.repaired,
+            "fsck repair complete"
+        );
+        Ok(report)
+    }
+}
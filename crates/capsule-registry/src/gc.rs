@@ -19,30 +19,55 @@ impl<'a> GarbageCollector<'a> {
     }
 
     /// Run a sweep pass and return the number of reclaimed segments.
+    ///
+    /// Segments still protected by a retained or held capsule are left in
+    /// place even if their count reached zero; each one is reported as a
+    /// retention violation instead of being reclaimed.
     pub fn sweep(&self) -> Result<usize> {
         let segments = self.nvram.list_segments()?;
+        let protected = self.registry.protected_segments()?;
         let mut reclaimed = 0usize;
 
         for segment in segments {
-            if segment.ref_count == 0 {
-                self.reclaim_segment(segment)?;
-                reclaimed += 1;
+            if segment.ref_count != 0 {
+                continue;
             }
+            if let Some(capsule) = protected.get(&segment.id) {
+                let reason = format!("segment {:?} is still retained", segment.id);
+                self.registry
+                    .retention_violation(*capsule, "reclaim", reason);
+                continue;
+            }
+            self.reclaim_segment(segment)?;
+            reclaimed += 1;
         }
 
         Ok(reclaimed)
     }
 
-    fn reclaim_segment(&self, segment: Segment) -> Result<()> {
+    /// Remove a segment nothing references any more, with its index entries.
+    ///
+    /// A delta segment also drops the reference it held on its base, and the
+    /// base goes with it if that was the last one.
+    pub(crate) fn reclaim_segment(&self, segment: Segment) -> Result<()> {
         if let Some(ref hash) = segment.content_hash {
             self.registry.deregister_content(hash, segment.id)?;
         }
+        if let Some(ref sketch) = segment.sketch {
+            self.registry.deregister_similar(sketch, segment.id)?;
+        }
 
-        // Remove the metadata entry; the on-disk bytes remain until compaction.
+        // Remove the metadata entry; `Compactor` reclaims the on-disk bytes later.
         if self.nvram.remove_segment(segment.id)?.is_none() {
             return Err(anyhow!("Segment {:?} vanished during GC", segment.id));
         }
 
+        if let Some(base) = segment.delta_base {
+            let base = self.nvram.decrement_refcount(base)?;
+            if base.ref_count == 0 {
+                self.reclaim_segment(base)?;
+            }
+        }
         Ok(())
     }
 }
@@ -0,0 +1,220 @@
+//! Secondary index over capsule tags, sizes and creation times, plus the
+//! compaction intervals their policies request.
+//!
+//! The index is derived state: it is rebuilt from the capsule map when the
+//! registry opens and kept current under the capsule lock, so it never needs
+//! journaling of its own.
+
+use common::{Capsule, CapsuleId};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Bound;
+
+/// Filter for [`CapsuleRegistry::find_capsules`](crate::CapsuleRegistry::find_capsules).
+///
+/// Every criterion that is set must match; an empty query matches every capsule.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CapsuleQuery {
+    /// Tags that must be present, optionally with a specific value.
+    pub tags: Vec<(String, Option<String>)>,
+    /// Inclusive size bounds in bytes.
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    /// Inclusive creation-time bounds in Unix seconds.
+    pub created_after: Option<u64>,
+    pub created_before: Option<u64>,
+}
+
+impl CapsuleQuery {
+    /// Require tag `key`, with any value.
+    pub fn tagged(mut self, key: impl Into<String>) -> Self {
+        self.tags.push((key.into(), None));
+        self
+    }
+
+    /// Require tag `key` set to `value`.
+    pub fn tag_value(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.tags.push((key.into(), Some(value.into())));
+        self
+    }
+
+    pub fn min_size(mut self, bytes: u64) -> Self {
+        self.min_size = Some(bytes);
+        self
+    }
+
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    pub fn created_after(mut self, secs: u64) -> Self {
+        self.created_after = Some(secs);
+        self
+    }
+
+    pub fn created_before(mut self, secs: u64) -> Self {
+        self.created_before = Some(secs);
+        self
+    }
+
+    fn matches(&self, entry: &IndexEntry) -> bool {
+        let tags_match = self
+            .tags
+            .iter()
+            .all(|(key, value)| match (entry.tags.get(key), value) {
+                (Some(actual), Some(wanted)) => actual == wanted,
+                (Some(_), None) => true,
+                (None, _) => false,
+            });
+        tags_match
+            && in_bounds(entry.size, self.min_size, self.max_size)
+            && in_bounds(entry.created_at, self.created_after, self.created_before)
+    }
+}
+
+fn in_bounds(value: u64, low: Option<u64>, high: Option<u64>) -> bool {
+    low.is_none_or(|low| value >= low) && high.is_none_or(|high| value <= high)
+}
+
+fn inverted(low: Option<u64>, high: Option<u64>) -> bool {
+    matches!((low, high), (Some(low), Some(high)) if low > high)
+}
+
+fn range_of(low: Option<u64>, high: Option<u64>) -> (Bound<u64>, Bound<u64>) {
+    (
+        low.map_or(Bound::Unbounded, Bound::Included),
+        high.map_or(Bound::Unbounded, Bound::Included),
+    )
+}
+
+#[derive(Debug, Clone)]
+struct IndexEntry {
+    size: u64,
+    created_at: u64,
+    tags: BTreeMap<String, String>,
+    compact_interval_secs: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct CapsuleIndex {
+    entries: HashMap<CapsuleId, IndexEntry>,
+    by_tag: HashMap<String, HashSet<CapsuleId>>,
+    by_size: BTreeMap<u64, HashSet<CapsuleId>>,
+    by_created: BTreeMap<u64, HashSet<CapsuleId>>,
+    by_compact_interval: BTreeMap<u64, HashSet<CapsuleId>>,
+}
+
+impl CapsuleIndex {
+    pub(crate) fn build<'a>(capsules: impl IntoIterator<Item = &'a Capsule>) -> Self {
+        let mut index = Self::default();
+        for capsule in capsules {
+            index.upsert(capsule);
+        }
+        index
+    }
+
+    pub(crate) fn upsert(&mut self, capsule: &Capsule) {
+        self.remove(capsule.id);
+        let entry = IndexEntry {
+            size: capsule.size,
+            created_at: capsule.created_at,
+            tags: capsule.tags.clone(),
+            compact_interval_secs: capsule
+                .policy
+                .compact_interval_secs
+                .filter(|secs| *secs > 0),
+        };
+        for key in entry.tags.keys() {
+            self.by_tag
+                .entry(key.clone())
+                .or_default()
+                .insert(capsule.id);
+        }
+        self.by_size
+            .entry(entry.size)
+            .or_default()
+            .insert(capsule.id);
+        self.by_created
+            .entry(entry.created_at)
+            .or_default()
+            .insert(capsule.id);
+        if let Some(secs) = entry.compact_interval_secs {
+            self.by_compact_interval
+                .entry(secs)
+                .or_default()
+                .insert(capsule.id);
+        }
+        self.entries.insert(capsule.id, entry);
+    }
+
+    pub(crate) fn remove(&mut self, id: CapsuleId) {
+        let Some(entry) = self.entries.remove(&id) else {
+            return;
+        };
+        for key in entry.tags.keys() {
+            if let Some(ids) = self.by_tag.get_mut(key) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.by_tag.remove(key);
+                }
+            }
+        }
+        remove_id(&mut self.by_size, entry.size, id);
+        remove_id(&mut self.by_created, entry.created_at, id);
+        if let Some(secs) = entry.compact_interval_secs {
+            remove_id(&mut self.by_compact_interval, secs, id);
+        }
+    }
+
+    /// Shortest non-zero `compact_interval_secs` across indexed capsules.
+    pub(crate) fn min_compact_interval(&self) -> Option<u64> {
+        self.by_compact_interval.keys().next().copied()
+    }
+
+    /// Capsules matching `query`, oldest first.
+    pub(crate) fn query(&self, query: &CapsuleQuery) -> Vec<CapsuleId> {
+        // `BTreeMap::range` panics on inverted bounds, and nothing can match them.
+        if inverted(query.min_size, query.max_size)
+            || inverted(query.created_after, query.created_before)
+        {
+            return Vec::new();
+        }
+
+        // Seed from the most selective structure available, then filter.
+        let candidates: Vec<CapsuleId> = if let Some((key, _)) = query.tags.first() {
+            self.by_tag
+                .get(key)
+                .map(|ids| ids.iter().copied().collect())
+                .unwrap_or_default()
+        } else if query.min_size.is_some() || query.max_size.is_some() {
+            self.by_size
+                .range(range_of(query.min_size, query.max_size))
+                .flat_map(|(_, ids)| ids.iter().copied())
+                .collect()
+        } else {
+            self.by_created
+                .range(range_of(query.created_after, query.created_before))
+                .flat_map(|(_, ids)| ids.iter().copied())
+                .collect()
+        };
+
+        let mut matches: Vec<(u64, CapsuleId)> = candidates
+            .into_iter()
+            .filter_map(|id| {
+                let entry = self.entries.get(&id)?;
+                query.matches(entry).then_some((entry.created_at, id))
+            })
+            .collect();
+        matches.sort_by_key(|(created_at, id)| (*created_at, id.0));
+        matches.into_iter().map(|(_, id)| id).collect()
+    }
+}
+
+fn remove_id(map: &mut BTreeMap<u64, HashSet<CapsuleId>>, key: u64, id: CapsuleId) {
+    if let Some(ids) = map.get_mut(&key) {
+        ids.remove(&id);
+        if ids.is_empty() {
+            map.remove(&key);
+        }
+    }
+}
@@ -0,0 +1,370 @@
+//! Append-only journal backing the registry metadata file.
+//!
+//! The metadata file holds a checkpoint record followed by one record per
+//! mutation. Every line is `<checksum> <json>`; replay stops at the first line
+//! that is incomplete or fails its checksum and truncates the file there, so a
+//! torn write only ever loses the mutation that was in flight.
+
+use crate::content_index::ContentChange;
+use crate::tenant::TenantQuota;
+use crate::{retain_version, RegistryState, WriteIntent};
+use anyhow::{bail, Result};
+use common::{Capsule, CapsuleId, ContentHash, SegmentId, TenantId};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use tracing::warn;
+
+/// Mutations appended between checkpoints before the journal is folded.
+pub(crate) const DEFAULT_CHECKPOINT_INTERVAL: u64 = 1024;
+
+const CHECKSUM_HEX_LEN: usize = 16;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub(crate) enum JournalOp {
+    Checkpoint {
+        state: RegistryState,
+    },
+    CapsuleUpserted {
+        capsule: Capsule,
+    },
+    CapsuleDeleted {
+        id: CapsuleId,
+    },
+    ContentRegistered {
+        hash: ContentHash,
+        segment: SegmentId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tenant: Option<TenantId>,
+    },
+    ContentDeregistered {
+        hash: ContentHash,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tenant: Option<TenantId>,
+    },
+    SimilarityRegistered {
+        keys: Vec<ContentHash>,
+        segment: SegmentId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tenant: Option<TenantId>,
+    },
+    SimilarityDeregistered {
+        keys: Vec<ContentHash>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tenant: Option<TenantId>,
+    },
+    WriteIntended {
+        intent: WriteIntent,
+    },
+    WriteExtended {
+        id: CapsuleId,
+        segments: Vec<SegmentId>,
+    },
+    WriteCommitted {
+        id: CapsuleId,
+        // Commit time, recorded so replay dates retained versions identically.
+        #[serde(default)]
+        at: u64,
+    },
+    WriteAborted {
+        id: CapsuleId,
+    },
+    VersionsPruned {
+        id: CapsuleId,
+        versions: Vec<u64>,
+    },
+    QuotaSet {
+        tenant: TenantId,
+        quota: TenantQuota,
+    },
+    DictionarySet {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tenant: Option<TenantId>,
+        capsule: CapsuleId,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct JournalRecord {
+    seq: u64,
+    next_segment_id: u64,
+    #[serde(flatten)]
+    op: JournalOp,
+}
+
+pub(crate) struct Journal {
+    path: String,
+    file: File,
+    seq: u64,
+    since_checkpoint: u64,
+    checkpoint_interval: u64,
+}
+
+fn checksum(payload: &str) -> String {
+    let digest = blake3::hash(payload.as_bytes()).to_hex();
+    digest.as_str()[..CHECKSUM_HEX_LEN].to_string()
+}
+
+fn encode(record: &JournalRecord) -> Result<String> {
+    let payload = serde_json::to_string(record)?;
+    Ok(format!("{} {}\n", checksum(&payload), payload))
+}
+
+fn decode(line: &str) -> Option<JournalRecord> {
+    let (sum, payload) = line.split_once(' ')?;
+    if sum != checksum(payload) {
+        return None;
+    }
+    serde_json::from_str(payload).ok()
+}
+
+fn apply(state: &mut RegistryState, record: JournalRecord) {
+    state.journal_seq = record.seq;
+    state.next_segment_id = state.next_segment_id.max(record.next_segment_id);
+    match record.op {
+        JournalOp::Checkpoint { state: snapshot } => {
+            *state = snapshot;
+            state.journal_seq = record.seq;
+            state.checkpoint_seq = record.seq;
+        }
+        JournalOp::CapsuleUpserted { capsule } => {
+            state.capsules.insert(capsule.id, capsule);
+        }
+        JournalOp::CapsuleDeleted { id } => {
+            state.capsules.remove(&id);
+            state.history.remove(&id);
+        }
+        JournalOp::ContentRegistered {
+            hash,
+            segment,
+            tenant,
+        } => {
+            state.content_changes.push((
+                record.seq,
+                ContentChange::Registered {
+                    tenant,
+                    hash,
+                    segment,
+                },
+            ));
+        }
+        JournalOp::ContentDeregistered { hash, tenant } => {
+            state
+                .content_changes
+                .push((record.seq, ContentChange::Deregistered { tenant, hash }));
+        }
+        JournalOp::SimilarityRegistered {
+            keys,
+            segment,
+            tenant,
+        } => {
+            state
+                .similarity_changes
+                .extend(keys.into_iter().map(|hash| {
+                    let change = ContentChange::Registered {
+                        tenant: tenant.clone(),
+                        hash,
+                        segment,
+                    };
+                    (record.seq, change)
+                }));
+        }
+        JournalOp::SimilarityDeregistered { keys, tenant } => {
+            state
+                .similarity_changes
+                .extend(keys.into_iter().map(|hash| {
+                    let change = ContentChange::Deregistered {
+                        tenant: tenant.clone(),
+                        hash,
+                    };
+                    (record.seq, change)
+                }));
+        }
+        JournalOp::WriteIntended { intent } => {
+            state.pending_writes.insert(intent.capsule_id(), intent);
+        }
+        JournalOp::WriteExtended { id, segments } => {
+            if let Some(intent) = state.pending_writes.get_mut(&id) {
+                intent.new_segments.extend(segments);
+            }
+        }
+        JournalOp::WriteCommitted { id, at } => {
+            if let Some(intent) = state.pending_writes.remove(&id) {
+                let tenant = &intent.capsule.tenant;
+                state
+                    .content_changes
+                    .extend(intent.registrations.into_iter().map(|(hash, segment)| {
+                        let change = ContentChange::Registered {
+                            tenant: tenant.clone(),
+                            hash,
+                            segment,
+                        };
+                        (record.seq, change)
+                    }));
+                let previous = state.capsules.insert(id, intent.capsule);
+                if let Some(previous) = previous.filter(|_| intent.retain_previous) {
+                    retain_version(&mut state.history, previous, at);
+                }
+            }
+        }
+        JournalOp::WriteAborted { id } => {
+            state.pending_writes.remove(&id);
+        }
+        JournalOp::VersionsPruned { id, versions } => {
+            if let Some(entries) = state.history.get_mut(&id) {
+                entries.retain(|entry| !versions.contains(&entry.capsule.version));
+                if entries.is_empty() {
+                    state.history.remove(&id);
+                }
+            }
+        }
+        JournalOp::QuotaSet { tenant, quota } => {
+            if quota.is_unlimited() {
+                state.quotas.remove(&tenant);
+            } else {
+                state.quotas.insert(tenant, quota);
+            }
+        }
+        JournalOp::DictionarySet { tenant, capsule } => {
+            state.dictionaries.retain(|(scope, _)| *scope != tenant);
+            state.dictionaries.push((tenant, capsule));
+        }
+    }
+}
+
+impl Journal {
+    /// Replay the metadata file and return the recovered state with a journal
+    /// positioned for appends.
+    pub(crate) fn open(path: &str, checkpoint_interval: u64) -> Result<(Self, RegistryState)> {
+        let mut state = RegistryState::default();
+
+        if !Path::new(path).exists() {
+            let journal = Self::checkpoint_file(path, &state, checkpoint_interval)?;
+            return Ok((journal, state));
+        }
+
+        let raw = fs::read(path)?;
+        let text = String::from_utf8_lossy(&raw);
+
+        // Pre-journal registries stored a single pretty-printed RegistryState.
+        if text.trim_start().starts_with('{') {
+            state = serde_json::from_str(&text)?;
+            let journal = Self::checkpoint_file(path, &state, checkpoint_interval)?;
+            return Ok((journal, state));
+        }
+
+        let mut valid_len = 0usize;
+        let mut since_checkpoint = 0u64;
+        let mut saw_checkpoint = false;
+        for (index, line) in text.split_inclusive('\n').enumerate() {
+            let Some(body) = line.strip_suffix('\n') else {
+                break;
+            };
+            let Some(record) = decode(body) else {
+                break;
+            };
+            if index == 0 && !matches!(record.op, JournalOp::Checkpoint { .. }) {
+                bail!("registry journal {} does not start with a checkpoint", path);
+            }
+            if matches!(record.op, JournalOp::Checkpoint { .. }) {
+                saw_checkpoint = true;
+                since_checkpoint = 0;
+            } else {
+                since_checkpoint += 1;
+            }
+            apply(&mut state, record);
+            valid_len += line.len();
+        }
+
+        if !saw_checkpoint {
+            // A crash while creating the very first checkpoint leaves nothing usable.
+            let journal = Self::checkpoint_file(path, &state, checkpoint_interval)?;
+            return Ok((journal, state));
+        }
+
+        if valid_len < raw.len() {
+            warn!(
+                path,
+                discarded_bytes = raw.len() - valid_len,
+                "truncating torn registry journal tail"
+            );
+        }
+
+        let file = OpenOptions::new().write(true).open(path)?;
+        file.set_len(valid_len as u64)?;
+        file.sync_all()?;
+        drop(file);
+
+        let file = OpenOptions::new().append(true).open(path)?;
+        Ok((
+            Self {
+                path: path.to_string(),
+                file,
+                seq: state.journal_seq,
+                since_checkpoint,
+                checkpoint_interval,
+            },
+            state,
+        ))
+    }
+
+    /// Append a mutation. Returns `true` once enough records have accumulated
+    /// that the caller should fold them into a checkpoint.
+    pub(crate) fn append(&mut self, op: JournalOp, next_segment_id: u64) -> Result<bool> {
+        let record = JournalRecord {
+            seq: self.seq + 1,
+            next_segment_id,
+            op,
+        };
+        self.file.write_all(encode(&record)?.as_bytes())?;
+        self.file.sync_data()?;
+        self.seq = record.seq;
+        self.since_checkpoint += 1;
+        Ok(self.since_checkpoint >= self.checkpoint_interval)
+    }
+
+    /// Replace the journal with a single checkpoint of `state`.
+    pub(crate) fn checkpoint(&mut self, state: &RegistryState) -> Result<()> {
+        let mut state = state.clone();
+        state.journal_seq = self.seq;
+        *self = Self::checkpoint_file(&self.path, &state, self.checkpoint_interval)?;
+        Ok(())
+    }
+
+    pub(crate) fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    fn checkpoint_file(
+        path: &str,
+        state: &RegistryState,
+        checkpoint_interval: u64,
+    ) -> Result<Self> {
+        let record = JournalRecord {
+            seq: state.journal_seq,
+            next_segment_id: state.next_segment_id,
+            op: JournalOp::Checkpoint {
+                state: state.clone(),
+            },
+        };
+
+        let tmp_path = format!("{}.tmp", path);
+        {
+            let mut tmp = File::create(&tmp_path)?;
+            tmp.write_all(encode(&record)?.as_bytes())?;
+            tmp.sync_all()?;
+        }
+        fs::rename(&tmp_path, path)?;
+
+        let file = OpenOptions::new().append(true).open(path)?;
+        Ok(Self {
+            path: path.to_string(),
+            file,
+            seq: state.journal_seq,
+            since_checkpoint: 0,
+            checkpoint_interval,
+        })
+    }
+}
@@ -1,22 +1,41 @@
 use anyhow::Result;
 #[cfg(feature = "advanced-security")]
-use common::security::bloom_dedup::BloomFilterWrapper;
-#[cfg(feature = "advanced-security")]
-use common::security::DedupOptimizer;
+use common::security::audit_log::AuditLog;
+use common::similarity::Sketch;
 use common::Policy;
 use common::*;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fs;
-use std::path::Path;
-use std::sync::{Arc, RwLock};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
 
+pub mod compaction;
+pub mod content_index;
 pub mod dedup; // NEW
+pub mod dictionary;
 pub mod error;
+pub mod fsck;
 pub mod gc;
+pub mod index;
+mod journal;
+pub mod lifecycle;
 pub mod pipeline;
+pub mod scrub;
+pub mod stream;
+pub mod tenant;
+
+pub use content_index::ContentIndexStats;
+pub use error::{CompressionError, DedupError, PipelineError, QuotaError, RetentionError};
+pub use index::CapsuleQuery;
+
+use content_index::{ContentChange, ContentStore};
+use index::CapsuleIndex;
+use journal::{Journal, JournalOp};
+use tenant::TenantQuota;
 
-pub use error::{CompressionError, DedupError, PipelineError};
+/// Subdirectory of the dedup index directory holding the similarity index.
+const SIMILARITY_INDEX_DIR: &str = "similar";
 
 #[cfg(feature = "modular_pipeline")]
 pub mod modular_pipeline {
@@ -27,11 +46,15 @@ pub mod modular_pipeline {
     use encryption::KeyManager;
     use nvram_sim::NvramLog;
     pub use pipeline::{
-        pipeline_with_nvram, pipeline_with_nvram_xts, DefaultPipeline, DefaultPolicyEvaluator,
-        InMemoryPipeline, KeyManagerKeyring, NoopEncryptor, NullKeyring, NvramPipeline,
-        NvramPipelineWithEncryption, Pipeline, PipelineBuilder, XtsEncryptor,
+        pipeline_with_erasure, pipeline_with_nvram, pipeline_with_nvram_xts, pipeline_with_tiers,
+        DefaultPipeline, DefaultPolicyEvaluator, ErasurePipeline, InMemoryPipeline,
+        KeyManagerKeyring, NoopEncryptor, NullKeyring, NvramPipeline, NvramPipelineWithEncryption,
+        Pipeline, PipelineBuilder, TierReport, TieredPipeline, XtsEncryptor,
+    };
+    pub use storage::{
+        ErasureBackend, ErasureRebuilder, FileBackend, InMemoryBackend, NvramBackend,
+        RebuildReport, TieredBackend,
     };
-    pub use storage::{InMemoryBackend, NvramBackend};
 
     pub fn nvram_pipeline_with_encryption<P: AsRef<std::path::Path>>(
         path: P,
@@ -41,7 +64,7 @@ pub mod modular_pipeline {
     }
 
     pub type RegistryEncryptedPipeline = Pipeline<
-        compression::Lz4ZstdCompressor,
+        compression::CodecCompressor,
         dedup::Blake3Deduper,
         XtsEncryptor,
         NvramBackend,
@@ -51,7 +74,7 @@ pub mod modular_pipeline {
     >;
 
     pub type RegistryPlainPipeline = Pipeline<
-        compression::Lz4ZstdCompressor,
+        compression::CodecCompressor,
         dedup::Blake3Deduper,
         NoopEncryptor,
         NvramBackend,
@@ -80,6 +103,13 @@ pub mod modular_pipeline {
             }
         }
 
+        pub async fn read_range(&self, id: CapsuleId, offset: u64, len: usize) -> Result<Vec<u8>> {
+            match self {
+                Self::Encrypted(p) => p.read_range(id, offset, len).await,
+                Self::Plain(p) => p.read_range(id, offset, len).await,
+            }
+        }
+
         pub async fn delete_capsule(&mut self, id: CapsuleId) -> Result<()> {
             match self {
                 Self::Encrypted(p) => p.delete_capsule(id).await,
@@ -93,6 +123,13 @@ pub mod modular_pipeline {
                 Self::Plain(p) => p.garbage_collect().await,
             }
         }
+
+        pub fn registry(&self) -> &crate::CapsuleRegistry {
+            match self {
+                Self::Encrypted(p) => p.catalog(),
+                Self::Plain(p) => p.catalog(),
+            }
+        }
     }
 
     pub fn registry_pipeline_from_env<P: AsRef<std::path::Path>>(
@@ -130,7 +167,7 @@ pub mod modular_pipeline {
             Ok(RegistryPipelineHandle::Encrypted(pipeline))
         } else {
             Ok(RegistryPipelineHandle::Plain(Pipeline::new(
-                compression::Lz4ZstdCompressor,
+                compression::CodecCompressor,
                 dedup::Blake3Deduper::default(),
                 NoopEncryptor,
                 storage,
@@ -147,7 +184,7 @@ pub mod modular_pipeline {
         key_manager: Arc<Mutex<KeyManager>>,
     ) -> Result<RegistryEncryptedPipeline> {
         Ok(Pipeline::new(
-            compression::Lz4ZstdCompressor,
+            compression::CodecCompressor,
             dedup::Blake3Deduper::default(),
             XtsEncryptor::new(Arc::clone(&key_manager)),
             storage,
@@ -174,22 +211,34 @@ impl common::traits::CapsuleCatalog for CapsuleRegistry {
         policy: &Policy,
         segments: Vec<SegmentId>,
         stats: &common::traits::DedupStats,
+        data_keys: Vec<WrappedDataKey>,
     ) -> Result<()> {
-        CapsuleRegistry::create_capsule_with_segments(self, id, size, segments, policy.clone())?;
-        let mut capsules = self.capsules.write().unwrap();
-        if let Some(capsule) = capsules.get_mut(&id) {
-            capsule.policy = policy.clone();
-            capsule.deduped_bytes = stats.bytes_saved;
-        }
-        drop(capsules);
-        CapsuleRegistry::save(self)?;
-        Ok(())
+        let mut capsule = new_capsule_record(id, size, segments, policy.clone())?;
+        capsule.deduped_bytes = stats.bytes_saved;
+        capsule.data_keys = data_keys;
+        self.insert_capsule(capsule)
     }
 
     fn delete_capsule(&self, id: CapsuleId) -> Result<Capsule> {
         CapsuleRegistry::delete_capsule(self, id)
     }
 
+    fn set_data_keys(&self, id: CapsuleId, keys: Vec<WrappedDataKey>) -> Result<()> {
+        self.update_capsule(id, |capsule| capsule.data_keys = keys)
+    }
+
+    fn replace_segments(
+        &self,
+        id: CapsuleId,
+        segments: Vec<SegmentId>,
+        data_keys: Vec<WrappedDataKey>,
+    ) -> Result<()> {
+        self.update_capsule(id, |capsule| {
+            capsule.segments = segments;
+            capsule.data_keys = data_keys;
+        })
+    }
+
     fn lookup_content(&self, hash: &ContentHash) -> Option<SegmentId> {
         CapsuleRegistry::lookup_content(self, hash)
     }
@@ -202,37 +251,262 @@ impl common::traits::CapsuleCatalog for CapsuleRegistry {
         CapsuleRegistry::deregister_content(self, hash, segment)
     }
 
+    fn lookup_similar(&self, sketch: &Sketch) -> Option<SegmentId> {
+        CapsuleRegistry::lookup_similar_in(self, None, sketch)
+    }
+
+    fn register_similar(&self, sketch: &Sketch, segment: SegmentId) -> Result<()> {
+        CapsuleRegistry::register_similar_in(self, None, sketch, segment)
+    }
+
+    fn deregister_similar(&self, sketch: &Sketch, segment: SegmentId) -> Result<bool> {
+        CapsuleRegistry::deregister_similar(self, sketch, segment)
+    }
+
     fn capsules(&self) -> Vec<Capsule> {
         self.capsules.read().unwrap().values().cloned().collect()
     }
 
     fn content_entries(&self) -> Vec<(ContentHash, SegmentId)> {
         self.content_store
-            .read()
+            .lock()
             .unwrap()
-            .iter()
-            .map(|(hash, seg)| (hash.clone(), *seg))
-            .collect()
+            .entries()
+            .unwrap_or_else(|err| {
+                tracing::warn!(error = %err, "failed to scan the dedup index");
+                Vec::new()
+            })
     }
 }
 
+/// A capsule write recorded in the journal before its segments are committed.
+///
+/// The capsule and its content registrations only become visible once the
+/// intent is committed; on restart an intent is rolled forward if all of its new
+/// segments reached NVRAM and rolled back otherwise.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WriteIntent {
+    pub capsule: Capsule,
+    /// Segments appended by this write, as opposed to dedup hits on existing ones.
+    pub new_segments: Vec<SegmentId>,
+    /// Content hashes to publish in the dedup store on commit.
+    pub registrations: Vec<(ContentHash, SegmentId)>,
+    /// Set while a streaming write is still appending; such intents are always
+    /// rolled back on restart.
+    #[serde(default)]
+    pub partial: bool,
+    /// Version of the existing capsule this write overwrites; `None` for a new capsule.
+    #[serde(default)]
+    pub base_version: Option<u64>,
+    /// Keep the overwritten version in the capsule's history on commit.
+    #[serde(default)]
+    pub retain_previous: bool,
+}
+
+/// A superseded state of a capsule, kept while its versioning policy allows.
+///
+/// The recorded capsule holds one reference on each of its segments, exactly
+/// like a live capsule, until the version is pruned.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-struct RegistryState {
+pub struct CapsuleVersion {
+    pub capsule: Capsule,
+    /// Unix time at which a newer version replaced this one.
+    pub superseded_at: u64,
+}
+
+impl WriteIntent {
+    pub fn new(id: CapsuleId, size: u64, segments: Vec<SegmentId>, policy: Policy) -> Result<Self> {
+        Ok(Self {
+            capsule: new_capsule_record(id, size, segments, policy)?,
+            new_segments: Vec::new(),
+            registrations: Vec::new(),
+            partial: false,
+            base_version: None,
+            retain_previous: false,
+        })
+    }
+
+    /// Intent for a new capsule that shares every segment of `source`, along
+    /// with its metadata and tags.
+    pub fn derived(source: &Capsule, read_only: bool) -> Result<Self> {
+        let mut intent = Self::new(
+            CapsuleId::new(),
+            source.size,
+            source.segments.clone(),
+            source.policy.clone(),
+        )?;
+        intent.capsule.deduped_bytes = source.deduped_bytes;
+        intent.capsule.metadata = source.metadata.clone();
+        intent.capsule.tags = source.tags.clone();
+        intent.capsule.read_only = read_only;
+        intent.capsule.origin = Some(source.id);
+        intent.capsule.tenant = source.tenant.clone();
+        // The shared segments stay sealed under the source's data keys.
+        intent.capsule.data_keys = source.data_keys.clone();
+        Ok(intent)
+    }
+
+    pub fn capsule_id(&self) -> CapsuleId {
+        self.capsule.id
+    }
+}
+
+fn unix_now() -> Result<u64> {
+    Ok(std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs())
+}
+
+pub(crate) fn retain_version(
+    history: &mut HashMap<CapsuleId, Vec<CapsuleVersion>>,
+    previous: Capsule,
+    superseded_at: u64,
+) {
+    history
+        .entry(previous.id)
+        .or_default()
+        .push(CapsuleVersion {
+            capsule: previous,
+            superseded_at,
+        });
+}
+
+/// Versions in `history` (oldest first) that `rules` no longer allows keeping.
+fn expired_versions(
+    history: &[CapsuleVersion],
+    rules: Option<&VersioningPolicy>,
+    now: u64,
+) -> Vec<u64> {
+    let Some(rules) = rules else {
+        return history
+            .iter()
+            .filter(|entry| !entry.capsule.retained_at(now))
+            .map(|entry| entry.capsule.version)
+            .collect();
+    };
+    let over_count = rules
+        .keep_versions
+        .map(|keep| history.len().saturating_sub(keep as usize))
+        .unwrap_or(0);
+    history
+        .iter()
+        .enumerate()
+        .filter(|(index, entry)| {
+            *index < over_count
+                || rules
+                    .keep_for_secs
+                    .is_some_and(|secs| entry.superseded_at.saturating_add(secs) < now)
+        })
+        .filter(|(_, entry)| !entry.capsule.retained_at(now))
+        .map(|(_, entry)| entry.capsule.version)
+        .collect()
+}
+
+fn new_capsule_record(
+    id: CapsuleId,
+    size: u64,
+    segments: Vec<SegmentId>,
+    policy: Policy,
+) -> Result<Capsule> {
+    let created_at = unix_now()?;
+    Ok(Capsule {
+        id,
+        size,
+        segments,
+        created_at,
+        retain_until: policy.retention.map(|rules| rules.deadline(created_at)),
+        policy,
+        deduped_bytes: 0, // Will be updated during write
+        version: 1,
+        read_only: false,
+        origin: None,
+        metadata: Default::default(),
+        tags: Default::default(),
+        legal_hold: false,
+        lifecycle_step: 0,
+        tenant: None,
+        data_keys: Vec::new(),
+    })
+}
+
+/// Why `capsule` may not be deleted or overwritten at `now`, if anything stops it.
+fn retention_block(capsule: &Capsule, now: u64) -> Option<String> {
+    if capsule.legal_hold {
+        Some("legal hold".to_string())
+    } else if capsule.retained_at(now) {
+        Some(format!(
+            "retained until {}",
+            capsule.retain_until.unwrap_or_default()
+        ))
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct RegistryState {
     capsules: HashMap<CapsuleId, Capsule>,
     next_segment_id: u64,
-    // Phase 2.2: Content-addressed storage for deduplication
-    #[serde(default)]
+    // Phase 2.2: Content-addressed storage for deduplication. Only registries
+    // from before the on-disk index kept it here; it is imported on open.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
     content_store: HashMap<ContentHash, SegmentId>,
+    // Dedup partitions of tenant-owned content, kept apart from the global one.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    tenant_content: HashMap<TenantId, HashMap<ContentHash, SegmentId>>,
+    // Set once the content store lives in the on-disk index.
+    #[serde(default)]
+    content_indexed: bool,
+    // Content mutations replayed from the journal, for the index to catch up on.
+    #[serde(skip)]
+    content_changes: Vec<(u64, ContentChange)>,
+    // Set once delta-base sketches live in their own on-disk index.
+    #[serde(default)]
+    similarity_indexed: bool,
+    // Similarity index mutations replayed from the journal.
+    #[serde(skip)]
+    similarity_changes: Vec<(u64, ContentChange)>,
+    // Sequence number of the checkpoint the replayed journal started from.
+    #[serde(skip)]
+    checkpoint_seq: u64,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    quotas: HashMap<TenantId, TenantQuota>,
+    // Capsule holding the Zstd dictionary of each tenant; `None` is the shared namespace.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    dictionaries: Vec<(Option<TenantId>, CapsuleId)>,
+    // Sequence number of the last journal record folded into this state.
+    #[serde(default)]
+    journal_seq: u64,
+    // Writes whose intent was journaled but not yet committed or aborted.
+    #[serde(default)]
+    pending_writes: HashMap<CapsuleId, WriteIntent>,
+    // Superseded versions per capsule, oldest first.
+    #[serde(default)]
+    history: HashMap<CapsuleId, Vec<CapsuleVersion>>,
 }
 
 pub struct CapsuleRegistry {
     capsules: Arc<RwLock<HashMap<CapsuleId, Capsule>>>,
     next_segment_id: Arc<RwLock<u64>>,
     metadata_path: String,
-    // Phase 2.2: Content store for deduplication
-    content_store: Arc<RwLock<HashMap<ContentHash, SegmentId>>>,
+    // Phase 2.2: Content store for deduplication, paged in from disk.
+    content_store: Arc<Mutex<ContentStore>>,
+    // Super-features of segments that later writes may store deltas against,
+    // mapped to those segments.
+    similarity_store: Arc<Mutex<ContentStore>>,
+    quotas: Arc<RwLock<HashMap<TenantId, TenantQuota>>>,
+    dictionaries: Arc<RwLock<HashMap<Option<TenantId>, CapsuleId>>>,
+    pending_writes: Arc<RwLock<HashMap<CapsuleId, WriteIntent>>>,
+    history: Arc<RwLock<HashMap<CapsuleId, Vec<CapsuleVersion>>>>,
+    // Derived from `capsules` and updated under its write lock.
+    index: Arc<RwLock<CapsuleIndex>>,
+    // Mutations are journaled under this lock so replay order matches memory.
+    journal: Arc<Mutex<Journal>>,
+    // Set once writes left over from before this open have been resolved.
+    recovered: Arc<OnceLock<()>>,
+    event_sink: Option<Sender<Event>>,
     #[cfg(feature = "advanced-security")]
-    bloom_filter: Option<Arc<BloomFilterWrapper>>,
+    audit_log: Option<AuditLog>,
 }
 
 impl CapsuleRegistry {
@@ -243,40 +517,201 @@ impl CapsuleRegistry {
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
         let metadata_path = path.as_ref().to_string_lossy().to_string();
 
-        // Try to load existing state
-        let (capsules, next_segment_id, content_store) = if Path::new(&metadata_path).exists() {
-            let data = fs::read_to_string(&metadata_path)?;
-            let state: RegistryState = serde_json::from_str(&data)?;
-            (state.capsules, state.next_segment_id, state.content_store)
-        } else {
-            (HashMap::new(), 0, HashMap::new())
-        };
+        let checkpoint_interval = std::env::var("SPACE_REGISTRY_CHECKPOINT_INTERVAL")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(journal::DEFAULT_CHECKPOINT_INTERVAL);
 
-        #[cfg(feature = "advanced-security")]
-        let bloom_filter = Self::configure_bloom(Some(&content_store));
+        // Replay the checkpoint and any journaled mutations after it.
+        let (journal, state) = Journal::open(&metadata_path, checkpoint_interval)?;
+        let RegistryState {
+            capsules,
+            next_segment_id,
+            content_store: legacy_content,
+            tenant_content,
+            content_indexed,
+            content_changes,
+            similarity_indexed,
+            similarity_changes,
+            checkpoint_seq,
+            quotas,
+            dictionaries,
+            pending_writes,
+            history,
+            ..
+        } = state;
 
-        Ok(Self {
+        let mut content_store = ContentStore::open(&Self::content_index_dir(&metadata_path))?;
+        if content_indexed {
+            content_store.replay(checkpoint_seq, content_changes)?;
+        } else {
+            // The checkpoint predates the on-disk index: rebuild the index from it.
+            content_store.reset()?;
+            content_store.import(legacy_content, tenant_content)?;
+            content_store.replay(0, content_changes)?;
+        }
+        let mut similarity_store = ContentStore::open(
+            &Self::content_index_dir(&metadata_path).join(SIMILARITY_INDEX_DIR),
+        )?;
+        if similarity_indexed {
+            similarity_store.replay(checkpoint_seq, similarity_changes)?;
+        } else {
+            similarity_store.reset()?;
+            similarity_store.replay(0, similarity_changes)?;
+        }
+        let index = CapsuleIndex::build(capsules.values());
+
+        let registry = Self {
             capsules: Arc::new(RwLock::new(capsules)),
             next_segment_id: Arc::new(RwLock::new(next_segment_id)),
             metadata_path,
-            content_store: Arc::new(RwLock::new(content_store)),
+            content_store: Arc::new(Mutex::new(content_store)),
+            similarity_store: Arc::new(Mutex::new(similarity_store)),
+            quotas: Arc::new(RwLock::new(quotas)),
+            dictionaries: Arc::new(RwLock::new(dictionaries.into_iter().collect())),
+            pending_writes: Arc::new(RwLock::new(pending_writes)),
+            history: Arc::new(RwLock::new(history)),
+            index: Arc::new(RwLock::new(index)),
+            journal: Arc::new(Mutex::new(journal)),
+            recovered: Arc::new(OnceLock::new()),
+            event_sink: None,
             #[cfg(feature = "advanced-security")]
-            bloom_filter,
-        })
+            audit_log: None,
+        };
+        if !content_indexed || !similarity_indexed {
+            registry.save()?;
+        }
+        Ok(registry)
     }
 
+    /// Directory beside the metadata file that holds the dedup index, and the
+    /// similarity index in its `similar` subdirectory.
+    pub fn content_index_dir(metadata_path: &str) -> PathBuf {
+        PathBuf::from(format!("{}.dedup", metadata_path))
+    }
+
+    /// Deliver `Event::RetentionViolated` for every refused delete or overwrite.
+    pub fn with_event_sink(mut self, sink: Sender<Event>) -> Self {
+        self.event_sink = Some(sink);
+        self
+    }
+
+    #[cfg(feature = "advanced-security")]
+    pub fn with_audit_log(mut self, audit_log: AuditLog) -> Self {
+        self.audit_log = Some(audit_log);
+        self
+    }
+
+    fn emit(&self, event: Event) {
+        #[cfg(feature = "advanced-security")]
+        if let Some(log) = &self.audit_log {
+            if let Err(err) = log.append(event.clone()) {
+                tracing::warn!(error = %err, "failed to append audit log entry");
+            }
+        }
+        if let Some(sink) = &self.event_sink {
+            let _ = sink.send(event);
+        }
+    }
+
+    /// Refuse `action` on `capsule` while retention or a legal hold protects it.
+    /// Every refusal is reported as an `Event::RetentionViolated`.
+    fn enforce_retention(&self, capsule: &Capsule, action: &'static str, now: u64) -> Result<()> {
+        match retention_block(capsule, now) {
+            Some(reason) => Err(self.retention_violation(capsule.id, action, reason).into()),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn retention_violation(
+        &self,
+        capsule: CapsuleId,
+        action: &'static str,
+        reason: String,
+    ) -> RetentionError {
+        self.emit(Event::RetentionViolated {
+            capsule_id: capsule,
+            action: action.to_string(),
+            reason: reason.clone(),
+        });
+        RetentionError {
+            capsule,
+            action,
+            reason,
+        }
+    }
+
+    /// Fold the journal into a fresh checkpoint of the full registry state.
     pub fn save(&self) -> Result<()> {
+        let mut journal = self.journal.lock().unwrap();
+        self.checkpoint(&mut journal)
+    }
+
+    /// Path of the metadata file holding the checkpoint and journal.
+    pub fn metadata_path(&self) -> &str {
+        &self.metadata_path
+    }
+
+    fn checkpoint(&self, journal: &mut Journal) -> Result<()> {
+        // The indexes must be durable before the journal records they replace are dropped.
+        for store in [&self.content_store, &self.similarity_store] {
+            store.lock().unwrap().checkpoint(journal.seq())?;
+        }
         let state = RegistryState {
             capsules: self.capsules.read().unwrap().clone(),
             next_segment_id: *self.next_segment_id.read().unwrap(),
-            content_store: self.content_store.read().unwrap().clone(),
+            content_indexed: true,
+            similarity_indexed: true,
+            quotas: self.quotas.read().unwrap().clone(),
+            dictionaries: self
+                .dictionaries
+                .read()
+                .unwrap()
+                .iter()
+                .map(|(tenant, capsule)| (tenant.clone(), *capsule))
+                .collect(),
+            journal_seq: journal.seq(),
+            pending_writes: self.pending_writes.read().unwrap().clone(),
+            history: self.history.read().unwrap().clone(),
+            ..RegistryState::default()
         };
+        journal.checkpoint(&state)
+    }
 
-        let json = serde_json::to_string_pretty(&state)?;
-        fs::write(&self.metadata_path, json)?;
+    /// Append a mutation that has already been applied in memory.
+    fn record(&self, journal: &mut Journal, op: JournalOp) -> Result<()> {
+        let next_segment_id = *self.next_segment_id.read().unwrap();
+        if journal.append(op, next_segment_id)? {
+            return self.checkpoint(journal);
+        }
+        // Dirty index pages stay in memory until written; write them once
+        // they crowd the cache, now that everything they hold is journaled.
+        for store in [&self.content_store, &self.similarity_store] {
+            let mut store = store.lock().unwrap();
+            if store.needs_flush() {
+                store.flush(journal.seq())?;
+            }
+        }
         Ok(())
     }
 
+    fn update_capsule<F>(&self, id: CapsuleId, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut Capsule),
+    {
+        let mut journal = self.journal.lock().unwrap();
+        let mut capsules = self.capsules.write().unwrap();
+        let capsule = capsules
+            .get_mut(&id)
+            .ok_or_else(|| anyhow::anyhow!("Capsule not found"))?;
+        f(capsule);
+        let capsule = capsule.clone();
+        self.index.write().unwrap().upsert(&capsule);
+        drop(capsules);
+        self.record(&mut journal, JournalOp::CapsuleUpserted { capsule })
+    }
+
     pub fn create_capsule_with_segments(
         &self,
         id: CapsuleId,
@@ -284,27 +719,169 @@ impl CapsuleRegistry {
         segments: Vec<SegmentId>,
         policy: Policy,
     ) -> Result<()> {
+        self.insert_capsule(new_capsule_record(id, size, segments, policy)?)
+    }
+
+    /// Publish a fully formed new capsule record in a single journal entry.
+    fn insert_capsule(&self, capsule: Capsule) -> Result<()> {
+        let mut journal = self.journal.lock().unwrap();
         let mut capsules = self.capsules.write().unwrap();
 
-        if capsules.contains_key(&id) {
+        if capsules.contains_key(&capsule.id) {
             anyhow::bail!("Capsule collision (extremely unlikely)");
         }
 
-        let capsule = Capsule {
-            id,
-            size,
-            segments,
-            created_at: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)?
-                .as_secs(),
-            policy,
-            deduped_bytes: 0, // Will be updated during write
+        capsules.insert(capsule.id, capsule.clone());
+        self.index.write().unwrap().upsert(&capsule);
+        drop(capsules);
+        self.record(&mut journal, JournalOp::CapsuleUpserted { capsule })
+    }
+
+    /// Journal a write intent ahead of committing its NVRAM transaction, replacing
+    /// any earlier intent for the same capsule.
+    ///
+    /// Overwrites (`base_version` set) must name the capsule's current version,
+    /// may not target a read-only snapshot, and may not overlap another pending
+    /// write to it.
+    pub fn begin_write(&self, intent: WriteIntent) -> Result<()> {
+        self.journal_intent(intent, false)
+    }
+
+    /// Journal the final intent of a write whose partial intent, journaled by
+    /// [`begin_write`](Self::begin_write) while it streamed, is still pending.
+    ///
+    /// An overwrite may replace only its own partial intent, not another
+    /// writer's pending one.
+    pub fn seal_write(&self, intent: WriteIntent) -> Result<()> {
+        self.journal_intent(intent, true)
+    }
+
+    fn journal_intent(&self, mut intent: WriteIntent, sealing: bool) -> Result<()> {
+        let mut journal = self.journal.lock().unwrap();
+        let id = intent.capsule_id();
+        let now = unix_now()?;
+        match (intent.base_version, self.capsules.read().unwrap().get(&id)) {
+            (None, Some(_)) => anyhow::bail!("Capsule collision (extremely unlikely)"),
+            (Some(_), None) => anyhow::bail!("Capsule not found"),
+            (Some(_), Some(current)) if current.read_only => {
+                anyhow::bail!("Capsule {:?} is a read-only snapshot", id)
+            }
+            (Some(_), Some(current)) if retention_block(current, now).is_some() => {
+                self.enforce_retention(current, "overwrite", now)?
+            }
+            (Some(base), Some(current)) if current.version != base => anyhow::bail!(
+                "Capsule {:?} changed concurrently (version {} != {})",
+                id,
+                current.version,
+                base
+            ),
+            _ => {}
+        }
+        if intent.base_version.is_some() {
+            match self.pending_writes.read().unwrap().get(&id) {
+                Some(pending) if sealing && pending.partial => {}
+                Some(_) => anyhow::bail!("Capsule {:?} already has a write in progress", id),
+                None => {}
+            }
+        }
+        // Partial intents don't know their final size yet; the sealing intent is checked.
+        if !intent.partial {
+            self.enforce_quota(&intent.capsule)?;
+        }
+        if intent.base_version.is_some() {
+            // Each new version starts its own retention period.
+            intent.capsule.retain_until = intent
+                .capsule
+                .policy
+                .retention
+                .map(|rules| rules.deadline(now));
+        }
+        self.pending_writes
+            .write()
+            .unwrap()
+            .insert(id, intent.clone());
+        self.record(&mut journal, JournalOp::WriteIntended { intent })
+    }
+
+    /// Record more segments flushed to NVRAM by a pending streaming write.
+    pub fn extend_write(&self, id: CapsuleId, segments: &[SegmentId]) -> Result<()> {
+        let mut journal = self.journal.lock().unwrap();
+        self.pending_writes
+            .write()
+            .unwrap()
+            .get_mut(&id)
+            .ok_or_else(|| anyhow::anyhow!("No pending write for capsule {:?}", id))?
+            .new_segments
+            .extend_from_slice(segments);
+        self.record(
+            &mut journal,
+            JournalOp::WriteExtended {
+                id,
+                segments: segments.to_vec(),
+            },
+        )
+    }
+
+    /// Publish the capsule and content registrations of a pending write in one record.
+    pub fn commit_write(&self, id: CapsuleId) -> Result<Capsule> {
+        let mut journal = self.journal.lock().unwrap();
+        let intent = self
+            .pending_writes
+            .write()
+            .unwrap()
+            .remove(&id)
+            .ok_or_else(|| anyhow::anyhow!("No pending write for capsule {:?}", id))?;
+
+        {
+            let mut store = self.content_store.lock().unwrap();
+            for (hash, seg_id) in &intent.registrations {
+                store.insert(intent.capsule.tenant.clone(), hash.clone(), *seg_id)?;
+            }
+        }
+        let at = unix_now()?;
+        let previous = {
+            let mut capsules = self.capsules.write().unwrap();
+            self.index.write().unwrap().upsert(&intent.capsule);
+            capsules.insert(id, intent.capsule.clone())
         };
+        if let Some(previous) = previous.filter(|_| intent.retain_previous) {
+            retain_version(&mut self.history.write().unwrap(), previous, at);
+        }
 
-        capsules.insert(id, capsule);
-        drop(capsules);
-        self.save()?;
-        Ok(())
+        self.record(&mut journal, JournalOp::WriteCommitted { id, at })?;
+        drop(journal);
+        if let Some(tenant) = &intent.capsule.tenant {
+            self.report_soft_limit(tenant);
+        }
+        Ok(intent.capsule)
+    }
+
+    /// Drop a pending write without publishing anything.
+    pub fn abort_write(&self, id: CapsuleId) -> Result<Option<WriteIntent>> {
+        let mut journal = self.journal.lock().unwrap();
+        let intent = self.pending_writes.write().unwrap().remove(&id);
+        if intent.is_some() {
+            self.record(&mut journal, JournalOp::WriteAborted { id })?;
+        }
+        Ok(intent)
+    }
+
+    /// Run `recover` the first time it is called after this registry was
+    /// opened. Later calls, including through clones, wait for it to finish and
+    /// then return without running theirs, so views opened alongside a live
+    /// writer never mistake its in-flight writes for interrupted ones.
+    pub fn recover_once(&self, recover: impl FnOnce()) {
+        self.recovered.get_or_init(recover);
+    }
+
+    /// Writes that were journaled but neither committed nor aborted.
+    pub fn pending_writes(&self) -> Vec<WriteIntent> {
+        self.pending_writes
+            .read()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect()
     }
 
     pub fn lookup(&self, id: CapsuleId) -> Result<Capsule> {
@@ -331,117 +908,615 @@ impl CapsuleRegistry {
     }
 
     pub fn add_segment(&self, capsule_id: CapsuleId, seg_id: SegmentId) -> Result<()> {
-        let mut capsules = self.capsules.write().unwrap();
-        let capsule = capsules
-            .get_mut(&capsule_id)
-            .ok_or_else(|| anyhow::anyhow!("Capsule not found"))?;
-        capsule.segments.push(seg_id);
-        drop(capsules);
-        self.save()?;
-        Ok(())
+        self.update_capsule(capsule_id, |capsule| capsule.segments.push(seg_id))
     }
 
     // NEW: Phase 2.2 - Deduplication methods
 
     /// Check if content hash already exists in store
     pub fn lookup_content(&self, hash: &ContentHash) -> Option<SegmentId> {
-        #[cfg(feature = "advanced-security")]
-        if let Some(filter) = &self.bloom_filter {
-            if !filter.might_contain(hash) {
-                return None;
-            }
-        }
-        self.content_store.read().unwrap().get(hash).copied()
+        self.lookup_content_in(None, hash)
+    }
+
+    /// Look `hash` up in the dedup partition of `tenant` only.
+    pub fn lookup_content_in(
+        &self,
+        tenant: Option<&TenantId>,
+        hash: &ContentHash,
+    ) -> Option<SegmentId> {
+        self.content_store
+            .lock()
+            .unwrap()
+            .get(tenant, hash)
+            .unwrap_or_else(|err| {
+                // A failed lookup only costs a dedup opportunity.
+                tracing::warn!(error = %err, "dedup index lookup failed");
+                None
+            })
     }
 
     /// Register new content hash → segment mapping
     pub fn register_content(&self, hash: ContentHash, seg_id: SegmentId) -> Result<()> {
+        self.register_content_in(None, hash, seg_id)
+    }
+
+    /// Register `hash` in the dedup partition of `tenant`.
+    pub fn register_content_in(
+        &self,
+        tenant: Option<TenantId>,
+        hash: ContentHash,
+        seg_id: SegmentId,
+    ) -> Result<()> {
+        let mut journal = self.journal.lock().unwrap();
         self.content_store
-            .write()
+            .lock()
             .unwrap()
-            .insert(hash.clone(), seg_id);
-        #[cfg(feature = "advanced-security")]
-        if let Some(filter) = &self.bloom_filter {
-            filter.record_insertion(&hash);
-        }
-        self.save()?;
-        Ok(())
+            .insert(tenant.clone(), hash.clone(), seg_id)?;
+        self.record(
+            &mut journal,
+            JournalOp::ContentRegistered {
+                hash,
+                segment: seg_id,
+                tenant,
+            },
+        )
     }
 
+    /// Drop the mapping of `hash` to `seg_id`, in whichever partition holds it.
     pub fn deregister_content(&self, hash: &ContentHash, seg_id: SegmentId) -> Result<bool> {
-        let mut store = self.content_store.write().unwrap();
-        if let Some(current) = store.get(hash) {
-            if *current == seg_id {
-                store.remove(hash);
-                #[cfg(feature = "advanced-security")]
-                if let Some(filter) = &self.bloom_filter {
-                    filter.record_removal(hash);
+        let mut journal = self.journal.lock().unwrap();
+        let mut store = self.content_store.lock().unwrap();
+        let Some(tenant) = store.remove_segment(hash, seg_id)? else {
+            return Ok(false);
+        };
+        drop(store);
+        self.record(
+            &mut journal,
+            JournalOp::ContentDeregistered {
+                hash: hash.clone(),
+                tenant,
+            },
+        )?;
+        Ok(true)
+    }
+
+    /// A segment in the similarity partition of `tenant` whose sketch shares a
+    /// super-feature with `sketch`.
+    pub fn lookup_similar_in(
+        &self,
+        tenant: Option<&TenantId>,
+        sketch: &Sketch,
+    ) -> Option<SegmentId> {
+        let mut store = self.similarity_store.lock().unwrap();
+        for key in sketch.keys() {
+            match store.get(tenant, &key) {
+                Ok(Some(seg_id)) => return Some(seg_id),
+                Ok(None) => {}
+                Err(err) => {
+                    // Like a failed dedup lookup, this only costs a smaller write.
+                    tracing::warn!(error = %err, "similarity index lookup failed");
+                    return None;
                 }
-                drop(store);
-                self.save()?;
-                return Ok(true);
             }
         }
-        Ok(false)
+        None
+    }
+
+    /// Offer `seg_id` to later writes in `tenant` as a base to store deltas against.
+    pub fn register_similar_in(
+        &self,
+        tenant: Option<TenantId>,
+        sketch: &Sketch,
+        seg_id: SegmentId,
+    ) -> Result<()> {
+        let mut journal = self.journal.lock().unwrap();
+        let keys: Vec<ContentHash> = sketch.keys().collect();
+        {
+            let mut store = self.similarity_store.lock().unwrap();
+            for key in &keys {
+                store.insert(tenant.clone(), key.clone(), seg_id)?;
+            }
+        }
+        self.record(
+            &mut journal,
+            JournalOp::SimilarityRegistered {
+                keys,
+                segment: seg_id,
+                tenant,
+            },
+        )
+    }
+
+    /// Withdraw `seg_id` as a delta base. Super-features a newer segment has
+    /// since taken over stay with that segment.
+    pub fn deregister_similar(&self, sketch: &Sketch, seg_id: SegmentId) -> Result<bool> {
+        let mut journal = self.journal.lock().unwrap();
+        let mut store = self.similarity_store.lock().unwrap();
+        let mut removed = Vec::new();
+        let mut partition = None;
+        for key in sketch.keys() {
+            if let Some(tenant) = store.remove_segment(&key, seg_id)? {
+                partition = Some(tenant);
+                removed.push(key);
+            }
+        }
+        drop(store);
+        let Some(tenant) = partition else {
+            return Ok(false);
+        };
+        self.record(
+            &mut journal,
+            JournalOp::SimilarityDeregistered {
+                keys: removed,
+                tenant,
+            },
+        )?;
+        Ok(true)
     }
 
     /// Increment dedup bytes counter for a capsule
     pub fn add_deduped_bytes(&self, capsule_id: CapsuleId, bytes: u64) -> Result<()> {
-        let mut capsules = self.capsules.write().unwrap();
-        if let Some(capsule) = capsules.get_mut(&capsule_id) {
-            capsule.deduped_bytes += bytes;
+        if !self.capsules.read().unwrap().contains_key(&capsule_id) {
+            return Ok(());
         }
-        Ok(())
+        self.update_capsule(capsule_id, |capsule| capsule.deduped_bytes += bytes)
     }
 
     pub fn list_capsules(&self) -> Vec<CapsuleId> {
         self.capsules.read().unwrap().keys().copied().collect()
     }
 
-    pub fn delete_capsule(&self, id: CapsuleId) -> Result<Capsule> {
-        let capsule = self
-            .capsules
+    /// Capsules matching `query`, oldest first, answered from the tag, size and
+    /// age index rather than a scan.
+    pub fn find_capsules(&self, query: &CapsuleQuery) -> Vec<CapsuleId> {
+        self.index.read().unwrap().query(query)
+    }
+
+    /// Apply `f` to the user metadata of capsule `id`.
+    ///
+    /// Snapshots are read-only, so their metadata cannot change.
+    pub fn update_metadata<F>(&self, id: CapsuleId, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut BTreeMap<String, String>),
+    {
+        if self.lookup(id)?.read_only {
+            anyhow::bail!("Capsule {:?} is a read-only snapshot", id);
+        }
+        self.update_capsule(id, |capsule| f(&mut capsule.metadata))
+    }
+
+    /// Apply `f` to the tags of capsule `id`. Snapshots may be tagged.
+    pub fn update_tags<F>(&self, id: CapsuleId, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut BTreeMap<String, String>),
+    {
+        self.update_capsule(id, |capsule| f(&mut capsule.tags))
+    }
+
+    /// Fail if capsule `id` is under retention or a legal hold, reporting the
+    /// attempt. Views call this before dropping their own handle on a capsule.
+    pub fn ensure_mutable(&self, id: CapsuleId, action: &'static str) -> Result<()> {
+        self.enforce_retention(&self.lookup(id)?, action, unix_now()?)
+    }
+
+    /// Extend the retention deadline of capsule `id` to `until` (Unix seconds).
+    ///
+    /// Deadlines only ever move forward; an attempt to shorten one is refused
+    /// and reported like any other retention violation.
+    pub fn set_retention(&self, id: CapsuleId, until: u64) -> Result<()> {
+        let current = self.lookup(id)?;
+        if let Some(existing) = current.retain_until.filter(|existing| until < *existing) {
+            let reason = format!("retained until {}", existing);
+            return Err(self
+                .retention_violation(id, "shorten retention", reason)
+                .into());
+        }
+        self.update_capsule(id, |capsule| capsule.retain_until = Some(until))
+    }
+
+    /// Place or release a legal hold on capsule `id`.
+    pub fn set_legal_hold(&self, id: CapsuleId, hold: bool) -> Result<()> {
+        self.update_capsule(id, |capsule| capsule.legal_hold = hold)
+    }
+
+    /// Set the byte limits of `tenant`; an unlimited quota clears them.
+    pub fn set_quota(&self, tenant: &TenantId, quota: TenantQuota) -> Result<()> {
+        let mut journal = self.journal.lock().unwrap();
+        let mut quotas = self.quotas.write().unwrap();
+        if quota.is_unlimited() {
+            quotas.remove(tenant);
+        } else {
+            quotas.insert(tenant.clone(), quota);
+        }
+        drop(quotas);
+        self.record(
+            &mut journal,
+            JournalOp::QuotaSet {
+                tenant: tenant.clone(),
+                quota,
+            },
+        )
+    }
+
+    pub fn quota(&self, tenant: &TenantId) -> TenantQuota {
+        self.quotas
+            .read()
+            .unwrap()
+            .get(tenant)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Make `capsule` the Zstd dictionary of `tenant`, or of the shared
+    /// namespace for `None`.
+    pub fn set_dictionary(&self, tenant: Option<&TenantId>, capsule: CapsuleId) -> Result<()> {
+        let mut journal = self.journal.lock().unwrap();
+        self.dictionaries
             .write()
             .unwrap()
-            .remove(&id)
+            .insert(tenant.cloned(), capsule);
+        self.record(
+            &mut journal,
+            JournalOp::DictionarySet {
+                tenant: tenant.cloned(),
+                capsule,
+            },
+        )
+    }
+
+    /// Capsule holding the Zstd dictionary of `tenant`, if one was trained.
+    pub fn dictionary(&self, tenant: Option<&TenantId>) -> Option<CapsuleId> {
+        self.dictionaries
+            .read()
+            .unwrap()
+            .get(&tenant.cloned())
+            .copied()
+    }
+
+    /// Every dictionary capsule with the tenant it serves, shared one first.
+    pub fn dictionaries(&self) -> Vec<(Option<TenantId>, CapsuleId)> {
+        let mut dictionaries: Vec<_> = self
+            .dictionaries
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(tenant, capsule)| (tenant.clone(), *capsule))
+            .collect();
+        dictionaries.sort_by(|a, b| a.0.cmp(&b.0));
+        dictionaries
+    }
+
+    /// Tenants that own a capsule or have a quota, sorted.
+    pub fn tenants(&self) -> Vec<TenantId> {
+        let mut tenants: Vec<_> = self
+            .capsules
+            .read()
+            .unwrap()
+            .values()
+            .filter_map(|capsule| capsule.tenant.clone())
+            .chain(self.quotas.read().unwrap().keys().cloned())
+            .collect();
+        tenants.sort();
+        tenants.dedup();
+        tenants
+    }
+
+    /// Live capsules owned by `tenant`.
+    pub fn tenant_capsules(&self, tenant: &TenantId) -> Vec<Capsule> {
+        self.capsules
+            .read()
+            .unwrap()
+            .values()
+            .filter(|capsule| capsule.tenant.as_ref() == Some(tenant))
+            .cloned()
+            .collect()
+    }
+
+    /// Logical bytes of `tenant`'s live capsules, leaving out `excluding`.
+    fn logical_usage(&self, tenant: &TenantId, excluding: Option<CapsuleId>) -> u64 {
+        self.capsules
+            .read()
+            .unwrap()
+            .values()
+            .filter(|capsule| capsule.tenant.as_ref() == Some(tenant))
+            .filter(|capsule| Some(capsule.id) != excluding)
+            .map(|capsule| capsule.size)
+            .sum()
+    }
+
+    /// Bytes `tenant` stores outside capsule `id`, with its hard quota; `None`
+    /// when the tenant has no hard quota.
+    pub(crate) fn hard_quota_usage(&self, tenant: &TenantId, id: CapsuleId) -> Option<(u64, u64)> {
+        let hard = self.quota(tenant).hard_bytes?;
+        Some((self.logical_usage(tenant, Some(id)), hard))
+    }
+
+    fn enforce_quota(&self, capsule: &Capsule) -> Result<()> {
+        let Some(tenant) = &capsule.tenant else {
+            return Ok(());
+        };
+        if let Some((used, hard)) = self.hard_quota_usage(tenant, capsule.id) {
+            tenant::check_hard_quota(tenant, used, capsule.size, hard)?;
+        }
+        Ok(())
+    }
+
+    /// Emit `Event::QuotaSoftLimitExceeded` if `tenant` is now over its soft limit.
+    fn report_soft_limit(&self, tenant: &TenantId) {
+        let Some(soft_limit_bytes) = self.quota(tenant).soft_bytes else {
+            return;
+        };
+        let used_bytes = self.logical_usage(tenant, None);
+        if used_bytes > soft_limit_bytes {
+            tracing::warn!(%tenant, used_bytes, soft_limit_bytes, "tenant over soft quota");
+            self.emit(Event::QuotaSoftLimitExceeded {
+                tenant: tenant.clone(),
+                used_bytes,
+                soft_limit_bytes,
+            });
+        }
+    }
+
+    /// Segments of every capsule or retained version that retention or a legal
+    /// hold currently protects, mapped to the capsule protecting them.
+    pub(crate) fn protected_segments(&self) -> Result<HashMap<SegmentId, CapsuleId>> {
+        let now = unix_now()?;
+        let capsules = self.capsules.read().unwrap();
+        let history = self.history.read().unwrap();
+        let mut protected = HashMap::new();
+        for capsule in capsules.values() {
+            let versions = history.get(&capsule.id).into_iter().flatten();
+            let held = capsule.legal_hold;
+            for version in std::iter::once(capsule).chain(versions.map(|entry| &entry.capsule)) {
+                if held || version.retained_at(now) {
+                    for seg_id in version.segments.iter().filter(|seg_id| !seg_id.is_hole()) {
+                        protected.insert(*seg_id, capsule.id);
+                    }
+                }
+            }
+        }
+        Ok(protected)
+    }
+
+    /// Snapshots and clones taken directly from `origin`.
+    pub fn derived_capsules(&self, origin: CapsuleId) -> Vec<CapsuleId> {
+        self.capsules
+            .read()
+            .unwrap()
+            .values()
+            .filter(|capsule| capsule.origin == Some(origin))
+            .map(|capsule| capsule.id)
+            .collect()
+    }
+
+    pub fn delete_capsule(&self, id: CapsuleId) -> Result<Capsule> {
+        self.delete_capsule_with_history(id)
+            .map(|(capsule, _)| capsule)
+    }
+
+    /// Delete a capsule along with its retained versions, returning both so the
+    /// caller can release their segments.
+    pub fn delete_capsule_with_history(
+        &self,
+        id: CapsuleId,
+    ) -> Result<(Capsule, Vec<CapsuleVersion>)> {
+        let mut journal = self.journal.lock().unwrap();
+        let now = unix_now()?;
+        let mut capsules = self.capsules.write().unwrap();
+        let current = capsules
+            .get(&id)
             .ok_or_else(|| anyhow::anyhow!("Capsule not found"))?;
-        self.save()?;
-        Ok(capsule)
+        self.enforce_retention(current, "delete", now)?;
+        if self
+            .dictionaries
+            .read()
+            .unwrap()
+            .values()
+            .any(|dict| *dict == id)
+        {
+            anyhow::bail!(
+                "Capsule {:?} holds a zstd dictionary that stored segments may need",
+                id
+            );
+        }
+
+        let mut history = self.history.write().unwrap();
+        if let Some(version) = history
+            .get(&id)
+            .into_iter()
+            .flatten()
+            .find(|entry| entry.capsule.retained_at(now))
+        {
+            let reason = format!(
+                "version {} retained until {}",
+                version.capsule.version,
+                version.capsule.retain_until.unwrap_or_default()
+            );
+            return Err(self.retention_violation(id, "delete", reason).into());
+        }
+        let history = history.remove(&id).unwrap_or_default();
+        self.index.write().unwrap().remove(id);
+        let capsule = capsules.remove(&id).expect("capsule checked above");
+        drop(capsules);
+        self.record(&mut journal, JournalOp::CapsuleDeleted { id })?;
+        Ok((capsule, history))
+    }
+
+    /// Superseded versions retained for `id`, oldest first.
+    pub fn version_history(&self, id: CapsuleId) -> Vec<CapsuleVersion> {
+        self.history
+            .read()
+            .unwrap()
+            .get(&id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Every readable version of `id` in ascending order, ending with the live one.
+    pub fn list_versions(&self, id: CapsuleId) -> Result<Vec<u64>> {
+        let current = self.lookup(id)?;
+        let mut versions: Vec<u64> = self
+            .version_history(id)
+            .iter()
+            .map(|entry| entry.capsule.version)
+            .collect();
+        versions.push(current.version);
+        Ok(versions)
+    }
+
+    /// The capsule as it stood at `version`, live or retained.
+    pub fn capsule_at(&self, id: CapsuleId, version: u64) -> Result<Capsule> {
+        let current = self.lookup(id)?;
+        if current.version == version {
+            return Ok(current);
+        }
+        self.version_history(id)
+            .into_iter()
+            .find(|entry| entry.capsule.version == version)
+            .map(|entry| entry.capsule)
+            .ok_or_else(|| anyhow::anyhow!("Capsule {:?} has no version {}", id, version))
+    }
+
+    /// Drop retained versions of `id` that its versioning policy no longer
+    /// keeps as of `now` (Unix seconds), returning them for segment release.
+    pub fn prune_versions(&self, id: CapsuleId, now: u64) -> Result<Vec<CapsuleVersion>> {
+        let mut journal = self.journal.lock().unwrap();
+        let current = self.lookup(id)?;
+        if current.legal_hold {
+            // A hold preserves the whole capsule, history included.
+            return Ok(Vec::new());
+        }
+        let rules = current.policy.versioning;
+        let mut history = self.history.write().unwrap();
+        let Some(entries) = history.get_mut(&id) else {
+            return Ok(Vec::new());
+        };
+
+        let versions = expired_versions(entries, rules.as_ref(), now);
+        if versions.is_empty() {
+            return Ok(Vec::new());
+        }
+        let (pruned, kept) = std::mem::take(entries)
+            .into_iter()
+            .partition(|entry| versions.contains(&entry.capsule.version));
+        *entries = kept;
+        if entries.is_empty() {
+            history.remove(&id);
+        }
+        drop(history);
+
+        self.record(&mut journal, JournalOp::VersionsPruned { id, versions })?;
+        Ok(pruned)
+    }
+
+    /// Wrapped data key `id` as carried by any capsule record, live or
+    /// retained. The key's owner is looked at first, but a deleted owner's
+    /// keys live on in the records of capsules sharing its segments.
+    pub fn find_data_key(&self, id: DataKeyRef) -> Option<WrappedDataKey> {
+        self.find_data_key_matching(id, |_| true)
+    }
+
+    /// Like [`find_data_key`](Self::find_data_key), but only from records of
+    /// capsules in the dedup partition of `tenant`.
+    pub fn find_data_key_in(
+        &self,
+        tenant: Option<&TenantId>,
+        id: DataKeyRef,
+    ) -> Option<WrappedDataKey> {
+        self.find_data_key_matching(id, |record| record.tenant.as_ref() == tenant)
+    }
+
+    fn find_data_key_matching(
+        &self,
+        id: DataKeyRef,
+        matches: impl Fn(&Capsule) -> bool,
+    ) -> Option<WrappedDataKey> {
+        let capsules = self.capsules.read().unwrap();
+        let history = self.history.read().unwrap();
+        let records = |capsule: CapsuleId| {
+            capsules.get(&capsule).into_iter().chain(
+                history
+                    .get(&capsule)
+                    .into_iter()
+                    .flat_map(|versions| versions.iter().rev().map(|entry| &entry.capsule)),
+            )
+        };
+        records(id.capsule)
+            .chain(capsules.keys().flat_map(|capsule| records(*capsule)))
+            .filter(|record| matches(record))
+            .find_map(|record| record.data_key(id).cloned())
+    }
+
+    /// Replace wrapped data keys across every live, retained and pending
+    /// record. `rewrap` returns the replacement for a key, or `None` to keep
+    /// it; the number of keys replaced is returned.
+    ///
+    /// Progress made before `rewrap` fails is kept.
+    pub fn rewrap_data_keys<F>(&self, mut rewrap: F) -> Result<usize>
+    where
+        F: FnMut(&WrappedDataKey) -> Result<Option<WrappedDataKey>>,
+    {
+        let mut journal = self.journal.lock().unwrap();
+        let mut replaced = 0;
+        let result = {
+            let mut capsules = self.capsules.write().unwrap();
+            let mut history = self.history.write().unwrap();
+            let mut pending = self.pending_writes.write().unwrap();
+            let records = capsules
+                .values_mut()
+                .chain(
+                    history
+                        .values_mut()
+                        .flatten()
+                        .map(|entry| &mut entry.capsule),
+                )
+                .chain(pending.values_mut().map(|intent| &mut intent.capsule));
+            let mut result = Ok(());
+            'records: for record in records {
+                for key in &mut record.data_keys {
+                    match rewrap(key) {
+                        Ok(Some(rewrapped)) => {
+                            *key = rewrapped;
+                            replaced += 1;
+                        }
+                        Ok(None) => {}
+                        Err(err) => {
+                            result = Err(err);
+                            break 'records;
+                        }
+                    }
+                }
+            }
+            result
+        };
+        if replaced > 0 {
+            self.checkpoint(&mut journal)?;
+        }
+        result.map(|()| replaced)
     }
 
     /// Get dedup statistics (for debugging/monitoring)
     pub fn get_dedup_stats(&self) -> (usize, usize) {
-        let content_store = self.content_store.read().unwrap();
+        let unique_segments = self.content_store.lock().unwrap().len();
         let capsules = self.capsules.read().unwrap();
 
-        let total_segments: usize = capsules.values().map(|c| c.segments.len()).sum();
-
-        let unique_segments = content_store.len();
+        let total_segments: usize = capsules
+            .values()
+            .flat_map(|c| c.segments.iter())
+            .filter(|seg_id| !seg_id.is_hole())
+            .count();
 
         (total_segments, unique_segments)
     }
 
-    #[cfg(feature = "advanced-security")]
-    fn configure_bloom(
-        existing: Option<&HashMap<ContentHash, SegmentId>>,
-    ) -> Option<Arc<BloomFilterWrapper>> {
-        let capacity = std::env::var("SPACE_BLOOM_CAPACITY")
-            .ok()
-            .and_then(|v| v.parse::<usize>().ok())
-            .unwrap_or(10_000_000);
-        let fp_rate = std::env::var("SPACE_BLOOM_FPR")
-            .ok()
-            .and_then(|v| v.parse::<f64>().ok())
-            .unwrap_or(0.001);
-
-        let filter = if let Some(store) = existing {
-            let hashes = store.keys().cloned().collect::<Vec<_>>();
-            BloomFilterWrapper::with_existing(capacity, fp_rate, hashes)
-        } else {
-            BloomFilterWrapper::new(capacity, fp_rate)
-        };
+    /// Size and cache use of the on-disk dedup index.
+    pub fn content_index_stats(&self) -> ContentIndexStats {
+        self.content_store.lock().unwrap().stats()
+    }
 
-        Some(Arc::new(filter))
+    /// Size and cache use of the on-disk similarity index.
+    pub fn similarity_index_stats(&self) -> ContentIndexStats {
+        self.similarity_store.lock().unwrap().stats()
     }
 }
 
@@ -458,8 +1533,17 @@ impl Clone for CapsuleRegistry {
             next_segment_id: Arc::clone(&self.next_segment_id),
             metadata_path: self.metadata_path.clone(),
             content_store: Arc::clone(&self.content_store),
+            similarity_store: Arc::clone(&self.similarity_store),
+            quotas: Arc::clone(&self.quotas),
+            dictionaries: Arc::clone(&self.dictionaries),
+            pending_writes: Arc::clone(&self.pending_writes),
+            history: Arc::clone(&self.history),
+            index: Arc::clone(&self.index),
+            journal: Arc::clone(&self.journal),
+            recovered: Arc::clone(&self.recovered),
+            event_sink: self.event_sink.clone(),
             #[cfg(feature = "advanced-security")]
-            bloom_filter: self.bloom_filter.clone(),
+            audit_log: self.audit_log.clone(),
         }
     }
 }
@@ -0,0 +1,199 @@
+use crate::compaction::SCHEDULER_TICK;
+use crate::pipeline::WritePipeline;
+use anyhow::Result;
+use common::{Capsule, CapsuleId, LifecycleAction, Policy};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// Outcome of one lifecycle evaluation pass.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LifecycleReport {
+    pub expired: usize,
+    pub transitioned: usize,
+    /// Capsules with a due rule that retention or a legal hold holds back.
+    pub deferred: usize,
+    /// Transitions skipped because the modular pipeline cannot rewrite
+    /// capsules; expiry still runs.
+    pub unsupported: usize,
+    pub failed: usize,
+}
+
+/// What a capsule's lifecycle rules call for at a given moment.
+enum Due {
+    Expire,
+    Transition { policy: Box<Policy>, step: u32 },
+}
+
+/// Applies the lifecycle rules of every capsule's policy through the pipeline.
+///
+/// Progress is kept on each capsule as `lifecycle_step` and published in the
+/// same commit as the transition it records, so a pass interrupted by a
+/// restart neither repeats nor skips a rule. When several rules fell due since
+/// the last pass only the outcome matters: expiry wins, otherwise the latest
+/// transition is applied directly.
+pub struct LifecycleManager<'a> {
+    pipeline: &'a WritePipeline,
+}
+
+impl<'a> LifecycleManager<'a> {
+    pub fn new(pipeline: &'a WritePipeline) -> Self {
+        Self { pipeline }
+    }
+
+    /// Evaluate every capsule against the current time.
+    pub fn run(&self) -> Result<LifecycleReport> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        Ok(self.run_at(now))
+    }
+
+    /// Evaluate every capsule as if the time were `now` (Unix seconds).
+    pub fn run_at(&self, now: u64) -> LifecycleReport {
+        let registry = self.pipeline.registry();
+        let mut ids = registry.list_capsules();
+        ids.sort_by_key(|id| id.0);
+
+        let mut report = LifecycleReport::default();
+        for id in ids {
+            let Ok(capsule) = registry.lookup(id) else {
+                continue;
+            };
+            let Some(due) = due_action(&capsule, now) else {
+                continue;
+            };
+            // Retention outranks lifecycle; the rule runs once it lapses.
+            if capsule.is_immutable_at(now) {
+                report.deferred += 1;
+                continue;
+            }
+            if matches!(due, Due::Transition { .. }) && self.pipeline.uses_modular() {
+                report.unsupported += 1;
+                continue;
+            }
+            match self.apply(id, due) {
+                Ok(Due::Expire) => report.expired += 1,
+                Ok(Due::Transition { .. }) => report.transitioned += 1,
+                Err(err) => {
+                    warn!(capsule = %id.as_uuid(), error = %err, "lifecycle rule failed");
+                    report.failed += 1;
+                }
+            }
+        }
+
+        if report != LifecycleReport::default() {
+            info!(
+                expired = report.expired,
+                transitioned = report.transitioned,
+                deferred = report.deferred,
+                unsupported = report.unsupported,
+                failed = report.failed,
+                "lifecycle pass complete"
+            );
+        }
+        report
+    }
+
+    fn apply(&self, id: CapsuleId, due: Due) -> Result<Due> {
+        match due {
+            Due::Expire => self.pipeline.delete_capsule(id)?,
+            Due::Transition { ref policy, step } => {
+                self.pipeline
+                    .rewrite_with_policy(id, policy, |capsule| capsule.lifecycle_step = step)?;
+            }
+        }
+        Ok(due)
+    }
+}
+
+fn due_action(capsule: &Capsule, now: u64) -> Option<Due> {
+    let mut rules: Vec<_> = capsule.policy.lifecycle.iter().collect();
+    rules.sort_by_key(|rule| rule.after_secs);
+
+    let age = now.saturating_sub(capsule.created_at);
+    let pending: Vec<_> = rules
+        .iter()
+        .skip(capsule.lifecycle_step as usize)
+        .take_while(|rule| rule.after_secs <= age)
+        .collect();
+
+    if pending
+        .iter()
+        .any(|rule| matches!(rule.action, LifecycleAction::Expire))
+    {
+        return Some(Due::Expire);
+    }
+    let target = pending.iter().rev().find_map(|rule| match &rule.action {
+        LifecycleAction::Transition(policy) => Some(policy),
+        LifecycleAction::Expire => None,
+    })?;
+    // Snapshots are frozen; they can expire but never be rewritten.
+    if capsule.read_only {
+        return None;
+    }
+    // A transition changes how the data is stored, never how it is protected.
+    let current = &capsule.policy;
+    Some(Due::Transition {
+        policy: Box::new(Policy {
+            encryption: current.encryption.clone(),
+            crypto_profile: current.crypto_profile,
+            versioning: current.versioning.clone(),
+            retention: current.retention,
+            erasure_profile: current.erasure_profile.clone(),
+            lifecycle: current.lifecycle.clone(),
+            ..(**target).clone()
+        }),
+        step: capsule.lifecycle_step + pending.len() as u32,
+    })
+}
+
+/// Background worker running a [`LifecycleManager`] pass every `interval`.
+pub struct LifecycleScheduler {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl LifecycleScheduler {
+    pub fn spawn(pipeline: Arc<WritePipeline>, interval: Duration) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_flag = Arc::clone(&stop);
+
+        let handle = thread::spawn(move || {
+            let mut next_run = Instant::now();
+            while !stop_flag.load(Ordering::SeqCst) {
+                if Instant::now() < next_run {
+                    thread::sleep(SCHEDULER_TICK);
+                    continue;
+                }
+                if let Err(err) = LifecycleManager::new(&pipeline).run() {
+                    warn!(error = %err, "lifecycle pass failed");
+                }
+                next_run = Instant::now() + interval;
+            }
+        });
+
+        Self {
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    /// Signal the worker to exit and wait for it.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for LifecycleScheduler {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
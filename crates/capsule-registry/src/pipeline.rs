@@ -1,19 +1,26 @@
+use crate::compaction::{CompactionReport, CompactionScheduler, Compactor};
 use crate::dedup::{hash_content, DedupStats};
 #[cfg(feature = "pipeline_async")]
 use crate::error::PipelineResult;
 use crate::error::{CompressionError, PipelineError};
 #[cfg(feature = "modular_pipeline")]
 use crate::modular_pipeline;
-use crate::{gc::GarbageCollector, CapsuleRegistry};
+use crate::scrub::{ScrubConfig, ScrubHandle, Scrubber};
+use crate::stream::{CapsuleReader, CapsuleWriter};
+use crate::tenant::{check_hard_quota, TenantUsage};
+use crate::{gc::GarbageCollector, CapsuleRegistry, WriteIntent};
 use anyhow::{Error as AnyhowError, Result};
 #[cfg(feature = "pipeline_async")]
 use bytes::Bytes;
 #[cfg(all(feature = "phase4", feature = "podms"))]
 use common::podms::SovereigntyLevel;
+use common::similarity::Sketch;
 use common::*;
-use compression::{compress_segment, decompress_lz4, decompress_zstd};
+use compression::codec::{self, IDENTITY};
+use compression::compress_segment_with_dictionary;
+use compression::delta::{apply_delta, encode_delta};
+use compression::dictionary::decompress_zstd_with_dictionary;
 use nvram_sim::NvramLog;
-#[cfg(feature = "pipeline_async")]
 use nvram_sim::NvramTransaction;
 use std::borrow::Cow;
 use std::collections::HashMap;
@@ -29,11 +36,10 @@ use common::security::audit_log::AuditLog;
 use common::security::crypto_profiles::{
     collect_base_material, serialize_ciphertext, HybridKeyMaterial, MlkemKeyManager, MlkemNonceExt,
 };
-#[cfg(feature = "advanced-security")]
 use encryption::keymanager::XtsKeyPair;
 use encryption::{
-    compute_mac, decrypt_segment, derive_tweak_from_hash, encrypt_segment, verify_mac,
-    EncryptionMetadata, KeyManager,
+    compute_mac, decrypt_segment, derive_tweak_from_hash, encrypt_segment, generate_data_key,
+    unwrap_data_key, verify_mac, wrap_data_key, EncryptionMetadata, KeyManager,
 };
 use sim_nvram::start_nvram_sim; // Pipeline integration hook for simulation mode
 use std::env; // For SPACE_SIM_MODE environment variable
@@ -113,79 +119,105 @@ fn map_nvram_error(operation: &'static str, err: AnyhowError) -> AnyhowError {
 
 #[cfg(feature = "pipeline_async")]
 #[instrument(
-    skip(chunk, policy, key_manager),
+    skip(chunk, policy, sealing, dictionary),
     fields(segment_index = index, chunk_len = chunk.len(), policy = ?policy)
 )]
 fn prepare_segment(
     index: usize,
     chunk: Vec<u8>,
     policy: Policy,
-    key_manager: Option<Arc<Mutex<KeyManager>>>,
+    sealing: Option<SealingKey>,
+    dictionary: Option<Arc<Vec<u8>>>,
 ) -> PipelineResult<SegmentPrepared> {
     let started = Instant::now();
-    let (compressed_data, comp_result) =
-        compress_segment(&chunk, &policy.compression).map_err(|err| {
-            let comp_err = match err.downcast::<CompressionError>() {
-                Ok(ce) => ce,
-                Err(other) => {
-                    error!(segment_index = index, error = %other, "Unexpected compression error");
-                    return PipelineError::Registry {
-                        operation: "compress_segment",
-                        source: other,
-                    };
-                }
-            };
-            PipelineError::Compression {
-                segment_index: index,
-                source: comp_err,
-            }
-        })?;
-    let content_hash = hash_content(compressed_data.as_ref());
+    let content_hash = hash_content(&chunk);
+    let (final_data, comp_result, encryption_meta) = seal_payload(
+        index,
+        &chunk,
+        &content_hash,
+        &policy,
+        sealing.as_ref(),
+        dictionary.as_deref().map(Vec::as_slice),
+    )?;
+
+    // Kept for the commit stage, which looks for a delta base.
+    let sketch = if policy.delta_compression {
+        Sketch::of(&chunk)
+    } else {
+        None
+    };
+    let plaintext = sketch.is_some().then_some(chunk);
 
-    let encryption_enabled = policy.encryption.is_enabled() && key_manager.is_some();
-    let mut encryption_meta = None;
+    Ok(SegmentPrepared {
+        index,
+        content_hash,
+        final_data,
+        comp_result,
+        encryption_meta,
+        sketch,
+        plaintext,
+        prepared_at: Instant::now(),
+        preparation_time: started.elapsed(),
+    })
+}
 
-    let final_data = if encryption_enabled {
-        let km = key_manager
-            .as_ref()
-            .ok_or_else(|| PipelineError::Registry {
-                operation: "key_manager",
-                source: anyhow::anyhow!("Key manager unavailable for encryption"),
-            })?;
-        let mut km = km.lock().unwrap();
+/// Compress and, given the capsule's sealing key, encrypt `payload` for storage.
+#[cfg(feature = "pipeline_async")]
+fn seal_payload(
+    index: usize,
+    payload: &[u8],
+    content_hash: &ContentHash,
+    policy: &Policy,
+    sealing: Option<&SealingKey>,
+    dictionary: Option<&[u8]>,
+) -> PipelineResult<(
+    Bytes,
+    compression::CompressionResult,
+    Option<EncryptionMetadata>,
+)> {
+    let (compressed_data, comp_result) = compress_segment_with_dictionary(
+        payload,
+        &policy.compression,
+        dictionary,
+    )
+    .map_err(|err| {
+        let comp_err = match err.downcast::<CompressionError>() {
+            Ok(ce) => ce,
+            Err(other) => {
+                error!(segment_index = index, error = %other, "Unexpected compression error");
+                return PipelineError::Registry {
+                    operation: "compress_segment",
+                    source: other,
+                };
+            }
+        };
+        PipelineError::Compression {
+            segment_index: index,
+            source: comp_err,
+        }
+    })?;
 
-        let key_version = km.current_version();
-        let key_pair = km
-            .get_key(key_version)
-            .map_err(|e| PipelineError::Registry {
-                operation: "get_key",
-                source: e.into(),
-            })?;
+    let mut encryption_meta = None;
 
+    let final_data = if let Some(sealing) = sealing.filter(|_| policy.encryption.is_enabled()) {
+        let key_pair = &sealing.pair;
         let tweak = derive_tweak_from_hash(content_hash.as_str().as_bytes());
-        let (ciphertext, mut enc_meta) =
-            encrypt_segment(compressed_data.as_ref(), key_pair, key_version, tweak)?;
+        let (ciphertext, mut enc_meta) = encrypt_segment(
+            compressed_data.as_ref(),
+            key_pair,
+            sealing.id.generation,
+            tweak,
+        )?;
 
         let mac_tag = compute_mac(&ciphertext, &enc_meta, key_pair.key1(), key_pair.key2())?;
         enc_meta.set_integrity_tag(mac_tag);
         encryption_meta = Some(enc_meta);
         Bytes::from(ciphertext)
     } else {
-        match compressed_data {
-            Cow::Borrowed(_) => Bytes::from(chunk),
-            Cow::Owned(vec) => Bytes::from(vec),
-        }
+        Bytes::from(compressed_data.into_owned())
     };
 
-    Ok(SegmentPrepared {
-        index,
-        content_hash,
-        final_data,
-        comp_result,
-        encryption_meta,
-        prepared_at: Instant::now(),
-        preparation_time: started.elapsed(),
-    })
+    Ok((final_data, comp_result, encryption_meta))
 }
 
 #[cfg(feature = "pipeline_async")]
@@ -195,21 +227,204 @@ struct SegmentPrepared {
     final_data: Bytes,
     comp_result: compression::CompressionResult,
     encryption_meta: Option<EncryptionMetadata>,
+    sketch: Option<Sketch>,
+    // The chunk itself, kept only while it may still be stored as a delta.
+    plaintext: Option<Vec<u8>>,
     prepared_at: Instant,
     preparation_time: Duration,
 }
 
 #[cfg(feature = "pipeline_async")]
 enum WriteDisposition {
-    NewSegment,
+    NewSegment { sketch: Option<Sketch> },
+    NewDelta { base: SegmentId },
     ReusedPersistent,
     ReusedStaged,
 }
 
+/// Segment index range `[first, last)` an overwrite of `offset..end` must
+/// rewrite, plus the capsule offset where that range begins.
+///
+/// A trailing partial segment is pulled in when the write appends, so the tail
+/// is refilled rather than left short. Capsules whose segment lengths are not
+/// all known are rewritten in full.
+fn rewrite_span(logical_lens: &[u64], size: u64, offset: u64, end: u64) -> (usize, usize, u64) {
+    let count = logical_lens.len();
+    let known = logical_lens.iter().all(|len| *len > 0) && logical_lens.iter().sum::<u64>() == size;
+    if !known {
+        return (0, count, 0);
+    }
+
+    let mut first = count;
+    let mut last = count;
+    let mut region_start = size;
+    let mut seg_start = 0u64;
+    for (index, len) in logical_lens.iter().enumerate() {
+        let seg_end = seg_start + *len;
+        if first == count && offset < seg_end {
+            first = index;
+            region_start = seg_start;
+        }
+        if seg_start >= end {
+            last = index;
+            break;
+        }
+        seg_start = seg_end;
+    }
+
+    if first == count {
+        if let Some(tail) = logical_lens
+            .last()
+            .filter(|len| (**len as usize) < SEGMENT_SIZE)
+        {
+            first = count - 1;
+            region_start = size - *tail;
+        }
+    }
+    (first, last, region_start)
+}
+
+/// Cut `data` into segments as `policy`'s layout strategy asks: content-defined
+/// boundaries for [`LayoutStrategy::ContentDefined`], `SEGMENT_SIZE` otherwise.
+pub(crate) fn segment_chunks<'a>(policy: &Policy, data: &'a [u8]) -> Vec<&'a [u8]> {
+    match policy.layout.strategy.chunker() {
+        Some(chunker) => chunker.split(data),
+        None => data.chunks(SEGMENT_SIZE).collect(),
+    }
+}
+
+/// Whether `chunk` is all zeroes and can be recorded as a hole instead of stored.
+fn is_zero_chunk(chunk: &[u8]) -> bool {
+    !chunk.is_empty() && chunk.iter().all(|byte| *byte == 0)
+}
+
+/// Split any hole overlapping `offset..end` so that only the `SEGMENT_SIZE`
+/// aligned stretch under the write has to be materialized; the zeroes before
+/// and after it stay holes.
+fn split_holes(segments: &mut Vec<SegmentId>, lens: &mut Vec<u64>, offset: u64, end: u64) {
+    let mut index = 0;
+    let mut seg_start = 0u64;
+    while index < segments.len() {
+        let len = lens[index];
+        let seg_end = seg_start + len;
+        if seg_start >= end {
+            break;
+        }
+        if segments[index].is_hole() && seg_end > offset && len > SEGMENT_SIZE as u64 {
+            let step = SEGMENT_SIZE as u64;
+            let cut_start = seg_start + (offset.max(seg_start) - seg_start) / step * step;
+            let cut_end = (seg_start + (end - seg_start).div_ceil(step) * step).min(seg_end);
+            let pieces: Vec<u64> = [
+                cut_start - seg_start,
+                cut_end - cut_start,
+                seg_end - cut_end,
+            ]
+            .into_iter()
+            .filter(|piece| *piece > 0)
+            .collect();
+            segments.splice(
+                index..=index,
+                pieces.iter().map(|piece| SegmentId::hole(*piece)),
+            );
+            lens.splice(index..=index, pieces.iter().copied());
+        }
+        seg_start = seg_end;
+        index += 1;
+    }
+}
+
+/// A capsule's data key, unwrapped for sealing new segments.
+#[derive(Clone)]
+struct SealingKey {
+    id: DataKeyRef,
+    pair: XtsKeyPair,
+}
+
+/// Data keys of a capsule being written.
+struct CapsuleKeys {
+    capsule: CapsuleId,
+    // Dedup partition of the capsule; keys of shared segments come only from it.
+    tenant: Option<TenantId>,
+    // Whether the capsule is encrypted, so that plaintext segments are not shared.
+    encrypted: bool,
+    // Key new segments are sealed under, chosen when the first one is.
+    sealing: Option<SealingKey>,
+    // Wrapped keys for the capsule record: its own and those of shared segments.
+    wrapped: Vec<WrappedDataKey>,
+    // Set while rotating: the capsule's older keys, and the master key itself,
+    // are not reused.
+    rotating: bool,
+}
+
+impl CapsuleKeys {
+    fn new(
+        capsule: CapsuleId,
+        tenant: Option<TenantId>,
+        encrypted: bool,
+        wrapped: Vec<WrappedDataKey>,
+    ) -> Self {
+        Self {
+            capsule,
+            tenant,
+            encrypted,
+            sealing: None,
+            wrapped,
+            rotating: false,
+        }
+    }
+}
+
+/// State of a capsule write between its first staged segment and sealing.
+pub(crate) struct WriteSession {
+    capsule_id: CapsuleId,
+    policy: Policy,
+    encryption_enabled: bool,
+    keys: CapsuleKeys,
+    // Open only while segments are staged, so a streaming write waiting on its
+    // client never holds off compaction.
+    transaction: Option<NvramTransaction>,
+    staged_content: HashMap<ContentHash, SegmentId>,
+    dedupe_increments: Vec<SegmentId>,
+    new_segments: Vec<SegmentId>,
+    // Prefix of `new_segments` already committed to NVRAM by `flush_session`.
+    flushed_segments: usize,
+    // Whether an intent for this session is in the journal and must be aborted on failure.
+    intent_journaled: bool,
+    // Version of the capsule an overwrite replaces; None for new capsules.
+    base_version: Option<u64>,
+    // Position in the capsule of the first segment this session stages.
+    index_base: usize,
+    registrations: Vec<(ContentHash, SegmentId)>,
+    // New segments to offer as delta bases once the write commits.
+    sketches: Vec<(Sketch, SegmentId)>,
+    // Trained dictionary the policy compresses against, when it has one.
+    dictionary: Option<(DictionaryRef, Arc<Vec<u8>>)>,
+    segment_ids: Vec<SegmentId>,
+    dedup_stats: DedupStats,
+    total_original_size: u64,
+    total_compressed_size: u64,
+    // Owner of the capsule; its dedup partition and quota apply to the write.
+    tenant: Option<TenantId>,
+    // Bytes the tenant stores in other capsules, and its hard quota.
+    hard_quota: Option<(u64, u64)>,
+}
+
+impl WriteSession {
+    pub(crate) fn staged_segments(&self) -> usize {
+        self.new_segments.len() - self.flushed_segments
+    }
+}
+
 pub struct WritePipeline {
     registry: CapsuleRegistry,
     nvram: NvramLog,
     key_manager: Option<Arc<Mutex<KeyManager>>>, // CHANGED: Wrapped in Arc<Mutex<>>
+    // Zstd dictionaries read so far. A dictionary version never changes, so
+    // entries never go stale.
+    dictionaries: Arc<Mutex<HashMap<DictionaryRef, Arc<Vec<u8>>>>>,
+    // Data keys unwrapped so far. Rewrapping changes how a key is stored, never
+    // the key, so entries never go stale either.
+    data_keys: Arc<Mutex<HashMap<DataKeyRef, XtsKeyPair>>>,
     #[cfg(feature = "advanced-security")]
     audit_log: Option<AuditLog>,
     #[cfg(feature = "advanced-security")]
@@ -230,6 +445,29 @@ pub struct WritePipeline {
 
 impl WritePipeline {
     pub fn new(registry: CapsuleRegistry, nvram: NvramLog) -> Self {
+        // Try to initialize key manager from environment
+        let key_manager = KeyManager::from_env()
+            .ok()
+            .map(|km| Arc::new(Mutex::new(km))); // CHANGED: Wrap in Arc<Mutex<>>
+
+        Self::assemble(registry, nvram, key_manager)
+    }
+
+    /// Create pipeline with explicit key manager (for testing)
+    pub fn with_key_manager(
+        registry: CapsuleRegistry,
+        nvram: NvramLog,
+        key_manager: KeyManager,
+    ) -> Self {
+        let key_manager = Some(Arc::new(Mutex::new(key_manager))); // CHANGED: Wrap in Arc<Mutex<>>
+        Self::assemble(registry, nvram, key_manager)
+    }
+
+    fn assemble(
+        registry: CapsuleRegistry,
+        nvram: NvramLog,
+        key_manager: Option<Arc<Mutex<KeyManager>>>,
+    ) -> Self {
         // Pipeline Integration Hook: Check for simulation mode override
         let nvram = if env::var("SPACE_SIM_MODE").ok().as_deref() == Some("nvram") {
             info!("SPACE_SIM_MODE=nvram detected, initializing simulation NVRAM");
@@ -250,11 +488,6 @@ impl WritePipeline {
             nvram
         };
 
-        // Try to initialize key manager from environment
-        let key_manager = KeyManager::from_env()
-            .ok()
-            .map(|km| Arc::new(Mutex::new(km))); // CHANGED: Wrap in Arc<Mutex<>>
-
         #[cfg(feature = "advanced-security")]
         let audit_log = AuditLog::from_env().ok();
 
@@ -267,6 +500,11 @@ impl WritePipeline {
         if let Some(log) = audit_log.as_ref() {
             nvram = nvram.with_audit(log.clone());
         }
+        #[cfg(feature = "advanced-security")]
+        let registry = match audit_log.as_ref() {
+            Some(log) => registry.with_audit_log(log.clone()),
+            None => registry,
+        };
 
         #[cfg(feature = "advanced-security")]
         let mlkem_manager = MlkemKeyManager::from_env().ok();
@@ -305,6 +543,8 @@ impl WritePipeline {
             registry,
             nvram,
             key_manager,
+            dictionaries: Arc::new(Mutex::new(HashMap::new())),
+            data_keys: Arc::new(Mutex::new(HashMap::new())),
             #[cfg(feature = "advanced-security")]
             audit_log,
             #[cfg(feature = "advanced-security")]
@@ -321,98 +561,21 @@ impl WritePipeline {
             mesh_node: None, // Initialized via with_mesh_node
         };
 
-        if let Err(err) = pipeline.reconcile_refcounts() {
-            error!(error = ?err, "failed to reconcile segment refcounts");
-        }
-
-        pipeline
-    }
-
-    /// Create pipeline with explicit key manager (for testing)
-    pub fn with_key_manager(
-        registry: CapsuleRegistry,
-        nvram: NvramLog,
-        key_manager: KeyManager,
-    ) -> Self {
-        // Pipeline Integration Hook: Check for simulation mode override
-        let nvram = if env::var("SPACE_SIM_MODE").ok().as_deref() == Some("nvram") {
-            info!("SPACE_SIM_MODE=nvram detected, initializing simulation NVRAM");
-            match start_nvram_sim("/sim/nvram/sim.log") {
-                Ok(sim_log) => {
-                    info!("Simulation NVRAM initialized at /sim/nvram/sim.log");
-                    sim_log
-                }
-                Err(err) => {
-                    warn!(
-                        error = %err,
-                        "Failed to initialize simulation NVRAM, using provided NVRAM"
-                    );
-                    nvram
-                }
+        // Only writes left by a previous process are recovered; other views over
+        // the same registry may already have writes of their own in flight.
+        pipeline.registry.recover_once(|| {
+            if let Err(err) = pipeline.recover_pending_writes() {
+                error!(error = ?err, "failed to recover interrupted capsule writes");
             }
-        } else {
-            nvram
-        };
-
-        let key_manager = Some(Arc::new(Mutex::new(key_manager))); // CHANGED: Wrap in Arc<Mutex<>>
-
-        #[cfg(feature = "advanced-security")]
-        let audit_log = AuditLog::from_env().ok();
-        #[cfg(feature = "advanced-security")]
-        let mlkem_manager = MlkemKeyManager::from_env().ok();
-        #[cfg(feature = "advanced-security")]
-        let mut nvram = nvram;
-        #[cfg(feature = "advanced-security")]
-        if let Some(log) = audit_log.as_ref() {
-            nvram = nvram.with_audit(log.clone());
-        }
-        #[cfg(not(feature = "advanced-security"))]
-        let nvram = nvram;
 
-        #[cfg(feature = "modular_pipeline")]
-        let modular_enabled = std::env::var("SPACE_DISABLE_MODULAR_PIPELINE").is_err();
-        #[cfg(feature = "modular_pipeline")]
-        let (modular, runtime) = if modular_enabled {
-            match modular_pipeline::registry_pipeline_from_log(nvram.clone(), registry.clone()) {
-                Ok(handle) => match TokioRuntime::new() {
-                    Ok(rt) => (Some(Arc::new(TokioMutex::new(handle))), Some(Arc::new(rt))),
-                    Err(err) => {
-                        warn!(error = %err, "failed to create tokio runtime for modular pipeline delegation");
-                        (None, None)
-                    }
-                },
-                Err(err) => {
-                    warn!(error = %err, "failed to initialise modular pipeline delegation");
-                    (None, None)
-                }
+            // Dedup hits take their references before the intent is journaled,
+            // so a crash can leak refcounts even when nothing was pending.
+            if let Err(err) = pipeline.reconcile_refcounts() {
+                error!(error = ?err, "failed to reconcile segment refcounts");
             }
-        } else {
-            (None, None)
-        };
-        #[cfg(not(feature = "modular_pipeline"))]
-        let _modular = ();
-        #[cfg(not(feature = "modular_pipeline"))]
-        let _runtime = ();
+        });
 
-        Self {
-            registry,
-            nvram,
-            key_manager,
-            #[cfg(feature = "advanced-security")]
-            audit_log,
-            #[cfg(feature = "advanced-security")]
-            mlkem_manager,
-            #[cfg(feature = "modular_pipeline")]
-            modular,
-            #[cfg(feature = "modular_pipeline")]
-            runtime,
-            #[cfg(feature = "pipeline_async")]
-            config: PipelineConfig::default(),
-            #[cfg(all(feature = "podms", feature = "pipeline_async"))]
-            telemetry_tx: None, // Initialized via set_telemetry_channel
-            #[cfg(all(feature = "podms", feature = "pipeline_async"))]
-            mesh_node: None, // Initialized via with_mesh_node
-        }
+        pipeline
     }
 
     #[cfg(feature = "pipeline_async")]
@@ -440,18 +603,72 @@ impl WritePipeline {
         self
     }
 
+    /// Resolve capsule writes interrupted between their intent and commit records.
+    ///
+    /// A write whose new segments all reached NVRAM is rolled forward; anything
+    /// else is rolled back. Refcounts are left to `reconcile_refcounts`.
+    fn recover_pending_writes(&self) -> Result<usize> {
+        let pending = self.registry.pending_writes();
+        for intent in &pending {
+            let capsule_id = intent.capsule_id();
+            // A streaming write that never sealed has no complete capsule to publish.
+            let durable = !intent.partial
+                && intent
+                    .new_segments
+                    .iter()
+                    .all(|seg_id| self.nvram.get_segment_metadata(*seg_id).is_ok());
+
+            if durable {
+                self.registry.commit_write(capsule_id)?;
+                info!(capsule = %capsule_id.as_uuid(), "rolled forward interrupted capsule write");
+            } else {
+                self.registry.abort_write(capsule_id)?;
+                warn!(capsule = %capsule_id.as_uuid(), "rolled back interrupted capsule write");
+            }
+        }
+        Ok(pending.len())
+    }
+
+    /// Drop one reference to each segment, removing those no capsule uses any more.
+    fn release_segments(&self, segments: &[SegmentId]) -> Result<()> {
+        for seg_id in segments.iter().filter(|seg_id| !seg_id.is_hole()) {
+            let segment = self.nvram.decrement_refcount(*seg_id)?;
+
+            if segment.ref_count == 0 {
+                GarbageCollector::new(&self.registry, &self.nvram).reclaim_segment(segment)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Undo refcount increments taken for dedup hits of a write that failed.
+    fn release_dedupe_hits(&self, segments: &[SegmentId]) {
+        for seg_id in segments.iter().rev() {
+            if let Err(err) = self.nvram.decrement_refcount(*seg_id) {
+                warn!(segment = seg_id.0, error = %err, "failed to release dedup refcount");
+            }
+        }
+    }
+
     fn reconcile_refcounts(&self) -> Result<()> {
         let mut counts: HashMap<SegmentId, u32> = HashMap::new();
 
         for capsule_id in self.registry.list_capsules() {
             if let Ok(capsule) = self.registry.lookup(capsule_id) {
-                for seg_id in capsule.segments {
+                let retained = self.registry.version_history(capsule_id);
+                let versions =
+                    std::iter::once(capsule).chain(retained.into_iter().map(|v| v.capsule));
+                for seg_id in versions.flat_map(|version| version.segments) {
                     counts.entry(seg_id).and_modify(|c| *c += 1).or_insert(1);
                 }
             }
         }
 
         let segments = self.nvram.list_segments()?;
+        // Each delta segment holds a reference on its base.
+        for base in segments.iter().filter_map(|segment| segment.delta_base) {
+            counts.entry(base).and_modify(|c| *c += 1).or_insert(1);
+        }
         for mut segment in segments {
             let expected = *counts.get(&segment.id).unwrap_or(&0);
             if segment.ref_count != expected {
@@ -481,23 +698,19 @@ impl WritePipeline {
     pub fn delete_capsule(&self, capsule_id: CapsuleId) -> Result<()> {
         #[cfg(feature = "modular_pipeline")]
         if let (Some(modular), Some(runtime)) = (&self.modular, &self.runtime) {
+            // Checked here so the refusal is reported before the modular
+            // pipeline starts releasing segments.
+            self.registry.ensure_mutable(capsule_id, "delete")?;
             return runtime.block_on(async {
                 let mut handle = modular.lock().await;
                 handle.delete_capsule(capsule_id).await
             });
         }
 
-        let capsule = self.registry.delete_capsule(capsule_id)?;
-
-        for seg_id in capsule.segments {
-            let segment = self.nvram.decrement_refcount(seg_id)?;
-
-            if segment.ref_count == 0 {
-                if let Some(ref hash) = segment.content_hash {
-                    self.registry.deregister_content(hash, seg_id)?;
-                }
-                self.nvram.remove_segment(seg_id)?;
-            }
+        let (capsule, history) = self.registry.delete_capsule_with_history(capsule_id)?;
+        self.release_segments(&capsule.segments)?;
+        for entry in &history {
+            self.release_segments(&entry.capsule.segments)?;
         }
 
         #[cfg(feature = "advanced-security")]
@@ -522,6 +735,27 @@ impl WritePipeline {
         gc.sweep()
     }
 
+    /// Sweep unreferenced segments and rewrite the NVRAM log without them.
+    pub fn compact(&self) -> Result<CompactionReport> {
+        Compactor::new(&self.registry, &self.nvram).run()
+    }
+
+    /// Start a background compactor driven by `Policy::compact_interval_secs`.
+    pub fn spawn_compactor(&self) -> CompactionScheduler {
+        CompactionScheduler::spawn(self.registry.clone(), self.nvram.clone())
+    }
+
+    /// Start a background scrubber over this pipeline's NVRAM log.
+    pub fn spawn_scrubber(&self, config: ScrubConfig) -> Result<ScrubHandle> {
+        let scrubber = Scrubber::new(self.nvram.clone())?.with_config(config);
+        #[cfg(feature = "advanced-security")]
+        let scrubber = match &self.audit_log {
+            Some(log) => scrubber.with_audit_log(log.clone()),
+            None => scrubber,
+        };
+        Ok(scrubber.spawn())
+    }
+
     /// Write data with compression and return the capsule ID
     #[instrument(skip(self, data), fields(bytes = data.len()))]
     pub fn write_capsule(&self, data: &[u8]) -> Result<CapsuleId> {
@@ -540,178 +774,371 @@ impl WritePipeline {
             });
         }
 
-        // Pre-allocate capsule ID but don't persist yet
-        let capsule_id = CapsuleId::new();
-        let policy_snapshot = policy.clone();
+        let mut session = self.open_session(policy)?;
+        for chunk in segment_chunks(policy, data) {
+            if let Err(err) = self.stage_chunk(&mut session, chunk) {
+                self.abandon_session(session);
+                return Err(err);
+            }
+        }
+        self.seal_session(session)
+    }
 
-        // Track stats
-        let mut segment_ids = Vec::new();
-        let mut total_compressed_size = 0u64;
-        let mut total_original_size = 0u64;
-        let mut dedup_stats = DedupStats::new();
+    /// Start a streaming write that is sealed into a capsule by [`CapsuleWriter::finish`].
+    pub fn capsule_writer(&self, policy: &Policy) -> Result<CapsuleWriter<'_>> {
+        CapsuleWriter::new(self, policy, None)
+    }
 
-        // Check if encryption is enabled
-        let encryption_enabled = policy.encryption.is_enabled() && self.key_manager.is_some();
+    /// Write a capsule owned by `tenant`.
+    ///
+    /// It deduplicates only against the tenant's own content, and the write is
+    /// refused with a [`QuotaError`](crate::QuotaError) if it would take the
+    /// tenant past its hard quota.
+    #[instrument(skip(self, data, policy), fields(%tenant, bytes = data.len()))]
+    pub fn write_capsule_for_tenant(
+        &self,
+        tenant: &TenantId,
+        data: &[u8],
+        policy: &Policy,
+    ) -> Result<CapsuleId> {
+        if self.uses_modular() {
+            anyhow::bail!("tenant namespaces are not supported by the modular pipeline");
+        }
+        let mut session = self.open_tenant_session(policy, Some(tenant.clone()))?;
+        for chunk in segment_chunks(policy, data) {
+            if let Err(err) = self.stage_chunk(&mut session, chunk) {
+                self.abandon_session(session);
+                return Err(err);
+            }
+        }
+        self.seal_session(session)
+    }
 
-        // Split into segments, compress, deduplicate, and encrypt
-        for (index, chunk) in data.chunks(SEGMENT_SIZE).enumerate() {
-            total_original_size += chunk.len() as u64;
+    /// Streaming counterpart of [`write_capsule_for_tenant`](Self::write_capsule_for_tenant).
+    pub fn capsule_writer_for_tenant(
+        &self,
+        tenant: &TenantId,
+        policy: &Policy,
+    ) -> Result<CapsuleWriter<'_>> {
+        CapsuleWriter::new(self, policy, Some(tenant.clone()))
+    }
 
-            // Step 1: Compress the segment based on policy
-            let (compressed_data, comp_result) = compress_segment(chunk, &policy.compression)
-                .map_err(|err| map_compression_error(index, err))?;
-            total_compressed_size += comp_result.compressed_size as u64;
+    /// Logical and physical space used by `tenant`.
+    pub fn tenant_usage(&self, tenant: &TenantId) -> Result<TenantUsage> {
+        let capsules = self.registry.tenant_capsules(tenant);
+        let mut segments: Vec<SegmentId> = Vec::new();
+        for capsule in &capsules {
+            segments.extend(&capsule.segments);
+            for version in self.registry.list_versions(capsule.id)? {
+                if version != capsule.version {
+                    segments.extend(self.registry.capsule_at(capsule.id, version)?.segments);
+                }
+            }
+        }
+        segments.retain(|seg_id| !seg_id.is_hole());
+        segments.sort_by_key(|seg_id| seg_id.0);
+        segments.dedup();
+
+        let mut physical_bytes = 0;
+        for seg_id in segments {
+            let segment = self
+                .nvram
+                .get_segment_metadata(seg_id)
+                .map_err(|err| map_nvram_error("get_segment_metadata", err))?;
+            physical_bytes += segment.len as u64;
+        }
+        Ok(TenantUsage {
+            capsules: capsules.len(),
+            logical_bytes: capsules.iter().map(|capsule| capsule.size).sum(),
+            physical_bytes,
+        })
+    }
 
-            // Step 2: Hash the compressed data for deduplication
-            let content_hash = hash_content(compressed_data.as_ref());
+    /// Read a capsule back one segment at a time.
+    pub fn capsule_reader(&self, id: CapsuleId) -> Result<CapsuleReader<'_>> {
+        CapsuleReader::new(self, id)
+    }
 
-            // Step 3: Encrypt if enabled (before dedup check)
-            let mut encryption_meta = None;
-            #[cfg(feature = "advanced-security")]
-            let mut hybrid_state: Option<HybridKeyMaterial> = None;
-            let final_data = if encryption_enabled {
-                let km = self.key_manager.as_ref().unwrap();
-                let mut km = km.lock().unwrap();
-                let key_version = km.current_version();
-                let key_pair = km.get_key(key_version)?;
+    /// Whether reads and writes are being served by the modular pipeline,
+    /// which keeps no version history and cannot rewrite a capsule in place.
+    pub fn uses_modular(&self) -> bool {
+        #[cfg(feature = "modular_pipeline")]
+        if self.modular.is_some() && self.runtime.is_some() {
+            return true;
+        }
+        false
+    }
 
-                #[cfg(feature = "advanced-security")]
-                let mut derived_pair: Option<XtsKeyPair> = None;
-                #[cfg(feature = "advanced-security")]
-                if policy.crypto_profile == CryptoProfile::HybridKyber {
-                    if let Some(manager) = &self.mlkem_manager {
-                        match manager.wrap_xts_key(
-                            policy.crypto_profile,
-                            &collect_base_material((key_pair.key1(), key_pair.key2())),
-                            &capsule_id,
-                            SegmentId(index as u64),
-                            &content_hash,
-                        ) {
-                            Ok(Some(material)) => {
-                                derived_pair = Some(XtsKeyPair::from_bytes(material.wrapped_key));
-                                hybrid_state = Some(material);
-                            }
-                            Ok(None) => {}
-                            Err(err) => warn!(error = %err, "mlkem key wrapping failed"),
-                        }
-                    } else {
-                        warn!("policy requested hybrid crypto but ML-KEM manager is unavailable");
-                    }
-                }
+    pub(crate) fn open_session(&self, policy: &Policy) -> Result<WriteSession> {
+        self.open_tenant_session(policy, None)
+    }
 
-                #[allow(unused_mut)]
-                let mut tweak = derive_tweak_from_hash(content_hash.as_str().as_bytes());
-                #[cfg(feature = "advanced-security")]
-                if let Some(material) = &hybrid_state {
-                    tweak = material.nonce.mix_with(tweak);
-                }
+    pub(crate) fn open_tenant_session(
+        &self,
+        policy: &Policy,
+        tenant: Option<TenantId>,
+    ) -> Result<WriteSession> {
+        self.begin_session(CapsuleId::new(), policy, tenant, Vec::new())
+    }
 
-                #[cfg(feature = "advanced-security")]
-                let pair_for_use = derived_pair
-                    .as_ref()
-                    .map(|pair| pair as &XtsKeyPair)
-                    .unwrap_or(key_pair);
-                #[cfg(not(feature = "advanced-security"))]
-                let pair_for_use = key_pair;
-
-                let (ciphertext, mut enc_meta) =
-                    encrypt_segment(compressed_data.as_ref(), pair_for_use, key_version, tweak)?;
-
-                let mac_tag = compute_mac(
-                    &ciphertext,
-                    &enc_meta,
-                    pair_for_use.key1(),
-                    pair_for_use.key2(),
-                )?;
-                enc_meta.set_integrity_tag(mac_tag);
-                encryption_meta = Some(enc_meta);
-                Cow::Owned(ciphertext)
-            } else {
-                compressed_data
-            };
+    /// Session writing capsule `capsule_id`, which already holds `data_keys`.
+    fn begin_session(
+        &self,
+        capsule_id: CapsuleId,
+        policy: &Policy,
+        tenant: Option<TenantId>,
+        data_keys: Vec<WrappedDataKey>,
+    ) -> Result<WriteSession> {
+        let hard_quota = tenant
+            .as_ref()
+            .and_then(|tenant| self.registry.hard_quota_usage(tenant, capsule_id));
 
-            // Step 4: Check if this content already exists (if dedup enabled)
-            let (seg_id, was_deduped) = if policy.dedupe {
-                if let Some(existing_seg_id) = self.registry.lookup_content(&content_hash) {
-                    // Content exists! Reuse the segment
-                    let updated_segment = self
-                        .nvram
-                        .increment_refcount(existing_seg_id)
-                        .map_err(|err| map_nvram_error("increment_refcount", err))?;
-                    let saved_bytes = updated_segment.len as u64;
+        let encryption_enabled = policy.encryption.is_enabled() && self.key_manager.is_some();
+        Ok(WriteSession {
+            capsule_id,
+            policy: policy.clone(),
+            encryption_enabled,
+            keys: CapsuleKeys::new(capsule_id, tenant.clone(), encryption_enabled, data_keys),
+            transaction: None,
+            staged_content: HashMap::new(),
+            dedupe_increments: Vec::new(),
+            new_segments: Vec::new(),
+            flushed_segments: 0,
+            intent_journaled: false,
+            base_version: None,
+            index_base: 0,
+            registrations: Vec::new(),
+            sketches: Vec::new(),
+            dictionary: self.dictionary_for(policy, tenant.as_ref()),
+            segment_ids: Vec::new(),
+            dedup_stats: DedupStats::new(),
+            total_original_size: 0,
+            total_compressed_size: 0,
+            tenant,
+            hard_quota,
+        })
+    }
 
-                    dedup_stats.add_segment(saved_bytes, true);
+    /// Session rewriting the existing `capsule` under `policy`, in its tenant.
+    fn open_overwrite_session(&self, capsule: &Capsule, policy: &Policy) -> Result<WriteSession> {
+        let mut session = self.begin_session(
+            capsule.id,
+            policy,
+            capsule.tenant.clone(),
+            capsule.data_keys.clone(),
+        )?;
+        session.base_version = Some(capsule.version);
+        Ok(session)
+    }
 
-                    info!(
-                        segment = existing_seg_id.0,
-                        saved_bytes,
-                        ref_count = updated_segment.ref_count,
-                        "dedup hit: reusing segment"
-                    );
-                    #[cfg(feature = "advanced-security")]
-                    self.audit_event(common::Event::DedupHit {
-                        segment_id: existing_seg_id,
-                        capsule_id,
-                        content_hash: content_hash.clone(),
-                    });
-                    (existing_seg_id, true)
-                } else {
-                    // New content - allocate and write
-                    let new_seg_id = self.registry.alloc_segment();
+    /// Compress, deduplicate and encrypt one segment's worth of data into the session.
+    pub(crate) fn stage_chunk(&self, session: &mut WriteSession, chunk: &[u8]) -> Result<()> {
+        let index = session.index_base + session.segment_ids.len();
+        if let (Some(tenant), Some((used, hard))) = (&session.tenant, session.hard_quota) {
+            // Fail early rather than stage a write that begin_write will refuse.
+            check_hard_quota(
+                tenant,
+                used,
+                session.total_original_size + chunk.len() as u64,
+                hard,
+            )?;
+        }
+        session.total_original_size += chunk.len() as u64;
 
-                    // Write to NVRAM
-                    let mut segment = self
-                        .nvram
-                        .append(new_seg_id, final_data.as_ref())
-                        .map_err(|err| map_nvram_error("append", err))?;
+        if is_zero_chunk(chunk) {
+            session
+                .segment_ids
+                .push(SegmentId::hole(chunk.len() as u64));
+            return Ok(());
+        }
 
-                    // Update segment metadata - compression
-                    segment.compressed = comp_result.compressed;
-                    segment.compression_algo = comp_result.algorithm.clone();
-                    segment.content_hash = Some(content_hash.clone());
-                    segment.ref_count = 1;
-                    segment.deduplicated = false;
-
-                    // Update segment metadata - encryption
-                    if let Some(ref enc_meta) = encryption_meta {
-                        segment.encrypted = true;
-                        segment.encryption_version = enc_meta.encryption_version;
-                        segment.key_version = enc_meta.key_version;
-                        segment.tweak_nonce = enc_meta.tweak_nonce;
-                        segment.integrity_tag = enc_meta.integrity_tag;
-                    }
-                    #[cfg(feature = "advanced-security")]
-                    if let Some(material) = hybrid_state.as_ref() {
-                        segment.pq_ciphertext = Some(serialize_ciphertext(&material.ciphertext));
-                        segment.pq_nonce = Some(material.nonce);
-                    }
+        // Step 1: Fingerprint the plaintext so dedup doesn't depend on the codec
+        let content_hash = hash_content(chunk);
+
+        // Step 2: New content resembling a committed segment is stored as a delta against it
+        let mut sketch = None;
+        let mut delta = None;
+        if session.policy.delta_compression && !self.is_known_content(session, &content_hash) {
+            sketch = Sketch::of(chunk);
+            delta = sketch.as_ref().and_then(|sketch| {
+                self.delta_against_similar(
+                    session.tenant.as_ref(),
+                    &mut session.keys,
+                    sketch,
+                    chunk,
+                )
+            });
+        }
+        let payload = delta.as_ref().map_or(chunk, |(_, delta)| delta.as_slice());
+
+        // Step 3: Compress the segment based on policy
+        let dictionary = session.dictionary.as_ref();
+        let (compressed_data, comp_result) = compress_segment_with_dictionary(
+            payload,
+            &session.policy.compression,
+            dictionary.map(|(_, bytes)| bytes.as_slice()),
+        )
+        .map_err(|err| map_compression_error(index, err))?;
+        session.total_compressed_size += comp_result.compressed_size as u64;
+
+        // Step 4: Encrypt if enabled (before dedup check)
+        let mut encryption_meta = None;
+        #[cfg(feature = "advanced-security")]
+        let mut hybrid_state: Option<HybridKeyMaterial> = None;
+        // Hybrid material is bound to the segment's ID, so it is allocated up front.
+        #[cfg(feature = "advanced-security")]
+        let mut hybrid_seg_id: Option<SegmentId> = None;
+        let final_data = if session.encryption_enabled {
+            let sealing = self.sealing_key(&mut session.keys)?;
+            let key_version = sealing.id.generation;
+            let key_pair = &sealing.pair;
 
-                    // Save updated metadata back to NVRAM
-                    self.nvram
-                        .update_segment_metadata(new_seg_id, segment)
-                        .map_err(|err| map_nvram_error("update_segment_metadata", err))?;
+            #[cfg(feature = "advanced-security")]
+            let mut derived_pair: Option<XtsKeyPair> = None;
+            #[cfg(feature = "advanced-security")]
+            if session.policy.crypto_profile == CryptoProfile::HybridKyber {
+                if let Some(manager) = &self.mlkem_manager {
+                    let seg_id = self.registry.alloc_segment();
+                    match manager.wrap_xts_key(
+                        session.policy.crypto_profile,
+                        &collect_base_material((key_pair.key1(), key_pair.key2())),
+                        &sealing.id.capsule,
+                        seg_id,
+                        &content_hash,
+                    ) {
+                        Ok(Some(material)) => {
+                            derived_pair = Some(XtsKeyPair::from_bytes(material.wrapped_key));
+                            hybrid_state = Some(material);
+                            hybrid_seg_id = Some(seg_id);
+                        }
+                        Ok(None) => {}
+                        Err(err) => warn!(error = %err, "mlkem key wrapping failed"),
+                    }
+                } else {
+                    warn!("policy requested hybrid crypto but ML-KEM manager is unavailable");
+                }
+            }
 
-                    // Register in content store
-                    self.registry
-                        .register_content(content_hash, new_seg_id)
-                        .map_err(|err| map_registry_error("register_content", err))?;
+            #[allow(unused_mut)]
+            let mut tweak = derive_tweak_from_hash(content_hash.as_str().as_bytes());
+            #[cfg(feature = "advanced-security")]
+            if let Some(material) = &hybrid_state {
+                tweak = material.nonce.mix_with(tweak);
+            }
 
-                    dedup_stats.add_segment(final_data.len() as u64, false);
+            #[cfg(feature = "advanced-security")]
+            let pair_for_use = derived_pair
+                .as_ref()
+                .map(|pair| pair as &XtsKeyPair)
+                .unwrap_or(key_pair);
+            #[cfg(not(feature = "advanced-security"))]
+            let pair_for_use = key_pair;
+
+            let (ciphertext, mut enc_meta) =
+                encrypt_segment(compressed_data.as_ref(), pair_for_use, key_version, tweak)?;
+
+            let mac_tag = compute_mac(
+                &ciphertext,
+                &enc_meta,
+                pair_for_use.key1(),
+                pair_for_use.key2(),
+            )?;
+            enc_meta.set_integrity_tag(mac_tag);
+            encryption_meta = Some(enc_meta);
+            Cow::Owned(ciphertext)
+        } else {
+            compressed_data
+        };
 
-                    (new_seg_id, false)
+        // Step 5: Reuse staged or committed content (if dedup enabled)
+        let reused = if session.policy.dedupe {
+            if let Some(&staged_seg_id) = session.staged_content.get(&content_hash) {
+                let mut saved_bytes = 0u64;
+                if let Some(transaction) = session
+                    .transaction
+                    .as_mut()
+                    .filter(|transaction| transaction.pending_segment(staged_seg_id).is_some())
+                {
+                    transaction
+                        .with_segment_mut(staged_seg_id, |segment| {
+                            segment.ref_count = segment.ref_count.saturating_add(1);
+                            segment.deduplicated = true;
+                            saved_bytes = segment.len as u64;
+                        })
+                        .map_err(|err| map_nvram_error("with_segment_mut", err))?;
+                } else {
+                    // Already flushed by a streaming write; bump it like a committed hit.
+                    let flushed = self
+                        .nvram
+                        .increment_refcount(staged_seg_id)
+                        .map_err(|err| map_nvram_error("increment_refcount", err))?;
+                    session.dedupe_increments.push(staged_seg_id);
+                    saved_bytes = flushed.len as u64;
                 }
+                session.dedup_stats.add_segment(saved_bytes, true);
+                info!(
+                    segment = staged_seg_id.0,
+                    saved_bytes, "dedup hit: reusing staged segment"
+                );
+                Some(staged_seg_id)
+            } else if let Some(existing_seg_id) = self
+                .registry
+                .lookup_content_in(session.tenant.as_ref(), &content_hash)
+                .filter(|seg_id| self.share_segment(&mut session.keys, *seg_id))
+            {
+                // Content exists! Reuse the segment
+                let updated_segment = self
+                    .nvram
+                    .increment_refcount(existing_seg_id)
+                    .map_err(|err| map_nvram_error("increment_refcount", err))?;
+                session.dedupe_increments.push(existing_seg_id);
+                let saved_bytes = updated_segment.len as u64;
+
+                session.dedup_stats.add_segment(saved_bytes, true);
+
+                info!(
+                    segment = existing_seg_id.0,
+                    saved_bytes,
+                    ref_count = updated_segment.ref_count,
+                    "dedup hit: reusing segment"
+                );
+                #[cfg(feature = "advanced-security")]
+                self.audit_event(common::Event::DedupHit {
+                    segment_id: existing_seg_id,
+                    capsule_id: session.capsule_id,
+                    content_hash: content_hash.clone(),
+                });
+                Some(existing_seg_id)
             } else {
-                // Dedup disabled - always write new segment
+                None
+            }
+        } else {
+            None
+        };
+
+        let (seg_id, was_deduped) = match reused {
+            Some(seg_id) => (seg_id, true),
+            None => {
+                // New content - allocate and stage in the transaction
+                #[cfg(feature = "advanced-security")]
+                let new_seg_id = hybrid_seg_id.unwrap_or_else(|| self.registry.alloc_segment());
+                #[cfg(not(feature = "advanced-security"))]
                 let new_seg_id = self.registry.alloc_segment();
 
                 let mut segment = self
-                    .nvram
-                    .append(new_seg_id, final_data.as_ref())
-                    .map_err(|err| map_nvram_error("append", err))?;
+                    .staging_transaction(&mut session.transaction)?
+                    .append_segment(new_seg_id, final_data.as_ref())
+                    .map_err(|err| map_nvram_error("append_segment", err))?;
+
+                // Update segment metadata - compression
+                segment.logical_len = chunk.len() as u32;
                 segment.compressed = comp_result.compressed;
                 segment.compression_algo = comp_result.algorithm.clone();
                 segment.ref_count = 1;
                 segment.deduplicated = false;
+                if comp_result.dictionary {
+                    segment.dictionary = dictionary.map(|(dictionary, _)| *dictionary);
+                }
 
                 // Update segment metadata - encryption
                 if let Some(ref enc_meta) = encryption_meta {
@@ -720,6 +1147,7 @@ impl WritePipeline {
                     segment.key_version = enc_meta.key_version;
                     segment.tweak_nonce = enc_meta.tweak_nonce;
                     segment.integrity_tag = enc_meta.integrity_tag;
+                    segment.data_key = session.keys.sealing.as_ref().map(|key| key.id);
                 }
                 #[cfg(feature = "advanced-security")]
                 if let Some(material) = hybrid_state.as_ref() {
@@ -727,102 +1155,476 @@ impl WritePipeline {
                     segment.pq_nonce = Some(material.nonce);
                 }
 
-                // Save updated metadata back to NVRAM
-                self.nvram
-                    .update_segment_metadata(new_seg_id, segment)
-                    .map_err(|err| map_nvram_error("update_segment_metadata", err))?;
+                // A delta keeps its base alive. The reference is taken now and,
+                // like a dedup hit, given back if the write fails.
+                if let Some((base, delta)) = &delta {
+                    self.nvram
+                        .increment_refcount(*base)
+                        .map_err(|err| map_nvram_error("increment_refcount", err))?;
+                    session.dedupe_increments.push(*base);
+                    segment.delta_base = Some(*base);
+                    info!(
+                        segment = new_seg_id.0,
+                        base = base.0,
+                        delta_len = delta.len(),
+                        "segment stored as a delta"
+                    );
+                } else if let Some(sketch) = sketch {
+                    segment.sketch = Some(sketch);
+                    session.sketches.push((sketch, new_seg_id));
+                }
+
+                // Content is only published in the dedup store once the write commits
+                if session.policy.dedupe {
+                    segment.content_hash = Some(content_hash.clone());
+                    session
+                        .staged_content
+                        .insert(content_hash.clone(), new_seg_id);
+                    session.registrations.push((content_hash, new_seg_id));
+                }
+
+                self.staging_transaction(&mut session.transaction)?
+                    .set_segment_metadata(new_seg_id, segment)
+                    .map_err(|err| map_nvram_error("set_segment_metadata", err))?;
 
-                dedup_stats.add_segment(final_data.len() as u64, false);
+                session.new_segments.push(new_seg_id);
+                session
+                    .dedup_stats
+                    .add_segment(final_data.len() as u64, false);
 
                 (new_seg_id, false)
-            };
+            }
+        };
 
-            segment_ids.push(seg_id);
+        session.segment_ids.push(seg_id);
 
-            // Log stats
-            if !was_deduped {
-                if encryption_enabled {
-                    if let Some(meta) = encryption_meta.as_ref() {
-                        info!(
-                            segment = seg_id.0,
-                            key_version = meta.key_version,
-                            "segment encrypted"
-                        );
-                    } else {
-                        warn!(
-                            segment = seg_id.0,
-                            "missing encryption metadata after encryption"
-                        );
-                    }
-                }
-                if comp_result.compressed {
+        // Log stats
+        if !was_deduped {
+            if session.encryption_enabled {
+                if let Some(meta) = encryption_meta.as_ref() {
                     info!(
                         segment = seg_id.0,
-                        ratio = comp_result.ratio(),
-                        original = comp_result.original_size,
-                        compressed = comp_result.compressed_size,
-                        algorithm = %comp_result.algorithm,
-                        ?comp_result.reason,
-                        "segment compressed"
+                        key_version = meta.key_version,
+                        "segment encrypted"
                     );
-                } else if let Some(reason) = &comp_result.reason {
+                } else {
                     warn!(
                         segment = seg_id.0,
-                        ?reason,
-                        "segment stored without compression"
+                        "missing encryption metadata after encryption"
                     );
                 }
             }
+            if comp_result.compressed {
+                info!(
+                    segment = seg_id.0,
+                    ratio = comp_result.ratio(),
+                    original = comp_result.original_size,
+                    compressed = comp_result.compressed_size,
+                    algorithm = %comp_result.algorithm,
+                    ?comp_result.reason,
+                    "segment compressed"
+                );
+            } else if let Some(reason) = &comp_result.reason {
+                warn!(
+                    segment = seg_id.0,
+                    ?reason,
+                    "segment stored without compression"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a dedup hit would store nothing new for `hash`.
+    fn is_known_content(&self, session: &WriteSession, hash: &ContentHash) -> bool {
+        session.policy.dedupe
+            && (session.staged_content.contains_key(hash)
+                || self
+                    .registry
+                    .lookup_content_in(session.tenant.as_ref(), hash)
+                    .is_some())
+    }
+
+    /// A committed segment resembling `chunk`, and the delta rebuilding `chunk` from it.
+    fn delta_against_similar(
+        &self,
+        tenant: Option<&TenantId>,
+        keys: &mut CapsuleKeys,
+        sketch: &Sketch,
+        chunk: &[u8],
+    ) -> Option<(SegmentId, Vec<u8>)> {
+        let base_id = self.registry.lookup_similar_in(tenant, sketch)?;
+        let base = self.nvram.get_segment_metadata(base_id).ok()?;
+        // Bases have to decode on their own: no chains, no capsule-bound keys.
+        if base.delta_base.is_some() || base.pq_ciphertext.is_some() {
+            return None;
+        }
+        if !self.adopt_data_key(keys, &base) {
+            return None;
+        }
+        let base_data = match self.decode_payload(None, &keys.wrapped, 0, &base) {
+            Ok(data) => data,
+            Err(err) => {
+                warn!(segment = base_id.0, error = %err, "failed to read delta base");
+                return None;
+            }
+        };
+        encode_delta(&base_data, chunk).map(|delta| (base_id, delta))
+    }
+
+    /// Generate data key `id`, wrapped under the current master key version.
+    fn new_data_key(&self, id: DataKeyRef) -> Result<(SealingKey, WrappedDataKey)> {
+        let km = self.key_manager.as_ref().ok_or_else(|| {
+            anyhow::anyhow!("Cannot generate data key: key manager not initialized")
+        })?;
+        let pair = generate_data_key()?;
+        let mut km = km.lock().unwrap();
+        let kek_version = km.current_version();
+        let (wrapped, tag) = wrap_data_key(&pair, km.get_key(kek_version)?, &id.wrap_context())?;
+        drop(km);
+
+        self.data_keys.lock().unwrap().insert(id, pair.clone());
+        let wrapped = WrappedDataKey {
+            id,
+            kek_version,
+            wrapped: wrapped.to_vec(),
+            tag,
+        };
+        Ok((SealingKey { id, pair }, wrapped))
+    }
+
+    /// Unwrap `key` under the master key version it was wrapped with.
+    fn open_data_key(&self, key: &WrappedDataKey) -> Result<XtsKeyPair> {
+        let km = self.key_manager.as_ref().ok_or_else(|| {
+            anyhow::anyhow!("Cannot unwrap data key: key manager not initialized")
+        })?;
+        let mut km = km.lock().unwrap();
+        let kek = km.get_key(key.kek_version)?;
+        let pair = unwrap_data_key(&key.wrapped, &key.tag, kek, &key.id.wrap_context())?;
+        drop(km);
+
+        self.data_keys.lock().unwrap().insert(key.id, pair.clone());
+        Ok(pair)
+    }
+
+    /// Data key `id`, unwrapped from `keys` or else from whichever capsule
+    /// record still carries it.
+    fn data_key_pair(&self, keys: &[WrappedDataKey], id: DataKeyRef) -> Result<XtsKeyPair> {
+        if let Some(pair) = self.data_keys.lock().unwrap().get(&id) {
+            return Ok(pair.clone());
+        }
+        match keys.iter().find(|key| key.id == id) {
+            Some(key) => self.open_data_key(key),
+            None => match self.registry.find_data_key(id) {
+                Some(key) => self.open_data_key(&key),
+                None => anyhow::bail!("data key {:?} is unavailable", id),
+            },
+        }
+    }
+
+    /// Key new segments of the capsule are sealed under: its newest data key,
+    /// or a new one if it has none yet or is rotating.
+    fn sealing_key(&self, keys: &mut CapsuleKeys) -> Result<SealingKey> {
+        if let Some(sealing) = &keys.sealing {
+            return Ok(sealing.clone());
+        }
+        let newest = keys
+            .wrapped
+            .iter()
+            .filter(|key| key.id.capsule == keys.capsule)
+            .max_by_key(|key| key.id.generation)
+            .map(|key| key.id);
+        let sealing = match newest {
+            Some(id) if !keys.rotating => SealingKey {
+                id,
+                pair: self.data_key_pair(&keys.wrapped, id)?,
+            },
+            newest => {
+                let (sealing, wrapped) = self.new_data_key(DataKeyRef {
+                    capsule: keys.capsule,
+                    generation: newest.map_or(1, |id| id.generation + 1),
+                })?;
+                keys.wrapped.push(wrapped);
+                sealing
+            }
+        };
+        keys.sealing = Some(sealing.clone());
+        Ok(sealing)
+    }
+
+    /// Whether a stored segment may be shared by the capsule `keys` belong
+    /// to, which then carries the data key the segment is sealed under.
+    ///
+    /// Encrypted capsules refuse plaintext segments, and keys are only copied
+    /// from records in the capsule's own dedup partition. While rotating,
+    /// segments sealed under the capsule's older keys or directly under the
+    /// master key are refused so that they get re-sealed.
+    fn adopt_data_key(&self, keys: &mut CapsuleKeys, segment: &Segment) -> bool {
+        if keys.encrypted && !segment.encrypted {
+            return false;
+        }
+        let Some(id) = segment.data_key else {
+            return !(keys.rotating && segment.encrypted);
+        };
+        let sealing = keys.sealing.as_ref().map(|key| key.id);
+        if keys.rotating && id.capsule == keys.capsule && Some(id) != sealing {
+            return false;
+        }
+        if keys.wrapped.iter().any(|key| key.id == id) {
+            return true;
+        }
+        match self.registry.find_data_key_in(keys.tenant.as_ref(), id) {
+            Some(key) => {
+                keys.wrapped.push(key);
+                true
+            }
+            None => {
+                warn!(segment = segment.id.0, key = ?id, "segment's data key is unavailable");
+                false
+            }
+        }
+    }
+
+    /// [`adopt_data_key`](Self::adopt_data_key) for committed segment `seg_id`.
+    fn share_segment(&self, keys: &mut CapsuleKeys, seg_id: SegmentId) -> bool {
+        match self.nvram.get_segment_metadata(seg_id) {
+            Ok(segment) => self.adopt_data_key(keys, &segment),
+            // Taking the reference reports the missing segment.
+            Err(_) => true,
+        }
+    }
+
+    /// The dictionary `policy` compresses against for `tenant`: the latest
+    /// version of the tenant's dictionary capsule, if one has been trained.
+    fn dictionary_for(
+        &self,
+        policy: &Policy,
+        tenant: Option<&TenantId>,
+    ) -> Option<(DictionaryRef, Arc<Vec<u8>>)> {
+        if !policy.zstd_dictionary || !matches!(policy.compression, CompressionPolicy::Zstd { .. })
+        {
+            return None;
+        }
+        let capsule = self.registry.dictionary(tenant)?;
+        let dictionary = DictionaryRef {
+            capsule,
+            version: self.registry.lookup(capsule).ok()?.version,
+        };
+        match self.load_dictionary(dictionary) {
+            Ok(bytes) => Some((dictionary, bytes)),
+            Err(err) => {
+                warn!(capsule = %capsule.as_uuid(), error = %err, "failed to load zstd dictionary");
+                None
+            }
         }
-        // Update dedup stats on capsule
-        if dedup_stats.bytes_saved > 0 {
+    }
+
+    /// Bytes of a trained dictionary, read from its capsule on first use.
+    pub(crate) fn load_dictionary(&self, dictionary: DictionaryRef) -> Result<Arc<Vec<u8>>> {
+        if let Some(bytes) = self.dictionaries.lock().unwrap().get(&dictionary) {
+            return Ok(Arc::clone(bytes));
+        }
+        let capsule = self
+            .registry
+            .capsule_at(dictionary.capsule, dictionary.version)?;
+        let bytes = Arc::new(self.decode_capsule(&capsule)?);
+        self.dictionaries
+            .lock()
+            .unwrap()
+            .insert(dictionary, Arc::clone(&bytes));
+        Ok(bytes)
+    }
+
+    /// The NVRAM transaction a session stages new segments in, begun on first
+    /// use. Nothing staged becomes visible until the registry commits the
+    /// write intent journaled on flush or seal.
+    fn staging_transaction<'s>(
+        &self,
+        slot: &'s mut Option<NvramTransaction>,
+    ) -> Result<&'s mut NvramTransaction> {
+        let transaction = match slot.take() {
+            Some(transaction) => transaction,
+            None => self
+                .nvram
+                .begin_transaction()
+                .map_err(|err| map_nvram_error("begin_transaction", err))?,
+        };
+        Ok(slot.insert(transaction))
+    }
+
+    /// Persist the segments staged so far so a long streaming write does not
+    /// hold them all in memory.
+    ///
+    /// The intent is journaled as partial before the segments reach NVRAM, so a
+    /// crash before sealing always rolls the write back.
+    pub(crate) fn flush_session(&self, session: &mut WriteSession) -> Result<()> {
+        let unflushed = session.new_segments[session.flushed_segments..].to_vec();
+        if unflushed.is_empty() {
+            return Ok(());
+        }
+
+        if session.flushed_segments == 0 {
+            let mut intent =
+                WriteIntent::new(session.capsule_id, 0, Vec::new(), session.policy.clone())
+                    .map_err(|err| map_registry_error("write_intent", err))?;
+            intent.partial = true;
+            // An overwrite claims the capsule for as long as it streams.
+            intent.base_version = session.base_version;
+            intent.capsule.tenant = session.tenant.clone();
+            intent.capsule.data_keys = session.keys.wrapped.clone();
+            intent.new_segments = unflushed.clone();
             self.registry
-                .add_deduped_bytes(capsule_id, dedup_stats.bytes_saved)
-                .map_err(|err| map_registry_error("add_deduped_bytes", err))?;
+                .begin_write(intent)
+                .map_err(|err| map_registry_error("begin_write", err))?;
+            session.intent_journaled = true;
+        } else {
+            self.registry
+                .extend_write(session.capsule_id, &unflushed)
+                .map_err(|err| map_registry_error("extend_write", err))?;
+        }
+
+        if let Some(mut transaction) = session.transaction.take() {
+            transaction
+                .commit()
+                .map_err(|err| map_nvram_error("commit", err))?;
         }
+        session.flushed_segments = session.new_segments.len();
+        Ok(())
+    }
+
+    /// Journal the final intent, commit the staged segments and publish the capsule.
+    pub(crate) fn seal_session(&self, session: WriteSession) -> Result<CapsuleId> {
+        let mut session = session;
+        let capsule_id = session.capsule_id;
+        let size = session.total_original_size;
 
         #[cfg(feature = "advanced-security")]
-        let segments_written = segment_ids.len();
-        self.registry
-            .create_capsule_with_segments(
-                capsule_id,
-                data.len() as u64,
-                segment_ids,
-                policy_snapshot.clone(),
-            )
-            .map_err(|err| map_registry_error("create_capsule_with_segments", err))?;
+        let segments_written = session.segment_ids.len();
+
+        let intent = match WriteIntent::new(
+            capsule_id,
+            size,
+            std::mem::take(&mut session.segment_ids),
+            session.policy.clone(),
+        ) {
+            Ok(mut intent) => {
+                intent.capsule.deduped_bytes = session.dedup_stats.bytes_saved;
+                intent.capsule.tenant = session.tenant.clone();
+                intent
+            }
+            Err(err) => {
+                self.discard_session(&mut session);
+                return Err(map_registry_error("write_intent", err));
+            }
+        };
+        self.publish_session(&mut session, intent)?;
 
         #[cfg(feature = "advanced-security")]
         self.audit_event(common::Event::CapsuleCreated {
             capsule_id,
-            size: data.len() as u64,
+            size,
             segments: segments_written,
-            policy: policy_snapshot.clone(),
+            policy: session.policy.clone(),
         });
 
         // Print summary stats
-        let compression_ratio = if total_compressed_size > 0 {
-            total_original_size as f32 / total_compressed_size as f32
+        let compression_ratio = if session.total_compressed_size > 0 {
+            session.total_original_size as f32 / session.total_compressed_size as f32
         } else {
             1.0
         };
 
-        let encryption_status = if encryption_enabled {
+        let encryption_status = if session.encryption_enabled {
             " 🔐 encrypted"
         } else {
             ""
         };
 
-        info!(
-            capsule = %capsule_id.as_uuid(),
-            ratio = compression_ratio,
-            dedupe_hits = dedup_stats.deduped_segments,
-            bytes_saved = dedup_stats.bytes_saved,
-            encryption = %encryption_status,
-            "capsule write complete"
-        );
+        info!(
+            capsule = %capsule_id.as_uuid(),
+            ratio = compression_ratio,
+            dedupe_hits = session.dedup_stats.deduped_segments,
+            bytes_saved = session.dedup_stats.bytes_saved,
+            encryption = %encryption_status,
+            "capsule write complete"
+        );
+
+        Ok(capsule_id)
+    }
+
+    /// Journal `intent`, commit the session's staged segments and publish the capsule.
+    fn publish_session(&self, session: &mut WriteSession, intent: WriteIntent) -> Result<()> {
+        let capsule_id = intent.capsule_id();
+        let mut intent = intent;
+        intent.new_segments = session.new_segments.clone();
+        intent.registrations = std::mem::take(&mut session.registrations);
+        intent.capsule.data_keys = session.keys.wrapped.clone();
+
+        // Journal the intent first so a crash after the NVRAM commit can be
+        // rolled forward, and one before it rolled back.
+        let journaled = if session.intent_journaled {
+            self.registry.seal_write(intent)
+        } else {
+            self.registry.begin_write(intent)
+        };
+        if let Err(err) = journaled {
+            self.discard_session(session);
+            return Err(map_registry_error("begin_write", err));
+        }
+        session.intent_journaled = true;
+
+        if let Some(mut transaction) = session.transaction.take() {
+            if let Err(err) = transaction.commit() {
+                self.discard_session(session);
+                return Err(map_nvram_error("commit", err));
+            }
+        }
+
+        self.registry
+            .commit_write(capsule_id)
+            .map_err(|err| map_registry_error("commit_write", err))?;
+
+        self.register_sketches(session.tenant.as_ref(), &session.sketches);
+        Ok(())
+    }
+
+    /// Offer committed segments as delta bases.
+    ///
+    /// Sketches only steer later writes toward deltas, so they are indexed
+    /// after the commit and failing to index one loses nothing else.
+    fn register_sketches(&self, tenant: Option<&TenantId>, sketches: &[(Sketch, SegmentId)]) {
+        for (sketch, seg_id) in sketches {
+            if let Err(err) = self
+                .registry
+                .register_similar_in(tenant.cloned(), sketch, *seg_id)
+            {
+                warn!(segment = seg_id.0, error = %err, "failed to index segment sketch");
+            }
+        }
+    }
+
+    /// Undo everything a failed or dropped write session did.
+    pub(crate) fn abandon_session(&self, session: WriteSession) {
+        let mut session = session;
+        self.discard_session(&mut session);
+    }
 
-        Ok(capsule_id)
+    fn discard_session(&self, session: &mut WriteSession) {
+        if let Some(mut transaction) = session.transaction.take() {
+            let _ = transaction.rollback();
+        }
+        self.release_dedupe_hits(&session.dedupe_increments);
+
+        if session.intent_journaled {
+            if let Err(err) = self.registry.abort_write(session.capsule_id) {
+                warn!(error = %err, "failed to journal aborted write");
+            }
+        }
+        for seg_id in &session.new_segments[..session.flushed_segments] {
+            if let Err(err) = self.nvram.remove_segment(*seg_id) {
+                warn!(segment = seg_id.0, error = %err, "failed to remove flushed segment");
+            }
+        }
     }
 
     #[cfg(feature = "pipeline_async")]
@@ -861,7 +1663,9 @@ impl WritePipeline {
         let capsule_id = CapsuleId::new();
 
         let encryption_enabled = policy.encryption.is_enabled() && self.key_manager.is_some();
-        let total_segments = data.len().div_ceil(SEGMENT_SIZE);
+        let dictionary = self.dictionary_for(policy, None);
+        let chunks = segment_chunks(policy, data);
+        let total_segments = chunks.len();
 
         if total_segments == 0 {
             self.registry
@@ -880,10 +1684,20 @@ impl WritePipeline {
             "async write pipeline start"
         );
 
+        // Workers seal segments under the capsule's data key, chosen up front.
+        let mut keys = CapsuleKeys::new(capsule_id, None, encryption_enabled, Vec::new());
+        let sealing = if encryption_enabled {
+            Some(self.sealing_key(&mut keys)?)
+        } else {
+            None
+        };
+
         let mut transaction = self.nvram.begin_transaction()?;
         let mut staged_content: HashMap<ContentHash, SegmentId> = HashMap::new();
         let mut dedupe_increments: Vec<SegmentId> = Vec::new();
         let mut pending_registrations: Vec<(ContentHash, SegmentId)> = Vec::new();
+        let mut new_segments: Vec<SegmentId> = Vec::new();
+        let mut pending_sketches: Vec<(Sketch, SegmentId)> = Vec::new();
 
         let (tx, mut rx) = mpsc::channel(std::cmp::max(1, total_segments));
         let semaphore = Arc::new(Semaphore::new(std::cmp::max(
@@ -893,11 +1707,21 @@ impl WritePipeline {
 
         let mut handles: Vec<JoinHandle<Result<()>>> = Vec::with_capacity(total_segments);
 
-        for (index, chunk) in data.chunks(SEGMENT_SIZE).enumerate() {
+        // All-zero chunks are recorded as holes in order below and never prepared.
+        let holes: Vec<Option<SegmentId>> = chunks
+            .iter()
+            .map(|chunk| is_zero_chunk(chunk).then(|| SegmentId::hole(chunk.len() as u64)))
+            .collect();
+
+        for (index, chunk) in chunks.into_iter().enumerate() {
+            if holes[index].is_some() {
+                continue;
+            }
             let permit = semaphore.clone().acquire_owned().await?;
             let tx = tx.clone();
             let policy_clone = policy.clone();
-            let key_manager = self.key_manager.clone();
+            let sealing = sealing.clone();
+            let dictionary_bytes = dictionary.as_ref().map(|(_, bytes)| Arc::clone(bytes));
 
             if chunk.len() > self.config.memory_limit_per_task {
                 anyhow::bail!(
@@ -914,7 +1738,7 @@ impl WritePipeline {
                 let _permit = permit;
 
                 let mut prepared = spawn_blocking(move || {
-                    prepare_segment(index, chunk_vec, policy_clone, key_manager)
+                    prepare_segment(index, chunk_vec, policy_clone, sealing, dictionary_bytes)
                 })
                 .await??;
 
@@ -964,6 +1788,12 @@ impl WritePipeline {
             ordered[idx] = Some(prepared);
 
             while next_index < total_segments {
+                if let Some(hole) = holes[next_index] {
+                    total_original_size += hole.hole_len().unwrap_or(0);
+                    segment_ids.push(hole);
+                    next_index += 1;
+                    continue;
+                }
                 let Some(next_prepared) = ordered[next_index].take() else {
                     break;
                 };
@@ -982,7 +1812,8 @@ impl WritePipeline {
                 match self.commit_segment(
                     next_prepared,
                     policy,
-                    encryption_enabled,
+                    &mut keys,
+                    dictionary.as_ref(),
                     &mut transaction,
                     &mut staged_content,
                 ) {
@@ -990,15 +1821,28 @@ impl WritePipeline {
                         let commit_duration = commit_start.elapsed();
                         commit_total += commit_duration;
 
+                        if let WriteDisposition::NewSegment { .. }
+                        | WriteDisposition::NewDelta { .. } = disposition
+                        {
+                            if let Some(hash) = registered_hash {
+                                pending_registrations.push((hash, seg_id));
+                            }
+                            new_segments.push(seg_id);
+                            new_segment_count += 1;
+                            dedup_stats.add_segment(bytes_tracked, false);
+                        }
+
                         let disposition_label = match disposition {
-                            WriteDisposition::NewSegment => {
-                                if let Some(hash) = registered_hash {
-                                    pending_registrations.push((hash, seg_id));
+                            WriteDisposition::NewSegment { sketch } => {
+                                if let Some(sketch) = sketch {
+                                    pending_sketches.push((sketch, seg_id));
                                 }
-                                new_segment_count += 1;
-                                dedup_stats.add_segment(bytes_tracked, false);
                                 "new"
                             }
+                            WriteDisposition::NewDelta { base } => {
+                                dedupe_increments.push(base);
+                                "delta"
+                            }
                             WriteDisposition::ReusedPersistent => {
                                 dedupe_increments.push(seg_id);
                                 dedup_stats.add_segment(bytes_tracked, true);
@@ -1032,6 +1876,16 @@ impl WritePipeline {
 
         drop(rx);
 
+        // Holes after the last prepared segment (or a capsule of nothing but holes).
+        while commit_error.is_none() && next_index < total_segments {
+            let Some(hole) = holes[next_index] else {
+                break;
+            };
+            total_original_size += hole.hole_len().unwrap_or(0);
+            segment_ids.push(hole);
+            next_index += 1;
+        }
+
         let join_results = join_all(handles).await;
         for handle_res in join_results {
             match handle_res {
@@ -1058,10 +1912,10 @@ impl WritePipeline {
         }
 
         if let Some(err) = commit_error {
-            transaction.rollback()?;
-            for seg_id in dedupe_increments.iter().rev() {
-                let _ = self.nvram.decrement_refcount(*seg_id)?;
+            if let Err(rollback_err) = transaction.rollback() {
+                warn!(error = %rollback_err, "failed to roll back async write transaction");
             }
+            self.release_dedupe_hits(&dedupe_increments);
             info!(
                 capsule = %capsule_id.as_uuid(),
                 "async write pipeline aborted; staged work rolled back"
@@ -1069,61 +1923,36 @@ impl WritePipeline {
             return Err(err);
         }
 
-        if let Err(err) = transaction.commit() {
-            for seg_id in dedupe_increments.iter().rev() {
-                let _ = self.nvram.decrement_refcount(*seg_id)?;
-            }
-            return Err(err);
-        }
-
-        let mut registered = Vec::new();
-        for (hash, seg_id) in &pending_registrations {
-            if let Err(err) = self.registry.register_content(hash.clone(), *seg_id) {
-                for (registered_hash, registered_seg_id) in &registered {
-                    let _ = self
-                        .registry
-                        .deregister_content(registered_hash, *registered_seg_id)?;
-                }
-                for (_, seg_id) in &pending_registrations {
-                    let _ = self.nvram.remove_segment(*seg_id)?;
-                }
-                for seg_id in dedupe_increments.iter().rev() {
-                    let _ = self.nvram.decrement_refcount(*seg_id)?;
-                }
-                info!(
-                    capsule = %capsule_id.as_uuid(),
-                    "async write pipeline aborted during content registration"
-                );
-                return Err(err);
+        let mut intent = WriteIntent::new(
+            capsule_id,
+            data.len() as u64,
+            segment_ids.clone(),
+            policy.clone(),
+        )
+        .map_err(|err| map_registry_error("write_intent", err))?;
+        intent.capsule.deduped_bytes = dedup_stats.bytes_saved;
+        intent.capsule.data_keys = keys.wrapped;
+        intent.new_segments = new_segments;
+        intent.registrations = pending_registrations;
+
+        if let Err(err) = self.registry.begin_write(intent) {
+            if let Err(rollback_err) = transaction.rollback() {
+                warn!(error = %rollback_err, "failed to roll back async write transaction");
             }
-            registered.push((hash.clone(), *seg_id));
+            self.release_dedupe_hits(&dedupe_increments);
+            return Err(map_registry_error("begin_write", err));
         }
 
-        if let Err(err) = self
-            .registry
-            .create_capsule_with_segments(
-                capsule_id,
-                data.len() as u64,
-                segment_ids.clone(),
-                policy.clone(),
-            )
-            .map_err(|err| map_registry_error("create_capsule_with_segments", err))
-        {
-            for (hash, seg_id) in &pending_registrations {
-                let _ = self.registry.deregister_content(hash, *seg_id)?;
-                let _ = self.nvram.remove_segment(*seg_id)?;
-            }
-            for seg_id in dedupe_increments.iter().rev() {
-                let _ = self.nvram.decrement_refcount(*seg_id)?;
-            }
+        if let Err(err) = transaction.commit() {
+            let _ = self.registry.abort_write(capsule_id);
+            self.release_dedupe_hits(&dedupe_increments);
             return Err(err);
         }
 
-        if dedup_stats.bytes_saved > 0 {
-            self.registry
-                .add_deduped_bytes(capsule_id, dedup_stats.bytes_saved)
-                .map_err(|err| map_registry_error("add_deduped_bytes", err))?;
-        }
+        self.registry
+            .commit_write(capsule_id)
+            .map_err(|err| map_registry_error("commit_write", err))?;
+        self.register_sketches(None, &pending_sketches);
 
         let compression_ratio = if total_compressed_size > 0 {
             total_original_size as f32 / total_compressed_size as f32
@@ -1208,7 +2037,7 @@ impl WritePipeline {
         if let Some(tx) = &self.telemetry_tx {
             let telemetry_event = common::podms::Telemetry::NewCapsule {
                 id: capsule_id,
-                policy: policy.clone(),
+                policy: Box::new(policy.clone()),
                 node_id: None, // Will be set by agent when node ID is known
             };
 
@@ -1273,6 +2102,11 @@ impl WritePipeline {
         let mut replicated_count = 0;
 
         for (seg_index, &seg_id) in segment_ids.iter().enumerate() {
+            // Holes have nothing stored to mirror.
+            if seg_id.is_hole() {
+                continue;
+            }
+
             // Read segment data from NVRAM
             let segment_data = self.nvram.read(seg_id)?;
 
@@ -1337,16 +2171,19 @@ impl WritePipeline {
         &self,
         prepared: SegmentPrepared,
         policy: &Policy,
-        encryption_enabled: bool,
+        keys: &mut CapsuleKeys,
+        dictionary: Option<&(DictionaryRef, Arc<Vec<u8>>)>,
         transaction: &mut NvramTransaction,
         staged_content: &mut HashMap<ContentHash, SegmentId>,
     ) -> Result<(SegmentId, WriteDisposition, u64, Option<ContentHash>)> {
         let SegmentPrepared {
-            index: _,
+            index,
             content_hash,
-            final_data,
-            comp_result,
-            encryption_meta,
+            mut final_data,
+            mut comp_result,
+            mut encryption_meta,
+            sketch,
+            plaintext,
             ..
         } = prepared;
 
@@ -1376,7 +2213,11 @@ impl WritePipeline {
                 ));
             }
 
-            if let Some(existing_seg_id) = self.registry.lookup_content(&content_hash) {
+            if let Some(existing_seg_id) = self
+                .registry
+                .lookup_content(&content_hash)
+                .filter(|seg_id| self.share_segment(keys, *seg_id))
+            {
                 let segment = self.nvram.increment_refcount(existing_seg_id)?;
                 let saved_bytes = segment.len as u64;
 
@@ -1395,14 +2236,35 @@ impl WritePipeline {
             }
         }
 
+        // New content resembling a committed segment is stored as a delta against it
+        let logical_len = comp_result.original_size as u32;
+        let delta = match (&sketch, &plaintext) {
+            (Some(sketch), Some(chunk)) => self.delta_against_similar(None, keys, sketch, chunk),
+            _ => None,
+        };
+        if let Some((_, delta)) = &delta {
+            (final_data, comp_result, encryption_meta) = seal_payload(
+                index,
+                delta,
+                &content_hash,
+                policy,
+                keys.sealing.as_ref(),
+                dictionary.map(|(_, bytes)| bytes.as_slice()),
+            )?;
+        }
+
         let seg_id = self.registry.alloc_segment();
         let data_len = final_data.len() as u64;
         let mut segment = transaction.append_segment(seg_id, final_data.as_ref())?;
 
+        segment.logical_len = logical_len;
         segment.compressed = comp_result.compressed;
         segment.compression_algo = comp_result.algorithm.clone();
         segment.ref_count = 1;
         segment.deduplicated = false;
+        if comp_result.dictionary {
+            segment.dictionary = dictionary.map(|(dictionary, _)| *dictionary);
+        }
 
         let registered_hash = if policy.dedupe {
             segment.content_hash = Some(content_hash.clone());
@@ -1419,11 +2281,32 @@ impl WritePipeline {
             segment.key_version = enc_meta.key_version;
             segment.tweak_nonce = enc_meta.tweak_nonce;
             segment.integrity_tag = enc_meta.integrity_tag;
+            segment.data_key = keys.sealing.as_ref().map(|key| key.id);
         }
 
+        // A delta keeps its base alive; the caller gives the reference back if
+        // the write fails.
+        let disposition = match delta {
+            Some((base, delta)) => {
+                self.nvram.increment_refcount(base)?;
+                segment.delta_base = Some(base);
+                debug!(
+                    segment = seg_id.0,
+                    base = base.0,
+                    delta_len = delta.len(),
+                    "segment stored as a delta"
+                );
+                WriteDisposition::NewDelta { base }
+            }
+            None => {
+                segment.sketch = sketch;
+                WriteDisposition::NewSegment { sketch }
+            }
+        };
+
         transaction.set_segment_metadata(seg_id, segment)?;
 
-        if encryption_enabled && encryption_meta.is_some() {
+        if encryption_meta.is_some() {
             debug!(segment = seg_id.0, "segment encrypted");
         }
         if comp_result.compressed {
@@ -1435,12 +2318,7 @@ impl WritePipeline {
             );
         }
 
-        Ok((
-            seg_id,
-            WriteDisposition::NewSegment,
-            data_len,
-            registered_hash,
-        ))
+        Ok((seg_id, disposition, data_len, registered_hash))
     }
     /// Read entire capsule contents (with decryption and decompression)
     #[instrument(skip(self), fields(capsule = %id.as_uuid()))]
@@ -1454,130 +2332,670 @@ impl WritePipeline {
         }
 
         let capsule = self.registry.lookup(id)?;
+        let result = self.decode_capsule(&capsule)?;
+        self.record_read(&capsule);
+        Ok(result)
+    }
 
+    fn decode_capsule(&self, capsule: &Capsule) -> Result<Vec<u8>> {
         let mut result = Vec::with_capacity(capsule.size as usize);
-
-        #[cfg_attr(
-            not(feature = "advanced-security"),
-            allow(clippy::unused_enumerate_index)
-        )]
-        #[cfg_attr(not(feature = "advanced-security"), allow(unused_variables))]
         for (seg_index, seg_id) in capsule.segments.iter().enumerate() {
-            // Read raw data from NVRAM
-            let raw_data = self.nvram.read(*seg_id)?;
-
-            // Get segment metadata to check if encrypted
+            if let Some(len) = seg_id.hole_len() {
+                result.resize(result.len() + len as usize, 0);
+                continue;
+            }
             let segment = self.nvram.get_segment_metadata(*seg_id)?;
+            result.extend_from_slice(&self.decode_segment(capsule, seg_index, &segment)?);
+        }
+        Ok(result)
+    }
 
-            // Step 1: Decrypt if encrypted
-            let decrypted_data = if segment.encrypted {
-                let km = self.key_manager.as_ref().ok_or_else(|| {
-                    anyhow::anyhow!("Cannot decrypt: key manager not initialized")
-                })?;
+    /// Read a range within a capsule (for block/file semantics)
+    ///
+    /// Only the segments covering the range are read and decoded. Capsules with
+    /// segments that predate logical length tracking fall back to a full read.
+    #[instrument(skip(self), fields(capsule = %id.as_uuid(), offset, len))]
+    pub fn read_range(&self, id: CapsuleId, offset: u64, len: usize) -> Result<Vec<u8>> {
+        #[cfg(feature = "modular_pipeline")]
+        if let (Some(modular), Some(runtime)) = (&self.modular, &self.runtime) {
+            return runtime.block_on(async {
+                let handle = modular.lock().await;
+                handle.read_range(id, offset, len).await
+            });
+        }
 
-                let mut km = km.lock().unwrap();
+        let capsule = self.registry.lookup(id)?;
 
-                let key_version = segment
-                    .key_version
-                    .ok_or_else(|| anyhow::anyhow!("Missing key version in encrypted segment"))?;
+        if offset
+            .checked_add(len as u64)
+            .filter(|end| *end <= capsule.size)
+            .is_none()
+        {
+            anyhow::bail!("Read beyond capsule boundary");
+        }
+        if len == 0 {
+            return Ok(Vec::new());
+        }
 
-                let key_pair = km.get_key(key_version)?;
+        let logical_lens = self.segment_lens(&capsule)?;
+        let Some(extents) = covering_segments(&logical_lens, offset, len) else {
+            let full_data = self.read_capsule(id)?;
+            return Ok(full_data[offset as usize..(offset as usize + len)].to_vec());
+        };
 
-                #[cfg(feature = "advanced-security")]
-                let mut derived_pair: Option<XtsKeyPair> = None;
-                #[cfg(feature = "advanced-security")]
-                if capsule.policy.crypto_profile == CryptoProfile::HybridKyber {
-                    if let (Some(manager), Some(cipher_hex), Some(hash)) = (
-                        self.mlkem_manager.as_ref(),
-                        &segment.pq_ciphertext,
-                        &segment.content_hash,
-                    ) {
-                        match manager.unwrap_xts_key(
-                            capsule.policy.crypto_profile,
-                            &collect_base_material((key_pair.key1(), key_pair.key2())),
-                            &capsule.id,
-                            SegmentId(seg_index as u64),
-                            hash,
-                            cipher_hex,
-                        ) {
-                            Ok(Some(material)) => {
-                                derived_pair = Some(XtsKeyPair::from_bytes(material.wrapped_key));
-                            }
-                            Ok(None) => {}
-                            Err(err) => warn!(error = %err, "mlkem unwrap failed"),
-                        }
-                    }
-                }
+        let mut result = Vec::with_capacity(len);
+        for extent in extents {
+            let seg_id = capsule.segments[extent.index];
+            if seg_id.is_hole() {
+                result.resize(result.len() + (extent.end - extent.start), 0);
+                continue;
+            }
+            let data = self.read_segment(&capsule, extent.index)?;
+            if data.len() < extent.end {
+                anyhow::bail!(
+                    "segment {} decoded to {} bytes, expected at least {}",
+                    seg_id.0,
+                    data.len(),
+                    extent.end
+                );
+            }
+            result.extend_from_slice(&data[extent.start..extent.end]);
+        }
 
-                #[cfg(feature = "advanced-security")]
-                let pair_for_use = derived_pair
-                    .as_ref()
-                    .map(|pair| pair as &XtsKeyPair)
-                    .unwrap_or(key_pair);
-                #[cfg(not(feature = "advanced-security"))]
-                let pair_for_use = key_pair;
-
-                let enc_meta = EncryptionMetadata {
-                    encryption_version: segment.encryption_version,
-                    key_version: segment.key_version,
-                    tweak_nonce: segment.tweak_nonce,
-                    integrity_tag: segment.integrity_tag,
-                    ciphertext_len: Some(raw_data.len() as u32),
-                };
+        Ok(result)
+    }
 
-                verify_mac(
-                    &raw_data,
-                    &enc_meta,
-                    pair_for_use.key1(),
-                    pair_for_use.key2(),
-                )?;
+    /// Overwrite `data.len()` bytes at `offset`, growing the capsule if the
+    /// write runs past its end.
+    ///
+    /// Only the segments overlapping the range are decoded, patched and staged
+    /// as new segments; the rest stay shared with the previous version through
+    /// their refcounts. Holes are split so that just the stretch under the write
+    /// is materialized. The capsule keeps its ID and its `version` is bumped.
+    #[instrument(skip(self, data), fields(capsule = %id.as_uuid(), offset, bytes = data.len()))]
+    pub fn write_range(&self, id: CapsuleId, offset: u64, data: &[u8]) -> Result<Capsule> {
+        let capsule = self.lookup_writable(id)?;
+        if offset > capsule.size {
+            anyhow::bail!(
+                "Write starts beyond capsule end ({} > {})",
+                offset,
+                capsule.size
+            );
+        }
+        if data.is_empty() {
+            return Ok(capsule);
+        }
+        let end = offset + data.len() as u64;
 
-                decrypt_segment(&raw_data, pair_for_use, &enc_meta)?
-            } else {
-                raw_data
-            };
+        let mut updated = capsule.clone();
+        let mut logical_lens = self.segment_lens(&capsule)?;
+        split_holes(&mut updated.segments, &mut logical_lens, offset, end);
+        let (first, last, region_start) = rewrite_span(&logical_lens, capsule.size, offset, end);
 
-            // Step 2: Decompress based on policy
-            let data = match capsule.policy.compression {
-                CompressionPolicy::None => decrypted_data,
-                CompressionPolicy::LZ4 { .. } => {
-                    match decompress_lz4(&decrypted_data) {
-                        Ok(decompressed) => decompressed,
-                        Err(_) => decrypted_data, // Wasn't compressed
-                    }
-                }
-                CompressionPolicy::Zstd { .. } => {
-                    match decompress_zstd(&decrypted_data) {
-                        Ok(decompressed) => decompressed,
-                        Err(_) => decrypted_data, // Wasn't compressed
-                    }
+        // Rebuild the affected region in memory; it spans only the touched segments.
+        let mut region = Vec::new();
+        for index in first..last {
+            region.extend_from_slice(&self.read_segment(&updated, index)?);
+        }
+        let patch_start = (offset - region_start) as usize;
+        let patch_end = (end - region_start) as usize;
+        if region.len() < patch_end {
+            region.resize(patch_end, 0);
+        }
+        region[patch_start..patch_end].copy_from_slice(data);
+
+        let mut session = self.open_overwrite_session(&capsule, &capsule.policy)?;
+        session.index_base = first;
+        for chunk in segment_chunks(&capsule.policy, &region) {
+            if let Err(err) = self.stage_chunk(&mut session, chunk) {
+                self.abandon_session(session);
+                return Err(err);
+            }
+        }
+
+        let replaced: Vec<SegmentId> = updated
+            .segments
+            .splice(first..last, std::mem::take(&mut session.segment_ids))
+            .collect();
+        updated.size = capsule.size.max(end);
+        updated.version = capsule.version + 1;
+        updated.deduped_bytes += session.dedup_stats.bytes_saved;
+        updated.data_keys = session.keys.wrapped.clone();
+        self.publish_overwrite(&mut session, &capsule, &updated, &replaced)?;
+
+        info!(
+            capsule = %id.as_uuid(),
+            version = updated.version,
+            rewritten_segments = last - first,
+            "capsule range overwritten"
+        );
+        Ok(updated)
+    }
+
+    /// Replace the whole contents of capsule `id` with `data`, in place.
+    ///
+    /// The capsule keeps its ID and gets a new version; segments identical to
+    /// the old contents still dedupe against them. If the capsule's policy keeps
+    /// history the old contents stay readable via
+    /// [`read_capsule_at`](Self::read_capsule_at).
+    #[instrument(skip(self, data), fields(capsule = %id.as_uuid(), bytes = data.len()))]
+    pub fn overwrite_capsule(&self, id: CapsuleId, data: &[u8]) -> Result<Capsule> {
+        let capsule = self.lookup_writable(id)?;
+
+        let mut session = self.open_overwrite_session(&capsule, &capsule.policy)?;
+        for chunk in segment_chunks(&capsule.policy, data) {
+            if let Err(err) = self.stage_chunk(&mut session, chunk) {
+                self.abandon_session(session);
+                return Err(err);
+            }
+        }
+        self.seal_overwrite(session, &capsule)
+    }
+
+    /// Start a streaming write that replaces the whole contents of capsule `id`
+    /// when [`CapsuleWriter::finish`] seals it, as
+    /// [`overwrite_capsule`](Self::overwrite_capsule) does.
+    pub fn capsule_overwriter(&self, id: CapsuleId) -> Result<CapsuleWriter<'_>> {
+        let capsule = self.lookup_writable(id)?;
+        let session = self.open_overwrite_session(&capsule, &capsule.policy)?;
+        Ok(CapsuleWriter::replacing(self, capsule, session))
+    }
+
+    /// Publish everything staged in `session` as the next version of `previous`.
+    pub(crate) fn seal_overwrite(
+        &self,
+        session: WriteSession,
+        previous: &Capsule,
+    ) -> Result<Capsule> {
+        let mut session = session;
+        let mut updated = previous.clone();
+        updated.segments = std::mem::take(&mut session.segment_ids);
+        updated.size = session.total_original_size;
+        updated.version = previous.version + 1;
+        updated.deduped_bytes = session.dedup_stats.bytes_saved;
+        updated.data_keys = session.keys.wrapped.clone();
+        self.publish_overwrite(&mut session, previous, &updated, &previous.segments)?;
+
+        info!(
+            capsule = %previous.id.as_uuid(),
+            version = updated.version,
+            "capsule overwritten"
+        );
+        Ok(updated)
+    }
+
+    /// Re-encode capsule `id` under `policy`, keeping its ID and contents.
+    ///
+    /// The capsule is rewritten as a new version, so a versioning policy on the
+    /// old policy keeps the previous encoding readable.
+    pub fn transition_capsule(&self, id: CapsuleId, policy: &Policy) -> Result<Capsule> {
+        self.rewrite_with_policy(id, policy, |_| {})
+    }
+
+    /// [`transition_capsule`](Self::transition_capsule), letting `finish`
+    /// amend the new record before it is published in the same commit.
+    #[instrument(skip(self, policy, finish), fields(capsule = %id.as_uuid()))]
+    pub(crate) fn rewrite_with_policy(
+        &self,
+        id: CapsuleId,
+        policy: &Policy,
+        finish: impl FnOnce(&mut Capsule),
+    ) -> Result<Capsule> {
+        let updated = self.rewrite_capsule(id, policy, false, finish)?;
+        info!(
+            capsule = %id.as_uuid(),
+            version = updated.version,
+            "capsule transitioned to a new policy"
+        );
+        Ok(updated)
+    }
+
+    /// Re-seal capsule `id` under a new data key of its own.
+    ///
+    /// Segments sealed under the capsule's older data keys, or directly under
+    /// the master key, are rewritten as a new version that no longer carries
+    /// those keys. Segments it shares under other capsules' keys are left as
+    /// they are. A versioning policy keeps the previous version readable.
+    #[instrument(skip(self), fields(capsule = %id.as_uuid()))]
+    pub fn rotate_data_key(&self, id: CapsuleId) -> Result<Capsule> {
+        let capsule = self.registry.lookup(id)?;
+        if !capsule.policy.encryption.is_enabled() || self.key_manager.is_none() {
+            anyhow::bail!("Capsule {:?} is not encrypted", id);
+        }
+        let updated = self.rewrite_capsule(id, &capsule.policy, true, |_| {})?;
+        info!(
+            capsule = %id.as_uuid(),
+            version = updated.version,
+            data_key = ?updated.own_data_key().map(|key| key.id),
+            "capsule data key rotated"
+        );
+        Ok(updated)
+    }
+
+    /// Rewrite capsule `id` under `policy` as a new version, under a new data
+    /// key if `rotate_key` is set.
+    fn rewrite_capsule(
+        &self,
+        id: CapsuleId,
+        policy: &Policy,
+        rotate_key: bool,
+        finish: impl FnOnce(&mut Capsule),
+    ) -> Result<Capsule> {
+        let capsule = self.lookup_writable(id)?;
+        let data = self.decode_capsule(&capsule)?;
+
+        let mut session = self.open_overwrite_session(&capsule, policy)?;
+        if rotate_key {
+            // The new key must exist before any segment is offered for reuse.
+            session.keys.rotating = true;
+            if let Err(err) = self.sealing_key(&mut session.keys) {
+                self.abandon_session(session);
+                return Err(err);
+            }
+        }
+        for chunk in segment_chunks(policy, &data) {
+            if let Err(err) = self.stage_chunk(&mut session, chunk) {
+                self.abandon_session(session);
+                return Err(err);
+            }
+        }
+        if rotate_key {
+            // Nothing in the new version is sealed under the older keys.
+            let current = session.keys.sealing.as_ref().map(|key| key.id);
+            session
+                .keys
+                .wrapped
+                .retain(|key| key.id.capsule != id || Some(key.id) == current);
+        }
+
+        let mut updated = capsule.clone();
+        updated.segments = std::mem::take(&mut session.segment_ids);
+        updated.version = capsule.version + 1;
+        updated.deduped_bytes = session.dedup_stats.bytes_saved;
+        updated.data_keys = session.keys.wrapped.clone();
+        updated.policy = policy.clone();
+        finish(&mut updated);
+        self.publish_overwrite(&mut session, &capsule, &updated, &capsule.segments)?;
+        Ok(updated)
+    }
+
+    /// Move to a new master key version and rewrap every data key under it.
+    ///
+    /// Only capsule records change; segments stay sealed under their data keys.
+    /// Keys left under an older version, and segments sealed directly under
+    /// one, remain readable with it. Returns the new version; if any key fails
+    /// to rewrap, the previous version stays current and the error is returned.
+    pub fn rotate_master_key(&self) -> Result<u32> {
+        let km = self
+            .key_manager
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Cannot rotate: key manager not initialized"))?;
+        let version = km.lock().unwrap().rotate()?;
+        // The new version only takes over once every data key is wrapped under it.
+        let rewrapped = match self.rewrap_data_keys() {
+            Ok(rewrapped) => rewrapped,
+            Err(err) => {
+                km.lock().unwrap().abort_rotation();
+                warn!(version, error = %err, "master key rotation abandoned");
+                return Err(err);
+            }
+        };
+        km.lock().unwrap().complete_rotation();
+
+        info!(version, rewrapped, "master key rotated");
+        Ok(version)
+    }
+
+    /// Rewrap data keys wrapped under older master key versions under the
+    /// current one. Returns how many keys were rewrapped.
+    pub fn rewrap_data_keys(&self) -> Result<usize> {
+        let km = self
+            .key_manager
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Cannot rewrap: key manager not initialized"))?;
+        self.registry.rewrap_data_keys(|key| {
+            let current = km.lock().unwrap().current_version();
+            if key.kek_version >= current {
+                return Ok(None);
+            }
+            let pair = self.data_key_pair(std::slice::from_ref(key), key.id)?;
+            let (wrapped, tag) = wrap_data_key(
+                &pair,
+                km.lock().unwrap().get_key(current)?,
+                &key.id.wrap_context(),
+            )?;
+            Ok(Some(WrappedDataKey {
+                id: key.id,
+                kek_version: current,
+                wrapped: wrapped.to_vec(),
+                tag,
+            }))
+        })
+    }
+
+    fn lookup_writable(&self, id: CapsuleId) -> Result<Capsule> {
+        if self.uses_modular() {
+            anyhow::bail!("in-place overwrites are not supported by the modular pipeline");
+        }
+        let capsule = self.registry.lookup(id)?;
+        if capsule.read_only {
+            anyhow::bail!("Capsule {:?} is a read-only snapshot", id);
+        }
+        self.registry.ensure_mutable(id, "overwrite")?;
+        Ok(capsule)
+    }
+
+    /// Publish `updated` over `previous` and drop the references `replaced`
+    /// held. Under a versioning policy `previous` is retained instead, holding
+    /// its own reference on each of its segments.
+    fn publish_overwrite(
+        &self,
+        session: &mut WriteSession,
+        previous: &Capsule,
+        updated: &Capsule,
+        replaced: &[SegmentId],
+    ) -> Result<()> {
+        let retain = previous.policy.versioning.is_some();
+        if retain {
+            // Taken like dedupe hits so a failed publish hands them back.
+            for seg_id in previous.segments.iter().filter(|seg_id| !seg_id.is_hole()) {
+                if let Err(err) = self.nvram.increment_refcount(*seg_id) {
+                    self.discard_session(session);
+                    return Err(map_nvram_error("increment_refcount", err));
                 }
-            };
+                session.dedupe_increments.push(*seg_id);
+            }
+        }
+
+        let intent = WriteIntent {
+            capsule: updated.clone(),
+            new_segments: Vec::new(),
+            registrations: Vec::new(),
+            partial: false,
+            base_version: Some(previous.version),
+            retain_previous: retain,
+        };
+        self.publish_session(session, intent)?;
+        self.release_segments(replaced)?;
+
+        if retain {
+            self.prune_versions(previous.id)?;
+        }
+        Ok(())
+    }
 
-            result.extend_from_slice(&data);
+    /// Read capsule `id` as it stood at `version`, which may be the live
+    /// version or one retained by its versioning policy.
+    #[instrument(skip(self), fields(capsule = %id.as_uuid(), version))]
+    pub fn read_capsule_at(&self, id: CapsuleId, version: u64) -> Result<Vec<u8>> {
+        let capsule = self.registry.capsule_at(id, version)?;
+        if self.uses_modular() {
+            // Nothing is retained under the modular pipeline, so this is the live version.
+            return self.read_capsule(id);
         }
 
+        let data = self.decode_capsule(&capsule)?;
+        self.record_read(&capsule);
+        Ok(data)
+    }
+
+    /// Versions of capsule `id` that can be read, oldest first.
+    pub fn list_versions(&self, id: CapsuleId) -> Result<Vec<u64>> {
+        self.registry.list_versions(id)
+    }
+
+    /// Apply capsule `id`'s versioning policy now, releasing the segments of
+    /// every retained version it no longer keeps. Returns how many were dropped.
+    pub fn prune_versions(&self, id: CapsuleId) -> Result<usize> {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs();
+        let pruned = self.registry.prune_versions(id, now)?;
+        for entry in &pruned {
+            self.release_segments(&entry.capsule.segments)?;
+        }
+        Ok(pruned.len())
+    }
+
+    /// Create a capsule of `size` zero bytes without storing anything.
+    ///
+    /// The capsule is made of hole markers alone, so it costs only metadata
+    /// until ranges of it are filled in with [`write_range`](Self::write_range).
+    pub fn create_sparse_capsule(&self, size: u64, policy: &Policy) -> Result<CapsuleId> {
+        let mut session = self.open_session(policy)?;
+        let mut remaining = size;
+        while remaining > 0 {
+            let len = remaining.min(SegmentId::MAX_HOLE_LEN);
+            session.segment_ids.push(SegmentId::hole(len));
+            remaining -= len;
+        }
+        session.total_original_size = size;
+        self.seal_session(session)
+    }
+
+    /// Take a read-only, point-in-time snapshot of capsule `id`.
+    ///
+    /// No data is copied: the snapshot references the same segments as its
+    /// source, whose refcounts are bumped so either side can be deleted alone.
+    pub fn snapshot_capsule(&self, id: CapsuleId) -> Result<CapsuleId> {
+        self.derive_capsule(id, true)
+    }
+
+    /// Create a writable copy of capsule `id` sharing all of its segments.
+    ///
+    /// Later [`write_range`](Self::write_range) calls on either capsule only
+    /// replace segments in that capsule, so the two diverge copy-on-write.
+    pub fn clone_capsule(&self, id: CapsuleId) -> Result<CapsuleId> {
+        self.derive_capsule(id, false)
+    }
+
+    #[instrument(skip(self), fields(source = %id.as_uuid()))]
+    fn derive_capsule(&self, id: CapsuleId, read_only: bool) -> Result<CapsuleId> {
+        let source = self.registry.lookup(id)?;
+        let intent = WriteIntent::derived(&source, read_only)
+            .map_err(|err| map_registry_error("write_intent", err))?;
+        let derived_id = intent.capsule_id();
+
+        // The shared segments are taken like dedupe hits, so a failure before
+        // publishing hands the references back.
+        let mut session = self.open_session(&source.policy)?;
+        session.capsule_id = derived_id;
+        session.keys = CapsuleKeys::new(
+            derived_id,
+            source.tenant.clone(),
+            session.encryption_enabled,
+            source.data_keys.clone(),
+        );
+        for seg_id in source.segments.iter().filter(|seg_id| !seg_id.is_hole()) {
+            if let Err(err) = self.nvram.increment_refcount(*seg_id) {
+                self.abandon_session(session);
+                return Err(map_nvram_error("increment_refcount", err));
+            }
+            session.dedupe_increments.push(*seg_id);
+        }
+        self.publish_session(&mut session, intent)?;
+
+        info!(
+            capsule = %derived_id.as_uuid(),
+            source = %id.as_uuid(),
+            read_only,
+            shared_segments = source.segments.len(),
+            "capsule derived"
+        );
+        Ok(derived_id)
+    }
+
+    /// Decoded length of each of `capsule`'s segments; 0 where it was never recorded.
+    fn segment_lens(&self, capsule: &Capsule) -> Result<Vec<u64>> {
+        capsule
+            .segments
+            .iter()
+            .map(|seg_id| match seg_id.hole_len() {
+                Some(len) => Ok(len),
+                None => Ok(self.nvram.get_segment_metadata(*seg_id)?.logical_len as u64),
+            })
+            .collect()
+    }
+
+    /// Registry backing this pipeline.
+    pub fn registry(&self) -> &CapsuleRegistry {
+        &self.registry
+    }
+
+    /// Registry record for capsule `id`.
+    pub fn lookup_capsule(&self, id: CapsuleId) -> Result<Capsule> {
+        self.registry.lookup(id)
+    }
+
+    /// Decode the segment at `index` within `capsule`; holes decode to zeroes.
+    pub(crate) fn read_segment(&self, capsule: &Capsule, index: usize) -> Result<Vec<u8>> {
+        if let Some(len) = capsule.segments[index].hole_len() {
+            return Ok(vec![0; len as usize]);
+        }
+        let segment = self.nvram.get_segment_metadata(capsule.segments[index])?;
+        self.decode_segment(capsule, index, &segment)
+    }
+
+    #[cfg_attr(not(feature = "advanced-security"), allow(unused_variables))]
+    pub(crate) fn record_read(&self, capsule: &Capsule) {
         #[cfg(feature = "advanced-security")]
         self.audit_event(common::Event::CapsuleRead {
-            capsule_id: id,
+            capsule_id: capsule.id,
             size: capsule.size,
         });
+    }
 
-        Ok(result)
+    /// Decrypt and decompress one of `capsule`'s segments back to its original bytes.
+    fn decode_segment(
+        &self,
+        capsule: &Capsule,
+        seg_index: usize,
+        segment: &Segment,
+    ) -> Result<Vec<u8>> {
+        let keys = &capsule.data_keys;
+        let payload = self.decode_payload(Some(capsule), keys, seg_index, segment)?;
+        let Some(base_id) = segment.delta_base else {
+            return Ok(payload);
+        };
+        // Bases are never deltas themselves and don't depend on the capsule,
+        // though the capsule carries their data keys.
+        let base = self.nvram.get_segment_metadata(base_id)?;
+        let base_data = self.decode_payload(None, keys, 0, &base)?;
+        Ok(apply_delta(&base_data, &payload)?)
     }
 
-    /// Read a range within a capsule (for block/file semantics)
-    #[instrument(skip(self), fields(capsule = %id.as_uuid(), offset, len))]
-    pub fn read_range(&self, id: CapsuleId, offset: u64, len: usize) -> Result<Vec<u8>> {
-        let capsule = self.registry.lookup(id)?;
+    /// Decrypt and decompress the bytes stored for `segment`, as its own
+    /// metadata describes them. `keys` holds the data key it is sealed under;
+    /// the capsule and index are needed only for hybrid segments sealed before
+    /// data keys, which were bound to their position in the capsule.
+    #[cfg_attr(not(feature = "advanced-security"), allow(unused_variables))]
+    fn decode_payload(
+        &self,
+        capsule: Option<&Capsule>,
+        keys: &[WrappedDataKey],
+        seg_index: usize,
+        segment: &Segment,
+    ) -> Result<Vec<u8>> {
+        // Read raw data from NVRAM
+        let raw_data = self.nvram.read(segment.id)?;
+
+        // Step 1: Decrypt if encrypted
+        let decrypted_data = if segment.encrypted {
+            let key_pair = match segment.data_key {
+                Some(id) => self.data_key_pair(keys, id)?,
+                None => {
+                    let km = self.key_manager.as_ref().ok_or_else(|| {
+                        anyhow::anyhow!("Cannot decrypt: key manager not initialized")
+                    })?;
+                    let key_version = segment.key_version.ok_or_else(|| {
+                        anyhow::anyhow!("Missing key version in encrypted segment")
+                    })?;
+                    km.lock().unwrap().get_key(key_version)?.clone()
+                }
+            };
+            let key_pair = &key_pair;
 
-        if offset + len as u64 > capsule.size {
-            anyhow::bail!("Read beyond capsule boundary");
-        }
+            #[cfg(feature = "advanced-security")]
+            let mut derived_pair: Option<XtsKeyPair> = None;
+            // Hybrid wrapping is bound to the segment and the capsule owning its
+            // data key, so it unwraps the same through snapshots, clones and
+            // rewrites that share or move the segment.
+            #[cfg(feature = "advanced-security")]
+            if let (Some(manager), Some(cipher_hex), Some(hash)) = (
+                self.mlkem_manager.as_ref(),
+                &segment.pq_ciphertext,
+                &segment.content_hash,
+            ) {
+                let binding = match (segment.data_key, capsule) {
+                    (Some(key), _) => Some((key.capsule, segment.id)),
+                    (None, Some(capsule)) => Some((capsule.id, SegmentId(seg_index as u64))),
+                    (None, None) => None,
+                };
+                if let Some((owner, bound_segment)) = binding {
+                    match manager.unwrap_xts_key(
+                        CryptoProfile::HybridKyber,
+                        &collect_base_material((key_pair.key1(), key_pair.key2())),
+                        &owner,
+                        bound_segment,
+                        hash,
+                        cipher_hex,
+                    ) {
+                        Ok(Some(material)) => {
+                            derived_pair = Some(XtsKeyPair::from_bytes(material.wrapped_key));
+                        }
+                        Ok(None) => {}
+                        Err(err) => warn!(error = %err, "mlkem unwrap failed"),
+                    }
+                }
+            }
+
+            #[cfg(feature = "advanced-security")]
+            let pair_for_use = derived_pair
+                .as_ref()
+                .map(|pair| pair as &XtsKeyPair)
+                .unwrap_or(key_pair);
+            #[cfg(not(feature = "advanced-security"))]
+            let pair_for_use = key_pair;
+
+            let enc_meta = EncryptionMetadata {
+                encryption_version: segment.encryption_version,
+                key_version: segment.key_version,
+                tweak_nonce: segment.tweak_nonce,
+                integrity_tag: segment.integrity_tag,
+                ciphertext_len: Some(raw_data.len() as u32),
+            };
+
+            verify_mac(
+                &raw_data,
+                &enc_meta,
+                pair_for_use.key1(),
+                pair_for_use.key2(),
+            )?;
+
+            decrypt_segment(&raw_data, pair_for_use, &enc_meta)?
+        } else {
+            raw_data
+        };
+
+        // Step 2: Decompress with the codec the segment was stored under, which
+        // differs from the capsule's policy when the segment was deduplicated
+        // against a write that used another codec or the policy changed since.
+        let algorithm = match segment.compression_algo.as_str() {
+            "" if segment.compressed => anyhow::bail!(
+                "segment {} is compressed but records no codec",
+                segment.id.0
+            ),
+            "" => IDENTITY,
+            algorithm => algorithm,
+        };
+        let data = match segment.dictionary {
+            Some(dictionary) => decompress_zstd_with_dictionary(
+                &decrypted_data,
+                &self.load_dictionary(dictionary)?,
+            )?,
+            None => codec::decompress(algorithm, &decrypted_data)?,
+        };
 
-        // Simple implementation - read full capsule then slice
-        // TODO Phase 2.3: Optimize to only read relevant segments
-        let full_data = self.read_capsule(id)?;
-        Ok(full_data[offset as usize..(offset as usize + len)].to_vec())
+        Ok(data)
     }
 }
@@ -0,0 +1,363 @@
+use crate::dedup::hash_content;
+use anyhow::Result;
+#[cfg(feature = "advanced-security")]
+use common::security::audit_log::AuditLog;
+use common::{Event, Segment, SegmentId};
+use nvram_sim::{segment_checksum, NvramLog};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{error, info, warn};
+
+/// Throughput and batching limits for a scrubber.
+#[derive(Debug, Clone)]
+pub struct ScrubConfig {
+    /// Upper bound on bytes read per second; `0` disables throttling.
+    pub max_bytes_per_sec: u64,
+    /// Segments verified between state checkpoints.
+    pub batch_segments: usize,
+    /// Pause between full passes when running in the background.
+    pub pass_interval: Duration,
+}
+
+impl Default for ScrubConfig {
+    fn default() -> Self {
+        Self {
+            max_bytes_per_sec: 64 * 1024 * 1024,
+            batch_segments: 64,
+            pass_interval: Duration::from_secs(3600),
+        }
+    }
+}
+
+/// A segment that failed verification.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScrubFinding {
+    pub reason: String,
+    pub detected_at: u64,
+}
+
+/// Scrub progress persisted between runs so a restart resumes mid-pass.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScrubState {
+    /// Last segment verified in the current pass.
+    pub cursor: Option<SegmentId>,
+    pub passes_completed: u64,
+    pub segments_verified: u64,
+    pub bytes_verified: u64,
+    /// Segments without a checksum that could not be checked another way.
+    pub segments_unverifiable: u64,
+    pub last_pass_completed_at: Option<u64>,
+    /// Outstanding corrupt segments; entries clear once the segment verifies or is removed.
+    pub corrupt: HashMap<SegmentId, ScrubFinding>,
+}
+
+/// Outcome of one scrub batch.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScrubProgress {
+    pub verified: usize,
+    pub corrupt_found: usize,
+    pub pass_completed: bool,
+}
+
+enum Verdict {
+    Healthy,
+    Corrupt(String),
+    Unverifiable,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Walks the NVRAM log verifying stored bytes against their recorded checksums.
+///
+/// Segments written before checksums existed fall back to the content hash when
+/// they are not encrypted; encrypted legacy segments are only checked on read
+/// through their integrity tag.
+pub struct Scrubber {
+    nvram: NvramLog,
+    state_path: String,
+    state: ScrubState,
+    // Segments still to verify this pass, in id order. Listed once per pass,
+    // starting after the cursor, so batches never re-list the log.
+    pending: Option<VecDeque<SegmentId>>,
+    config: ScrubConfig,
+    event_sink: Option<Sender<Event>>,
+    #[cfg(feature = "advanced-security")]
+    audit_log: Option<AuditLog>,
+}
+
+impl Scrubber {
+    /// Open a scrubber whose progress lives at `<log path>.scrub`.
+    pub fn new(nvram: NvramLog) -> Result<Self> {
+        let state_path = format!("{}.scrub", nvram.path());
+        Self::open(nvram, state_path)
+    }
+
+    pub fn open<P: AsRef<Path>>(nvram: NvramLog, state_path: P) -> Result<Self> {
+        let state_path = state_path.as_ref().to_string_lossy().to_string();
+        let state = if Path::new(&state_path).exists() {
+            serde_json::from_str(&std::fs::read_to_string(&state_path)?)?
+        } else {
+            ScrubState::default()
+        };
+
+        Ok(Self {
+            nvram,
+            state_path,
+            state,
+            pending: None,
+            config: ScrubConfig::default(),
+            event_sink: None,
+            #[cfg(feature = "advanced-security")]
+            audit_log: None,
+        })
+    }
+
+    pub fn with_config(mut self, config: ScrubConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Deliver `Event::SegmentCorrupted` for each newly detected corrupt segment.
+    pub fn with_event_sink(mut self, sink: Sender<Event>) -> Self {
+        self.event_sink = Some(sink);
+        self
+    }
+
+    #[cfg(feature = "advanced-security")]
+    pub fn with_audit_log(mut self, audit_log: AuditLog) -> Self {
+        self.audit_log = Some(audit_log);
+        self
+    }
+
+    pub fn state(&self) -> &ScrubState {
+        &self.state
+    }
+
+    /// Verify up to `batch_segments` segments after the cursor and persist progress.
+    ///
+    /// A pass covers the segments listed when it started; segments appended
+    /// since are verified by the next one.
+    pub fn scrub_batch(&mut self) -> Result<ScrubProgress> {
+        let start = self.state.cursor.map(|id| id.0);
+        let nvram = &self.nvram;
+        let pending = self.pending.get_or_insert_with(|| {
+            let mut ids: Vec<SegmentId> = nvram
+                .list_segment_ids()
+                .into_iter()
+                .filter(|id| start.is_none_or(|cursor| id.0 > cursor))
+                .collect();
+            ids.sort_unstable_by_key(|id| id.0);
+            ids.into()
+        });
+        let batch = self.config.batch_segments.max(1);
+        let batch_ids: Vec<SegmentId> = pending.drain(..batch.min(pending.len())).collect();
+        let exhausted = pending.is_empty();
+
+        let mut progress = ScrubProgress::default();
+        let started = Instant::now();
+        let mut bytes_read = 0u64;
+
+        for seg_id in batch_ids {
+            // Removed since the pass was listed.
+            let Ok(segment) = self.nvram.get_segment_metadata(seg_id) else {
+                continue;
+            };
+            match self.verify(&segment) {
+                Verdict::Healthy => {
+                    self.state.corrupt.remove(&segment.id);
+                }
+                Verdict::Unverifiable => {
+                    self.state.segments_unverifiable += 1;
+                }
+                Verdict::Corrupt(reason) => {
+                    progress.corrupt_found += 1;
+                    self.report_corrupt(&segment, reason);
+                }
+            }
+
+            self.state.cursor = Some(segment.id);
+            self.state.segments_verified += 1;
+            self.state.bytes_verified += segment.len as u64;
+            progress.verified += 1;
+            bytes_read += segment.len as u64;
+            self.throttle(started, bytes_read);
+        }
+
+        if exhausted {
+            self.pending = None;
+            self.finish_pass();
+            progress.pass_completed = true;
+        }
+
+        self.save_state()?;
+        Ok(progress)
+    }
+
+    /// Scrub from the current cursor to the end of the log.
+    pub fn run_pass(&mut self) -> Result<&ScrubState> {
+        while !self.scrub_batch()?.pass_completed {}
+        Ok(&self.state)
+    }
+
+    /// Run passes on a background thread until the returned handle is stopped.
+    pub fn spawn(mut self) -> ScrubHandle {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_flag = Arc::clone(&stop);
+
+        let handle = thread::spawn(move || {
+            let mut next_pass = Instant::now();
+            while !stop_flag.load(Ordering::SeqCst) {
+                if Instant::now() < next_pass {
+                    thread::sleep(Duration::from_millis(250));
+                    continue;
+                }
+                match self.scrub_batch() {
+                    Ok(progress) if progress.pass_completed => {
+                        next_pass = Instant::now() + self.config.pass_interval;
+                    }
+                    Ok(_) => {}
+                    Err(err) => {
+                        error!(error = %err, "scrub batch failed");
+                        next_pass = Instant::now() + self.config.pass_interval;
+                    }
+                }
+            }
+        });
+
+        ScrubHandle {
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    fn verify(&self, segment: &Segment) -> Verdict {
+        let stored = match self.nvram.read(segment.id) {
+            Ok(bytes) => bytes,
+            // Removed by GC since the batch was listed; nothing left to verify.
+            Err(_) if self.nvram.get_segment_metadata(segment.id).is_err() => {
+                return Verdict::Healthy
+            }
+            Err(err) => return Verdict::Corrupt(format!("unreadable: {}", err)),
+        };
+
+        if let Some(expected) = &segment.checksum {
+            if &segment_checksum(&stored) == expected {
+                return Verdict::Healthy;
+            }
+            return Verdict::Corrupt("checksum mismatch".into());
+        }
+
+        match (&segment.content_hash, segment.encrypted) {
+            (Some(expected), false) if &hash_content(&stored) == expected => Verdict::Healthy,
+            (Some(_), false) => Verdict::Corrupt("content hash mismatch".into()),
+            _ => Verdict::Unverifiable,
+        }
+    }
+
+    fn report_corrupt(&mut self, segment: &Segment, reason: String) {
+        if self.state.corrupt.contains_key(&segment.id) {
+            return;
+        }
+
+        warn!(
+            segment = segment.id.0,
+            offset = segment.offset,
+            len = segment.len,
+            %reason,
+            "scrub detected corrupt segment"
+        );
+
+        let event = Event::SegmentCorrupted {
+            segment_id: segment.id,
+            offset: segment.offset,
+            len: segment.len,
+            reason: reason.clone(),
+        };
+        #[cfg(feature = "advanced-security")]
+        if let Some(log) = &self.audit_log {
+            if let Err(err) = log.append(event.clone()) {
+                warn!(error = %err, "failed to append audit log entry");
+            }
+        }
+        if let Some(sink) = &self.event_sink {
+            let _ = sink.send(event);
+        }
+
+        self.state.corrupt.insert(
+            segment.id,
+            ScrubFinding {
+                reason,
+                detected_at: now_secs(),
+            },
+        );
+    }
+
+    fn finish_pass(&mut self) {
+        let live: HashSet<SegmentId> = self.nvram.list_segment_ids().into_iter().collect();
+        self.state.corrupt.retain(|id, _| live.contains(id));
+        self.state.cursor = None;
+        self.state.passes_completed += 1;
+        self.state.last_pass_completed_at = Some(now_secs());
+        info!(
+            passes = self.state.passes_completed,
+            corrupt = self.state.corrupt.len(),
+            "scrub pass complete"
+        );
+    }
+
+    fn throttle(&self, started: Instant, bytes_read: u64) {
+        if self.config.max_bytes_per_sec == 0 {
+            return;
+        }
+        let budget =
+            Duration::from_secs_f64(bytes_read as f64 / self.config.max_bytes_per_sec as f64);
+        let elapsed = started.elapsed();
+        if budget > elapsed {
+            thread::sleep(budget - elapsed);
+        }
+    }
+
+    fn save_state(&self) -> Result<()> {
+        let tmp_path = format!("{}.tmp", self.state_path);
+        std::fs::write(&tmp_path, serde_json::to_string_pretty(&self.state)?)?;
+        std::fs::rename(&tmp_path, &self.state_path)?;
+        Ok(())
+    }
+}
+
+/// Handle to a background scrubber started with [`Scrubber::spawn`].
+pub struct ScrubHandle {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ScrubHandle {
+    /// Signal the worker to exit after its current batch and wait for it.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for ScrubHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
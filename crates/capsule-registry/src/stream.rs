@@ -0,0 +1,360 @@
+use crate::pipeline::{WritePipeline, WriteSession};
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use common::chunking::ContentDefinedChunker;
+use common::{Capsule, CapsuleId, Policy, TenantId, SEGMENT_SIZE};
+use std::io::{self, Read, Write};
+
+/// Staged segments a single large write keeps in memory before flushing them to NVRAM.
+pub const DEFAULT_FLUSH_SEGMENTS: usize = 16;
+
+/// Largest capsule a streaming write buffers for the modular pipeline, which
+/// only accepts whole capsules.
+pub const MODULAR_BUFFER_LIMIT: usize = 256 * 1024 * 1024;
+
+/// Incrementally builds a capsule from pushed chunks or a reader.
+///
+/// Input is cut into segments as it arrives, `SEGMENT_SIZE` apiece or along
+/// content-defined boundaries if the policy's layout asks for them. Staged segments
+/// are flushed to NVRAM before each write returns, and every `flush_segments`
+/// within a large one, so memory use stays bounded no matter how large the
+/// capsule grows and no NVRAM transaction holds off compaction while the
+/// writer waits for input. Nothing is visible until [`finish`] seals the
+/// capsule; dropping the writer discards everything written so far.
+///
+/// A writer from [`WritePipeline::capsule_overwriter`] publishes the result as
+/// the next version of an existing capsule instead of a new one.
+///
+/// When the modular pipeline is serving writes the input is buffered, up to
+/// [`MODULAR_BUFFER_LIMIT`], and handed to it on `finish`.
+///
+/// [`finish`]: CapsuleWriter::finish
+pub struct CapsuleWriter<'a> {
+    pipeline: &'a WritePipeline,
+    policy: Policy,
+    session: Option<WriteSession>,
+    // Capsule whose contents `finish` replaces, for overwrites.
+    replacing: Option<Capsule>,
+    buffer: Vec<u8>,
+    chunker: Option<ContentDefinedChunker>,
+    flush_segments: usize,
+    modular: bool,
+    written: u64,
+}
+
+impl<'a> CapsuleWriter<'a> {
+    pub(crate) fn new(
+        pipeline: &'a WritePipeline,
+        policy: &Policy,
+        tenant: Option<TenantId>,
+    ) -> Result<Self> {
+        let modular = pipeline.uses_modular();
+        let session = if !modular {
+            Some(pipeline.open_tenant_session(policy, tenant)?)
+        } else if tenant.is_some() {
+            anyhow::bail!("tenant namespaces are not supported by the modular pipeline");
+        } else {
+            None
+        };
+
+        Ok(Self {
+            pipeline,
+            policy: policy.clone(),
+            session,
+            replacing: None,
+            buffer: Vec::new(),
+            chunker: policy.layout.strategy.chunker(),
+            flush_segments: DEFAULT_FLUSH_SEGMENTS,
+            modular,
+            written: 0,
+        })
+    }
+
+    pub(crate) fn replacing(
+        pipeline: &'a WritePipeline,
+        capsule: Capsule,
+        session: WriteSession,
+    ) -> Self {
+        Self {
+            pipeline,
+            policy: capsule.policy.clone(),
+            session: Some(session),
+            chunker: capsule.policy.layout.strategy.chunker(),
+            replacing: Some(capsule),
+            buffer: Vec::new(),
+            flush_segments: DEFAULT_FLUSH_SEGMENTS,
+            modular: false,
+            written: 0,
+        }
+    }
+
+    /// Flush staged segments to NVRAM every `segments` segments (minimum 1).
+    pub fn with_flush_segments(mut self, segments: usize) -> Self {
+        self.flush_segments = segments.max(1);
+        self
+    }
+
+    /// Bytes accepted so far.
+    pub fn bytes_written(&self) -> u64 {
+        self.written
+    }
+
+    /// Append `data` to the capsule.
+    pub fn write_chunk(&mut self, data: &[u8]) -> Result<()> {
+        if !self.modular && self.session.is_none() {
+            return Err(anyhow!(
+                "capsule writer cannot continue after a failed write"
+            ));
+        }
+        if self.modular {
+            if self.buffer.len() + data.len() > MODULAR_BUFFER_LIMIT {
+                anyhow::bail!(
+                    "streamed capsule exceeds the {} byte limit of the modular pipeline",
+                    MODULAR_BUFFER_LIMIT
+                );
+            }
+            self.written += data.len() as u64;
+            self.buffer.extend_from_slice(data);
+            return Ok(());
+        }
+        self.written += data.len() as u64;
+        match self.chunker {
+            Some(chunker) => self.write_content_defined(chunker, data)?,
+            None => self.write_fixed(data)?,
+        }
+        // Whatever was staged goes out before the caller brings more input.
+        self.flush_staged()
+    }
+
+    fn write_fixed(&mut self, data: &[u8]) -> Result<()> {
+        let mut input = data;
+        if !self.buffer.is_empty() {
+            let take = (SEGMENT_SIZE - self.buffer.len()).min(input.len());
+            self.buffer.extend_from_slice(&input[..take]);
+            input = &input[take..];
+            if self.buffer.len() == SEGMENT_SIZE {
+                let full = std::mem::take(&mut self.buffer);
+                self.stage(&full)?;
+            }
+        }
+        while input.len() >= SEGMENT_SIZE {
+            self.stage(&input[..SEGMENT_SIZE])?;
+            input = &input[SEGMENT_SIZE..];
+        }
+        self.buffer.extend_from_slice(input);
+        Ok(())
+    }
+
+    /// Drain `reader` into the capsule, returning the number of bytes copied.
+    pub fn write_from<R: Read>(&mut self, mut reader: R) -> Result<u64> {
+        let mut chunk = vec![0u8; SEGMENT_SIZE];
+        let mut copied = 0u64;
+        loop {
+            let read = match reader.read(&mut chunk) {
+                Ok(0) => return Ok(copied),
+                Ok(read) => read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            };
+            self.write_chunk(&chunk[..read])?;
+            copied += read as u64;
+        }
+    }
+
+    /// Stage any trailing partial segment and publish the capsule.
+    pub fn finish(mut self) -> Result<CapsuleId> {
+        if self.modular {
+            let data = std::mem::take(&mut self.buffer);
+            return self.pipeline.write_capsule_with_policy(&data, &self.policy);
+        }
+
+        let tail = std::mem::take(&mut self.buffer);
+        match self.chunker {
+            Some(chunker) => {
+                for chunk in chunker.split(&tail) {
+                    self.stage(chunk)?;
+                }
+            }
+            None if !tail.is_empty() => self.stage(&tail)?,
+            None => {}
+        }
+        let session = self
+            .session
+            .take()
+            .ok_or_else(|| anyhow!("capsule writer cannot continue after a failed write"))?;
+        match self.replacing.take() {
+            Some(previous) => Ok(self.pipeline.seal_overwrite(session, &previous)?.id),
+            None => self.pipeline.seal_session(session),
+        }
+    }
+
+    /// Stage every segment whose boundary is already decided: a cut depends on
+    /// at most `max_size` bytes, so anything shorter waits for more input.
+    fn write_content_defined(&mut self, chunker: ContentDefinedChunker, data: &[u8]) -> Result<()> {
+        let mut pending = std::mem::take(&mut self.buffer);
+        pending.extend_from_slice(data);
+        let mut start = 0;
+        while pending.len() - start >= chunker.max_size() {
+            let len = chunker.cut(&pending[start..]);
+            self.stage(&pending[start..start + len])?;
+            start += len;
+        }
+        pending.drain(..start);
+        self.buffer = pending;
+        Ok(())
+    }
+
+    fn stage(&mut self, chunk: &[u8]) -> Result<()> {
+        let (pipeline, flush_segments) = (self.pipeline, self.flush_segments);
+        self.with_session(|session| {
+            pipeline.stage_chunk(session, chunk)?;
+            if session.staged_segments() >= flush_segments {
+                pipeline.flush_session(session)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn flush_staged(&mut self) -> Result<()> {
+        let pipeline = self.pipeline;
+        self.with_session(|session| pipeline.flush_session(session))
+    }
+
+    /// Run `step` on the session, abandoning it if the step fails.
+    fn with_session(&mut self, step: impl FnOnce(&mut WriteSession) -> Result<()>) -> Result<()> {
+        let session = self
+            .session
+            .as_mut()
+            .ok_or_else(|| anyhow!("capsule writer cannot continue after a failed write"))?;
+
+        let result = step(session);
+        if result.is_err() {
+            if let Some(session) = self.session.take() {
+                self.pipeline.abandon_session(session);
+            }
+        }
+        result
+    }
+}
+
+impl Write for CapsuleWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_chunk(buf).map_err(io::Error::other)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for CapsuleWriter<'_> {
+    fn drop(&mut self) {
+        if let Some(session) = self.session.take() {
+            self.pipeline.abandon_session(session);
+        }
+    }
+}
+
+/// Reads a capsule back one decoded segment at a time.
+///
+/// Iterating yields each segment's plaintext as [`Bytes`]; the [`Read`] impl
+/// serves the same data as a byte stream. Only one segment is held in memory,
+/// and holes come back as zero chunks of at most `SEGMENT_SIZE`.
+/// Under the modular pipeline the capsule is read in full and yielded as a
+/// single chunk.
+pub struct CapsuleReader<'a> {
+    pipeline: &'a WritePipeline,
+    capsule: Capsule,
+    next_segment: usize,
+    current: Bytes,
+    // Zeroes still to be yielded for the hole being read.
+    hole_remaining: u64,
+}
+
+impl<'a> CapsuleReader<'a> {
+    pub(crate) fn new(pipeline: &'a WritePipeline, id: CapsuleId) -> Result<Self> {
+        let capsule = pipeline.lookup_capsule(id)?;
+        let mut reader = Self {
+            pipeline,
+            next_segment: 0,
+            current: Bytes::new(),
+            hole_remaining: 0,
+            capsule,
+        };
+
+        if pipeline.uses_modular() {
+            reader.current = Bytes::from(pipeline.read_capsule(id)?);
+            reader.next_segment = reader.capsule.segments.len();
+        } else {
+            pipeline.record_read(&reader.capsule);
+        }
+        Ok(reader)
+    }
+
+    pub fn capsule_id(&self) -> CapsuleId {
+        self.capsule.id
+    }
+
+    /// Total capsule size in bytes.
+    pub fn size(&self) -> u64 {
+        self.capsule.size
+    }
+
+    fn next_chunk(&mut self) -> Option<Result<Bytes>> {
+        if !self.current.is_empty() {
+            return Some(Ok(std::mem::take(&mut self.current)));
+        }
+        if self.hole_remaining > 0 {
+            let len = self.hole_remaining.min(SEGMENT_SIZE as u64);
+            self.hole_remaining -= len;
+            return Some(Ok(Bytes::from(vec![0; len as usize])));
+        }
+        while self.next_segment < self.capsule.segments.len() {
+            let index = self.next_segment;
+            self.next_segment += 1;
+            if let Some(len) = self.capsule.segments[index].hole_len() {
+                // Large holes are yielded a segment's worth at a time.
+                self.hole_remaining = len;
+                return self.next_chunk();
+            }
+            match self.pipeline.read_segment(&self.capsule, index) {
+                Ok(data) if data.is_empty() => continue,
+                Ok(data) => return Some(Ok(Bytes::from(data))),
+                Err(err) => {
+                    // Stop after an error rather than skipping over missing data.
+                    self.next_segment = self.capsule.segments.len();
+                    return Some(Err(err));
+                }
+            }
+        }
+        None
+    }
+}
+
+impl Iterator for CapsuleReader<'_> {
+    type Item = Result<Bytes>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_chunk()
+    }
+}
+
+impl Read for CapsuleReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.current.is_empty() {
+            match self.next_chunk() {
+                Some(Ok(chunk)) => self.current = chunk,
+                Some(Err(err)) => return Err(io::Error::other(err)),
+                None => return Ok(0),
+            }
+        }
+
+        let len = buf.len().min(self.current.len());
+        buf[..len].copy_from_slice(&self.current.split_to(len));
+        Ok(len)
+    }
+}
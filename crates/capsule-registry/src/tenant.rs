@@ -0,0 +1,59 @@
+//! Tenant isolation: per-tenant dedup domains, quotas and usage accounting.
+//!
+//! Dedup across tenants would let one tenant learn, from how fast a write
+//! completes, whether another already stored the same bytes. The content store
+//! is therefore partitioned, and a write only ever looks up hashes in the
+//! partition of the tenant it writes for.
+
+use crate::QuotaError;
+use common::TenantId;
+use serde::{Deserialize, Serialize};
+
+/// Byte limits on a tenant's logical usage, the sum of its live capsule sizes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TenantQuota {
+    /// Exceeding this is allowed but reported as a `QuotaSoftLimitExceeded` event.
+    #[serde(default)]
+    pub soft_bytes: Option<u64>,
+    /// Writes that would exceed this are refused with a [`QuotaError`](crate::QuotaError).
+    #[serde(default)]
+    pub hard_bytes: Option<u64>,
+}
+
+impl TenantQuota {
+    pub fn is_unlimited(&self) -> bool {
+        self.soft_bytes.is_none() && self.hard_bytes.is_none()
+    }
+}
+
+/// Refuse a capsule of `size` bytes from a tenant already storing `used`
+/// bytes elsewhere, if together they exceed `hard`.
+pub(crate) fn check_hard_quota(
+    tenant: &TenantId,
+    used: u64,
+    size: u64,
+    hard: u64,
+) -> Result<(), QuotaError> {
+    let requested_bytes = used.saturating_add(size);
+    if requested_bytes > hard {
+        return Err(QuotaError {
+            tenant: tenant.clone(),
+            requested_bytes,
+            hard_limit_bytes: hard,
+        });
+    }
+    Ok(())
+}
+
+/// Space a tenant is using, as reported by
+/// [`WritePipeline::tenant_usage`](crate::pipeline::WritePipeline::tenant_usage).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TenantUsage {
+    pub capsules: usize,
+    /// Sum of live capsule sizes; this is what quotas limit.
+    pub logical_bytes: u64,
+    /// Stored bytes of the distinct segments behind the tenant's capsules and
+    /// retained versions. Dedup never crosses tenants, so no segment is
+    /// counted against two of them.
+    pub physical_bytes: u64,
+}
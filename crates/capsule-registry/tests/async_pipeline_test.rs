@@ -9,6 +9,7 @@ fn cleanup(log_path: &str, meta_path: &str) {
     let _ = fs::remove_file(log_path);
     let _ = fs::remove_file(format!("{}.segments", log_path));
     let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
 }
 
 use std::sync::Once;
@@ -36,8 +37,9 @@ fn async_pipeline_processes_segments_in_order() {
     // Create data spanning multiple segments to exercise ordering.
     let segment_count = 3;
     let mut data = Vec::with_capacity(SEGMENT_SIZE * segment_count + 2048);
+    // Non-zero fill: all-zero segments would be elided as holes.
     for seg in 0..segment_count {
-        data.extend(std::iter::repeat_n(seg as u8, SEGMENT_SIZE));
+        data.extend(std::iter::repeat_n(seg as u8 + 1, SEGMENT_SIZE));
     }
     data.extend((0..2048).map(|i| ((i * 37) % 251) as u8));
 
@@ -105,3 +107,43 @@ fn async_pipeline_deduplicates_repeated_payloads() {
 
     cleanup(log_path, meta_path);
 }
+
+#[test]
+fn async_pipeline_elides_zero_segments() {
+    init();
+    let log_path = "async_pipeline_holes.log";
+    let meta_path = "async_pipeline_holes.metadata";
+    cleanup(log_path, meta_path);
+
+    let registry = CapsuleRegistry::open(meta_path).expect("open registry");
+    let nvram = NvramLog::open(log_path).expect("open nvram");
+    let pipeline = WritePipeline::new(registry.clone(), nvram.clone());
+
+    let mut data = vec![0u8; SEGMENT_SIZE];
+    data.extend(std::iter::repeat_n(9u8, SEGMENT_SIZE));
+    data.extend(vec![0u8; 4096]);
+
+    let rt = tokio::runtime::Runtime::new().expect("runtime");
+    let capsule_id = rt
+        .block_on(pipeline.write_capsule_with_policy_async(&data, &Policy::default()))
+        .expect("write capsule");
+
+    let capsule = registry.lookup(capsule_id).expect("capsule lookup");
+    assert_eq!(capsule.segments.len(), 3);
+    assert_eq!(capsule.segments[0].hole_len(), Some(SEGMENT_SIZE as u64));
+    assert!(!capsule.segments[1].is_hole());
+    assert_eq!(capsule.segments[2].hole_len(), Some(4096));
+    assert_eq!(nvram.list_segment_ids().len(), 1);
+    assert_eq!(pipeline.read_capsule(capsule_id).expect("read"), data);
+
+    let zeroes = vec![0u8; 100];
+    let all_holes = rt
+        .block_on(pipeline.write_capsule_with_policy_async(&zeroes, &Policy::default()))
+        .expect("write zero capsule");
+    assert_eq!(
+        registry.lookup(all_holes).expect("lookup").segments,
+        vec![common::SegmentId::hole(100)]
+    );
+
+    cleanup(log_path, meta_path);
+}
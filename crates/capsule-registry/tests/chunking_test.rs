@@ -0,0 +1,119 @@
+use capsule_registry::{pipeline::WritePipeline, CapsuleRegistry};
+use common::{LayoutStrategy, Policy};
+use nvram_sim::NvramLog;
+use std::fs;
+use std::io::Write;
+use std::sync::Once;
+
+fn init_native_pipeline() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        std::env::set_var("SPACE_DISABLE_MODULAR_PIPELINE", "1");
+    });
+}
+
+fn setup_paths(prefix: &str) -> (String, String) {
+    let log_path = format!("{}_chunking.log", prefix);
+    let meta_path = format!("{}_chunking.metadata", prefix);
+    cleanup(&log_path, &meta_path);
+    (log_path, meta_path)
+}
+
+fn cleanup(log_path: &str, meta_path: &str) {
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
+}
+
+/// Pseudo-random bytes; the usual multiplicative pattern repeats too often
+/// for content-defined boundaries to appear in it.
+fn sample_data(len: usize, seed: u64) -> Vec<u8> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (state >> 56) as u8
+        })
+        .collect()
+}
+
+fn cdc_policy() -> Policy {
+    let mut policy = Policy::default();
+    policy.layout.strategy = LayoutStrategy::ContentDefined {
+        min_size: 16 * 1024,
+        avg_size: 64 * 1024,
+        max_size: 256 * 1024,
+    };
+    policy
+}
+
+#[test]
+fn insertion_keeps_later_segments_deduplicated() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("insertion");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+    let policy = cdc_policy();
+
+    let data = sample_data(2 * 1024 * 1024, 1);
+    let original = pipeline.write_capsule_with_policy(&data, &policy).unwrap();
+    let segments = pipeline.lookup_capsule(original).unwrap().segments.len();
+    assert!(segments > 8, "expected many segments, got {}", segments);
+    assert_eq!(nvram_view.list_segment_ids().len(), segments);
+
+    let mut edited = vec![b'!'];
+    edited.extend_from_slice(&data);
+    let id = pipeline
+        .write_capsule_with_policy(&edited, &policy)
+        .unwrap();
+    let added = nvram_view.list_segment_ids().len() - segments;
+    assert!(
+        added <= 2,
+        "a one-byte insertion stored {} new segments",
+        added
+    );
+    assert_eq!(pipeline.read_capsule(id).unwrap(), edited);
+
+    // Range writes re-chunk only the region they touch.
+    pipeline.write_range(id, 700_000, b"patched").unwrap();
+    edited[700_000..700_007].copy_from_slice(b"patched");
+    assert_eq!(pipeline.read_capsule(id).unwrap(), edited);
+    assert_eq!(
+        pipeline.read_range(id, 699_990, 30).unwrap(),
+        edited[699_990..700_020]
+    );
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn streaming_writes_cut_at_the_same_boundaries() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("streaming");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let pipeline = WritePipeline::new(registry, NvramLog::open(log_path.as_str()).unwrap());
+    let policy = cdc_policy();
+    let data = sample_data(1024 * 1024 + 333, 2);
+
+    let whole = pipeline.write_capsule_with_policy(&data, &policy).unwrap();
+    let mut writer = pipeline.capsule_writer(&policy).unwrap();
+    for piece in data.chunks(7_001) {
+        writer.write_all(piece).unwrap();
+    }
+    let streamed = writer.finish().unwrap();
+
+    assert_eq!(
+        pipeline.lookup_capsule(streamed).unwrap().segments,
+        pipeline.lookup_capsule(whole).unwrap().segments
+    );
+    assert_eq!(pipeline.read_capsule(streamed).unwrap(), data);
+
+    cleanup(&log_path, &meta_path);
+}
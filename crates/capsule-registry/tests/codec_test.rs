@@ -0,0 +1,183 @@
+use capsule_registry::fsck::Fsck;
+use capsule_registry::{pipeline::WritePipeline, CapsuleRegistry};
+use common::{CompressionPolicy, Policy, VersioningPolicy};
+use compression::{register_codec, Codec, CompressionError};
+use nvram_sim::NvramLog;
+use std::fs;
+use std::sync::Once;
+
+fn init_native_pipeline() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        std::env::set_var("SPACE_DISABLE_MODULAR_PIPELINE", "1");
+        register_codec(RunLength);
+    });
+}
+
+fn setup_paths(prefix: &str) -> (String, String) {
+    let log_path = format!("{}_codec.log", prefix);
+    let meta_path = format!("{}_codec.metadata", prefix);
+    cleanup(&log_path, &meta_path);
+    (log_path, meta_path)
+}
+
+fn cleanup(log_path: &str, meta_path: &str) {
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(CapsuleRegistry::content_index_dir(meta_path));
+}
+
+/// Run-length coding as (run, byte) pairs, standing in for a third-party codec.
+struct RunLength;
+
+impl Codec for RunLength {
+    fn name(&self) -> &'static str {
+        "rle"
+    }
+
+    fn compress(&self, data: &[u8], _level: i32) -> Result<Vec<u8>, CompressionError> {
+        let mut out = Vec::new();
+        for run in data.chunk_by(|a, b| a == b) {
+            for part in run.chunks(u8::MAX as usize) {
+                out.extend_from_slice(&[part.len() as u8, part[0]]);
+            }
+        }
+        Ok(out)
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        if !data.len().is_multiple_of(2) {
+            return Err(CompressionError::codec("rle", "odd length"));
+        }
+        Ok(data
+            .chunks(2)
+            .flat_map(|pair| std::iter::repeat_n(pair[1], pair[0] as usize))
+            .collect())
+    }
+}
+
+/// Long runs of a few byte values.
+fn runs(len: usize) -> Vec<u8> {
+    (0..len).map(|i| b"abcd"[(i / 1000) % 4]).collect()
+}
+
+fn versioned(compression: CompressionPolicy) -> Policy {
+    Policy {
+        compression,
+        versioning: Some(VersioningPolicy::default()),
+        ..Policy::default()
+    }
+}
+
+#[test]
+fn registered_codecs_store_segments_and_survive_policy_changes() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("registered");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    let data = runs(2 * 1024 * 1024);
+    let rle = CompressionPolicy::Codec {
+        name: "rle".into(),
+        level: 1,
+    };
+    let id = pipeline
+        .write_capsule_with_policy(&data, &versioned(rle))
+        .unwrap();
+    let segments = pipeline.lookup_capsule(id).unwrap().segments;
+    let first = nvram_view.get_segment_metadata(segments[0]).unwrap();
+    assert_eq!(first.compression_algo, "rle:1");
+    assert!((first.len as usize) < data.len() / 100);
+    assert_eq!(pipeline.read_capsule(id).unwrap(), data);
+
+    // Moving the capsule to another codec leaves the old version decodable.
+    // Without dedupe the new version is re-encoded rather than sharing segments.
+    let zstd = Policy {
+        dedupe: false,
+        ..versioned(CompressionPolicy::Zstd { level: 3 })
+    };
+    pipeline.transition_capsule(id, &zstd).unwrap();
+    let current = pipeline.lookup_capsule(id).unwrap().segments;
+    assert!(nvram_view
+        .get_segment_metadata(current[0])
+        .unwrap()
+        .compression_algo
+        .starts_with("zstd"));
+    assert_eq!(pipeline.read_capsule(id).unwrap(), data);
+    assert_eq!(pipeline.read_capsule_at(id, 1).unwrap(), data);
+
+    let report = Fsck::new(&registry_view, &nvram_view).check().unwrap();
+    assert!(report.is_clean(), "unexpected issues: {:?}", report.issues);
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn writes_with_unregistered_codecs_fail() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("unregistered");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    let policy = Policy {
+        compression: CompressionPolicy::Codec {
+            name: "brotli".into(),
+            level: 5,
+        },
+        ..Policy::default()
+    };
+    assert!(pipeline
+        .write_capsule_with_policy(&runs(64 * 1024), &policy)
+        .is_err());
+    assert!(nvram_view.list_segment_ids().is_empty());
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn adaptive_policies_choose_a_codec_within_the_throughput_floor() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("adaptive");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+    let adaptive = |min_bytes_per_sec| Policy {
+        compression: CompressionPolicy::Adaptive { min_bytes_per_sec },
+        dedupe: false,
+        ..Policy::default()
+    };
+    let first_segment = |id| {
+        let seg_id = pipeline.lookup_capsule(id).unwrap().segments[0];
+        nvram_view.get_segment_metadata(seg_id).unwrap()
+    };
+
+    let data = b"GET /v1/buckets/photos/objects/42 200 12ms\n".repeat(20_000);
+    let best_ratio = pipeline
+        .write_capsule_with_policy(&data, &adaptive(0))
+        .unwrap();
+    let segment = first_segment(best_ratio);
+    assert!(segment.compressed);
+    assert!(!segment.compression_algo.starts_with("identity"));
+    assert_eq!(pipeline.read_capsule(best_ratio).unwrap(), data);
+
+    // No codec compresses that fast, and storing the data as-is meets any floor.
+    let unreachable = pipeline
+        .write_capsule_with_policy(&data, &adaptive(u64::MAX))
+        .unwrap();
+    let segment = first_segment(unreachable);
+    assert!(!segment.compressed);
+    assert_eq!(segment.compression_algo, "identity");
+    assert_eq!(pipeline.read_capsule(unreachable).unwrap(), data);
+
+    cleanup(&log_path, &meta_path);
+}
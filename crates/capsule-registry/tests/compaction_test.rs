@@ -0,0 +1,182 @@
+use capsule_registry::{compaction::Compactor, pipeline::WritePipeline, CapsuleRegistry};
+use common::{Policy, SegmentId};
+use nvram_sim::NvramLog;
+use std::fs;
+use std::sync::Once;
+use std::time::Duration;
+
+fn init_native_pipeline() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        std::env::set_var("SPACE_DISABLE_MODULAR_PIPELINE", "1");
+    });
+}
+
+fn setup_paths(prefix: &str) -> (String, String) {
+    let log_path = format!("{}_compaction.log", prefix);
+    let meta_path = format!("{}_compaction.metadata", prefix);
+    cleanup(&log_path, &meta_path);
+    (log_path, meta_path)
+}
+
+fn cleanup(log_path: &str, meta_path: &str) {
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_file(format!("{}.compact", log_path));
+    let _ = fs::remove_file(format!("{}.segments.compact", log_path));
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
+}
+
+#[test]
+fn compaction_reclaims_deleted_capsule_bytes() {
+    init_native_pipeline();
+
+    let (log_path, meta_path) = setup_paths("reclaim");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    let policy = Policy {
+        compression: common::CompressionPolicy::None,
+        ..Policy::default()
+    };
+    let doomed: Vec<u8> = (0..64 * 1024).map(|i| (i % 251) as u8).collect();
+    let survivor: Vec<u8> = (0..32 * 1024).map(|i| (i % 241) as u8).collect();
+
+    let doomed_id = pipeline
+        .write_capsule_with_policy(&doomed, &policy)
+        .unwrap();
+    let survivor_id = pipeline
+        .write_capsule_with_policy(&survivor, &policy)
+        .unwrap();
+
+    pipeline.delete_capsule(doomed_id).unwrap();
+    let size_before = nvram_view.log_size().unwrap();
+    assert!(size_before >= (doomed.len() + survivor.len()) as u64);
+
+    let report = pipeline.compact().unwrap();
+    assert_eq!(report.stats.bytes_before, size_before);
+    assert_eq!(report.stats.bytes_after, nvram_view.live_bytes());
+    assert!(report.reclaimed_bytes() >= doomed.len() as u64);
+    assert_eq!(nvram_view.log_size().unwrap(), report.stats.bytes_after);
+
+    assert_eq!(pipeline.read_capsule(survivor_id).unwrap(), survivor);
+
+    // Offsets must survive a reopen of the compacted log.
+    let reopened = NvramLog::open(log_path.as_str()).unwrap();
+    let capsule = registry_view.lookup(survivor_id).unwrap();
+    let first: SegmentId = capsule.segments[0];
+    let segment = reopened.get_segment_metadata(first).unwrap();
+    assert_eq!(segment.offset, 0);
+    assert_eq!(
+        reopened.read(first).unwrap(),
+        nvram_view.read(first).unwrap()
+    );
+
+    // Appends after compaction land at the end of the compacted log.
+    let next = pipeline
+        .write_capsule_with_policy(b"post-compaction", &policy)
+        .unwrap();
+    assert_eq!(pipeline.read_capsule(next).unwrap(), b"post-compaction");
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn compaction_is_deferred_while_transaction_open() {
+    let (log_path, meta_path) = setup_paths("deferred");
+
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    nvram.append(SegmentId(1), b"live").unwrap();
+
+    let mut txn = nvram.begin_transaction().unwrap();
+    txn.append_segment(SegmentId(2), b"pending").unwrap();
+    assert!(nvram.compact().is_err());
+
+    txn.commit().unwrap();
+    let stats = nvram.compact().unwrap();
+    assert_eq!(stats.segments_moved, 2);
+    assert_eq!(nvram.read(SegmentId(2)).unwrap(), b"pending");
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn compaction_interval_follows_capsule_policies() {
+    init_native_pipeline();
+
+    let (log_path, meta_path) = setup_paths("interval");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    let compactor = Compactor::new(&registry_view, &nvram_view);
+    let manual = Policy {
+        compact_interval_secs: None,
+        ..Policy::default()
+    };
+    pipeline
+        .write_capsule_with_policy(b"no schedule", &manual)
+        .unwrap();
+    assert_eq!(compactor.interval(), None);
+
+    let hourly = Policy {
+        compact_interval_secs: Some(3600),
+        ..Policy::default()
+    };
+    let minutely = Policy {
+        compact_interval_secs: Some(60),
+        ..Policy::default()
+    };
+    pipeline
+        .write_capsule_with_policy(b"hourly", &hourly)
+        .unwrap();
+    let tightest = pipeline
+        .write_capsule_with_policy(b"minutely", &minutely)
+        .unwrap();
+    assert_eq!(compactor.interval(), Some(Duration::from_secs(60)));
+
+    // Dropping the tightest schedule falls back to the next one, and the
+    // cached minimum is rebuilt when the registry is reopened.
+    pipeline.delete_capsule(tightest).unwrap();
+    assert_eq!(compactor.interval(), Some(Duration::from_secs(3600)));
+    drop(pipeline);
+    let reopened = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    assert_eq!(
+        Compactor::new(&reopened, &nvram_view).interval(),
+        Some(Duration::from_secs(3600))
+    );
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn metadata_updates_keep_offsets_assigned_by_compaction() {
+    let (log_path, meta_path) = setup_paths("stale_offset");
+
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    nvram.append(SegmentId(1), b"reclaimed").unwrap();
+    nvram.append(SegmentId(2), b"survivor").unwrap();
+    let mut stale = nvram.get_segment_metadata(SegmentId(2)).unwrap();
+
+    nvram.remove_segment(SegmentId(1)).unwrap();
+    nvram.compact().unwrap();
+    assert_eq!(nvram.get_segment_metadata(SegmentId(2)).unwrap().offset, 0);
+
+    // A caller that read the metadata before compaction writes it back.
+    stale.ref_count = 2;
+    nvram.update_segment_metadata(SegmentId(2), stale).unwrap();
+    let segment = nvram.get_segment_metadata(SegmentId(2)).unwrap();
+    assert_eq!(segment.offset, 0);
+    assert_eq!(segment.ref_count, 2);
+    assert_eq!(nvram.read(SegmentId(2)).unwrap(), b"survivor");
+
+    cleanup(&log_path, &meta_path);
+}
@@ -0,0 +1,127 @@
+use capsule_registry::CapsuleRegistry;
+use common::{ContentHash, SegmentId, TenantId};
+use std::fs;
+use std::sync::Once;
+
+const CACHE_PAGES: usize = 8;
+
+fn init_small_cache() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        std::env::set_var("SPACE_DEDUP_CACHE_PAGES", CACHE_PAGES.to_string());
+    });
+}
+
+fn setup_path(prefix: &str) -> String {
+    init_small_cache();
+    let meta_path = format!("{}_content_index.metadata", prefix);
+    cleanup(&meta_path);
+    meta_path
+}
+
+fn cleanup(meta_path: &str) {
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(CapsuleRegistry::content_index_dir(meta_path));
+}
+
+fn hash(i: u64) -> ContentHash {
+    ContentHash::from_bytes(blake3::hash(&i.to_le_bytes()).as_bytes())
+}
+
+#[test]
+fn index_pages_in_lazily_after_restart() {
+    let meta_path = setup_path("restart");
+    let entries = 2_000u64;
+    {
+        let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+        for i in 0..entries {
+            registry.register_content(hash(i), SegmentId(i)).unwrap();
+        }
+        for i in (0..entries).step_by(10) {
+            assert!(registry.deregister_content(&hash(i), SegmentId(i)).unwrap());
+        }
+        registry.save().unwrap();
+
+        let stats = registry.content_index_stats();
+        assert_eq!(stats.entries, entries - entries / 10);
+        assert!(stats.buckets > 16, "the table never grew: {:?}", stats);
+        assert!(stats.cached_pages <= CACHE_PAGES);
+    }
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let stats = registry.content_index_stats();
+    assert!(stats.filter_restored);
+    assert_eq!(stats.cached_pages, 0, "opening should not read the table");
+
+    for i in 0..entries {
+        let expected = (i % 10 != 0).then_some(SegmentId(i));
+        assert_eq!(registry.lookup_content(&hash(i)), expected);
+    }
+    assert_eq!(registry.lookup_content(&hash(entries + 1)), None);
+    assert!(registry.content_index_stats().cached_pages <= CACHE_PAGES);
+
+    cleanup(&meta_path);
+}
+
+#[test]
+fn unflushed_changes_are_replayed_from_the_journal() {
+    let meta_path = setup_path("replay");
+    let tenant = TenantId::new("acme");
+    {
+        let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+        registry.register_content(hash(1), SegmentId(1)).unwrap();
+        registry.save().unwrap();
+
+        // None of these reach the index file before the registry goes away.
+        registry.register_content(hash(2), SegmentId(2)).unwrap();
+        registry
+            .register_content_in(Some(tenant.clone()), hash(3), SegmentId(3))
+            .unwrap();
+        assert!(registry.deregister_content(&hash(1), SegmentId(1)).unwrap());
+    }
+    // A flush that died before its log was complete is ignored.
+    let index_dir = CapsuleRegistry::content_index_dir(&meta_path);
+    fs::write(index_dir.join("wal"), b"torn").unwrap();
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    assert_eq!(registry.lookup_content(&hash(1)), None);
+    assert_eq!(registry.lookup_content(&hash(2)), Some(SegmentId(2)));
+    assert_eq!(registry.lookup_content(&hash(3)), None);
+    assert_eq!(
+        registry.lookup_content_in(Some(&tenant), &hash(3)),
+        Some(SegmentId(3))
+    );
+    assert_eq!(registry.content_index_stats().entries, 2);
+    assert!(!index_dir.join("wal").exists());
+
+    cleanup(&meta_path);
+}
+
+#[test]
+fn in_memory_content_stores_are_imported() {
+    let meta_path = setup_path("import");
+    let legacy = serde_json::json!({
+        "capsules": {},
+        "next_segment_id": 9,
+        "content_store": { hash(1).as_str(): 1, hash(2).as_str(): 2 },
+        "tenant_content": { "acme": { hash(3).as_str(): 3 } },
+    });
+    fs::write(&meta_path, serde_json::to_string_pretty(&legacy).unwrap()).unwrap();
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    assert_eq!(registry.lookup_content(&hash(2)), Some(SegmentId(2)));
+    assert_eq!(
+        registry.lookup_content_in(Some(&TenantId::new("acme")), &hash(3)),
+        Some(SegmentId(3))
+    );
+    // The checkpoint no longer carries the entries itself.
+    let metadata = fs::read_to_string(&meta_path).unwrap();
+    assert!(!metadata.contains(hash(1).as_str()));
+    drop(registry);
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    assert_eq!(registry.lookup_content(&hash(1)), Some(SegmentId(1)));
+    assert_eq!(registry.get_dedup_stats().1, 3);
+
+    cleanup(&meta_path);
+}
@@ -0,0 +1,203 @@
+use capsule_registry::{pipeline::WritePipeline, CapsuleRegistry, WriteIntent};
+use common::{CapsuleId, ContentHash, Policy, SegmentId};
+use encryption::keymanager::{KeyManager, MASTER_KEY_SIZE};
+use nvram_sim::NvramLog;
+use std::fs;
+use std::sync::Once;
+
+fn init_native_pipeline() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        std::env::set_var("SPACE_DISABLE_MODULAR_PIPELINE", "1");
+    });
+}
+
+fn setup_paths(prefix: &str) -> (String, String) {
+    let log_path = format!("{}_recovery.log", prefix);
+    let meta_path = format!("{}_recovery.metadata", prefix);
+    cleanup(&log_path, &meta_path);
+    (log_path, meta_path)
+}
+
+fn cleanup(log_path: &str, meta_path: &str) {
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
+}
+
+/// Journal an intent for a one-segment capsule, optionally committing its NVRAM
+/// transaction, and then "crash" by dropping everything.
+fn interrupted_write(
+    log_path: &str,
+    meta_path: &str,
+    reach_nvram: bool,
+) -> (CapsuleId, ContentHash) {
+    let registry = CapsuleRegistry::open(meta_path).unwrap();
+    let nvram = NvramLog::open(log_path).unwrap();
+
+    let capsule_id = CapsuleId::new();
+    let seg_id = registry.alloc_segment();
+    let hash = ContentHash("interrupted-content".into());
+
+    let mut txn = nvram.begin_transaction().unwrap();
+    let mut segment = txn.append_segment(seg_id, b"payload").unwrap();
+    segment.content_hash = Some(hash.clone());
+    txn.set_segment_metadata(seg_id, segment).unwrap();
+
+    let mut intent = WriteIntent::new(capsule_id, 7, vec![seg_id], Policy::default()).unwrap();
+    intent.new_segments = vec![seg_id];
+    intent.registrations = vec![(hash.clone(), seg_id)];
+    registry.begin_write(intent).unwrap();
+
+    if reach_nvram {
+        txn.commit().unwrap();
+    } else {
+        txn.rollback().unwrap();
+    }
+
+    (capsule_id, hash)
+}
+
+#[test]
+fn interrupted_write_rolls_forward_when_segments_are_durable() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("forward");
+
+    let (capsule_id, hash) = interrupted_write(&log_path, &meta_path, true);
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    assert_eq!(registry.pending_writes().len(), 1);
+    assert!(registry.lookup(capsule_id).is_err());
+
+    let registry_view = registry.clone();
+    let pipeline = WritePipeline::new(registry, NvramLog::open(log_path.as_str()).unwrap());
+
+    assert!(registry_view.pending_writes().is_empty());
+    assert_eq!(pipeline.read_capsule(capsule_id).unwrap(), b"payload");
+    assert!(registry_view.lookup_content(&hash).is_some());
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn interrupted_write_rolls_back_when_segments_are_missing() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("back");
+
+    let (capsule_id, hash) = interrupted_write(&log_path, &meta_path, false);
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let _pipeline = WritePipeline::new(registry, nvram);
+
+    assert!(registry_view.pending_writes().is_empty());
+    assert!(registry_view.lookup(capsule_id).is_err());
+    assert!(registry_view.lookup_content(&hash).is_none());
+    assert!(nvram_view.list_segment_ids().is_empty());
+
+    // The resolution is journaled, so a second restart sees the same catalog.
+    let reopened = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    assert!(reopened.pending_writes().is_empty());
+    assert!(reopened.lookup(capsule_id).is_err());
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn completed_write_leaves_no_intent_and_dedupes_within_capsule() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("complete");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    // Two identical 4 MiB segments in one capsule share a single staged segment.
+    let data = vec![0x5Au8; common::SEGMENT_SIZE * 2];
+    let capsule_id = pipeline
+        .write_capsule_with_policy(&data, &Policy::default())
+        .unwrap();
+
+    assert!(registry_view.pending_writes().is_empty());
+    let capsule = registry_view.lookup(capsule_id).unwrap();
+    assert_eq!(capsule.segments.len(), 2);
+    assert_eq!(capsule.segments[0], capsule.segments[1]);
+    assert!(capsule.deduped_bytes > 0);
+
+    let shared: SegmentId = capsule.segments[0];
+    let segment = nvram_view.get_segment_metadata(shared).unwrap();
+    assert_eq!(segment.ref_count, 2);
+    assert_eq!(pipeline.read_capsule(capsule_id).unwrap(), data);
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn dedupe_hit_interrupted_before_its_intent_is_reconciled() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("leak");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+    let capsule_id = pipeline
+        .write_capsule_with_policy(b"shared payload", &Policy::default())
+        .unwrap();
+    let seg_id = registry_view.lookup(capsule_id).unwrap().segments[0];
+
+    // A second write took a dedup reference and crashed before journaling.
+    nvram_view.increment_refcount(seg_id).unwrap();
+    assert!(registry_view.pending_writes().is_empty());
+    drop(pipeline);
+
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let _pipeline = WritePipeline::with_key_manager(
+        CapsuleRegistry::open(meta_path.as_str()).unwrap(),
+        nvram,
+        KeyManager::new([0x5A; MASTER_KEY_SIZE]),
+    );
+    assert_eq!(
+        nvram_view.get_segment_metadata(seg_id).unwrap().ref_count,
+        1
+    );
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn opening_another_view_leaves_live_writes_alone() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("live_view");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let writer_pipeline = WritePipeline::new(registry.clone(), nvram.clone());
+
+    let data: Vec<u8> = (0..common::SEGMENT_SIZE * 3)
+        .map(|i| (i % 253) as u8)
+        .collect();
+    let mut writer = writer_pipeline
+        .capsule_writer(&Policy::default())
+        .unwrap()
+        .with_flush_segments(1);
+    writer.write_chunk(&data).unwrap();
+    assert_eq!(registry.pending_writes().len(), 1);
+
+    // A second protocol view over the same registry must not treat the
+    // in-flight write as one left over from a crash.
+    let reader_pipeline = WritePipeline::new(registry.clone(), nvram);
+    assert_eq!(registry.pending_writes().len(), 1);
+
+    let id = writer.finish().unwrap();
+    assert_eq!(reader_pipeline.read_capsule(id).unwrap(), data);
+
+    cleanup(&log_path, &meta_path);
+}
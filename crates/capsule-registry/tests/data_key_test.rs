@@ -0,0 +1,276 @@
+use capsule_registry::fsck::Fsck;
+use capsule_registry::{pipeline::WritePipeline, CapsuleRegistry};
+use common::{CapsuleId, Policy, TenantId, VersioningPolicy};
+use encryption::keymanager::{KeyManager, MASTER_KEY_SIZE};
+use nvram_sim::NvramLog;
+use std::fs;
+use std::sync::Once;
+
+const MASTER_KEY: [u8; MASTER_KEY_SIZE] = [0x5A; MASTER_KEY_SIZE];
+
+fn init_native_pipeline() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        std::env::set_var("SPACE_DISABLE_MODULAR_PIPELINE", "1");
+    });
+}
+
+fn setup_paths(prefix: &str) -> (String, String) {
+    let log_path = format!("{}_data_key.log", prefix);
+    let meta_path = format!("{}_data_key.metadata", prefix);
+    cleanup(&log_path, &meta_path);
+    (log_path, meta_path)
+}
+
+fn cleanup(log_path: &str, meta_path: &str) {
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(CapsuleRegistry::content_index_dir(meta_path));
+}
+
+fn open_pipeline(log_path: &str, meta_path: &str) -> (WritePipeline, NvramLog) {
+    let registry = CapsuleRegistry::open(meta_path).unwrap();
+    let nvram = NvramLog::open(log_path).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::with_key_manager(registry, nvram, KeyManager::new(MASTER_KEY));
+    (pipeline, nvram_view)
+}
+
+fn assert_clean(pipeline: &WritePipeline, nvram: &NvramLog) {
+    let report = Fsck::new(pipeline.registry(), nvram).check().unwrap();
+    assert!(report.is_clean(), "unexpected issues: {:?}", report.issues);
+}
+
+#[test]
+fn capsules_seal_under_their_own_keys_and_carry_shared_ones() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("shared");
+    let (pipeline, nvram) = open_pipeline(&log_path, &meta_path);
+
+    let data = b"sealed under a per-capsule key ".repeat(40_000);
+    let other = b"a different capsule, a different key ".repeat(30_000);
+    let first = pipeline
+        .write_capsule_with_policy(&data, &Policy::encrypted())
+        .unwrap();
+    let second = pipeline
+        .write_capsule_with_policy(&other, &Policy::encrypted())
+        .unwrap();
+
+    let own_key = |id: CapsuleId| pipeline.lookup_capsule(id).unwrap().own_data_key().cloned();
+    let first_key = own_key(first).unwrap();
+    let second_key = own_key(second).unwrap();
+    assert_eq!(first_key.id.capsule, first);
+    assert_eq!(first_key.id.generation, 1);
+    assert_ne!(first_key.wrapped, second_key.wrapped);
+    for seg_id in pipeline.lookup_capsule(first).unwrap().segments {
+        let segment = nvram.get_segment_metadata(seg_id).unwrap();
+        assert!(segment.encrypted);
+        assert_eq!(segment.data_key, Some(first_key.id));
+    }
+
+    // A duplicate shares the first capsule's segments and so carries its key,
+    // which keeps it readable once that capsule is gone.
+    let copy = pipeline
+        .write_capsule_with_policy(&data, &Policy::encrypted())
+        .unwrap();
+    let copy_record = pipeline.lookup_capsule(copy).unwrap();
+    assert_eq!(
+        copy_record.segments,
+        pipeline.lookup_capsule(first).unwrap().segments
+    );
+    assert_eq!(copy_record.data_key(first_key.id), Some(&first_key));
+
+    pipeline.delete_capsule(first).unwrap();
+    assert_eq!(pipeline.read_capsule(copy).unwrap(), data);
+    assert_eq!(pipeline.read_capsule(second).unwrap(), other);
+    assert_clean(&pipeline, &nvram);
+
+    drop(pipeline);
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn master_key_rotation_only_rewraps_data_keys() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("master");
+    let (pipeline, nvram) = open_pipeline(&log_path, &meta_path);
+
+    let data = b"rotating the master key leaves data alone ".repeat(30_000);
+    let id = pipeline
+        .write_capsule_with_policy(&data, &Policy::encrypted())
+        .unwrap();
+    let before = pipeline.lookup_capsule(id).unwrap();
+    let sealed = nvram.get_segment_metadata(before.segments[0]).unwrap();
+    assert_eq!(before.own_data_key().unwrap().kek_version, 1);
+
+    assert_eq!(pipeline.rotate_master_key().unwrap(), 2);
+    let after = pipeline.lookup_capsule(id).unwrap();
+    let rewrapped = after.own_data_key().unwrap();
+    assert_eq!(rewrapped.id, before.own_data_key().unwrap().id);
+    assert_eq!(rewrapped.kek_version, 2);
+    assert_ne!(rewrapped.wrapped, before.own_data_key().unwrap().wrapped);
+    assert_eq!(after.segments, before.segments);
+    let segment = nvram.get_segment_metadata(after.segments[0]).unwrap();
+    assert_eq!(segment.integrity_tag, sealed.integrity_tag);
+    assert_eq!(pipeline.rewrap_data_keys().unwrap(), 0);
+
+    // The rewrapped key is persisted and unwraps from scratch after a restart.
+    drop(pipeline);
+    let (pipeline, nvram) = open_pipeline(&log_path, &meta_path);
+    assert_eq!(
+        pipeline
+            .lookup_capsule(id)
+            .unwrap()
+            .own_data_key()
+            .unwrap()
+            .kek_version,
+        2
+    );
+    assert_eq!(pipeline.read_capsule(id).unwrap(), data);
+    assert_clean(&pipeline, &nvram);
+
+    drop(pipeline);
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn rotating_a_data_key_reseals_the_capsule() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("rotate");
+    let (pipeline, nvram) = open_pipeline(&log_path, &meta_path);
+
+    let policy = Policy {
+        versioning: Some(VersioningPolicy::default()),
+        ..Policy::encrypted()
+    };
+    let data = b"re-sealed under the next generation ".repeat(30_000);
+    let id = pipeline.write_capsule_with_policy(&data, &policy).unwrap();
+    let original = pipeline.lookup_capsule(id).unwrap();
+
+    let rotated = pipeline.rotate_data_key(id).unwrap();
+    let key = rotated.own_data_key().unwrap().id;
+    assert_eq!(key.generation, 2);
+    assert_eq!(rotated.data_keys.len(), 1);
+    for seg_id in &rotated.segments {
+        assert!(!original.segments.contains(seg_id));
+        let segment = nvram.get_segment_metadata(*seg_id).unwrap();
+        assert_eq!(segment.data_key, Some(key));
+    }
+
+    // The retained version keeps its own key.
+    assert_eq!(pipeline.read_capsule(id).unwrap(), data);
+    assert_eq!(
+        pipeline.read_capsule_at(id, original.version).unwrap(),
+        data
+    );
+    assert_clean(&pipeline, &nvram);
+
+    let plain = pipeline
+        .write_capsule_with_policy(b"not encrypted", &Policy::default())
+        .unwrap();
+    assert!(pipeline.rotate_data_key(plain).is_err());
+
+    drop(pipeline);
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn failed_master_key_rotation_keeps_the_previous_version() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("failed_rotation");
+    let (pipeline, _nvram) = open_pipeline(&log_path, &meta_path);
+    let sealed = pipeline
+        .write_capsule_with_policy(
+            &b"sealed under the first master key ".repeat(1_000),
+            &Policy::encrypted(),
+        )
+        .unwrap();
+    drop(pipeline);
+
+    // Under a different master key the existing data key cannot be rewrapped.
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let pipeline =
+        WritePipeline::with_key_manager(registry, nvram, KeyManager::new([0x17; MASTER_KEY_SIZE]));
+    assert!(pipeline.rotate_master_key().is_err());
+    assert_eq!(
+        pipeline
+            .lookup_capsule(sealed)
+            .unwrap()
+            .own_data_key()
+            .unwrap()
+            .kek_version,
+        1
+    );
+
+    // New keys are still wrapped under the version that stayed current.
+    let fresh = pipeline
+        .write_capsule_with_policy(
+            &b"written after the failed rotation ".repeat(1_000),
+            &Policy::encrypted(),
+        )
+        .unwrap();
+    let record = pipeline.lookup_capsule(fresh).unwrap();
+    assert_eq!(record.own_data_key().unwrap().kek_version, 1);
+
+    drop(pipeline);
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn encrypted_capsules_never_share_plaintext_segments() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("plaintext");
+    let (pipeline, nvram) = open_pipeline(&log_path, &meta_path);
+
+    let data = b"written in the clear before anyone asked ".repeat(30_000);
+    let plain = pipeline
+        .write_capsule_with_policy(&data, &Policy::default())
+        .unwrap();
+    let sealed = pipeline
+        .write_capsule_with_policy(&data, &Policy::encrypted())
+        .unwrap();
+
+    let plain_segments = pipeline.lookup_capsule(plain).unwrap().segments;
+    let sealed_segments = pipeline.lookup_capsule(sealed).unwrap().segments;
+    for seg_id in &sealed_segments {
+        assert!(!plain_segments.contains(seg_id));
+        assert!(nvram.get_segment_metadata(*seg_id).unwrap().encrypted);
+    }
+    assert_eq!(pipeline.read_capsule(sealed).unwrap(), data);
+    assert_clean(&pipeline, &nvram);
+
+    drop(pipeline);
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn data_keys_are_only_found_within_a_tenant() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("tenant");
+    let (pipeline, _nvram) = open_pipeline(&log_path, &meta_path);
+
+    let (acme, globex) = (TenantId::new("acme"), TenantId::new("globex"));
+    let id = pipeline
+        .write_capsule_for_tenant(
+            &acme,
+            &b"acme's secrets ".repeat(10_000),
+            &Policy::encrypted(),
+        )
+        .unwrap();
+    let key = pipeline
+        .lookup_capsule(id)
+        .unwrap()
+        .own_data_key()
+        .unwrap()
+        .id;
+
+    let registry = pipeline.registry();
+    assert!(registry.find_data_key_in(Some(&acme), key).is_some());
+    assert!(registry.find_data_key_in(Some(&globex), key).is_none());
+    assert!(registry.find_data_key_in(None, key).is_none());
+
+    drop(pipeline);
+    cleanup(&log_path, &meta_path);
+}
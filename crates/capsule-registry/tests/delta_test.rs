@@ -0,0 +1,161 @@
+use capsule_registry::fsck::Fsck;
+use capsule_registry::{pipeline::WritePipeline, CapsuleRegistry};
+use common::{EncryptionPolicy, Policy};
+use encryption::keymanager::{KeyManager, MASTER_KEY_SIZE};
+use nvram_sim::NvramLog;
+use std::fs;
+use std::sync::Once;
+
+fn init_native_pipeline() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        std::env::set_var("SPACE_DISABLE_MODULAR_PIPELINE", "1");
+    });
+}
+
+fn setup_paths(prefix: &str) -> (String, String) {
+    let log_path = format!("{}_delta.log", prefix);
+    let meta_path = format!("{}_delta.metadata", prefix);
+    cleanup(&log_path, &meta_path);
+    (log_path, meta_path)
+}
+
+fn cleanup(log_path: &str, meta_path: &str) {
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(CapsuleRegistry::content_index_dir(meta_path));
+}
+
+fn sample_data(len: usize, seed: u64) -> Vec<u8> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (state >> 56) as u8
+        })
+        .collect()
+}
+
+/// `data` with a few bytes overwritten and a few inserted.
+fn edited(data: &[u8]) -> Vec<u8> {
+    let mut edited = data.to_vec();
+    edited[4_096..4_112].copy_from_slice(b"page header v2!!");
+    edited.splice(300_000..300_000, b"log line".iter().copied());
+    edited
+}
+
+fn delta_policy() -> Policy {
+    Policy {
+        delta_compression: true,
+        ..Policy::default()
+    }
+}
+
+#[test]
+fn near_duplicates_are_stored_as_deltas() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("near_duplicate");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+    let policy = delta_policy();
+
+    let original = sample_data(1024 * 1024, 1);
+    let first = pipeline
+        .write_capsule_with_policy(&original, &policy)
+        .unwrap();
+    let base = pipeline.lookup_capsule(first).unwrap().segments[0];
+    assert!(nvram_view
+        .get_segment_metadata(base)
+        .unwrap()
+        .sketch
+        .is_some());
+
+    let changed = edited(&original);
+    let second = pipeline
+        .write_capsule_with_policy(&changed, &policy)
+        .unwrap();
+    let delta_id = pipeline.lookup_capsule(second).unwrap().segments[0];
+    let delta = nvram_view.get_segment_metadata(delta_id).unwrap();
+    assert_eq!(delta.delta_base, Some(base));
+    assert!(delta.len < 1024, "delta stored {} bytes", delta.len);
+    assert_eq!(delta.logical_len as usize, changed.len());
+    assert_eq!(nvram_view.get_segment_metadata(base).unwrap().ref_count, 2);
+
+    assert_eq!(pipeline.read_capsule(second).unwrap(), changed);
+    assert_eq!(
+        pipeline.read_range(second, 299_990, 30).unwrap(),
+        changed[299_990..300_020]
+    );
+    let report = Fsck::new(&registry_view, &nvram_view).check().unwrap();
+    assert!(report.is_clean(), "unexpected issues: {:?}", report.issues);
+
+    // The base outlives the capsule that wrote it while a delta needs it.
+    pipeline.delete_capsule(first).unwrap();
+    assert_eq!(nvram_view.get_segment_metadata(base).unwrap().ref_count, 1);
+    assert_eq!(pipeline.read_capsule(second).unwrap(), changed);
+    let report = Fsck::new(&registry_view, &nvram_view).check().unwrap();
+    assert!(report.is_clean(), "unexpected issues: {:?}", report.issues);
+
+    pipeline.delete_capsule(second).unwrap();
+    assert!(nvram_view.list_segment_ids().is_empty());
+    assert_eq!(registry_view.similarity_index_stats().entries, 0);
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn similarity_index_survives_restart() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("restart");
+    let policy = Policy {
+        encryption: EncryptionPolicy::XtsAes256 { key_version: None },
+        ..delta_policy()
+    };
+    let key_manager = || KeyManager::new([0x24; MASTER_KEY_SIZE]);
+
+    let original = sample_data(512 * 1024, 2);
+    let base = {
+        let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+        let nvram = NvramLog::open(log_path.as_str()).unwrap();
+        let pipeline = WritePipeline::with_key_manager(registry, nvram, key_manager());
+        let id = pipeline
+            .write_capsule_with_policy(&original, &policy)
+            .unwrap();
+        pipeline.lookup_capsule(id).unwrap().segments[0]
+    };
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::with_key_manager(registry, nvram, key_manager());
+
+    let changed = edited(&original);
+    let id = pipeline
+        .write_capsule_with_policy(&changed, &policy)
+        .unwrap();
+    let segment = nvram_view
+        .get_segment_metadata(pipeline.lookup_capsule(id).unwrap().segments[0])
+        .unwrap();
+    assert_eq!(segment.delta_base, Some(base));
+    assert!(segment.encrypted);
+    assert_eq!(pipeline.read_capsule(id).unwrap(), changed);
+
+    // Unrelated data is stored whole and becomes a base of its own.
+    let other = pipeline
+        .write_capsule_with_policy(&sample_data(512 * 1024, 3), &policy)
+        .unwrap();
+    let segment = nvram_view
+        .get_segment_metadata(pipeline.lookup_capsule(other).unwrap().segments[0])
+        .unwrap();
+    assert_eq!(segment.delta_base, None);
+    assert!(segment.sketch.is_some());
+
+    cleanup(&log_path, &meta_path);
+}
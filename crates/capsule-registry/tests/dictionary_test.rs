@@ -0,0 +1,161 @@
+use capsule_registry::dictionary::DictionaryTrainer;
+use capsule_registry::fsck::Fsck;
+use capsule_registry::{pipeline::WritePipeline, CapsuleRegistry};
+use common::{CapsuleId, CompressionPolicy, Policy, Segment, TenantId};
+use nvram_sim::NvramLog;
+use std::fs;
+use std::sync::Once;
+
+fn init_native_pipeline() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        std::env::set_var("SPACE_DISABLE_MODULAR_PIPELINE", "1");
+    });
+}
+
+fn setup_paths(prefix: &str) -> (String, String) {
+    let log_path = format!("{}_dictionary.log", prefix);
+    let meta_path = format!("{}_dictionary.metadata", prefix);
+    cleanup(&log_path, &meta_path);
+    (log_path, meta_path)
+}
+
+fn cleanup(log_path: &str, meta_path: &str) {
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(CapsuleRegistry::content_index_dir(meta_path));
+}
+
+/// A small JSON log record; records share their keys and most of their values.
+fn record(n: usize) -> Vec<u8> {
+    format!(
+        "{{\"request_id\":\"req-{n:08}\",\"method\":\"{}\",\"path\":\"/v1/buckets/photos/objects/{}\",\"status\":{},\"latency_ms\":{},\"user_agent\":\"space-client/2.3 (linux; x86_64)\",\"region\":\"eu-west-{}\"}}\n",
+        ["GET", "PUT", "DELETE"][n % 3],
+        n * 31 % 1000,
+        [200, 204, 404][n % 3],
+        n % 97,
+        n % 3
+    )
+    .into_bytes()
+}
+
+fn json_policy(zstd_dictionary: bool) -> Policy {
+    Policy {
+        compression: CompressionPolicy::Zstd { level: 3 },
+        zstd_dictionary,
+        ..Policy::default()
+    }
+}
+
+fn first_segment(pipeline: &WritePipeline, nvram: &NvramLog, id: CapsuleId) -> Segment {
+    let seg_id = pipeline.lookup_capsule(id).unwrap().segments[0];
+    nvram.get_segment_metadata(seg_id).unwrap()
+}
+
+#[test]
+fn trained_dictionaries_compress_small_records_across_retraining() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("small_records");
+    let tenant = TenantId::new("logs");
+    let policy = json_policy(true);
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    for n in 0..120 {
+        pipeline
+            .write_capsule_for_tenant(&tenant, &record(n), &policy)
+            .unwrap();
+    }
+    let report = DictionaryTrainer::new(&pipeline)
+        .train(Some(&tenant))
+        .unwrap();
+    assert_eq!(report.dictionary.version, 1);
+    assert!(
+        report.gain.dictionary_ratio() > report.gain.plain_ratio(),
+        "ratio {:.2} with dictionary vs {:.2} without",
+        report.gain.dictionary_ratio(),
+        report.gain.plain_ratio()
+    );
+
+    let with_dictionary = pipeline
+        .write_capsule_for_tenant(&tenant, &record(1_000), &policy)
+        .unwrap();
+    let without = pipeline
+        .write_capsule_for_tenant(&tenant, &record(1_003), &json_policy(false))
+        .unwrap();
+    let compact = first_segment(&pipeline, &nvram_view, with_dictionary);
+    let plain = first_segment(&pipeline, &nvram_view, without);
+    assert_eq!(compact.dictionary, Some(report.dictionary));
+    assert_eq!(plain.dictionary, None);
+    assert!(
+        compact.len < plain.len,
+        "{} bytes with dictionary vs {} without",
+        compact.len,
+        plain.len
+    );
+
+    // Retraining adds a version; segments written against the first still read.
+    let retrained = DictionaryTrainer::new(&pipeline)
+        .train(Some(&tenant))
+        .unwrap();
+    assert_eq!(retrained.dictionary.capsule, report.dictionary.capsule);
+    assert_eq!(retrained.dictionary.version, 2);
+    let later = pipeline
+        .write_capsule_for_tenant(&tenant, &record(2_000), &policy)
+        .unwrap();
+    assert_eq!(
+        first_segment(&pipeline, &nvram_view, later).dictionary,
+        Some(retrained.dictionary)
+    );
+    assert_eq!(
+        pipeline.list_versions(report.dictionary.capsule).unwrap(),
+        vec![1, 2]
+    );
+    assert!(pipeline.delete_capsule(report.dictionary.capsule).is_err());
+    drop(pipeline);
+
+    // A fresh pipeline reads the dictionaries back from their capsule.
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let pipeline = WritePipeline::new(registry, nvram);
+    assert_eq!(
+        registry_view.dictionary(Some(&tenant)),
+        Some(report.dictionary.capsule)
+    );
+    assert_eq!(registry_view.dictionary(None), None);
+    assert_eq!(
+        pipeline.read_capsule(with_dictionary).unwrap(),
+        record(1_000)
+    );
+    assert_eq!(pipeline.read_capsule(later).unwrap(), record(2_000));
+    let report = Fsck::new(&registry_view, &nvram_view).check().unwrap();
+    assert!(report.is_clean(), "unexpected issues: {:?}", report.issues);
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn training_needs_enough_samples() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("few_records");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let pipeline = WritePipeline::new(registry, nvram);
+    for n in 0..5 {
+        pipeline
+            .write_capsule_with_policy(&record(n), &json_policy(true))
+            .unwrap();
+    }
+
+    assert!(DictionaryTrainer::new(&pipeline).train(None).is_err());
+    assert_eq!(registry_view.dictionary(None), None);
+
+    cleanup(&log_path, &meta_path);
+}
@@ -0,0 +1,120 @@
+use capsule_registry::fsck::Fsck;
+use capsule_registry::{pipeline::WritePipeline, CapsuleRegistry};
+use common::{CompressionPolicy, EncryptionPolicy, Policy};
+use encryption::keymanager::{KeyManager, MASTER_KEY_SIZE};
+use nvram_sim::NvramLog;
+use std::fs;
+use std::sync::Once;
+
+fn init_native_pipeline() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        std::env::set_var("SPACE_DISABLE_MODULAR_PIPELINE", "1");
+    });
+}
+
+fn setup_paths(prefix: &str) -> (String, String) {
+    let log_path = format!("{}_fingerprint.log", prefix);
+    let meta_path = format!("{}_fingerprint.metadata", prefix);
+    cleanup(&log_path, &meta_path);
+    (log_path, meta_path)
+}
+
+fn cleanup(log_path: &str, meta_path: &str) {
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
+}
+
+fn policy_with(compression: CompressionPolicy) -> Policy {
+    Policy {
+        compression,
+        ..Policy::default()
+    }
+}
+
+#[test]
+fn identical_data_dedupes_across_codecs() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("codecs");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    let data = b"plaintext fingerprints ignore the codec ".repeat(300_000);
+    let lz4 = pipeline
+        .write_capsule_with_policy(&data, &policy_with(CompressionPolicy::LZ4 { level: 1 }))
+        .unwrap();
+    let stored = nvram_view.list_segment_ids().len();
+
+    let others = [
+        CompressionPolicy::Zstd { level: 3 },
+        CompressionPolicy::None,
+    ]
+    .map(|compression| {
+        pipeline
+            .write_capsule_with_policy(&data, &policy_with(compression))
+            .unwrap()
+    });
+    assert_eq!(nvram_view.list_segment_ids().len(), stored);
+
+    let segments = pipeline.lookup_capsule(lz4).unwrap().segments;
+    for id in others {
+        assert_eq!(pipeline.lookup_capsule(id).unwrap().segments, segments);
+        // Segments are decoded with the codec they were stored under, not the
+        // reading capsule's policy.
+        assert_eq!(pipeline.read_capsule(id).unwrap(), data);
+        assert_eq!(
+            pipeline.read_range(id, 5_000_000, 64).unwrap(),
+            data[5_000_000..5_000_064]
+        );
+    }
+    let first = nvram_view.get_segment_metadata(segments[0]).unwrap();
+    assert!(first.compression_algo.starts_with("lz4"));
+    assert_eq!(first.ref_count, 3);
+
+    let report = Fsck::new(&registry_view, &nvram_view).check().unwrap();
+    assert!(report.is_clean(), "unexpected issues: {:?}", report.issues);
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn encrypted_segments_dedupe_across_codecs() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("encrypted");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline =
+        WritePipeline::with_key_manager(registry, nvram, KeyManager::new([0x42; MASTER_KEY_SIZE]));
+
+    let data = b"encrypted under one tweak ".repeat(40_000);
+    let encrypted = |compression| Policy {
+        compression,
+        encryption: EncryptionPolicy::XtsAes256 { key_version: None },
+        ..Policy::default()
+    };
+    let zstd = pipeline
+        .write_capsule_with_policy(&data, &encrypted(CompressionPolicy::Zstd { level: 3 }))
+        .unwrap();
+    let lz4 = pipeline
+        .write_capsule_with_policy(&data, &encrypted(CompressionPolicy::LZ4 { level: 1 }))
+        .unwrap();
+
+    let segments = pipeline.lookup_capsule(zstd).unwrap().segments;
+    assert_eq!(pipeline.lookup_capsule(lz4).unwrap().segments, segments);
+    assert_eq!(nvram_view.list_segment_ids().len(), segments.len());
+    let segment = nvram_view.get_segment_metadata(segments[0]).unwrap();
+    assert!(segment.encrypted);
+    assert!(segment.tweak_nonce.is_some());
+    assert_eq!(pipeline.read_capsule(lz4).unwrap(), data);
+    assert_eq!(pipeline.read_capsule(zstd).unwrap(), data);
+
+    cleanup(&log_path, &meta_path);
+}
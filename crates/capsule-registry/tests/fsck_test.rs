@@ -0,0 +1,272 @@
+use capsule_registry::{
+    fsck::{Fsck, FsckIssue},
+    pipeline::WritePipeline,
+    CapsuleRegistry,
+};
+use common::{CapsuleId, ContentHash, Policy, Segment, SegmentId};
+use nvram_sim::NvramLog;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::sync::Once;
+
+fn init_native_pipeline() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        std::env::set_var("SPACE_DISABLE_MODULAR_PIPELINE", "1");
+    });
+}
+
+fn setup_paths(prefix: &str) -> (String, String) {
+    let log_path = format!("{}_fsck.log", prefix);
+    let meta_path = format!("{}_fsck.metadata", prefix);
+    cleanup(&log_path, &meta_path);
+    (log_path, meta_path)
+}
+
+fn cleanup(log_path: &str, meta_path: &str) {
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
+}
+
+#[test]
+fn clean_store_passes_fsck() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("clean");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    let data = b"fsck clean payload ".repeat(256);
+    pipeline
+        .write_capsule_with_policy(&data, &Policy::default())
+        .unwrap();
+    pipeline
+        .write_capsule_with_policy(&data, &Policy::default())
+        .unwrap();
+
+    let report = Fsck::new(&registry_view, &nvram_view).check().unwrap();
+    assert!(report.is_clean(), "unexpected issues: {:?}", report.issues);
+    assert_eq!(report.capsules_checked, 2);
+    assert_eq!(report.segments_checked, 1);
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn fsck_detects_and_repairs_inconsistencies() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("repair");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    let shared = pipeline
+        .write_capsule_with_policy(&b"shared block ".repeat(512), &Policy::default())
+        .unwrap();
+    let corrupt = pipeline
+        .write_capsule_with_policy(&b"soon to rot ".repeat(512), &Policy::default())
+        .unwrap();
+
+    // Inflate a refcount.
+    let shared_seg = registry_view.lookup(shared).unwrap().segments[0];
+    let mut meta = nvram_view.get_segment_metadata(shared_seg).unwrap();
+    meta.ref_count = 5;
+    nvram_view
+        .update_segment_metadata(shared_seg, meta)
+        .unwrap();
+
+    // Leave an unreferenced segment behind.
+    let orphan = registry_view.alloc_segment();
+    nvram_view.append(orphan, b"orphaned bytes").unwrap();
+
+    // Point the dedup index at a segment that does not exist.
+    let stale_hash = ContentHash("stale".into());
+    registry_view
+        .register_content(stale_hash.clone(), SegmentId(9_999))
+        .unwrap();
+
+    // Reference a missing segment from a capsule.
+    let dangling = CapsuleId::new();
+    registry_view
+        .create_capsule_with_segments(dangling, 1, vec![SegmentId(8_888)], Policy::default())
+        .unwrap();
+
+    // Flip bytes underneath a live segment.
+    let rot_seg = registry_view.lookup(corrupt).unwrap().segments[0];
+    let rot_meta = nvram_view.get_segment_metadata(rot_seg).unwrap();
+    {
+        let mut file = OpenOptions::new().write(true).open(&log_path).unwrap();
+        file.seek(SeekFrom::Start(rot_meta.offset)).unwrap();
+        file.write_all(b"XXXX").unwrap();
+    }
+
+    let fsck = Fsck::new(&registry_view, &nvram_view);
+    let report = fsck.check().unwrap();
+    assert!(report.issues.contains(&FsckIssue::RefcountMismatch {
+        segment: shared_seg,
+        recorded: 5,
+        actual: 1,
+    }));
+    assert!(report
+        .issues
+        .contains(&FsckIssue::OrphanSegment { segment: orphan }));
+    assert!(report.issues.contains(&FsckIssue::ContentIndexDrift {
+        hash: stale_hash.clone(),
+        segment: SegmentId(9_999),
+    }));
+    assert!(report.issues.contains(&FsckIssue::DanglingSegment {
+        capsule: dangling,
+        segment: SegmentId(8_888),
+    }));
+    assert!(report
+        .issues
+        .contains(&FsckIssue::ContentHashMismatch { segment: rot_seg }));
+    assert_eq!(report.repaired, 0);
+
+    let repaired = fsck.repair().unwrap();
+    assert_eq!(repaired.repaired, 3);
+    assert_eq!(repaired.unresolved(), 2);
+
+    assert_eq!(
+        nvram_view
+            .get_segment_metadata(shared_seg)
+            .unwrap()
+            .ref_count,
+        1
+    );
+    assert!(nvram_view.get_segment_metadata(orphan).is_err());
+    assert!(registry_view.lookup_content(&stale_hash).is_none());
+    // Damaged data is withdrawn from dedup so new writes do not share it.
+    let rot_hash = rot_meta.content_hash.unwrap();
+    assert!(registry_view.lookup_content(&rot_hash).is_none());
+
+    let after = fsck.check().unwrap();
+    assert_eq!(after.issues.len(), 2);
+    assert!(after.issues.iter().all(|issue| !issue.is_repairable()));
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn repair_settles_base_refcounts_after_reclaiming_orphan_deltas() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("delta");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    // The orphaned delta sorts before its base, so it is reclaimed first.
+    let delta = registry_view.alloc_segment();
+    let capsule = pipeline
+        .write_capsule_with_policy(&b"delta base ".repeat(512), &Policy::default())
+        .unwrap();
+    let base = registry_view.lookup(capsule).unwrap().segments[0];
+    assert!(delta.0 < base.0);
+
+    let mut delta_meta = nvram_view.append(delta, b"orphaned delta").unwrap();
+    delta_meta.delta_base = Some(base);
+    nvram_view
+        .update_segment_metadata(delta, delta_meta)
+        .unwrap();
+    let mut base_meta = nvram_view.get_segment_metadata(base).unwrap();
+    base_meta.ref_count = 5;
+    nvram_view.update_segment_metadata(base, base_meta).unwrap();
+
+    let fsck = Fsck::new(&registry_view, &nvram_view);
+    let report = fsck.check().unwrap();
+    assert!(report
+        .issues
+        .contains(&FsckIssue::OrphanSegment { segment: delta }));
+    assert!(report.issues.contains(&FsckIssue::RefcountMismatch {
+        segment: base,
+        recorded: 5,
+        actual: 2,
+    }));
+
+    let repaired = fsck.repair().unwrap();
+    assert_eq!(repaired.unresolved(), 0);
+    assert!(nvram_view.get_segment_metadata(delta).is_err());
+    // Only the capsule references the base once its delta is gone.
+    assert_eq!(nvram_view.get_segment_metadata(base).unwrap().ref_count, 1);
+    assert!(fsck.check().unwrap().is_clean());
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn fsck_flags_out_of_bounds_segments() {
+    let (log_path, meta_path) = setup_paths("bounds");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+
+    let seg_id = registry.alloc_segment();
+    nvram.append(seg_id, b"short").unwrap();
+    // Point the recorded offset past the end of the log, as a torn map write would.
+    let map_path = format!("{}.segments", log_path);
+    let mut map: HashMap<SegmentId, Segment> =
+        serde_json::from_str(&fs::read_to_string(&map_path).unwrap()).unwrap();
+    map.get_mut(&seg_id).unwrap().offset = 1 << 20;
+    fs::write(&map_path, serde_json::to_string(&map).unwrap()).unwrap();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    registry
+        .create_capsule_with_segments(CapsuleId::new(), 5, vec![seg_id], Policy::default())
+        .unwrap();
+
+    let report = Fsck::new(&registry, &nvram).check().unwrap();
+    assert_eq!(
+        report.issues,
+        vec![FsckIssue::OutOfBounds {
+            segment: seg_id,
+            offset: 1 << 20,
+            len: 5,
+            log_size: 5,
+        }]
+    );
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn fsck_flags_offsets_that_overflow() {
+    let (log_path, meta_path) = setup_paths("overflow");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+
+    let seg_id = registry.alloc_segment();
+    nvram.append(seg_id, b"short").unwrap();
+    let map_path = format!("{}.segments", log_path);
+    let mut map: HashMap<SegmentId, Segment> =
+        serde_json::from_str(&fs::read_to_string(&map_path).unwrap()).unwrap();
+    map.get_mut(&seg_id).unwrap().offset = u64::MAX - 1;
+    fs::write(&map_path, serde_json::to_string(&map).unwrap()).unwrap();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    registry
+        .create_capsule_with_segments(CapsuleId::new(), 5, vec![seg_id], Policy::default())
+        .unwrap();
+
+    let report = Fsck::new(&registry, &nvram).check().unwrap();
+    let issue = FsckIssue::OutOfBounds {
+        segment: seg_id,
+        offset: u64::MAX - 1,
+        len: 5,
+        log_size: 5,
+    };
+    assert_eq!(report.issues, vec![issue.clone()]);
+    assert!(issue.to_string().contains("past any log size"));
+
+    cleanup(&log_path, &meta_path);
+}
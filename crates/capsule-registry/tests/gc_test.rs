@@ -17,6 +17,7 @@ fn setup_paths(prefix: &str) -> (String, String) {
     let _ = fs::remove_file(log_path.as_str());
     let _ = fs::remove_file(format!("{}.segments", log_path));
     let _ = fs::remove_file(meta_path.as_str());
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
     (log_path, meta_path)
 }
 
@@ -64,6 +65,7 @@ fn refcounts_increase_and_decrease_with_capsules() {
     let _ = fs::remove_file(log_path.as_str());
     let _ = fs::remove_file(format!("{}.segments", log_path));
     let _ = fs::remove_file(meta_path.as_str());
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
 }
 
 #[test]
@@ -105,6 +107,7 @@ fn garbage_collect_reclaims_orphan_segments() {
     let _ = fs::remove_file(log_path.as_str());
     let _ = fs::remove_file(format!("{}.segments", log_path));
     let _ = fs::remove_file(meta_path.as_str());
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
 }
 
 #[cfg(feature = "modular_pipeline")]
@@ -113,8 +116,9 @@ mod modular_pipeline_gc {
     use capsule_registry::modular_pipeline::{
         DefaultPolicyEvaluator, KeyManagerKeyring, XtsEncryptor,
     };
+    use common::traits::Keyring;
     use common::{Policy, SegmentId};
-    use compression::Lz4ZstdCompressor;
+    use compression::CodecCompressor;
     use dedup::Blake3Deduper;
     use encryption::keymanager::{KeyManager, MASTER_KEY_SIZE};
     use futures::executor::block_on;
@@ -136,8 +140,9 @@ mod modular_pipeline_gc {
 
         let encryptor = XtsEncryptor::new(Arc::clone(&key_manager));
         let keyring = KeyManagerKeyring::new(Arc::clone(&key_manager));
+        let keyring_view = keyring.clone();
         let mut pipeline = ModularPipeline::new(
-            Lz4ZstdCompressor,
+            CodecCompressor,
             Blake3Deduper::default(),
             encryptor,
             storage.clone(),
@@ -149,21 +154,26 @@ mod modular_pipeline_gc {
         let mut policy = Policy::encrypted();
         policy.dedupe = false;
 
-        block_on(pipeline.write_capsule(b"modular gc data payload", &policy)).unwrap();
+        let first_id =
+            block_on(pipeline.write_capsule(b"modular gc data payload", &policy)).unwrap();
 
         {
             let mut km = key_manager.lock().unwrap();
             km.rotate().unwrap();
         }
 
-        block_on(pipeline.write_capsule(b"modular gc data payload second", &policy)).unwrap();
+        let second_id =
+            block_on(pipeline.write_capsule(b"modular gc data payload second", &policy)).unwrap();
 
         let log = nvram_sim::NvramLog::open(log_path).unwrap();
         let first = log.get_segment_metadata(SegmentId(0)).unwrap();
         let second = log.get_segment_metadata(SegmentId(1)).unwrap();
 
         assert!(first.encrypted && second.encrypted);
-        assert_ne!(first.key_version, second.key_version);
+        // New data keys are wrapped under the rotated master key version.
+        assert_ne!(first.data_key, second.data_key);
+        let kek_version = |id| keyring_view.wrapped_keys(id).unwrap()[0].kek_version;
+        assert_ne!(kek_version(first_id), kek_version(second_id));
 
         let _ = fs::remove_file(log_path);
         let _ = fs::remove_file(segments_path.as_str());
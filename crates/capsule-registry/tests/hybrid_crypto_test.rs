@@ -0,0 +1,127 @@
+#![cfg(feature = "advanced-security")]
+
+use capsule_registry::{pipeline::WritePipeline, CapsuleRegistry};
+use common::{CryptoProfile, Policy, SEGMENT_SIZE};
+use encryption::keymanager::{KeyManager, MASTER_KEY_SIZE};
+use nvram_sim::NvramLog;
+use std::fs;
+use std::sync::Once;
+
+const MASTER_KEY: [u8; MASTER_KEY_SIZE] = [0x3C; MASTER_KEY_SIZE];
+const KYBER_KEY_PATH: &str = "hybrid_crypto_test.kyber.key";
+
+fn init_hybrid_pipeline() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        std::env::set_var("SPACE_DISABLE_MODULAR_PIPELINE", "1");
+        std::env::set_var("SPACE_KYBER_KEY_PATH", KYBER_KEY_PATH);
+    });
+}
+
+fn setup_paths(prefix: &str) -> (String, String) {
+    let log_path = format!("{}_hybrid.log", prefix);
+    let meta_path = format!("{}_hybrid.metadata", prefix);
+    cleanup(&log_path, &meta_path);
+    (log_path, meta_path)
+}
+
+fn cleanup(log_path: &str, meta_path: &str) {
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(CapsuleRegistry::content_index_dir(meta_path));
+    let _ = fs::remove_file(KYBER_KEY_PATH);
+}
+
+fn open_pipeline(log_path: &str, meta_path: &str) -> WritePipeline {
+    let registry = CapsuleRegistry::open(meta_path).unwrap();
+    let nvram = NvramLog::open(log_path).unwrap();
+    WritePipeline::with_key_manager(registry, nvram, KeyManager::new(MASTER_KEY))
+}
+
+fn hybrid_policy() -> Policy {
+    Policy {
+        crypto_profile: CryptoProfile::HybridKyber,
+        ..Policy::encrypted()
+    }
+}
+
+/// Three full segments plus a partial tail, with no repeated segments.
+fn sample_data() -> Vec<u8> {
+    (0..SEGMENT_SIZE * 3 + 321)
+        .map(|i| ((i / 7) as u32).wrapping_mul(2_654_435_761).to_le_bytes()[1])
+        .collect()
+}
+
+#[test]
+fn hybrid_capsule_reads_through_a_snapshot() {
+    init_hybrid_pipeline();
+    let (log_path, meta_path) = setup_paths("snapshot");
+    let pipeline = open_pipeline(&log_path, &meta_path);
+
+    let data = sample_data();
+    let source = pipeline
+        .write_capsule_with_policy(&data, &hybrid_policy())
+        .unwrap();
+    let snapshot = pipeline.snapshot_capsule(source).unwrap();
+    assert_eq!(pipeline.read_capsule(snapshot).unwrap(), data);
+
+    // The snapshot keeps decoding once the capsule it was taken from is gone.
+    pipeline.delete_capsule(source).unwrap();
+    assert_eq!(pipeline.read_capsule(snapshot).unwrap(), data);
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn hybrid_capsule_reads_through_a_clone() {
+    init_hybrid_pipeline();
+    let (log_path, meta_path) = setup_paths("clone");
+    let pipeline = open_pipeline(&log_path, &meta_path);
+
+    let data = sample_data();
+    let source = pipeline
+        .write_capsule_with_policy(&data, &hybrid_policy())
+        .unwrap();
+    let clone = pipeline.clone_capsule(source).unwrap();
+    assert_eq!(pipeline.read_capsule(clone).unwrap(), data);
+
+    // The clone's own writes land beside segments it still shares.
+    let patch = vec![0xA5; 4096];
+    pipeline
+        .write_range(clone, SEGMENT_SIZE as u64 + 100, &patch)
+        .unwrap();
+    let mut expected = data.clone();
+    expected[SEGMENT_SIZE + 100..SEGMENT_SIZE + 100 + patch.len()].copy_from_slice(&patch);
+    assert_eq!(pipeline.read_capsule(clone).unwrap(), expected);
+    assert_eq!(pipeline.read_capsule(source).unwrap(), data);
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn hybrid_capsule_reads_after_a_mid_capsule_write() {
+    init_hybrid_pipeline();
+    let (log_path, meta_path) = setup_paths("write_range");
+    let pipeline = open_pipeline(&log_path, &meta_path);
+
+    let data = sample_data();
+    let id = pipeline
+        .write_capsule_with_policy(&data, &hybrid_policy())
+        .unwrap();
+
+    // Spans a segment boundary so later segments may shift position.
+    let patch = vec![0x5A; SEGMENT_SIZE / 2];
+    let offset = SEGMENT_SIZE + SEGMENT_SIZE * 3 / 4;
+    pipeline.write_range(id, offset as u64, &patch).unwrap();
+
+    let mut expected = data;
+    expected[offset..offset + patch.len()].copy_from_slice(&patch);
+    assert_eq!(pipeline.read_capsule(id).unwrap(), expected);
+    assert_eq!(
+        pipeline.read_range(id, offset as u64 - 10, 64).unwrap(),
+        expected[offset - 10..offset + 54]
+    );
+
+    cleanup(&log_path, &meta_path);
+}
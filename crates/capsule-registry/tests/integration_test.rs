@@ -9,6 +9,7 @@ fn test_write_and_read_capsule() {
     let _ = fs::remove_file(log_path);
     let _ = fs::remove_file(format!("{}.segments", log_path));
     let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
 
     let registry = CapsuleRegistry::open(meta_path).unwrap();
     let nvram = NvramLog::open(log_path).unwrap();
@@ -26,6 +27,7 @@ fn test_write_and_read_capsule() {
     let _ = fs::remove_file(log_path);
     let _ = fs::remove_file(format!("{}.segments", log_path));
     let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
 }
 
 #[test]
@@ -35,6 +37,7 @@ fn test_compression_integration() {
     let _ = fs::remove_file(log_path);
     let _ = fs::remove_file(format!("{}.segments", log_path));
     let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
 
     let registry = CapsuleRegistry::open(meta_path).unwrap();
     let nvram = NvramLog::open(log_path).unwrap();
@@ -50,6 +53,7 @@ fn test_compression_integration() {
     let _ = fs::remove_file(log_path);
     let _ = fs::remove_file(format!("{}.segments", log_path));
     let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
 }
 
 #[cfg(feature = "modular_pipeline")]
@@ -61,7 +65,7 @@ mod modular_pipeline_integration {
     };
     use capsule_registry::CapsuleRegistry;
     use common::{Policy, SegmentId};
-    use compression::Lz4ZstdCompressor;
+    use compression::CodecCompressor;
     use dedup::Blake3Deduper;
     use encryption::keymanager::{KeyManager, MASTER_KEY_SIZE};
     use futures::executor::block_on;
@@ -103,7 +107,7 @@ mod modular_pipeline_integration {
         let encryptor = XtsEncryptor::new(Arc::clone(&key_manager));
         let keyring = KeyManagerKeyring::new(key_manager);
         let mut pipeline = ModularPipeline::new(
-            Lz4ZstdCompressor,
+            CodecCompressor,
             Blake3Deduper::default(),
             encryptor,
             storage.clone(),
@@ -137,6 +141,7 @@ mod modular_pipeline_integration {
         let _ = fs::remove_file(&log_path);
         let _ = fs::remove_file(&segments_path);
         let _ = fs::remove_file(meta_path);
+        let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
 
         let registry = CapsuleRegistry::open(meta_path).unwrap();
         let key_manager = Arc::new(Mutex::new(KeyManager::new([0x5Au8; MASTER_KEY_SIZE])));
@@ -160,6 +165,7 @@ mod modular_pipeline_integration {
         let _ = fs::remove_file(&log_path);
         let _ = fs::remove_file(segments_path);
         let _ = fs::remove_file(meta_path);
+        let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
     }
 
     #[test]
@@ -170,6 +176,7 @@ mod modular_pipeline_integration {
         let _ = fs::remove_file(&log_path);
         let _ = fs::remove_file(&segments_path);
         let _ = fs::remove_file(meta_path);
+        let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
 
         let registry = CapsuleRegistry::open(meta_path).unwrap();
         let key_manager = Arc::new(Mutex::new(KeyManager::new([0x4Bu8; MASTER_KEY_SIZE])));
@@ -211,5 +218,60 @@ mod modular_pipeline_integration {
         let _ = fs::remove_file(&log_path);
         let _ = fs::remove_file(segments_path);
         let _ = fs::remove_file(meta_path);
+        let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
+    }
+
+    #[test]
+    fn modular_lifecycle_reports_transitions_as_unsupported() {
+        use capsule_registry::lifecycle::LifecycleManager;
+        use common::{CompressionPolicy, LifecycleRule};
+
+        let log_path = "modular_lifecycle_rules.log";
+        let meta_path = "modular_lifecycle_rules.metadata";
+        let _ = fs::remove_file(log_path);
+        let _ = fs::remove_file(format!("{}.segments", log_path));
+        let _ = fs::remove_file(meta_path);
+        let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
+
+        let registry = CapsuleRegistry::open(meta_path).unwrap();
+        let pipeline = WritePipeline::new(registry, NvramLog::open(log_path).unwrap());
+        let cold = Policy {
+            compression: CompressionPolicy::Zstd { level: 19 },
+            ..Policy::default()
+        };
+        let transitioning = pipeline
+            .write_capsule_with_policy(
+                b"transitions are not rewritten here",
+                &Policy {
+                    lifecycle: vec![LifecycleRule::transition_after(10, cold)],
+                    ..Policy::default()
+                },
+            )
+            .unwrap();
+        let expiring = pipeline
+            .write_capsule_with_policy(
+                b"expiry still runs",
+                &Policy {
+                    lifecycle: vec![LifecycleRule::expire_after(10)],
+                    ..Policy::default()
+                },
+            )
+            .unwrap();
+        let created_at = pipeline.lookup_capsule(transitioning).unwrap().created_at;
+
+        let manager = LifecycleManager::new(&pipeline);
+        let report = manager.run_at(created_at + 60);
+        assert_eq!(report.unsupported, 1);
+        assert_eq!(report.expired, 1);
+        assert_eq!(report.failed, 0);
+        assert!(pipeline.lookup_capsule(expiring).is_err());
+        let capsule = pipeline.lookup_capsule(transitioning).unwrap();
+        assert_eq!(capsule.lifecycle_step, 0);
+        assert_eq!(capsule.version, 1);
+
+        let _ = fs::remove_file(log_path);
+        let _ = fs::remove_file(format!("{}.segments", log_path));
+        let _ = fs::remove_file(meta_path);
+        let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
     }
 }
@@ -0,0 +1,154 @@
+use capsule_registry::CapsuleRegistry;
+use common::{CapsuleId, ContentHash, Policy};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+
+fn setup_path(prefix: &str) -> String {
+    let meta_path = format!("{}_journal.metadata", prefix);
+    cleanup(&meta_path);
+    meta_path
+}
+
+fn cleanup(meta_path: &str) {
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
+    let _ = fs::remove_file(format!("{}.tmp", meta_path));
+}
+
+fn line_count(path: &str) -> usize {
+    fs::read_to_string(path).unwrap().lines().count()
+}
+
+#[test]
+fn journal_replays_mutations_on_open() {
+    let meta_path = setup_path("replay");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let seg_a = registry.alloc_segment();
+    let seg_b = registry.alloc_segment();
+    let kept = CapsuleId::new();
+    let dropped = CapsuleId::new();
+    let hash = ContentHash("abc123".into());
+
+    registry
+        .create_capsule_with_segments(kept, 10, vec![seg_a], Policy::default())
+        .unwrap();
+    registry.add_segment(kept, seg_b).unwrap();
+    registry
+        .create_capsule_with_segments(dropped, 5, vec![seg_b], Policy::default())
+        .unwrap();
+    registry.register_content(hash.clone(), seg_a).unwrap();
+    registry.add_deduped_bytes(kept, 42).unwrap();
+    registry.delete_capsule(dropped).unwrap();
+
+    // One checkpoint line plus one record per mutation; nothing is rewritten.
+    assert_eq!(line_count(&meta_path), 7);
+    drop(registry);
+
+    let reopened = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let capsule = reopened.lookup(kept).unwrap();
+    assert_eq!(capsule.segments, vec![seg_a, seg_b]);
+    assert_eq!(capsule.deduped_bytes, 42);
+    assert!(reopened.lookup(dropped).is_err());
+    assert_eq!(reopened.lookup_content(&hash), Some(seg_a));
+    assert_eq!(reopened.alloc_segment().0, 2);
+
+    cleanup(&meta_path);
+}
+
+#[test]
+fn torn_tail_is_discarded_on_open() {
+    let meta_path = setup_path("torn");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let seg = registry.alloc_segment();
+    let id = CapsuleId::new();
+    registry
+        .create_capsule_with_segments(id, 1, vec![seg], Policy::default())
+        .unwrap();
+    drop(registry);
+
+    let intact_len = fs::metadata(&meta_path).unwrap().len();
+    {
+        let mut file = OpenOptions::new().append(true).open(&meta_path).unwrap();
+        file.write_all(b"0123456789abcdef {\"seq\":9,\"op\":\"capsule_del")
+            .unwrap();
+    }
+
+    let reopened = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    assert!(reopened.lookup(id).is_ok());
+    assert_eq!(fs::metadata(&meta_path).unwrap().len(), intact_len);
+
+    // Appends continue cleanly after the truncated tail.
+    reopened.delete_capsule(id).unwrap();
+    drop(reopened);
+    let again = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    assert!(again.lookup(id).is_err());
+
+    cleanup(&meta_path);
+}
+
+#[test]
+fn corrupted_record_stops_replay() {
+    let meta_path = setup_path("checksum");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let first = CapsuleId::new();
+    let second = CapsuleId::new();
+    registry
+        .create_capsule_with_segments(first, 1, vec![], Policy::default())
+        .unwrap();
+    registry
+        .create_capsule_with_segments(second, 1, vec![], Policy::default())
+        .unwrap();
+    drop(registry);
+
+    // Flip a payload byte in the last record so its checksum no longer matches.
+    let mut raw = fs::read(&meta_path).unwrap();
+    let idx = raw.len() - 3;
+    raw[idx] ^= 0x01;
+    fs::write(&meta_path, raw).unwrap();
+
+    let reopened = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    assert!(reopened.lookup(first).is_ok());
+    assert!(reopened.lookup(second).is_err());
+
+    cleanup(&meta_path);
+}
+
+#[test]
+fn save_folds_journal_into_checkpoint() {
+    let meta_path = setup_path("checkpoint");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    for _ in 0..5 {
+        registry
+            .create_capsule_with_segments(CapsuleId::new(), 1, vec![], Policy::default())
+            .unwrap();
+    }
+    assert_eq!(line_count(&meta_path), 6);
+
+    registry.save().unwrap();
+    assert_eq!(line_count(&meta_path), 1);
+
+    let reopened = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    assert_eq!(reopened.list_capsules().len(), 5);
+
+    cleanup(&meta_path);
+}
+
+#[test]
+fn legacy_json_metadata_is_migrated() {
+    let meta_path = setup_path("legacy");
+    fs::write(
+        &meta_path,
+        "{\n  \"capsules\": {},\n  \"next_segment_id\": 7,\n  \"content_store\": {}\n}",
+    )
+    .unwrap();
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    assert_eq!(registry.alloc_segment().0, 7);
+    assert_eq!(line_count(&meta_path), 1);
+
+    cleanup(&meta_path);
+}
@@ -0,0 +1,223 @@
+use capsule_registry::lifecycle::{LifecycleManager, LifecycleReport, LifecycleScheduler};
+use capsule_registry::{pipeline::WritePipeline, CapsuleRegistry};
+use common::{
+    CompressionPolicy, LifecycleRule, Policy, RetentionPolicy, VersioningPolicy, SEGMENT_SIZE,
+};
+use encryption::keymanager::{KeyManager, MASTER_KEY_SIZE};
+use nvram_sim::NvramLog;
+use std::fs;
+use std::sync::{Arc, Once};
+use std::time::{Duration, Instant};
+
+fn init_native_pipeline() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        std::env::set_var("SPACE_DISABLE_MODULAR_PIPELINE", "1");
+    });
+}
+
+fn setup_paths(prefix: &str) -> (String, String) {
+    let log_path = format!("{}_lifecycle.log", prefix);
+    let meta_path = format!("{}_lifecycle.metadata", prefix);
+    cleanup(&log_path, &meta_path);
+    (log_path, meta_path)
+}
+
+fn cleanup(log_path: &str, meta_path: &str) {
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
+}
+
+/// Distinct bytes per segment, varied by `seed` so capsules don't dedupe.
+fn sample_data(len: usize, seed: u32) -> Vec<u8> {
+    (0..len)
+        .map(|i| {
+            ((i / 3) as u32 ^ seed)
+                .wrapping_mul(2_654_435_761)
+                .to_le_bytes()[1]
+        })
+        .collect()
+}
+
+fn with_lifecycle(rules: Vec<LifecycleRule>) -> Policy {
+    Policy {
+        lifecycle: rules,
+        ..Policy::default()
+    }
+}
+
+fn open_pipeline(log_path: &str, meta_path: &str) -> WritePipeline {
+    let registry = CapsuleRegistry::open(meta_path).unwrap();
+    WritePipeline::new(registry, NvramLog::open(log_path).unwrap())
+}
+
+#[test]
+fn capsules_expire_after_their_ttl() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("expire");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    let id = pipeline
+        .write_capsule_with_policy(
+            &sample_data(SEGMENT_SIZE, 1),
+            &with_lifecycle(vec![LifecycleRule::expire_after(60)]),
+        )
+        .unwrap();
+    let keeper = pipeline
+        .write_capsule_with_policy(&sample_data(SEGMENT_SIZE, 2), &Policy::default())
+        .unwrap();
+    let created_at = pipeline.lookup_capsule(id).unwrap().created_at;
+
+    let manager = LifecycleManager::new(&pipeline);
+    assert_eq!(manager.run_at(created_at + 59), LifecycleReport::default());
+    assert_eq!(manager.run_at(created_at + 60).expired, 1);
+
+    assert!(pipeline.lookup_capsule(id).is_err());
+    assert!(pipeline.lookup_capsule(keeper).is_ok());
+    assert_eq!(nvram_view.list_segment_ids().len(), 1);
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn transitions_apply_once_across_restarts() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("transition");
+
+    let cold = Policy {
+        compression: CompressionPolicy::Zstd { level: 19 },
+        ..Policy::default()
+    };
+    let policy = with_lifecycle(vec![
+        LifecycleRule::expire_after(100),
+        LifecycleRule::transition_after(10, cold),
+    ]);
+    let data = sample_data(SEGMENT_SIZE + 4_096, 3);
+
+    let (id, created_at) = {
+        let pipeline = open_pipeline(&log_path, &meta_path);
+        let id = pipeline.write_capsule_with_policy(&data, &policy).unwrap();
+        let created_at = pipeline.lookup_capsule(id).unwrap().created_at;
+
+        let manager = LifecycleManager::new(&pipeline);
+        assert_eq!(manager.run_at(created_at + 10).transitioned, 1);
+        assert_eq!(manager.run_at(created_at + 11), LifecycleReport::default());
+        (id, created_at)
+    };
+
+    // The applied step is durable, so a restarted scheduler picks up after it.
+    let pipeline = open_pipeline(&log_path, &meta_path);
+    let capsule = pipeline.lookup_capsule(id).unwrap();
+    assert_eq!(capsule.lifecycle_step, 1);
+    assert_eq!(capsule.version, 2);
+    assert_eq!(
+        capsule.policy.compression,
+        CompressionPolicy::Zstd { level: 19 }
+    );
+    assert_eq!(capsule.policy.lifecycle.len(), 2);
+    assert_eq!(pipeline.read_capsule(id).unwrap(), data);
+
+    let manager = LifecycleManager::new(&pipeline);
+    assert_eq!(manager.run_at(created_at + 50), LifecycleReport::default());
+    assert_eq!(manager.run_at(created_at + 100).expired, 1);
+    assert!(pipeline.lookup_capsule(id).is_err());
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn transitions_keep_encryption_and_versioning() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("protected");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline =
+        WritePipeline::with_key_manager(registry, nvram, KeyManager::new([0x4B; MASTER_KEY_SIZE]));
+
+    // The target only names a colder compression; everything else is defaults.
+    let cold = Policy {
+        compression: CompressionPolicy::Zstd { level: 19 },
+        ..Policy::default()
+    };
+    let policy = Policy {
+        versioning: Some(VersioningPolicy::keep_last(2)),
+        lifecycle: vec![LifecycleRule::transition_after(10, cold)],
+        ..Policy::encrypted()
+    };
+    let data = sample_data(SEGMENT_SIZE + 4_096, 7);
+    let id = pipeline.write_capsule_with_policy(&data, &policy).unwrap();
+    let created_at = pipeline.lookup_capsule(id).unwrap().created_at;
+
+    let report = LifecycleManager::new(&pipeline).run_at(created_at + 10);
+    assert_eq!(report.transitioned, 1);
+
+    let capsule = pipeline.lookup_capsule(id).unwrap();
+    assert_eq!(
+        capsule.policy.compression,
+        CompressionPolicy::Zstd { level: 19 }
+    );
+    assert!(capsule.policy.encryption.is_enabled());
+    assert!(capsule.policy.versioning.is_some());
+    for seg_id in &capsule.segments {
+        assert!(nvram_view.get_segment_metadata(*seg_id).unwrap().encrypted);
+    }
+    assert_eq!(pipeline.list_versions(id).unwrap(), vec![1, 2]);
+    assert_eq!(pipeline.read_capsule_at(id, 1).unwrap(), data);
+    assert_eq!(pipeline.read_capsule(id).unwrap(), data);
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn expiry_waits_for_retention() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("retained");
+
+    let pipeline = open_pipeline(&log_path, &meta_path);
+    let policy = Policy {
+        retention: Some(RetentionPolicy::for_secs(3_600)),
+        ..with_lifecycle(vec![LifecycleRule::expire_after(0)])
+    };
+    let id = pipeline
+        .write_capsule_with_policy(&sample_data(SEGMENT_SIZE, 4), &policy)
+        .unwrap();
+    let created_at = pipeline.lookup_capsule(id).unwrap().created_at;
+
+    let manager = LifecycleManager::new(&pipeline);
+    assert_eq!(manager.run_at(created_at + 1).deferred, 1);
+    assert!(pipeline.lookup_capsule(id).is_ok());
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn scheduler_runs_in_the_background() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("scheduler");
+
+    let pipeline = Arc::new(open_pipeline(&log_path, &meta_path));
+    let id = pipeline
+        .write_capsule_with_policy(
+            &sample_data(SEGMENT_SIZE, 5),
+            &with_lifecycle(vec![LifecycleRule::expire_after(0)]),
+        )
+        .unwrap();
+
+    let scheduler = LifecycleScheduler::spawn(Arc::clone(&pipeline), Duration::from_secs(3_600));
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while pipeline.lookup_capsule(id).is_ok() && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    scheduler.stop();
+    assert!(pipeline.lookup_capsule(id).is_err());
+
+    cleanup(&log_path, &meta_path);
+}
@@ -0,0 +1,167 @@
+use capsule_registry::{pipeline::WritePipeline, CapsuleQuery, CapsuleRegistry};
+use common::{CapsuleId, Policy};
+use nvram_sim::NvramLog;
+use std::fs;
+use std::sync::Once;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn init_native_pipeline() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        std::env::set_var("SPACE_DISABLE_MODULAR_PIPELINE", "1");
+    });
+}
+
+fn setup_paths(prefix: &str) -> (String, String) {
+    let log_path = format!("{}_query.log", prefix);
+    let meta_path = format!("{}_query.metadata", prefix);
+    cleanup(&log_path, &meta_path);
+    (log_path, meta_path)
+}
+
+fn cleanup(log_path: &str, meta_path: &str) {
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
+}
+
+fn tagged_capsule(registry: &CapsuleRegistry, size: u64, tags: &[(&str, &str)]) -> CapsuleId {
+    let id = CapsuleId::new();
+    registry
+        .create_capsule_with_segments(id, size, Vec::new(), Policy::default())
+        .unwrap();
+    registry
+        .update_tags(id, |current| {
+            current.extend(tags.iter().map(|(k, v)| (k.to_string(), v.to_string())))
+        })
+        .unwrap();
+    id
+}
+
+fn sorted(mut ids: Vec<CapsuleId>) -> Vec<CapsuleId> {
+    ids.sort_by_key(|id| id.0);
+    ids
+}
+
+#[test]
+fn queries_combine_tags_size_and_age() {
+    let (log_path, meta_path) = setup_paths("combine");
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+
+    let logs = tagged_capsule(&registry, 100, &[("team", "infra"), ("kind", "logs")]);
+    let backup = tagged_capsule(&registry, 10_000, &[("team", "infra"), ("kind", "backup")]);
+    let media = tagged_capsule(&registry, 5_000, &[("team", "media")]);
+
+    assert_eq!(
+        sorted(registry.find_capsules(&CapsuleQuery::default().tag_value("team", "infra"))),
+        sorted(vec![logs, backup])
+    );
+    assert_eq!(
+        registry.find_capsules(&CapsuleQuery::default().tagged("kind").min_size(1_000)),
+        vec![backup]
+    );
+    assert_eq!(
+        sorted(registry.find_capsules(&CapsuleQuery::default().max_size(5_000))),
+        sorted(vec![logs, media])
+    );
+    assert!(registry
+        .find_capsules(&CapsuleQuery::default().min_size(10).max_size(5))
+        .is_empty());
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    assert_eq!(
+        registry
+            .find_capsules(&CapsuleQuery::default().created_after(now - 3_600))
+            .len(),
+        3
+    );
+    assert!(registry
+        .find_capsules(&CapsuleQuery::default().created_before(now - 3_600))
+        .is_empty());
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn index_tracks_updates_deletes_and_reopen() {
+    let (log_path, meta_path) = setup_paths("tracks");
+
+    let (kept, retagged) = {
+        let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+        let kept = tagged_capsule(&registry, 1, &[("env", "prod")]);
+        let retagged = tagged_capsule(&registry, 2, &[("env", "prod")]);
+        let deleted = tagged_capsule(&registry, 3, &[("env", "prod")]);
+
+        registry
+            .update_tags(retagged, |tags| {
+                tags.insert("env".into(), "dev".into());
+            })
+            .unwrap();
+        registry.delete_capsule(deleted).unwrap();
+        (kept, retagged)
+    };
+
+    // The index is rebuilt from the replayed capsules.
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    assert_eq!(
+        registry.find_capsules(&CapsuleQuery::default().tag_value("env", "prod")),
+        vec![kept]
+    );
+    assert_eq!(
+        registry.find_capsules(&CapsuleQuery::default().tag_value("env", "dev")),
+        vec![retagged]
+    );
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn snapshots_inherit_attributes_but_freeze_metadata() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("snapshot");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let pipeline = WritePipeline::new(registry, NvramLog::open(log_path.as_str()).unwrap());
+
+    let id = pipeline
+        .write_capsule_with_policy(&vec![7u8; 4096], &Policy::default())
+        .unwrap();
+    registry_view
+        .update_metadata(id, |metadata| {
+            metadata.insert("owner".into(), "ops".into());
+        })
+        .unwrap();
+    registry_view
+        .update_tags(id, |tags| {
+            tags.insert("tier".into(), "gold".into());
+        })
+        .unwrap();
+
+    let snapshot = pipeline.snapshot_capsule(id).unwrap();
+    let copy = registry_view.lookup(snapshot).unwrap();
+    assert_eq!(copy.metadata["owner"], "ops");
+    assert_eq!(
+        sorted(registry_view.find_capsules(&CapsuleQuery::default().tag_value("tier", "gold"))),
+        sorted(vec![id, snapshot])
+    );
+
+    assert!(registry_view
+        .update_metadata(snapshot, |metadata| metadata.clear())
+        .is_err());
+    registry_view
+        .update_tags(snapshot, |tags| {
+            tags.insert("frozen".into(), "yes".into());
+        })
+        .unwrap();
+    assert_eq!(
+        registry_view.find_capsules(&CapsuleQuery::default().tagged("frozen")),
+        vec![snapshot]
+    );
+
+    cleanup(&log_path, &meta_path);
+}
@@ -0,0 +1,145 @@
+use capsule_registry::{pipeline::WritePipeline, CapsuleRegistry};
+use common::{Policy, Segment, SegmentId, SEGMENT_SIZE};
+use nvram_sim::NvramLog;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Once;
+
+fn init_native_pipeline() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        std::env::set_var("SPACE_DISABLE_MODULAR_PIPELINE", "1");
+    });
+}
+
+fn setup_paths(prefix: &str) -> (String, String) {
+    let log_path = format!("{}_range.log", prefix);
+    let meta_path = format!("{}_range.metadata", prefix);
+    cleanup(&log_path, &meta_path);
+    (log_path, meta_path)
+}
+
+fn cleanup(log_path: &str, meta_path: &str) {
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
+}
+
+/// Two full segments plus a partial tail, with no repeated segments.
+fn sample_data() -> Vec<u8> {
+    (0..SEGMENT_SIZE * 2 + 12_345)
+        .map(|i| ((i / 7) as u32).wrapping_mul(2_654_435_761).to_le_bytes()[1])
+        .collect()
+}
+
+#[test]
+fn read_range_matches_full_read_across_boundaries() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("boundaries");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    let data = sample_data();
+    let id = pipeline
+        .write_capsule_with_policy(&data, &Policy::default())
+        .unwrap();
+
+    let boundary = SEGMENT_SIZE as u64;
+    let cases = [
+        (0u64, 4096usize),
+        (boundary - 100, 200),
+        (boundary * 2 - 1, 12_346),
+        (17, SEGMENT_SIZE * 2),
+        (data.len() as u64, 0),
+    ];
+    for (offset, len) in cases {
+        let start = offset as usize;
+        assert_eq!(
+            pipeline.read_range(id, offset, len).unwrap(),
+            &data[start..start + len],
+            "range {}+{}",
+            offset,
+            len
+        );
+    }
+
+    assert!(pipeline.read_range(id, data.len() as u64 - 1, 2).is_err());
+    // An offset near the top of the range must not wrap around into bounds.
+    assert!(pipeline.read_range(id, u64::MAX - 10, 20).is_err());
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn read_range_only_touches_covering_segments() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("covering");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let writer = WritePipeline::new(registry, nvram);
+
+    let data = sample_data();
+    let id = writer
+        .write_capsule_with_policy(&data, &Policy::default())
+        .unwrap();
+    drop(writer);
+
+    // Make the first segment unreadable; ranges beyond it must still succeed.
+    let first = registry_view.lookup(id).unwrap().segments[0];
+    let map_path = format!("{}.segments", log_path);
+    let mut map: HashMap<SegmentId, Segment> =
+        serde_json::from_str(&fs::read_to_string(&map_path).unwrap()).unwrap();
+    let meta = map.get_mut(&first).unwrap();
+    assert_eq!(meta.logical_len as usize, SEGMENT_SIZE);
+    meta.offset = 1 << 40;
+    fs::write(&map_path, serde_json::to_string(&map).unwrap()).unwrap();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let pipeline = WritePipeline::new(registry_view, nvram);
+
+    let offset = SEGMENT_SIZE + 1_000;
+    assert_eq!(
+        pipeline.read_range(id, offset as u64, 8_192).unwrap(),
+        &data[offset..offset + 8_192]
+    );
+    assert!(pipeline.read_range(id, 0, 16).is_err());
+    assert!(pipeline.read_capsule(id).is_err());
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn read_range_falls_back_for_legacy_segments() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("legacy");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    let data = sample_data();
+    let id = pipeline
+        .write_capsule_with_policy(&data, &Policy::default())
+        .unwrap();
+
+    // Segments written before logical lengths were recorded deserialize as 0.
+    for seg_id in registry_view.lookup(id).unwrap().segments {
+        let mut meta = nvram_view.get_segment_metadata(seg_id).unwrap();
+        meta.logical_len = 0;
+        nvram_view.update_segment_metadata(seg_id, meta).unwrap();
+    }
+
+    let offset = SEGMENT_SIZE - 10;
+    assert_eq!(
+        pipeline.read_range(id, offset as u64, 20).unwrap(),
+        &data[offset..offset + 20]
+    );
+
+    cleanup(&log_path, &meta_path);
+}
@@ -0,0 +1,251 @@
+use capsule_registry::{
+    gc::GarbageCollector, pipeline::WritePipeline, CapsuleRegistry, RetentionError,
+};
+use common::{Event, Policy, RetentionPolicy, VersioningPolicy, SEGMENT_SIZE};
+use nvram_sim::NvramLog;
+use std::fs;
+use std::sync::mpsc;
+use std::sync::Once;
+
+fn init_native_pipeline() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        std::env::set_var("SPACE_DISABLE_MODULAR_PIPELINE", "1");
+    });
+}
+
+fn setup_paths(prefix: &str) -> (String, String) {
+    let log_path = format!("{}_retention.log", prefix);
+    let meta_path = format!("{}_retention.metadata", prefix);
+    cleanup(&log_path, &meta_path);
+    (log_path, meta_path)
+}
+
+fn cleanup(log_path: &str, meta_path: &str) {
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
+}
+
+/// Distinct bytes per segment, varied by `seed` so writes don't dedupe.
+fn sample_data(len: usize, seed: u32) -> Vec<u8> {
+    (0..len)
+        .map(|i| {
+            ((i / 3) as u32 ^ seed)
+                .wrapping_mul(2_654_435_761)
+                .to_le_bytes()[1]
+        })
+        .collect()
+}
+
+fn retained(secs: u64) -> Policy {
+    Policy {
+        retention: Some(RetentionPolicy::for_secs(secs)),
+        ..Policy::default()
+    }
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn violations(events: &mpsc::Receiver<Event>) -> Vec<String> {
+    events
+        .try_iter()
+        .filter_map(|event| match event {
+            Event::RetentionViolated { action, .. } => Some(action),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn retained_capsule_refuses_delete_and_overwrite() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("refuses");
+
+    let (tx, events) = mpsc::channel();
+    let registry = CapsuleRegistry::open(meta_path.as_str())
+        .unwrap()
+        .with_event_sink(tx);
+    let registry_view = registry.clone();
+    let pipeline = WritePipeline::new(registry, NvramLog::open(log_path.as_str()).unwrap());
+
+    let data = sample_data(SEGMENT_SIZE, 1);
+    let id = pipeline
+        .write_capsule_with_policy(&data, &retained(3_600))
+        .unwrap();
+    let retain_until = registry_view.lookup(id).unwrap().retain_until.unwrap();
+    assert!(retain_until >= now() + 3_500);
+
+    let err = pipeline.delete_capsule(id).unwrap_err();
+    let refusal = err.downcast_ref::<RetentionError>().unwrap();
+    assert_eq!(refusal.capsule, id);
+    assert_eq!(refusal.action, "delete");
+    assert!(pipeline.write_range(id, 0, b"tampered").is_err());
+    assert!(pipeline
+        .overwrite_capsule(id, &sample_data(SEGMENT_SIZE, 2))
+        .is_err());
+
+    assert_eq!(pipeline.read_capsule(id).unwrap(), data);
+    assert_eq!(violations(&events), ["delete", "overwrite", "overwrite"]);
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn legal_hold_blocks_until_released() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("hold");
+
+    let (tx, events) = mpsc::channel();
+    let registry = CapsuleRegistry::open(meta_path.as_str())
+        .unwrap()
+        .with_event_sink(tx);
+    let registry_view = registry.clone();
+    let pipeline = WritePipeline::new(registry, NvramLog::open(log_path.as_str()).unwrap());
+
+    let id = pipeline
+        .write_capsule_with_policy(&sample_data(SEGMENT_SIZE, 3), &Policy::default())
+        .unwrap();
+    registry_view.set_legal_hold(id, true).unwrap();
+    assert!(pipeline.delete_capsule(id).is_err());
+    assert_eq!(violations(&events), ["delete"]);
+
+    // The hold survives a reopen of the registry.
+    drop(registry_view);
+    drop(pipeline);
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    assert!(registry.lookup(id).unwrap().legal_hold);
+
+    let registry_view = registry.clone();
+    let pipeline = WritePipeline::new(registry, NvramLog::open(log_path.as_str()).unwrap());
+    registry_view.set_legal_hold(id, false).unwrap();
+    pipeline.delete_capsule(id).unwrap();
+    assert!(registry_view.lookup(id).is_err());
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn retention_only_extends() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("extends");
+
+    let (tx, events) = mpsc::channel();
+    let registry = CapsuleRegistry::open(meta_path.as_str())
+        .unwrap()
+        .with_event_sink(tx);
+    let registry_view = registry.clone();
+    let pipeline = WritePipeline::new(registry, NvramLog::open(log_path.as_str()).unwrap());
+
+    let id = pipeline
+        .write_capsule_with_policy(&sample_data(SEGMENT_SIZE, 4), &Policy::default())
+        .unwrap();
+    let until = now() + 600;
+    registry_view.set_retention(id, until).unwrap();
+    assert!(registry_view.set_retention(id, until - 1).is_err());
+    registry_view.set_retention(id, until + 600).unwrap();
+    assert_eq!(
+        registry_view.lookup(id).unwrap().retain_until,
+        Some(until + 600)
+    );
+    assert_eq!(violations(&events), ["shorten retention"]);
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn expired_retention_allows_delete() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("expired");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let pipeline = WritePipeline::new(registry, NvramLog::open(log_path.as_str()).unwrap());
+
+    // A zero-length period expires the moment the capsule is written.
+    let id = pipeline
+        .write_capsule_with_policy(&sample_data(SEGMENT_SIZE, 5), &retained(0))
+        .unwrap();
+    assert!(registry_view.lookup(id).unwrap().retain_until.is_some());
+    pipeline.delete_capsule(id).unwrap();
+    assert!(registry_view.list_capsules().is_empty());
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn legal_hold_covers_version_history() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("history");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    let policy = Policy {
+        versioning: Some(VersioningPolicy::keep_last(4)),
+        ..Policy::default()
+    };
+    let first = sample_data(SEGMENT_SIZE, 6);
+    let id = pipeline.write_capsule_with_policy(&first, &policy).unwrap();
+    pipeline
+        .overwrite_capsule(id, &sample_data(SEGMENT_SIZE, 7))
+        .unwrap();
+    registry_view.set_legal_hold(id, true).unwrap();
+
+    assert!(pipeline
+        .overwrite_capsule(id, &sample_data(SEGMENT_SIZE, 8))
+        .is_err());
+    assert!(pipeline.delete_capsule(id).is_err());
+    assert_eq!(pipeline.list_versions(id).unwrap(), vec![1, 2]);
+    assert_eq!(pipeline.read_capsule_at(id, 1).unwrap(), first);
+
+    registry_view.set_legal_hold(id, false).unwrap();
+    pipeline.delete_capsule(id).unwrap();
+    assert!(nvram_view.list_segment_ids().is_empty());
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn gc_keeps_segments_of_retained_capsules() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("gc");
+
+    let (tx, events) = mpsc::channel();
+    let registry = CapsuleRegistry::open(meta_path.as_str())
+        .unwrap()
+        .with_event_sink(tx);
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    let data = sample_data(SEGMENT_SIZE, 8);
+    let id = pipeline
+        .write_capsule_with_policy(&data, &retained(3_600))
+        .unwrap();
+
+    // Simulate a refcount bug that would otherwise let GC reclaim live data.
+    let seg_id = registry_view.lookup(id).unwrap().segments[0];
+    let mut meta = nvram_view.get_segment_metadata(seg_id).unwrap();
+    meta.ref_count = 0;
+    nvram_view.update_segment_metadata(seg_id, meta).unwrap();
+
+    let reclaimed = GarbageCollector::new(&registry_view, &nvram_view)
+        .sweep()
+        .unwrap();
+    assert_eq!(reclaimed, 0);
+    assert_eq!(violations(&events), ["reclaim"]);
+    assert_eq!(pipeline.read_capsule(id).unwrap(), data);
+
+    cleanup(&log_path, &meta_path);
+}
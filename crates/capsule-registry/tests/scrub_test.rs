@@ -0,0 +1,210 @@
+use capsule_registry::{
+    pipeline::WritePipeline,
+    scrub::{ScrubConfig, Scrubber},
+    CapsuleRegistry,
+};
+use common::{Event, Policy, SegmentId};
+use nvram_sim::{segment_checksum, NvramLog};
+use std::fs::{self, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::sync::mpsc;
+use std::sync::Once;
+
+fn init_native_pipeline() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        std::env::set_var("SPACE_DISABLE_MODULAR_PIPELINE", "1");
+    });
+}
+
+fn setup_paths(prefix: &str) -> (String, String) {
+    let log_path = format!("{}_scrub.log", prefix);
+    let meta_path = format!("{}_scrub.metadata", prefix);
+    cleanup(&log_path, &meta_path);
+    (log_path, meta_path)
+}
+
+fn cleanup(log_path: &str, meta_path: &str) {
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_file(format!("{}.scrub", log_path));
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
+}
+
+fn unthrottled(batch_segments: usize) -> ScrubConfig {
+    ScrubConfig {
+        max_bytes_per_sec: 0,
+        batch_segments,
+        ..ScrubConfig::default()
+    }
+}
+
+#[test]
+fn checksums_are_recorded_for_every_segment() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("checksum");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    let plain = Policy {
+        compression: common::CompressionPolicy::None,
+        dedupe: false,
+        ..Policy::default()
+    };
+    let id = pipeline
+        .write_capsule_with_policy(b"checksummed bytes", &plain)
+        .unwrap();
+    let seg_id = registry_view.lookup(id).unwrap().segments[0];
+    let segment = nvram_view.get_segment_metadata(seg_id).unwrap();
+    assert_eq!(
+        segment.checksum,
+        Some(segment_checksum(&nvram_view.read(seg_id).unwrap()))
+    );
+
+    let direct = nvram_view.append(SegmentId(500), b"direct").unwrap();
+    assert_eq!(direct.checksum, Some(segment_checksum(b"direct")));
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn scrub_reports_corrupt_segments_once() {
+    let (log_path, meta_path) = setup_paths("corrupt");
+
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    nvram.append(SegmentId(1), b"healthy segment").unwrap();
+    let victim = nvram.append(SegmentId(2), b"doomed segment").unwrap();
+    nvram.append(SegmentId(3), b"another healthy one").unwrap();
+
+    {
+        let mut file = OpenOptions::new().write(true).open(&log_path).unwrap();
+        file.seek(SeekFrom::Start(victim.offset)).unwrap();
+        file.write_all(b"ZZ").unwrap();
+    }
+
+    let (tx, rx) = mpsc::channel();
+    let mut scrubber = Scrubber::new(nvram.clone())
+        .unwrap()
+        .with_config(unthrottled(16))
+        .with_event_sink(tx);
+
+    let state = scrubber.run_pass().unwrap().clone();
+    assert_eq!(state.passes_completed, 1);
+    assert_eq!(state.segments_verified, 3);
+    assert_eq!(state.corrupt.len(), 1);
+    assert!(state.corrupt.contains_key(&SegmentId(2)));
+
+    match rx.try_recv().unwrap() {
+        Event::SegmentCorrupted { segment_id, .. } => assert_eq!(segment_id, SegmentId(2)),
+        other => panic!("unexpected event {:?}", other),
+    }
+
+    // A second pass keeps the finding without re-emitting the event.
+    scrubber.run_pass().unwrap();
+    assert!(rx.try_recv().is_err());
+    assert_eq!(scrubber.state().corrupt.len(), 1);
+
+    // Once the segment is gone, the finding is dropped.
+    nvram.remove_segment(SegmentId(2)).unwrap();
+    scrubber.run_pass().unwrap();
+    assert!(scrubber.state().corrupt.is_empty());
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn scrub_progress_resumes_after_restart() {
+    let (log_path, meta_path) = setup_paths("resume");
+
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    for id in 0..5u64 {
+        nvram
+            .append(SegmentId(id), format!("segment {}", id).as_bytes())
+            .unwrap();
+    }
+
+    let mut first = Scrubber::new(nvram.clone())
+        .unwrap()
+        .with_config(unthrottled(2));
+    let progress = first.scrub_batch().unwrap();
+    assert_eq!(progress.verified, 2);
+    assert!(!progress.pass_completed);
+    drop(first);
+
+    let mut resumed = Scrubber::new(nvram.clone())
+        .unwrap()
+        .with_config(unthrottled(2));
+    assert_eq!(resumed.state().cursor, Some(SegmentId(1)));
+
+    let progress = resumed.scrub_batch().unwrap();
+    assert_eq!(progress.verified, 2);
+    let progress = resumed.scrub_batch().unwrap();
+    assert_eq!(progress.verified, 1);
+    assert!(progress.pass_completed);
+
+    let state = resumed.state();
+    assert_eq!(state.cursor, None);
+    assert_eq!(state.passes_completed, 1);
+    assert_eq!(state.segments_verified, 5);
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn pass_covers_the_segments_listed_when_it_started() {
+    let (log_path, meta_path) = setup_paths("listing");
+
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    for id in 0..4u64 {
+        nvram
+            .append(SegmentId(id), format!("segment {}", id).as_bytes())
+            .unwrap();
+    }
+
+    let mut scrubber = Scrubber::new(nvram.clone())
+        .unwrap()
+        .with_config(unthrottled(2));
+    assert_eq!(scrubber.scrub_batch().unwrap().verified, 2);
+
+    // Removed segments are skipped; new ones wait for the next pass.
+    nvram.remove_segment(SegmentId(3)).unwrap();
+    nvram.append(SegmentId(9), b"segment 9").unwrap();
+    let progress = scrubber.scrub_batch().unwrap();
+    assert_eq!(progress.verified, 1);
+    assert!(progress.pass_completed);
+
+    let state = scrubber.run_pass().unwrap();
+    assert_eq!(state.passes_completed, 2);
+    assert_eq!(state.segments_verified, 3 + 4);
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn legacy_segments_fall_back_to_content_hash() {
+    let (log_path, meta_path) = setup_paths("legacy");
+
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+
+    let mut plain = nvram.append(SegmentId(1), b"legacy plain").unwrap();
+    plain.checksum = None;
+    plain.content_hash = Some(dedup::hash_content(b"legacy plain"));
+    nvram.update_segment_metadata(SegmentId(1), plain).unwrap();
+
+    let mut sealed = nvram.append(SegmentId(2), b"legacy sealed").unwrap();
+    sealed.checksum = None;
+    sealed.encrypted = true;
+    nvram.update_segment_metadata(SegmentId(2), sealed).unwrap();
+
+    let mut scrubber = Scrubber::new(nvram).unwrap().with_config(unthrottled(8));
+    let state = scrubber.run_pass().unwrap();
+    assert!(state.corrupt.is_empty());
+    assert_eq!(state.segments_unverifiable, 1);
+
+    cleanup(&log_path, &meta_path);
+}
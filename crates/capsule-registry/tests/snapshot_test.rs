@@ -0,0 +1,144 @@
+use capsule_registry::{pipeline::WritePipeline, CapsuleRegistry};
+use common::{Policy, SEGMENT_SIZE};
+use nvram_sim::NvramLog;
+use std::fs;
+use std::sync::Once;
+
+fn init_native_pipeline() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        std::env::set_var("SPACE_DISABLE_MODULAR_PIPELINE", "1");
+    });
+}
+
+fn setup_paths(prefix: &str) -> (String, String) {
+    let log_path = format!("{}_snapshot.log", prefix);
+    let meta_path = format!("{}_snapshot.metadata", prefix);
+    cleanup(&log_path, &meta_path);
+    (log_path, meta_path)
+}
+
+fn cleanup(log_path: &str, meta_path: &str) {
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
+}
+
+/// Two full segments plus a partial tail, with no repeated segments.
+fn sample_data() -> Vec<u8> {
+    (0..SEGMENT_SIZE * 2 + 999)
+        .map(|i| ((i / 9) as u32).wrapping_mul(2_654_435_761).to_le_bytes()[2])
+        .collect()
+}
+
+#[test]
+fn snapshot_shares_segments_and_stays_frozen() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("frozen");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    let data = sample_data();
+    let source = pipeline
+        .write_capsule_with_policy(&data, &Policy::default())
+        .unwrap();
+    let segments_before = nvram_view.list_segment_ids().len();
+
+    let snapshot = pipeline.snapshot_capsule(source).unwrap();
+    let source_meta = registry_view.lookup(source).unwrap();
+    let snapshot_meta = registry_view.lookup(snapshot).unwrap();
+
+    assert_eq!(snapshot_meta.segments, source_meta.segments);
+    assert_eq!(snapshot_meta.size, source_meta.size);
+    assert!(snapshot_meta.read_only);
+    assert_eq!(snapshot_meta.origin, Some(source));
+    assert_eq!(registry_view.derived_capsules(source), vec![snapshot]);
+    assert_eq!(nvram_view.list_segment_ids().len(), segments_before);
+    for seg_id in &source_meta.segments {
+        assert_eq!(
+            nvram_view.get_segment_metadata(*seg_id).unwrap().ref_count,
+            2
+        );
+    }
+    assert_eq!(pipeline.read_capsule(snapshot).unwrap(), data);
+
+    assert!(pipeline.write_range(snapshot, 0, b"nope").is_err());
+
+    // Overwriting the source leaves the snapshot's view untouched.
+    pipeline.write_range(source, 10, b"changed").unwrap();
+    assert_eq!(pipeline.read_capsule(snapshot).unwrap(), data);
+    assert_eq!(&pipeline.read_range(source, 10, 7).unwrap(), b"changed");
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn clones_diverge_copy_on_write() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("clone");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let pipeline = WritePipeline::new(registry, NvramLog::open(log_path.as_str()).unwrap());
+
+    let data = sample_data();
+    let source = pipeline
+        .write_capsule_with_policy(&data, &Policy::default())
+        .unwrap();
+    let clone = pipeline.clone_capsule(source).unwrap();
+    assert!(!registry_view.lookup(clone).unwrap().read_only);
+
+    let offset = SEGMENT_SIZE + 5;
+    pipeline
+        .write_range(clone, offset as u64, b"dev copy")
+        .unwrap();
+
+    let mut expected = data.clone();
+    expected[offset..offset + 8].copy_from_slice(b"dev copy");
+    assert_eq!(pipeline.read_capsule(clone).unwrap(), expected);
+    assert_eq!(pipeline.read_capsule(source).unwrap(), data);
+
+    let source_meta = registry_view.lookup(source).unwrap();
+    let clone_meta = registry_view.lookup(clone).unwrap();
+    assert_eq!(clone_meta.segments[0], source_meta.segments[0]);
+    assert_ne!(clone_meta.segments[1], source_meta.segments[1]);
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn deleting_any_copy_keeps_the_others_readable() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("delete");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    let data = sample_data();
+    let source = pipeline
+        .write_capsule_with_policy(&data, &Policy::default())
+        .unwrap();
+    let first = pipeline.snapshot_capsule(source).unwrap();
+    let second = pipeline.snapshot_capsule(source).unwrap();
+
+    pipeline.delete_capsule(source).unwrap();
+    assert_eq!(pipeline.garbage_collect().unwrap(), 0);
+    assert_eq!(pipeline.read_capsule(first).unwrap(), data);
+
+    pipeline.delete_capsule(first).unwrap();
+    assert_eq!(pipeline.garbage_collect().unwrap(), 0);
+    assert_eq!(pipeline.read_capsule(second).unwrap(), data);
+
+    pipeline.delete_capsule(second).unwrap();
+    pipeline.garbage_collect().unwrap();
+    assert!(nvram_view.list_segment_ids().is_empty());
+
+    cleanup(&log_path, &meta_path);
+}
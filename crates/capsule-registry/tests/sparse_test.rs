@@ -0,0 +1,122 @@
+use capsule_registry::{pipeline::WritePipeline, CapsuleRegistry};
+use common::{Policy, SEGMENT_SIZE};
+use nvram_sim::NvramLog;
+use std::fs;
+use std::io::Read;
+use std::sync::Once;
+
+fn init_native_pipeline() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        std::env::set_var("SPACE_DISABLE_MODULAR_PIPELINE", "1");
+    });
+}
+
+fn setup_paths(prefix: &str) -> (String, String) {
+    let log_path = format!("{}_sparse.log", prefix);
+    let meta_path = format!("{}_sparse.metadata", prefix);
+    cleanup(&log_path, &meta_path);
+    (log_path, meta_path)
+}
+
+fn cleanup(log_path: &str, meta_path: &str) {
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
+}
+
+const TIB: u64 = 1 << 40;
+
+#[test]
+fn zero_segments_are_elided_on_write() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("elide");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    let mut data = vec![0x5A; SEGMENT_SIZE];
+    data.extend(vec![0u8; SEGMENT_SIZE * 2]);
+    data.extend(b"trailer");
+    let id = pipeline
+        .write_capsule_with_policy(&data, &Policy::default())
+        .unwrap();
+
+    let capsule = registry_view.lookup(id).unwrap();
+    assert_eq!(capsule.size, data.len() as u64);
+    assert_eq!(capsule.segments.len(), 4);
+    assert!(!capsule.segments[0].is_hole());
+    assert_eq!(capsule.segments[1].hole_len(), Some(SEGMENT_SIZE as u64));
+    assert_eq!(capsule.segments[2].hole_len(), Some(SEGMENT_SIZE as u64));
+    assert_eq!(nvram_view.list_segment_ids().len(), 2);
+
+    assert_eq!(pipeline.read_capsule(id).unwrap(), data);
+    let offset = SEGMENT_SIZE * 3 - 3;
+    assert_eq!(
+        pipeline.read_range(id, offset as u64, 10).unwrap(),
+        &data[offset..offset + 10]
+    );
+    let mut streamed = Vec::new();
+    pipeline
+        .capsule_reader(id)
+        .unwrap()
+        .read_to_end(&mut streamed)
+        .unwrap();
+    assert_eq!(streamed, data);
+
+    pipeline.delete_capsule(id).unwrap();
+    assert!(nvram_view.list_segment_ids().is_empty());
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn sparse_capsule_costs_nothing_until_written() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("thin");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    let id = pipeline
+        .create_sparse_capsule(TIB, &Policy::default())
+        .unwrap();
+    let capsule = registry_view.lookup(id).unwrap();
+    assert_eq!(capsule.size, TIB);
+    assert_eq!(capsule.segments.len(), 1);
+    assert!(nvram_view.list_segment_ids().is_empty());
+    assert_eq!(
+        pipeline.read_range(id, TIB / 2, 4096).unwrap(),
+        vec![0; 4096]
+    );
+
+    // A write deep inside the hole materializes one segment and splits the rest.
+    let offset = TIB - 3 * SEGMENT_SIZE as u64 + 17;
+    let updated = pipeline.write_range(id, offset, b"written").unwrap();
+    assert_eq!(updated.size, TIB);
+    assert_eq!(updated.segments.len(), 3);
+    assert!(updated.segments[0].is_hole());
+    assert!(!updated.segments[1].is_hole());
+    assert!(updated.segments[2].is_hole());
+    assert_eq!(nvram_view.list_segment_ids().len(), 1);
+
+    let around = pipeline.read_range(id, offset - 5, 17).unwrap();
+    assert_eq!(&around[..5], &[0; 5]);
+    assert_eq!(&around[5..12], b"written");
+    assert_eq!(&around[12..], &[0; 5]);
+    assert_eq!(pipeline.read_range(id, 0, 16).unwrap(), vec![0; 16]);
+
+    // Zeroing the written range punches the hole back.
+    pipeline.write_range(id, offset, &[0; 7]).unwrap();
+    assert!(nvram_view.list_segment_ids().is_empty());
+    assert_eq!(pipeline.read_range(id, offset, 7).unwrap(), vec![0; 7]);
+
+    cleanup(&log_path, &meta_path);
+}
@@ -0,0 +1,255 @@
+use capsule_registry::{pipeline::WritePipeline, CapsuleRegistry};
+use common::{Policy, SEGMENT_SIZE};
+use nvram_sim::NvramLog;
+use std::fs;
+use std::io::Read;
+use std::sync::Once;
+
+fn init_native_pipeline() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        std::env::set_var("SPACE_DISABLE_MODULAR_PIPELINE", "1");
+    });
+}
+
+fn setup_paths(prefix: &str) -> (String, String) {
+    let log_path = format!("{}_stream.log", prefix);
+    let meta_path = format!("{}_stream.metadata", prefix);
+    cleanup(&log_path, &meta_path);
+    (log_path, meta_path)
+}
+
+fn cleanup(log_path: &str, meta_path: &str) {
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
+}
+
+/// Distinct bytes per segment so nothing dedupes unless a test wants it to.
+fn sample_data(len: usize) -> Vec<u8> {
+    (0..len)
+        .map(|i| ((i / 3) as u32).wrapping_mul(2_654_435_761).to_le_bytes()[2])
+        .collect()
+}
+
+#[test]
+fn streamed_write_round_trips_through_reader() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("roundtrip");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let pipeline = WritePipeline::new(registry, NvramLog::open(log_path.as_str()).unwrap());
+
+    let data = sample_data(SEGMENT_SIZE * 3 + 777);
+    let mut writer = pipeline
+        .capsule_writer(&Policy::default())
+        .unwrap()
+        .with_flush_segments(1);
+    // Odd-sized pushes straddle segment boundaries.
+    for chunk in data.chunks(1_000_003) {
+        writer.write_chunk(chunk).unwrap();
+    }
+    assert_eq!(writer.bytes_written(), data.len() as u64);
+    let id = writer.finish().unwrap();
+
+    assert!(registry_view.pending_writes().is_empty());
+    let capsule = registry_view.lookup(id).unwrap();
+    assert_eq!(capsule.size, data.len() as u64);
+    assert_eq!(capsule.segments.len(), 4);
+
+    let chunks: Vec<_> = pipeline
+        .capsule_reader(id)
+        .unwrap()
+        .map(|chunk| chunk.unwrap())
+        .collect();
+    assert_eq!(chunks.len(), 4);
+    assert_eq!(chunks.concat(), data);
+
+    let mut streamed = Vec::new();
+    pipeline
+        .capsule_reader(id)
+        .unwrap()
+        .read_to_end(&mut streamed)
+        .unwrap();
+    assert_eq!(streamed, data);
+    assert_eq!(pipeline.read_capsule(id).unwrap(), data);
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn write_from_reader_dedupes_across_flushes() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("dedupe");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    let block = sample_data(SEGMENT_SIZE);
+    let data = [block.clone(), block].concat();
+    let mut writer = pipeline
+        .capsule_writer(&Policy::default())
+        .unwrap()
+        .with_flush_segments(1);
+    assert_eq!(
+        writer.write_from(data.as_slice()).unwrap(),
+        data.len() as u64
+    );
+    let id = writer.finish().unwrap();
+
+    let capsule = registry_view.lookup(id).unwrap();
+    assert_eq!(capsule.segments[0], capsule.segments[1]);
+    assert_eq!(
+        nvram_view
+            .get_segment_metadata(capsule.segments[0])
+            .unwrap()
+            .ref_count,
+        2
+    );
+    assert_eq!(pipeline.read_capsule(id).unwrap(), data);
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn dropped_writer_leaves_nothing_behind() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("dropped");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    {
+        let mut writer = pipeline
+            .capsule_writer(&Policy::default())
+            .unwrap()
+            .with_flush_segments(1);
+        writer.write_chunk(&sample_data(SEGMENT_SIZE * 2)).unwrap();
+        assert_eq!(nvram_view.list_segment_ids().len(), 2);
+        assert_eq!(registry_view.pending_writes().len(), 1);
+    }
+
+    assert!(registry_view.pending_writes().is_empty());
+    assert!(registry_view.list_capsules().is_empty());
+    assert!(nvram_view.list_segment_ids().is_empty());
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn unsealed_stream_rolls_back_after_crash() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("crash");
+
+    {
+        let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+        let pipeline = WritePipeline::new(registry, NvramLog::open(log_path.as_str()).unwrap());
+        let mut writer = pipeline
+            .capsule_writer(&Policy::default())
+            .unwrap()
+            .with_flush_segments(1);
+        writer.write_chunk(&sample_data(SEGMENT_SIZE * 2)).unwrap();
+        // Simulate a crash: skip the writer's cleanup entirely.
+        std::mem::forget(writer);
+    }
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let intents = registry.pending_writes();
+    assert_eq!(intents.len(), 1);
+    assert!(intents[0].partial);
+    assert_eq!(intents[0].new_segments.len(), 2);
+
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let _pipeline = WritePipeline::new(registry, nvram);
+
+    assert!(registry_view.pending_writes().is_empty());
+    assert!(registry_view.list_capsules().is_empty());
+    assert!(nvram_view.list_segment_ids().is_empty());
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn streamed_overwrite_claims_the_capsule_until_sealed() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("overwrite");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let pipeline = WritePipeline::new(registry, NvramLog::open(log_path.as_str()).unwrap());
+
+    let policy = Policy {
+        versioning: Some(common::VersioningPolicy::keep_last(2)),
+        ..Policy::default()
+    };
+    let original = sample_data(SEGMENT_SIZE + 99);
+    let id = pipeline
+        .write_capsule_with_policy(&original, &policy)
+        .unwrap();
+
+    let replacement: Vec<u8> = sample_data(SEGMENT_SIZE * 3 + 5)
+        .into_iter()
+        .rev()
+        .collect();
+    let mut writer = pipeline
+        .capsule_overwriter(id)
+        .unwrap()
+        .with_flush_segments(1);
+    writer.write_chunk(&replacement).unwrap();
+
+    // Flushed segments hold the capsule against competing overwrites.
+    assert_eq!(registry_view.pending_writes().len(), 1);
+    assert!(pipeline.overwrite_capsule(id, b"competing").is_err());
+    assert_eq!(pipeline.read_capsule(id).unwrap(), original);
+
+    assert_eq!(writer.finish().unwrap(), id);
+    assert!(registry_view.pending_writes().is_empty());
+    assert_eq!(pipeline.read_capsule(id).unwrap(), replacement);
+    assert_eq!(pipeline.read_capsule_at(id, 1).unwrap(), original);
+    assert_eq!(registry_view.lookup(id).unwrap().version, 2);
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn open_streams_do_not_hold_off_compaction() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("compaction");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    let data = sample_data(SEGMENT_SIZE * 2 + 4_096);
+    let stored = pipeline
+        .write_capsule_with_policy(&data, &Policy::default())
+        .unwrap();
+
+    // A slow upload and a slow download, each stopped part way through.
+    let upload = data.iter().rev().copied().collect::<Vec<_>>();
+    let mut writer = pipeline.capsule_writer(&Policy::default()).unwrap();
+    writer.write_chunk(&upload[..SEGMENT_SIZE + 10]).unwrap();
+    let mut reader = pipeline.capsule_reader(stored).unwrap();
+    let first = reader.next().unwrap().unwrap();
+
+    nvram_view.compact().unwrap();
+
+    writer.write_chunk(&upload[SEGMENT_SIZE + 10..]).unwrap();
+    let uploaded = writer.finish().unwrap();
+    assert_eq!(pipeline.read_capsule(uploaded).unwrap(), upload);
+    let rest: Vec<_> = reader.map(|chunk| chunk.unwrap()).collect();
+    assert_eq!([vec![first], rest].concat().concat(), data);
+
+    cleanup(&log_path, &meta_path);
+}
@@ -0,0 +1,261 @@
+use capsule_registry::tenant::TenantQuota;
+use capsule_registry::{pipeline::WritePipeline, CapsuleRegistry, QuotaError};
+use common::{Event, Policy, TenantId, SEGMENT_SIZE};
+use nvram_sim::NvramLog;
+use std::fs;
+use std::sync::mpsc;
+use std::sync::Once;
+
+fn init_native_pipeline() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        std::env::set_var("SPACE_DISABLE_MODULAR_PIPELINE", "1");
+    });
+}
+
+fn setup_paths(prefix: &str) -> (String, String) {
+    let log_path = format!("{}_tenant.log", prefix);
+    let meta_path = format!("{}_tenant.metadata", prefix);
+    cleanup(&log_path, &meta_path);
+    (log_path, meta_path)
+}
+
+fn cleanup(log_path: &str, meta_path: &str) {
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
+}
+
+/// Distinct bytes per segment, varied by `seed` so writes don't dedupe.
+fn sample_data(len: usize, seed: u32) -> Vec<u8> {
+    (0..len)
+        .map(|i| {
+            ((i / 3) as u32 ^ seed)
+                .wrapping_mul(2_654_435_761)
+                .to_le_bytes()[1]
+        })
+        .collect()
+}
+
+fn open_pipeline(log_path: &str, meta_path: &str) -> WritePipeline {
+    let registry = CapsuleRegistry::open(meta_path).unwrap();
+    WritePipeline::new(registry, NvramLog::open(log_path).unwrap())
+}
+
+#[test]
+fn dedup_never_crosses_tenants() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("isolation");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    let (acme, globex) = (TenantId::new("acme"), TenantId::new("globex"));
+    let data = sample_data(SEGMENT_SIZE, 1);
+    let policy = Policy::default();
+
+    let a1 = pipeline
+        .write_capsule_for_tenant(&acme, &data, &policy)
+        .unwrap();
+    let a2 = pipeline
+        .write_capsule_for_tenant(&acme, &data, &policy)
+        .unwrap();
+    assert_eq!(
+        pipeline.lookup_capsule(a1).unwrap().segments,
+        pipeline.lookup_capsule(a2).unwrap().segments
+    );
+    assert_eq!(nvram_view.list_segment_ids().len(), 1);
+
+    // Neither another tenant nor the global namespace sees acme's content.
+    let g = pipeline
+        .write_capsule_for_tenant(&globex, &data, &policy)
+        .unwrap();
+    let shared = pipeline.write_capsule(&data).unwrap();
+    assert_eq!(nvram_view.list_segment_ids().len(), 3);
+    assert_eq!(
+        pipeline.lookup_capsule(g).unwrap().tenant,
+        Some(globex.clone())
+    );
+    assert_eq!(pipeline.lookup_capsule(shared).unwrap().tenant, None);
+    assert_eq!(pipeline.read_capsule(g).unwrap(), data);
+
+    // Partitions survive a restart, as do their dedup domains.
+    drop(pipeline);
+    let pipeline = open_pipeline(&log_path, &meta_path);
+    let g2 = pipeline
+        .write_capsule_for_tenant(&globex, &data, &policy)
+        .unwrap();
+    assert_eq!(
+        pipeline.lookup_capsule(g2).unwrap().segments,
+        pipeline.lookup_capsule(g).unwrap().segments
+    );
+    assert_eq!(
+        pipeline.registry().tenants(),
+        vec![acme.clone(), globex.clone()]
+    );
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn hard_quota_refuses_writes() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("hard");
+
+    let pipeline = open_pipeline(&log_path, &meta_path);
+    let tenant = TenantId::new("acme");
+    let limit = 2 * SEGMENT_SIZE as u64;
+    pipeline
+        .registry()
+        .set_quota(
+            &tenant,
+            TenantQuota {
+                soft_bytes: None,
+                hard_bytes: Some(limit),
+            },
+        )
+        .unwrap();
+
+    let policy = Policy::default();
+    let id = pipeline
+        .write_capsule_for_tenant(&tenant, &sample_data(SEGMENT_SIZE, 2), &policy)
+        .unwrap();
+    let err = pipeline
+        .write_capsule_for_tenant(&tenant, &sample_data(2 * SEGMENT_SIZE, 3), &policy)
+        .unwrap_err();
+    let refusal = err.downcast_ref::<QuotaError>().unwrap();
+    assert_eq!(refusal.tenant, tenant);
+    assert_eq!(refusal.hard_limit_bytes, limit);
+
+    // Streaming writes are held to the same limit.
+    let mut writer = pipeline
+        .capsule_writer_for_tenant(&tenant, &policy)
+        .unwrap();
+    std::io::Write::write_all(&mut writer, &sample_data(2 * SEGMENT_SIZE, 4)).unwrap_err();
+
+    // Overwrites count only the growth over the bytes they replace.
+    pipeline
+        .overwrite_capsule(id, &sample_data(2 * SEGMENT_SIZE, 5))
+        .unwrap();
+    assert!(pipeline
+        .overwrite_capsule(id, &sample_data(2 * SEGMENT_SIZE + 1, 6))
+        .is_err());
+
+    let usage = pipeline.tenant_usage(&tenant).unwrap();
+    assert_eq!(usage.capsules, 1);
+    assert_eq!(usage.logical_bytes, limit);
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn soft_quota_reports_but_allows() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("soft");
+
+    let (tx, events) = mpsc::channel();
+    let registry = CapsuleRegistry::open(meta_path.as_str())
+        .unwrap()
+        .with_event_sink(tx);
+    let pipeline = WritePipeline::new(registry, NvramLog::open(log_path.as_str()).unwrap());
+    let tenant = TenantId::new("acme");
+    let soft = SEGMENT_SIZE as u64;
+    pipeline
+        .registry()
+        .set_quota(
+            &tenant,
+            TenantQuota {
+                soft_bytes: Some(soft),
+                hard_bytes: None,
+            },
+        )
+        .unwrap();
+
+    let policy = Policy::default();
+    pipeline
+        .write_capsule_for_tenant(&tenant, &sample_data(SEGMENT_SIZE, 7), &policy)
+        .unwrap();
+    assert_eq!(events.try_iter().count(), 0);
+    pipeline
+        .write_capsule_for_tenant(&tenant, &sample_data(SEGMENT_SIZE, 8), &policy)
+        .unwrap();
+
+    let reported: Vec<_> = events
+        .try_iter()
+        .filter_map(|event| match event {
+            Event::QuotaSoftLimitExceeded {
+                tenant,
+                used_bytes,
+                soft_limit_bytes,
+            } => Some((tenant, used_bytes, soft_limit_bytes)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(reported, [(tenant, 2 * soft, soft)]);
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn usage_and_quotas_persist() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("usage");
+
+    let tenant = TenantId::new("acme");
+    let quota = TenantQuota {
+        soft_bytes: Some(1 << 20),
+        hard_bytes: Some(1 << 30),
+    };
+    let data = sample_data(SEGMENT_SIZE + 4_096, 9);
+    let original = {
+        let pipeline = open_pipeline(&log_path, &meta_path);
+        pipeline.registry().set_quota(&tenant, quota).unwrap();
+        let policy = Policy::default();
+        let original = pipeline
+            .write_capsule_for_tenant(&tenant, &data, &policy)
+            .unwrap();
+        pipeline
+            .write_capsule_for_tenant(&tenant, &data, &policy)
+            .unwrap();
+        // Clones stay in the tenant of their source.
+        let clone = pipeline.clone_capsule(original).unwrap();
+        assert_eq!(
+            pipeline.lookup_capsule(clone).unwrap().tenant,
+            Some(tenant.clone())
+        );
+        pipeline.write_capsule(&data).unwrap();
+        original
+    };
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+    assert_eq!(pipeline.registry().quota(&tenant), quota);
+
+    // Three copies are three times the logical bytes but share one set of segments.
+    let stored: u64 = pipeline
+        .lookup_capsule(original)
+        .unwrap()
+        .segments
+        .iter()
+        .map(|seg_id| nvram_view.get_segment_metadata(*seg_id).unwrap().len as u64)
+        .sum();
+    let usage = pipeline.tenant_usage(&tenant).unwrap();
+    assert_eq!(usage.capsules, 3);
+    assert_eq!(usage.logical_bytes, 3 * data.len() as u64);
+    assert_eq!(usage.physical_bytes, stored);
+
+    // An unlimited quota clears the entry.
+    pipeline
+        .registry()
+        .set_quota(&tenant, TenantQuota::default())
+        .unwrap();
+    assert_eq!(pipeline.registry().tenants(), vec![tenant.clone()]);
+    assert_eq!(pipeline.registry().quota(&tenant), TenantQuota::default());
+
+    cleanup(&log_path, &meta_path);
+}
@@ -0,0 +1,203 @@
+use capsule_registry::{pipeline::WritePipeline, CapsuleRegistry};
+use common::{Policy, VersioningPolicy, SEGMENT_SIZE};
+use nvram_sim::NvramLog;
+use std::fs;
+use std::sync::Once;
+
+fn init_native_pipeline() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        std::env::set_var("SPACE_DISABLE_MODULAR_PIPELINE", "1");
+    });
+}
+
+fn setup_paths(prefix: &str) -> (String, String) {
+    let log_path = format!("{}_versions.log", prefix);
+    let meta_path = format!("{}_versions.metadata", prefix);
+    cleanup(&log_path, &meta_path);
+    (log_path, meta_path)
+}
+
+fn cleanup(log_path: &str, meta_path: &str) {
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
+}
+
+/// Distinct bytes per segment, varied by `seed` so versions don't dedupe.
+fn sample_data(len: usize, seed: u32) -> Vec<u8> {
+    (0..len)
+        .map(|i| {
+            ((i / 5) as u32 ^ seed)
+                .wrapping_mul(2_654_435_761)
+                .to_le_bytes()[2]
+        })
+        .collect()
+}
+
+fn versioned(rules: VersioningPolicy) -> Policy {
+    Policy {
+        versioning: Some(rules),
+        ..Policy::default()
+    }
+}
+
+#[test]
+fn overwrites_keep_readable_versions() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("readable");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    let first = sample_data(SEGMENT_SIZE * 2, 1);
+    let id = pipeline
+        .write_capsule_with_policy(&first, &versioned(VersioningPolicy::keep_last(4)))
+        .unwrap();
+
+    // A range write shares the untouched segment with version 1.
+    pipeline.write_range(id, 10, b"patched").unwrap();
+    let mut second = first.clone();
+    second[10..17].copy_from_slice(b"patched");
+    assert_eq!(nvram_view.list_segment_ids().len(), 3);
+
+    let third = sample_data(SEGMENT_SIZE + 99, 3);
+    let updated = pipeline.overwrite_capsule(id, &third).unwrap();
+    assert_eq!(updated.id, id);
+    assert_eq!(updated.version, 3);
+
+    assert_eq!(pipeline.list_versions(id).unwrap(), vec![1, 2, 3]);
+    assert_eq!(pipeline.read_capsule_at(id, 1).unwrap(), first);
+    assert_eq!(pipeline.read_capsule_at(id, 2).unwrap(), second);
+    assert_eq!(pipeline.read_capsule_at(id, 3).unwrap(), third);
+    assert_eq!(pipeline.read_capsule(id).unwrap(), third);
+    assert!(pipeline.read_capsule_at(id, 4).is_err());
+
+    // Deleting the capsule releases the retained versions as well.
+    pipeline.delete_capsule(id).unwrap();
+    assert!(nvram_view.list_segment_ids().is_empty());
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn pruning_by_count_releases_segments() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("count");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    let id = pipeline
+        .write_capsule_with_policy(
+            &sample_data(SEGMENT_SIZE, 0),
+            &versioned(VersioningPolicy::keep_last(1)),
+        )
+        .unwrap();
+    for seed in 1..4 {
+        pipeline
+            .overwrite_capsule(id, &sample_data(SEGMENT_SIZE, seed))
+            .unwrap();
+    }
+
+    assert_eq!(pipeline.list_versions(id).unwrap(), vec![3, 4]);
+    assert_eq!(
+        pipeline.read_capsule_at(id, 3).unwrap(),
+        sample_data(SEGMENT_SIZE, 2)
+    );
+    assert_eq!(nvram_view.list_segment_ids().len(), 2);
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn pruning_by_age_drops_old_versions() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("age");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    let id = pipeline
+        .write_capsule_with_policy(b"v1", &versioned(VersioningPolicy::keep_for(3_600)))
+        .unwrap();
+    pipeline.overwrite_capsule(id, b"v2").unwrap();
+    pipeline.overwrite_capsule(id, b"v3").unwrap();
+
+    // Nothing is old enough yet.
+    assert_eq!(pipeline.prune_versions(id).unwrap(), 0);
+    assert_eq!(pipeline.list_versions(id).unwrap(), vec![1, 2, 3]);
+
+    let later = registry_view.version_history(id)[1].superseded_at + 3_601;
+    let pruned = registry_view.prune_versions(id, later).unwrap();
+    assert_eq!(pruned.len(), 2);
+    assert_eq!(pipeline.list_versions(id).unwrap(), vec![3]);
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn history_survives_reopen() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("reopen");
+
+    let id = {
+        let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+        let pipeline = WritePipeline::new(registry, NvramLog::open(log_path.as_str()).unwrap());
+        let id = pipeline
+            .write_capsule_with_policy(
+                &sample_data(SEGMENT_SIZE, 1),
+                &versioned(VersioningPolicy::keep_last(3)),
+            )
+            .unwrap();
+        pipeline
+            .overwrite_capsule(id, &sample_data(SEGMENT_SIZE, 2))
+            .unwrap();
+        id
+    };
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    assert_eq!(registry.list_versions(id).unwrap(), vec![1, 2]);
+    let pipeline = WritePipeline::new(registry, NvramLog::open(log_path.as_str()).unwrap());
+    assert_eq!(
+        pipeline.read_capsule_at(id, 1).unwrap(),
+        sample_data(SEGMENT_SIZE, 1)
+    );
+    assert_eq!(
+        pipeline.read_capsule(id).unwrap(),
+        sample_data(SEGMENT_SIZE, 2)
+    );
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn unversioned_capsules_keep_no_history() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("unversioned");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    let id = pipeline
+        .write_capsule_with_policy(&sample_data(SEGMENT_SIZE, 7), &Policy::default())
+        .unwrap();
+    pipeline
+        .overwrite_capsule(id, &sample_data(SEGMENT_SIZE, 8))
+        .unwrap();
+
+    assert_eq!(pipeline.list_versions(id).unwrap(), vec![2]);
+    assert!(pipeline.read_capsule_at(id, 1).is_err());
+    assert_eq!(nvram_view.list_segment_ids().len(), 1);
+
+    cleanup(&log_path, &meta_path);
+}
@@ -0,0 +1,198 @@
+use capsule_registry::{pipeline::WritePipeline, CapsuleRegistry};
+use common::{Policy, SEGMENT_SIZE};
+use nvram_sim::NvramLog;
+use std::fs;
+use std::sync::Once;
+
+fn init_native_pipeline() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        std::env::set_var("SPACE_DISABLE_MODULAR_PIPELINE", "1");
+    });
+}
+
+fn setup_paths(prefix: &str) -> (String, String) {
+    let log_path = format!("{}_overwrite.log", prefix);
+    let meta_path = format!("{}_overwrite.metadata", prefix);
+    cleanup(&log_path, &meta_path);
+    (log_path, meta_path)
+}
+
+fn cleanup(log_path: &str, meta_path: &str) {
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
+}
+
+/// Three full segments plus a partial tail, with no repeated segments.
+fn sample_data() -> Vec<u8> {
+    (0..SEGMENT_SIZE * 3 + 4_321)
+        .map(|i| ((i / 5) as u32).wrapping_mul(2_654_435_761).to_le_bytes()[3])
+        .collect()
+}
+
+#[test]
+fn overwrite_rewrites_only_the_touched_segment() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("single");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    let mut data = sample_data();
+    let id = pipeline
+        .write_capsule_with_policy(&data, &Policy::default())
+        .unwrap();
+    let before = registry_view.lookup(id).unwrap();
+    assert_eq!(before.version, 1);
+
+    let offset = SEGMENT_SIZE + 100;
+    let patch = vec![0xEE; 4_096];
+    let updated = pipeline.write_range(id, offset as u64, &patch).unwrap();
+    data[offset..offset + patch.len()].copy_from_slice(&patch);
+
+    assert_eq!(updated.id, id);
+    assert_eq!(updated.version, 2);
+    assert_eq!(updated.size, data.len() as u64);
+    assert_eq!(updated.segments.len(), before.segments.len());
+    for index in [0, 2, 3] {
+        assert_eq!(updated.segments[index], before.segments[index]);
+    }
+    assert_ne!(updated.segments[1], before.segments[1]);
+
+    // The superseded segment is released once nothing references it.
+    assert!(nvram_view.get_segment_metadata(before.segments[1]).is_err());
+    assert_eq!(registry_view.lookup(id).unwrap().version, 2);
+    assert!(registry_view.pending_writes().is_empty());
+    assert_eq!(pipeline.read_capsule(id).unwrap(), data);
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn overwrite_across_boundary_and_past_end() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("boundary");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let pipeline = WritePipeline::new(registry, NvramLog::open(log_path.as_str()).unwrap());
+
+    let mut data = sample_data();
+    let id = pipeline
+        .write_capsule_with_policy(&data, &Policy::default())
+        .unwrap();
+    let before = registry_view.lookup(id).unwrap();
+
+    // Straddle the boundary between the first and second segments.
+    let offset = SEGMENT_SIZE - 10;
+    pipeline
+        .write_range(id, offset as u64, &[0x11; 20])
+        .unwrap();
+    data[offset..offset + 20].copy_from_slice(&[0x11; 20]);
+    let straddled = registry_view.lookup(id).unwrap();
+    assert_ne!(straddled.segments[0], before.segments[0]);
+    assert_ne!(straddled.segments[1], before.segments[1]);
+    assert_eq!(straddled.segments[2..], before.segments[2..]);
+
+    // Append past the end: the short tail is refilled and a new segment added.
+    let tail = vec![0x22; SEGMENT_SIZE];
+    let appended = pipeline
+        .write_range(id, data.len() as u64 - 21, &tail)
+        .unwrap();
+    data.truncate(data.len() - 21);
+    data.extend_from_slice(&tail);
+    assert_eq!(appended.version, 3);
+    assert_eq!(appended.size, data.len() as u64);
+    assert_eq!(appended.segments.len(), 5);
+    assert_eq!(appended.segments[..3], straddled.segments[..3]);
+    assert_eq!(pipeline.read_capsule(id).unwrap(), data);
+    assert_eq!(
+        pipeline
+            .read_range(id, SEGMENT_SIZE as u64 * 3, 64)
+            .unwrap(),
+        &data[SEGMENT_SIZE * 3..SEGMENT_SIZE * 3 + 64]
+    );
+
+    assert!(pipeline
+        .write_range(id, data.len() as u64 + 1, b"gap")
+        .is_err());
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn shared_segments_survive_overwrite() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("shared");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    let data = sample_data();
+    let first = pipeline
+        .write_capsule_with_policy(&data, &Policy::default())
+        .unwrap();
+    let second = pipeline
+        .write_capsule_with_policy(&data, &Policy::default())
+        .unwrap();
+    let shared = registry_view.lookup(first).unwrap().segments[0];
+    assert_eq!(
+        nvram_view.get_segment_metadata(shared).unwrap().ref_count,
+        2
+    );
+
+    pipeline.write_range(first, 0, b"patched").unwrap();
+
+    assert_eq!(
+        nvram_view.get_segment_metadata(shared).unwrap().ref_count,
+        1
+    );
+    assert_eq!(pipeline.read_capsule(second).unwrap(), data);
+    assert_eq!(&pipeline.read_range(first, 0, 7).unwrap(), b"patched");
+
+    cleanup(&log_path, &meta_path);
+}
+
+#[test]
+fn legacy_capsules_are_rewritten_in_full() {
+    init_native_pipeline();
+    let (log_path, meta_path) = setup_paths("legacy");
+
+    let registry = CapsuleRegistry::open(meta_path.as_str()).unwrap();
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path.as_str()).unwrap();
+    let nvram_view = nvram.clone();
+    let pipeline = WritePipeline::new(registry, nvram);
+
+    let mut data = sample_data();
+    let id = pipeline
+        .write_capsule_with_policy(&data, &Policy::default())
+        .unwrap();
+    let before = registry_view.lookup(id).unwrap();
+    for seg_id in &before.segments {
+        let mut meta = nvram_view.get_segment_metadata(*seg_id).unwrap();
+        meta.logical_len = 0;
+        nvram_view.update_segment_metadata(*seg_id, meta).unwrap();
+    }
+
+    let offset = SEGMENT_SIZE * 2 + 5;
+    pipeline.write_range(id, offset as u64, b"legacy").unwrap();
+    data[offset..offset + 6].copy_from_slice(b"legacy");
+
+    let after = registry_view.lookup(id).unwrap();
+    assert_eq!(after.segments.len(), before.segments.len());
+    // Unchanged segments dedupe back onto the originals, which stay live.
+    assert_eq!(after.segments[0], before.segments[0]);
+    assert_ne!(after.segments[2], before.segments[2]);
+    assert_eq!(pipeline.read_capsule(id).unwrap(), data);
+
+    cleanup(&log_path, &meta_path);
+}
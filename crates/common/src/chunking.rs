@@ -0,0 +1,183 @@
+//! FastCDC content-defined chunking.
+//!
+//! Boundaries are chosen where a rolling gear hash of the preceding bytes
+//! matches a mask, so they move with the content: inserting a byte near the
+//! start of a file shifts only the chunk that contains it, and every later
+//! chunk keeps its bytes and therefore its dedup hash.
+
+/// Per-byte values mixed into the rolling hash, generated with splitmix64.
+pub(crate) static GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut state = 0x9e37_79b9_7f4a_7c15u64;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// Mask over the top `bits` bits of the hash, which depend on the last 64 bytes.
+fn high_mask(bits: u32) -> u64 {
+    match bits {
+        0 => 0,
+        64.. => u64::MAX,
+        _ => u64::MAX << (64 - bits),
+    }
+}
+
+/// Splits data into chunks of `min_size..=max_size` bytes averaging about `avg_size`.
+///
+/// Uses normalized chunking: before `avg_size` a stricter mask makes a cut
+/// less likely, after it a looser one makes it more likely, which keeps chunk
+/// sizes close to the average.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentDefinedChunker {
+    min_size: usize,
+    avg_size: usize,
+    max_size: usize,
+    strict_mask: u64,
+    loose_mask: u64,
+}
+
+impl ContentDefinedChunker {
+    /// Sizes are adjusted so that `1 <= min_size <= avg_size <= max_size`.
+    pub fn new(min_size: usize, avg_size: usize, max_size: usize) -> Self {
+        let min_size = min_size.max(1);
+        let max_size = max_size.max(min_size);
+        let avg_size = avg_size.clamp(min_size, max_size);
+        let bits = avg_size.max(2).ilog2();
+        Self {
+            min_size,
+            avg_size,
+            max_size,
+            strict_mask: high_mask(bits + 1),
+            loose_mask: high_mask(bits - 1),
+        }
+    }
+
+    pub fn min_size(&self) -> usize {
+        self.min_size
+    }
+
+    pub fn avg_size(&self) -> usize {
+        self.avg_size
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Length of the first chunk of `data`.
+    ///
+    /// The result depends only on the first `max_size` bytes, so a stream can
+    /// be cut once that much is buffered; shorter input is cut as if it ended
+    /// there.
+    pub fn cut(&self, data: &[u8]) -> usize {
+        if data.len() <= self.min_size {
+            return data.len();
+        }
+        let end = data.len().min(self.max_size);
+        let normal = end.min(self.avg_size);
+        let mut hash = 0u64;
+        for (i, byte) in data.iter().enumerate().take(end).skip(self.min_size) {
+            hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+            let mask = if i < normal {
+                self.strict_mask
+            } else {
+                self.loose_mask
+            };
+            if hash & mask == 0 {
+                return i + 1;
+            }
+        }
+        end
+    }
+
+    /// Cut all of `data` into chunks.
+    pub fn split<'a>(&self, data: &'a [u8]) -> Vec<&'a [u8]> {
+        let mut chunks = Vec::new();
+        let mut rest = data;
+        while !rest.is_empty() {
+            let (chunk, tail) = rest.split_at(self.cut(rest));
+            chunks.push(chunk);
+            rest = tail;
+        }
+        chunks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_data(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6_364_136_223_846_793_005)
+                    .wrapping_add(1_442_695_040_888_963_407);
+                (state >> 56) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn chunks_respect_size_bounds() {
+        let chunker = ContentDefinedChunker::new(2 * 1024, 8 * 1024, 32 * 1024);
+        let data = sample_data(1024 * 1024, 1);
+        let chunks = chunker.split(&data);
+
+        assert_eq!(chunks.concat(), data);
+        let (last, body) = chunks.split_last().unwrap();
+        assert!(last.len() <= 32 * 1024);
+        for chunk in body {
+            assert!((2 * 1024..=32 * 1024).contains(&chunk.len()));
+        }
+        let average = data.len() / chunks.len();
+        assert!(
+            (4 * 1024..=16 * 1024).contains(&average),
+            "average chunk was {} bytes",
+            average
+        );
+    }
+
+    #[test]
+    fn insertion_only_disturbs_nearby_chunks() {
+        let chunker = ContentDefinedChunker::new(2 * 1024, 8 * 1024, 32 * 1024);
+        let data = sample_data(512 * 1024, 2);
+        let mut shifted = vec![0xa5];
+        shifted.extend_from_slice(&data);
+
+        let original = chunker.split(&data);
+        let moved = chunker.split(&shifted);
+        let shared = moved
+            .iter()
+            .filter(|chunk| original.contains(chunk))
+            .count();
+        assert!(
+            shared + 2 >= original.len(),
+            "only {} of {} chunks survived a one-byte insertion",
+            shared,
+            original.len()
+        );
+    }
+
+    #[test]
+    fn sizes_are_normalized() {
+        let chunker = ContentDefinedChunker::new(0, 1 << 30, 4096);
+        assert_eq!(
+            (chunker.min_size(), chunker.avg_size(), chunker.max_size()),
+            (1, 4096, 4096)
+        );
+        assert_eq!(chunker.cut(&[7u8; 10_000]), 4096);
+        assert!(chunker.split(&[]).is_empty());
+    }
+}
@@ -1,15 +1,18 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::convert::TryInto;
 use uuid::Uuid;
 
 #[cfg(feature = "advanced-security")]
 pub mod security;
 
+pub mod chunking;
 pub mod policy;
+pub mod similarity;
 pub mod traits;
 pub use policy::{
-    CompressionPolicy, CryptoProfile, EncryptionPolicy, LayoutPolicy, LayoutStrategy, MerkleAlgo,
-    Policy,
+    CompressionPolicy, CryptoProfile, EncryptionPolicy, LayoutPolicy, LayoutStrategy,
+    LifecycleAction, LifecycleRule, MerkleAlgo, Policy, RetentionPolicy, VersioningPolicy,
 };
 
 pub const SEGMENT_SIZE: usize = 4 * 1024 * 1024; // 4 MiB
@@ -17,6 +20,31 @@ pub const SEGMENT_SIZE: usize = 4 * 1024 * 1024; // 4 MiB
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct SegmentId(pub u64);
 
+impl SegmentId {
+    const HOLE_FLAG: u64 = 1 << 63;
+
+    /// Longest run of zeroes a single hole marker can describe.
+    pub const MAX_HOLE_LEN: u64 = Self::HOLE_FLAG - 1;
+
+    /// Marker standing in for `len` bytes of zeroes that were never stored.
+    ///
+    /// Holes sit in `Capsule::segments` alongside real segments but have no
+    /// NVRAM metadata or refcount; readers synthesize their bytes.
+    pub fn hole(len: u64) -> Self {
+        debug_assert!(len > 0 && len <= Self::MAX_HOLE_LEN);
+        Self(Self::HOLE_FLAG | len)
+    }
+
+    pub fn is_hole(self) -> bool {
+        self.0 & Self::HOLE_FLAG != 0
+    }
+
+    /// Length of the zero run if this is a hole marker.
+    pub fn hole_len(self) -> Option<u64> {
+        self.is_hole().then_some(self.0 & Self::MAX_HOLE_LEN)
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct CapsuleId(pub Uuid);
 
@@ -50,6 +78,29 @@ impl Default for CapsuleId {
     }
 }
 
+/// Owner of a capsule in a multi-tenant registry.
+///
+/// Each tenant deduplicates only against its own content and is accounted and
+/// limited separately; capsules without a tenant share the global namespace.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct TenantId(pub String);
+
+impl TenantId {
+    pub fn new(id: impl Into<String>) -> Self {
+        Self(id.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for TenantId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
 // NEW: Content-addressable hash for deduplication
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ContentHash(pub String);
@@ -77,6 +128,75 @@ pub struct Capsule {
     // Phase 2.2: Track dedup stats per capsule
     #[serde(default)]
     pub deduped_bytes: u64, // How many bytes were deduplicated
+
+    /// Bumped on every in-place overwrite; 1 for a freshly written capsule.
+    #[serde(default)]
+    pub version: u64,
+
+    /// Set on snapshots, which refuse in-place overwrites.
+    #[serde(default)]
+    pub read_only: bool,
+
+    /// Capsule this one was snapshotted or cloned from.
+    #[serde(default)]
+    pub origin: Option<CapsuleId>,
+
+    /// User-defined key/value metadata, surfaced as S3 `x-amz-meta-*` headers
+    /// and NFS extended attributes.
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+
+    /// User-defined tags; the registry indexes these for capsule queries.
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+
+    /// Unix time before which the capsule may not be deleted or overwritten.
+    #[serde(default)]
+    pub retain_until: Option<u64>,
+
+    /// Blocks deletion and overwrites until explicitly released.
+    #[serde(default)]
+    pub legal_hold: bool,
+
+    /// How many of the policy's lifecycle rules, in `after_secs` order, have
+    /// already been applied.
+    #[serde(default)]
+    pub lifecycle_step: u32,
+
+    /// Tenant owning the capsule; `None` for the shared global namespace.
+    #[serde(default)]
+    pub tenant: Option<TenantId>,
+
+    /// Wrapped data keys its encrypted segments are sealed under: the
+    /// capsule's own, and those of segments it shares with other capsules.
+    #[serde(default)]
+    pub data_keys: Vec<WrappedDataKey>,
+}
+
+impl Capsule {
+    /// Whether the retention deadline is still in the future at `now`.
+    pub fn retained_at(&self, now: u64) -> bool {
+        self.retain_until.is_some_and(|until| until > now)
+    }
+
+    /// Whether retention or a legal hold forbids deleting or overwriting the
+    /// capsule at `now`.
+    pub fn is_immutable_at(&self, now: u64) -> bool {
+        self.legal_hold || self.retained_at(now)
+    }
+
+    /// The wrapped data key `id`, if the capsule carries it.
+    pub fn data_key(&self, id: DataKeyRef) -> Option<&WrappedDataKey> {
+        self.data_keys.iter().find(|key| key.id == id)
+    }
+
+    /// The newest data key generated for this capsule itself.
+    pub fn own_data_key(&self) -> Option<&WrappedDataKey> {
+        self.data_keys
+            .iter()
+            .filter(|key| key.id.capsule == self.id)
+            .max_by_key(|key| key.id.generation)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -84,6 +204,9 @@ pub struct Segment {
     pub id: SegmentId,
     pub offset: u64,
     pub len: u32,
+    /// Bytes of capsule data this segment decodes to; 0 for segments written before it was tracked.
+    #[serde(default)]
+    pub logical_len: u32,
 
     // Phase 2.1: Compression metadata
     #[serde(default)]
@@ -93,7 +216,7 @@ pub struct Segment {
 
     // Phase 2.2: Deduplication metadata
     #[serde(default)]
-    pub content_hash: Option<ContentHash>, // Hash of compressed data
+    pub content_hash: Option<ContentHash>, // Fingerprint of the uncompressed data
     #[serde(default)]
     pub ref_count: u32, // Reference count for GC
 
@@ -119,6 +242,146 @@ pub struct Segment {
     pub pq_ciphertext: Option<String>,
     #[serde(default)]
     pub pq_nonce: Option<[u8; 16]>,
+
+    // Integrity: BLAKE3 of the bytes as stored in the log, recorded at append
+    #[serde(default)]
+    pub checksum: Option<String>,
+
+    // Tiering: the backend of a tiered store that currently holds the bytes
+    #[serde(default)]
+    pub tier: StorageTier,
+
+    // Erasure coding: how an erasure-coded store split the bytes into fragments
+    #[serde(default)]
+    pub erasure: Option<ErasureLayout>,
+
+    // Similarity: super-features of a segment other segments may be stored as deltas against
+    #[serde(default)]
+    pub sketch: Option<similarity::Sketch>,
+
+    // Delta compression: the segment this one's bytes are a delta against; it
+    // holds one reference on that base for as long as it exists
+    #[serde(default)]
+    pub delta_base: Option<SegmentId>,
+
+    // Zstd dictionary the bytes were compressed against
+    #[serde(default)]
+    pub dictionary: Option<DictionaryRef>,
+
+    // Envelope encryption: the data key the bytes are sealed under; `None` for
+    // segments sealed directly under master key version `key_version`
+    #[serde(default)]
+    pub data_key: Option<DataKeyRef>,
+}
+
+/// A trained Zstd dictionary, identified by the capsule that stores it and the
+/// version of that capsule holding this particular training run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DictionaryRef {
+    pub capsule: CapsuleId,
+    pub version: u64,
+}
+
+/// A capsule data encryption key, identified by the capsule it was generated
+/// for and which of that capsule's keys it is; rotation bumps the generation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DataKeyRef {
+    pub capsule: CapsuleId,
+    pub generation: u32,
+}
+
+impl DataKeyRef {
+    /// Bytes a wrapped key is bound to, so it only unwraps as this key.
+    pub fn wrap_context(&self) -> [u8; 20] {
+        let mut context = [0u8; 20];
+        context[..16].copy_from_slice(self.capsule.as_uuid().as_bytes());
+        context[16..].copy_from_slice(&self.generation.to_le_bytes());
+        context
+    }
+}
+
+/// A data encryption key as stored in capsule metadata, wrapped under master
+/// key version `kek_version`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WrappedDataKey {
+    pub id: DataKeyRef,
+    pub kek_version: u32,
+    pub wrapped: Vec<u8>,
+    pub tag: [u8; 16],
+}
+
+/// Fragment layout of a segment held by an erasure-coded store.
+///
+/// The stored bytes are padded to a multiple of `data_fragments`, split into
+/// that many equal fragments, and extended with `parity_fragments` parity
+/// fragments; any `data_fragments` of them rebuild the segment.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErasureLayout {
+    pub data_fragments: usize,
+    pub parity_fragments: usize,
+    /// Length of the stored bytes before padding.
+    pub payload_len: u64,
+    /// BLAKE3 of each fragment, data fragments first.
+    pub fragment_checksums: Vec<String>,
+}
+
+/// Storage tier a segment lives on.
+///
+/// New segments land on the hot tier; placement passes move segments of cold
+/// capsules to the capacity tier and back again once they heat up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageTier {
+    /// Low-latency media such as NVRAM.
+    #[default]
+    Hot,
+    /// Cheaper, slower media for data that is rarely read.
+    Capacity,
+}
+
+/// The part of one segment's decoded bytes that falls inside a capsule byte range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentExtent {
+    /// Position of the segment within `Capsule::segments`.
+    pub index: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Map `offset..offset + len` of a capsule onto the segments that cover it.
+///
+/// `logical_lens` holds each segment's decoded length in capsule order (a hole's
+/// length for hole markers). Returns `None` when a length is unknown (0) or the
+/// segments do not span the range, in which case callers must decode the whole
+/// capsule instead.
+pub fn covering_segments(
+    logical_lens: &[u64],
+    offset: u64,
+    len: usize,
+) -> Option<Vec<SegmentExtent>> {
+    let end = offset.checked_add(len as u64)?;
+    let mut extents = Vec::new();
+    let mut seg_start = 0u64;
+
+    for (index, &logical_len) in logical_lens.iter().enumerate() {
+        if logical_len == 0 {
+            return None;
+        }
+        let seg_end = seg_start + logical_len;
+        if seg_end > offset && seg_start < end {
+            extents.push(SegmentExtent {
+                index,
+                start: (offset.max(seg_start) - seg_start) as usize,
+                end: (end.min(seg_end) - seg_start) as usize,
+            });
+        }
+        if seg_end >= end {
+            return Some(extents);
+        }
+        seg_start = seg_end;
+    }
+
+    (len == 0 && offset <= seg_start).then_some(extents)
 }
 
 /// Immutable audit log events emitted by the platform.
@@ -155,6 +418,22 @@ pub enum Event {
         capsules: usize,
         segments: usize,
     },
+    SegmentCorrupted {
+        segment_id: SegmentId,
+        offset: u64,
+        len: u32,
+        reason: String,
+    },
+    RetentionViolated {
+        capsule_id: CapsuleId,
+        action: String,
+        reason: String,
+    },
+    QuotaSoftLimitExceeded {
+        tenant: TenantId,
+        used_bytes: u64,
+        soft_limit_bytes: u64,
+    },
 }
 
 // ============================================================================
@@ -246,7 +525,7 @@ pub mod podms {
         /// New capsule created - may trigger replication
         NewCapsule {
             id: CapsuleId,
-            policy: Policy,
+            policy: Box<Policy>,
             node_id: Option<NodeId>,
         },
         /// Heat spike detected - may trigger migration
@@ -326,9 +605,10 @@ pub mod podms {
             // Determine if we need recompression based on policy
             let needs_recompression = match &policy.compression {
                 CompressionPolicy::None => false,
-                CompressionPolicy::LZ4 { .. } | CompressionPolicy::Zstd { .. } => {
-                    !self.is_compressed()
-                }
+                CompressionPolicy::LZ4 { .. }
+                | CompressionPolicy::Zstd { .. }
+                | CompressionPolicy::Codec { .. }
+                | CompressionPolicy::Adaptive { .. } => !self.is_compressed(),
             };
 
             if needs_recompression {
@@ -452,7 +732,7 @@ pub mod podms {
 
             let telemetry = Telemetry::NewCapsule {
                 id: capsule_id,
-                policy,
+                policy: Box::new(policy),
                 node_id: Some(node_id),
             };
 
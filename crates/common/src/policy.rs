@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::chunking::ContentDefinedChunker;
+
 /// Cryptography profile for the write pipeline.
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
 pub enum CryptoProfile {
@@ -19,6 +21,10 @@ pub enum CompressionPolicy {
     LZ4 { level: i32 },
     /// Zstd balanced compression (level 1-22)
     Zstd { level: i32 },
+    /// A codec registered with the compression crate's codec registry
+    Codec { name: String, level: i32 },
+    /// Best ratio among trial-compressed codecs that sustain this throughput
+    Adaptive { min_bytes_per_sec: u64 },
 }
 
 impl Default for CompressionPolicy {
@@ -70,6 +76,13 @@ pub enum LayoutStrategy {
     QuantumReady {
         merkle_algo: MerkleAlgo,
     },
+    /// FastCDC content-defined segments, so boundaries follow the data and an
+    /// insertion only changes the segments around it.
+    ContentDefined {
+        min_size: u32,
+        avg_size: u32,
+        max_size: u32,
+    },
 }
 
 /// Merkle variant for QuantumReady mode.
@@ -109,6 +122,125 @@ impl LayoutStrategy {
             }
             LayoutStrategy::Learned { .. } => 4 * 1024 * 1024,
             LayoutStrategy::QuantumReady { .. } => 4 * 1024 * 1024,
+            LayoutStrategy::ContentDefined { avg_size, .. } => *avg_size as usize,
+        }
+    }
+
+    /// Content-defined segments of 256 KiB to 4 MiB, averaging 1 MiB.
+    pub fn content_defined() -> Self {
+        LayoutStrategy::ContentDefined {
+            min_size: 256 * 1024,
+            avg_size: 1024 * 1024,
+            max_size: 4 * 1024 * 1024,
+        }
+    }
+
+    /// Chunker for content-defined strategies; `None` when segments are cut
+    /// at fixed offsets.
+    pub fn chunker(&self) -> Option<ContentDefinedChunker> {
+        match self {
+            LayoutStrategy::ContentDefined {
+                min_size,
+                avg_size,
+                max_size,
+            } => Some(ContentDefinedChunker::new(
+                *min_size as usize,
+                *avg_size as usize,
+                *max_size as usize,
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// Retention rules for superseded capsule versions.
+///
+/// A version is pruned as soon as it falls outside any limit that is set; with
+/// neither set every version is kept until the capsule is deleted.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct VersioningPolicy {
+    /// Keep at most this many superseded versions per capsule.
+    #[serde(default)]
+    pub keep_versions: Option<u32>,
+    /// Drop versions superseded more than this many seconds ago.
+    #[serde(default)]
+    pub keep_for_secs: Option<u64>,
+}
+
+impl VersioningPolicy {
+    /// Keep the `versions` most recent superseded versions.
+    pub fn keep_last(versions: u32) -> Self {
+        Self {
+            keep_versions: Some(versions),
+            keep_for_secs: None,
+        }
+    }
+
+    /// Keep superseded versions for `secs` seconds.
+    pub fn keep_for(secs: u64) -> Self {
+        Self {
+            keep_versions: None,
+            keep_for_secs: Some(secs),
+        }
+    }
+}
+
+/// Write-once retention applied to capsules when they are written.
+///
+/// Each write stamps the capsule with a `retain_until` deadline; until it
+/// passes, the capsule can be neither deleted nor overwritten.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Seconds after each write during which the capsule is immutable.
+    pub retain_for_secs: u64,
+}
+
+impl RetentionPolicy {
+    pub fn for_secs(secs: u64) -> Self {
+        Self {
+            retain_for_secs: secs,
+        }
+    }
+
+    /// Deadline for a capsule written at `written_at` (Unix seconds).
+    pub fn deadline(&self, written_at: u64) -> u64 {
+        written_at.saturating_add(self.retain_for_secs)
+    }
+}
+
+/// What a lifecycle rule does to a capsule once it is old enough.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum LifecycleAction {
+    /// Delete the capsule.
+    Expire,
+    /// Rewrite the capsule under another policy, e.g. a stronger compression
+    /// level for data that has gone cold. The capsule keeps its encryption,
+    /// crypto profile, versioning, retention and erasure profile.
+    Transition(Box<Policy>),
+}
+
+/// Age-based lifecycle step, applied by the registry's lifecycle scheduler.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LifecycleRule {
+    /// Capsule age, in seconds since creation, at which the rule applies.
+    pub after_secs: u64,
+    pub action: LifecycleAction,
+}
+
+impl LifecycleRule {
+    /// Delete capsules `secs` seconds after they were created.
+    pub fn expire_after(secs: u64) -> Self {
+        Self {
+            after_secs: secs,
+            action: LifecycleAction::Expire,
+        }
+    }
+
+    /// Move capsules to `policy` `secs` seconds after they were created.
+    pub fn transition_after(secs: u64, policy: Policy) -> Self {
+        Self {
+            after_secs: secs,
+            action: LifecycleAction::Transition(Box::new(policy)),
         }
     }
 }
@@ -119,13 +251,21 @@ pub struct Policy {
     /// Compression algorithm and level
     pub compression: CompressionPolicy,
 
+    /// Compress Zstd segments against the tenant's trained dictionary, once one exists
+    #[serde(default)]
+    pub zstd_dictionary: bool,
+
     /// Enable inline deduplication
     pub dedupe: bool,
 
+    /// Store segments that closely resemble an existing one as a delta against it
+    #[serde(default)]
+    pub delta_compression: bool,
+
     /// Background compaction interval in seconds (None = disabled)
     pub compact_interval_secs: Option<u64>,
 
-    /// Erasure coding profile (future use)
+    /// Erasure coding profile as "k+m"; overrides `layout.ec_profile` when set.
     pub erasure_profile: Option<String>,
 
     /// Encryption policy (Phase 3)
@@ -140,6 +280,19 @@ pub struct Policy {
     #[serde(default)]
     pub layout: LayoutPolicy,
 
+    /// Keep superseded versions of overwritten capsules (None = no history).
+    #[serde(default)]
+    pub versioning: Option<VersioningPolicy>,
+
+    /// WORM retention stamped on each write (None = freely mutable).
+    #[serde(default)]
+    pub retention: Option<RetentionPolicy>,
+
+    /// Age-based expiry and transitions. A transition swaps in the target
+    /// policy's storage settings but keeps these rules, so later steps still run.
+    #[serde(default)]
+    pub lifecycle: Vec<LifecycleRule>,
+
     // ========================================================================
     // PODMS (Policy-Orchestrated Disaggregated Mesh Scaling) Fields
     // ========================================================================
@@ -177,12 +330,17 @@ impl Default for Policy {
     fn default() -> Self {
         Self {
             compression: CompressionPolicy::default(),
+            zstd_dictionary: false,
             dedupe: true,
+            delta_compression: false,
             compact_interval_secs: Some(3600), // 1 hour
             erasure_profile: None,
             encryption: EncryptionPolicy::default(),
             crypto_profile: CryptoProfile::default(),
             layout: LayoutPolicy::default(),
+            versioning: None,
+            retention: None,
+            lifecycle: Vec::new(),
             #[cfg(feature = "podms")]
             rpo: default_rpo(),
             #[cfg(feature = "podms")]
@@ -198,12 +356,17 @@ impl Policy {
     pub fn text_optimized() -> Self {
         Self {
             compression: CompressionPolicy::Zstd { level: 3 },
+            zstd_dictionary: false,
             dedupe: true,
+            delta_compression: false,
             compact_interval_secs: Some(1800),
             erasure_profile: None,
             encryption: EncryptionPolicy::default(),
             crypto_profile: CryptoProfile::default(),
             layout: LayoutPolicy::default(),
+            versioning: None,
+            retention: None,
+            lifecycle: Vec::new(),
             #[cfg(feature = "podms")]
             rpo: default_rpo(),
             #[cfg(feature = "podms")]
@@ -217,12 +380,17 @@ impl Policy {
     pub fn precompressed() -> Self {
         Self {
             compression: CompressionPolicy::None,
+            zstd_dictionary: false,
             dedupe: false,
+            delta_compression: false,
             compact_interval_secs: Some(7200),
             erasure_profile: None,
             encryption: EncryptionPolicy::default(),
             crypto_profile: CryptoProfile::default(),
             layout: LayoutPolicy::default(),
+            versioning: None,
+            retention: None,
+            lifecycle: Vec::new(),
             #[cfg(feature = "podms")]
             rpo: default_rpo(),
             #[cfg(feature = "podms")]
@@ -236,12 +404,17 @@ impl Policy {
     pub fn edge_optimized() -> Self {
         Self {
             compression: CompressionPolicy::LZ4 { level: 1 },
+            zstd_dictionary: false,
             dedupe: false,
+            delta_compression: false,
             compact_interval_secs: None, // Manual compaction
             erasure_profile: None,
             encryption: EncryptionPolicy::default(),
             crypto_profile: CryptoProfile::default(),
             layout: LayoutPolicy::default(),
+            versioning: None,
+            retention: None,
+            lifecycle: Vec::new(),
             #[cfg(feature = "podms")]
             rpo: std::time::Duration::from_secs(300), // 5 min RPO for edge
             #[cfg(feature = "podms")]
@@ -255,12 +428,17 @@ impl Policy {
     pub fn encrypted() -> Self {
         Self {
             compression: CompressionPolicy::default(),
+            zstd_dictionary: false,
             dedupe: true,
+            delta_compression: false,
             compact_interval_secs: Some(3600),
             erasure_profile: None,
             encryption: EncryptionPolicy::XtsAes256 { key_version: None },
             crypto_profile: CryptoProfile::default(),
             layout: LayoutPolicy::default(),
+            versioning: None,
+            retention: None,
+            lifecycle: Vec::new(),
             #[cfg(feature = "podms")]
             rpo: default_rpo(),
             #[cfg(feature = "podms")]
@@ -274,12 +452,17 @@ impl Policy {
     pub fn encrypted_compressed() -> Self {
         Self {
             compression: CompressionPolicy::Zstd { level: 3 },
+            zstd_dictionary: false,
             dedupe: true,
+            delta_compression: false,
             compact_interval_secs: Some(3600),
             erasure_profile: None,
             encryption: EncryptionPolicy::XtsAes256 { key_version: None },
             crypto_profile: CryptoProfile::default(),
             layout: LayoutPolicy::default(),
+            versioning: None,
+            retention: None,
+            lifecycle: Vec::new(),
             #[cfg(feature = "podms")]
             rpo: default_rpo(),
             #[cfg(feature = "podms")]
@@ -289,18 +472,47 @@ impl Policy {
         }
     }
 
+    /// Data and parity fragment counts for erasure-coded placement.
+    ///
+    /// `erasure_profile` takes precedence when set, written as `"k+m"` (e.g.
+    /// `"4+2"`); otherwise `layout.ec_profile` applies.
+    pub fn ec_profile(&self) -> anyhow::Result<(usize, usize)> {
+        let (data, parity) = match &self.erasure_profile {
+            None => self.layout.ec_profile,
+            Some(profile) => {
+                let (data, parity) = profile.split_once('+').ok_or_else(|| {
+                    anyhow::anyhow!("erasure profile {:?} is not of the form k+m", profile)
+                })?;
+                (data.trim().parse()?, parity.trim().parse()?)
+            }
+        };
+        if data == 0 || parity == 0 || data + parity > 255 {
+            anyhow::bail!(
+                "erasure profile {}+{} needs k >= 1, m >= 1 and k + m <= 255",
+                data,
+                parity
+            );
+        }
+        Ok((data, parity))
+    }
+
     // PODMS-specific policy presets
     #[cfg(feature = "podms")]
     /// Create a policy for metro-sync replication (low RPO, low latency)
     pub fn metro_sync() -> Self {
         Self {
             compression: CompressionPolicy::LZ4 { level: 1 },
+            zstd_dictionary: false,
             dedupe: true,
+            delta_compression: false,
             compact_interval_secs: Some(3600),
             erasure_profile: None,
             encryption: EncryptionPolicy::XtsAes256 { key_version: None },
             crypto_profile: CryptoProfile::default(),
             layout: LayoutPolicy::default(),
+            versioning: None,
+            retention: None,
+            lifecycle: Vec::new(),
             rpo: std::time::Duration::ZERO, // Synchronous replication
             latency_target: std::time::Duration::from_millis(2), // 2ms target
             sovereignty: crate::podms::SovereigntyLevel::Zone,
@@ -312,12 +524,17 @@ impl Policy {
     pub fn geo_replicated() -> Self {
         Self {
             compression: CompressionPolicy::Zstd { level: 3 },
+            zstd_dictionary: false,
             dedupe: true,
+            delta_compression: false,
             compact_interval_secs: Some(3600),
             erasure_profile: None,
             encryption: EncryptionPolicy::XtsAes256 { key_version: None },
             crypto_profile: CryptoProfile::default(),
             layout: LayoutPolicy::default(),
+            versioning: None,
+            retention: None,
+            lifecycle: Vec::new(),
             rpo: std::time::Duration::from_secs(300), // 5 min async
             latency_target: std::time::Duration::from_millis(100), // 100ms target
             sovereignty: crate::podms::SovereigntyLevel::Global,
@@ -393,6 +610,20 @@ mod tests {
         assert_eq!(policy.crypto_profile, CryptoProfile::Classical);
     }
 
+    #[test]
+    fn test_ec_profile() {
+        let mut policy = Policy::default();
+        assert_eq!(policy.ec_profile().unwrap(), (6, 2));
+
+        policy.erasure_profile = Some("4 + 2".into());
+        assert_eq!(policy.ec_profile().unwrap(), (4, 2));
+
+        for bad in ["4", "4+0", "0+2", "200+100", "a+b"] {
+            policy.erasure_profile = Some(bad.into());
+            assert!(policy.ec_profile().is_err(), "{bad} should be rejected");
+        }
+    }
+
     #[cfg(feature = "podms")]
     #[test]
     fn test_podms_policy_fields() {
@@ -0,0 +1,144 @@
+//! Similarity sketches for spotting near-duplicate segments.
+//!
+//! Exact fingerprints only match identical data. A sketch instead reduces a
+//! segment to a few super-features: each of [`FEATURES`] features is the
+//! largest value a different linear transform of the rolling gear hash takes
+//! over the data, and each super-feature hashes a group of them. A small edit
+//! only changes the features whose maximum it happens to cover, so two
+//! segments sharing any super-feature almost certainly share most of their
+//! bytes, while unrelated data practically never shares one.
+
+use crate::chunking::GEAR;
+use crate::ContentHash;
+use serde::{Deserialize, Serialize};
+
+pub const SUPER_FEATURES: usize = 3;
+const FEATURES_PER_SUPER_FEATURE: usize = 4;
+pub const FEATURES: usize = SUPER_FEATURES * FEATURES_PER_SUPER_FEATURE;
+
+/// Only positions whose hash has these top bits clear are sampled, which
+/// keeps sketching cheap while still picking positions by content.
+const SAMPLE_MASK: u64 = 0xf800_0000_0000_0000;
+
+/// Multipliers and offsets of the per-feature transforms; multipliers are odd
+/// so each transform is a permutation of the hash.
+static TRANSFORMS: [(u64, u64); FEATURES] = transforms();
+
+const fn transforms() -> [(u64, u64); FEATURES] {
+    let mut table = [(0u64, 0u64); FEATURES];
+    let mut state = 0x5851_f42d_4c95_7f2du64;
+    let mut i = 0;
+    while i < FEATURES {
+        state = state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1);
+        let multiplier = state | 1;
+        state = state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1);
+        table[i] = (multiplier, state);
+        i += 1;
+    }
+    table
+}
+
+fn combine(features: &[u64]) -> u64 {
+    features.iter().fold(0x243f_6a88_85a3_08d3, |acc, feature| {
+        (acc ^ feature)
+            .wrapping_mul(0x9e37_79b9_7f4a_7c15)
+            .rotate_left(29)
+    })
+}
+
+/// Super-features of a segment's plaintext.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Sketch(pub [u64; SUPER_FEATURES]);
+
+impl Sketch {
+    /// Sketch of `data`, or `None` when it is too short to have any sampled
+    /// positions.
+    pub fn of(data: &[u8]) -> Option<Self> {
+        let mut features = [0u64; FEATURES];
+        let mut sampled = false;
+        let mut hash = 0u64;
+        for byte in data {
+            hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+            if hash & SAMPLE_MASK != 0 {
+                continue;
+            }
+            sampled = true;
+            for (feature, (multiplier, offset)) in features.iter_mut().zip(&TRANSFORMS) {
+                *feature = (*feature).max(hash.wrapping_mul(*multiplier).wrapping_add(*offset));
+            }
+        }
+        if !sampled {
+            return None;
+        }
+
+        let mut super_features = [0u64; SUPER_FEATURES];
+        for (super_feature, group) in super_features
+            .iter_mut()
+            .zip(features.chunks(FEATURES_PER_SUPER_FEATURE))
+        {
+            *super_feature = combine(group);
+        }
+        Some(Self(super_features))
+    }
+
+    /// Whether the two sketches share a super-feature.
+    pub fn resembles(&self, other: &Sketch) -> bool {
+        self.0.iter().zip(&other.0).any(|(a, b)| a == b)
+    }
+
+    /// One index key per super-feature, qualified by its position so equal
+    /// values in different positions don't collide.
+    pub fn keys(&self) -> impl Iterator<Item = ContentHash> + '_ {
+        self.0.iter().enumerate().map(|(position, super_feature)| {
+            let mut key = [0u8; 9];
+            key[0] = position as u8;
+            key[1..].copy_from_slice(&super_feature.to_be_bytes());
+            ContentHash::from_bytes(&key)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_data(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6_364_136_223_846_793_005)
+                    .wrapping_add(1_442_695_040_888_963_407);
+                (state >> 56) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn small_edits_keep_a_super_feature() {
+        let data = sample_data(256 * 1024, 1);
+        let sketch = Sketch::of(&data).unwrap();
+
+        let mut edited = data.clone();
+        edited[1_000..1_016].copy_from_slice(b"a handful of new");
+        edited.insert(90_000, 0x5a);
+        edited.truncate(250 * 1024);
+        assert!(sketch.resembles(&Sketch::of(&edited).unwrap()));
+
+        let other = Sketch::of(&sample_data(256 * 1024, 2)).unwrap();
+        assert!(!sketch.resembles(&other));
+    }
+
+    #[test]
+    fn keys_are_distinct_per_position() {
+        let sketch = Sketch([7; SUPER_FEATURES]);
+        let keys: Vec<_> = sketch.keys().collect();
+        assert_eq!(keys.len(), SUPER_FEATURES);
+        assert!(keys.windows(2).all(|pair| pair[0] != pair[1]));
+        assert_eq!(Sketch::of(&[]), None);
+    }
+}
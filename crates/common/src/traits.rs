@@ -3,9 +3,10 @@ use std::borrow::Cow;
 use anyhow::Result;
 use futures::future::BoxFuture;
 
+use crate::similarity::Sketch;
 use crate::{
     Capsule, CapsuleId, CompressionPolicy, ContentHash, EncryptionPolicy, Policy, Segment,
-    SegmentId,
+    SegmentId, StorageTier, WrappedDataKey,
 };
 
 /// Summary information produced by a compression engine.
@@ -153,6 +154,30 @@ pub trait Encryptor: Send + Sync {
     fn compute_mac(&self, data: &[u8], segment: SegmentId) -> Result<Vec<u8>>;
 
     fn verify_mac(&self, data: &[u8], mac: &[u8], segment: SegmentId) -> Result<()>;
+
+    /// Encrypt under `key`, as derived by a [`Keyring`], rather than the
+    /// encryptor's own key. Encryptors without keys ignore it.
+    fn encrypt_with_key(
+        &self,
+        data: Cow<'_, [u8]>,
+        policy: &EncryptionPolicy,
+        segment: SegmentId,
+        _key: &[u8; 32],
+    ) -> Result<(Vec<u8>, EncryptionSummary)> {
+        self.encrypt(data, policy, segment)
+    }
+
+    /// Decrypt bytes that [`Encryptor::encrypt_with_key`] sealed under `key`,
+    /// as `segment`'s metadata describes them.
+    fn decrypt_with_key(
+        &self,
+        data: &[u8],
+        policy: &EncryptionPolicy,
+        segment: &Segment,
+        _key: &[u8; 32],
+    ) -> Result<Vec<u8>> {
+        self.decrypt(data, policy, segment.id)
+    }
 }
 
 /// Transaction object returned by storage backends.
@@ -195,6 +220,12 @@ pub trait StorageBackend: Send + Sync {
     fn segment_ids(&self) -> BoxFuture<'_, Result<Vec<SegmentId>>>;
 
     fn begin_txn(&mut self) -> BoxFuture<'_, Result<Self::Transaction>>;
+
+    /// Move `segment` to `tier`, returning whether it moved. Backends with a
+    /// single tier keep every segment where it is.
+    fn relocate(&mut self, _segment: SegmentId, _tier: StorageTier) -> BoxFuture<'_, Result<bool>> {
+        Box::pin(async { Ok(false) })
+    }
 }
 
 /// Evaluates policy directives for a given capsule write.
@@ -209,10 +240,33 @@ pub trait PolicyEvaluator: Send + Sync {
 }
 
 /// Abstract key management used by the encryptor.
+///
+/// Keyrings with envelope keys give each capsule its own data key and derive
+/// segment keys from it. The data keys travel, wrapped, in capsule records.
 pub trait Keyring: Send + Sync {
+    /// Key sealing `segment` of `capsule`, from the capsule's current data key.
     fn derive_key(&self, capsule: CapsuleId, segment: SegmentId) -> Result<[u8; 32]>;
 
+    /// Give `capsule` a new data key; segments sealed from now on use it.
     fn rotate_key(&mut self, capsule: CapsuleId) -> Result<()>;
+
+    /// Adopt `capsule`'s data keys as read back from its record, so that
+    /// generations continue from them after a restart. Keyrings without
+    /// per-capsule keys ignore them.
+    fn restore_keys(&mut self, _capsule: CapsuleId, _keys: &[WrappedDataKey]) -> Result<()> {
+        Ok(())
+    }
+
+    /// `capsule`'s data keys wrapped for its record, oldest first. Keyrings
+    /// without per-capsule keys have none.
+    fn wrapped_keys(&self, _capsule: CapsuleId) -> Result<Vec<WrappedDataKey>> {
+        Ok(Vec::new())
+    }
+
+    /// Key for `segment` from a data key read back from a capsule record.
+    fn unwrap_key(&self, key: &WrappedDataKey, segment: SegmentId) -> Result<[u8; 32]> {
+        self.derive_key(key.id.capsule, segment)
+    }
 }
 
 /// Protocol view abstraction for front-end handlers.
@@ -229,6 +283,8 @@ pub trait CapsuleCatalog: Send + Sync {
 
     fn lookup_capsule(&self, id: CapsuleId) -> Result<Capsule>;
 
+    /// Publish capsule `id` in one update, together with the wrapped data keys
+    /// its segments are sealed under, so no crash can leave it without them.
     fn create_capsule(
         &self,
         id: CapsuleId,
@@ -236,16 +292,43 @@ pub trait CapsuleCatalog: Send + Sync {
         policy: &Policy,
         segments: Vec<SegmentId>,
         stats: &DedupStats,
+        data_keys: Vec<WrappedDataKey>,
     ) -> Result<()>;
 
     fn delete_capsule(&self, id: CapsuleId) -> Result<Capsule>;
 
+    /// Record the wrapped data keys capsule `id`'s segments are sealed under.
+    fn set_data_keys(&self, id: CapsuleId, keys: Vec<WrappedDataKey>) -> Result<()>;
+
+    /// Point capsule `id` at `segments` and the data keys they are sealed
+    /// under, in one update.
+    fn replace_segments(
+        &self,
+        id: CapsuleId,
+        segments: Vec<SegmentId>,
+        data_keys: Vec<WrappedDataKey>,
+    ) -> Result<()>;
+
     fn lookup_content(&self, hash: &ContentHash) -> Option<SegmentId>;
 
     fn register_content(&self, hash: ContentHash, segment: SegmentId) -> Result<()>;
 
     fn deregister_content(&self, hash: &ContentHash, segment: SegmentId) -> Result<bool>;
 
+    /// A segment whose sketch shares a super-feature with `sketch`. Catalogs
+    /// without a similarity index never find one.
+    fn lookup_similar(&self, _sketch: &Sketch) -> Option<SegmentId> {
+        None
+    }
+
+    fn register_similar(&self, _sketch: &Sketch, _segment: SegmentId) -> Result<()> {
+        Ok(())
+    }
+
+    fn deregister_similar(&self, _sketch: &Sketch, _segment: SegmentId) -> Result<bool> {
+        Ok(false)
+    }
+
     fn capsules(&self) -> Vec<Capsule>;
 
     fn content_entries(&self) -> Vec<(ContentHash, SegmentId)>;
@@ -0,0 +1,234 @@
+//! Compression codecs, looked up by the name segments record.
+//!
+//! Every compressed segment stores the algorithm it was written with as
+//! `"<codec>:<level>"`, and reads decode with the codec registered under that
+//! name rather than whatever the capsule's policy says today. LZ4 and Zstd are
+//! built in; other codecs (Brotli, Snappy, XZ, ...) plug in through
+//! [`register_codec`] and are selected with `CompressionPolicy::Codec`.
+//!
+//! The registry is process-wide so that every pipeline, scrub and fsck in the
+//! process can decode what any of them wrote. A codec must stay registered
+//! under the same name for as long as segments written with it exist.
+
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use crate::error::CompressionError;
+
+type CodecResult<T> = std::result::Result<T, CompressionError>;
+
+/// Algorithm recorded for segments stored as-is.
+pub const IDENTITY: &str = "identity";
+
+/// A compression algorithm segments can be stored with.
+pub trait Codec: Send + Sync {
+    /// Name recorded on segments; must not contain `:`.
+    fn name(&self) -> &'static str;
+
+    /// Levels the codec accepts. Policy levels outside it are clamped.
+    fn levels(&self) -> RangeInclusive<i32> {
+        i32::MIN..=i32::MAX
+    }
+
+    fn compress(&self, data: &[u8], level: i32) -> CodecResult<Vec<u8>>;
+
+    fn decompress(&self, data: &[u8]) -> CodecResult<Vec<u8>>;
+}
+
+/// The codec name in a recorded algorithm such as `"zstd:3"`.
+pub fn codec_name(algorithm: &str) -> &str {
+    algorithm
+        .split_once(':')
+        .map_or(algorithm, |(name, _)| name)
+}
+
+/// Whether `algorithm` means the bytes were stored uncompressed. `"none"` is
+/// what the log records before a write fills in the real algorithm.
+pub fn is_identity(algorithm: &str) -> bool {
+    matches!(algorithm, IDENTITY | "none")
+}
+
+/// Codecs by name.
+pub struct CodecRegistry {
+    codecs: RwLock<HashMap<&'static str, Arc<dyn Codec>>>,
+}
+
+impl CodecRegistry {
+    /// A registry holding the built-in codecs.
+    pub fn new() -> Self {
+        let registry = Self {
+            codecs: RwLock::new(HashMap::new()),
+        };
+        registry.register(Arc::new(Lz4Codec));
+        registry.register(Arc::new(ZstdCodec));
+        registry
+    }
+
+    /// Add `codec`, replacing any codec already registered under its name.
+    pub fn register(&self, codec: Arc<dyn Codec>) {
+        let name = codec.name();
+        assert!(
+            !name.is_empty() && !name.contains(':') && !is_identity(name),
+            "invalid codec name {name:?}"
+        );
+        self.codecs.write().unwrap().insert(name, codec);
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Codec>> {
+        self.codecs.read().unwrap().get(name).cloned()
+    }
+
+    /// The codec registered as `name`, or an error naming it.
+    pub fn require(&self, name: &str) -> CodecResult<Arc<dyn Codec>> {
+        self.get(name)
+            .ok_or_else(|| CompressionError::unknown_codec(name))
+    }
+
+    /// Registered codec names, sorted.
+    pub fn names(&self) -> Vec<&'static str> {
+        let mut names: Vec<_> = self.codecs.read().unwrap().keys().copied().collect();
+        names.sort_unstable();
+        names
+    }
+
+    pub fn supports(&self, algorithm: &str) -> bool {
+        is_identity(algorithm) || self.get(codec_name(algorithm)).is_some()
+    }
+
+    /// Decode bytes stored under `algorithm`, as recorded on their segment.
+    pub fn decompress(&self, algorithm: &str, data: &[u8]) -> CodecResult<Vec<u8>> {
+        if is_identity(algorithm) {
+            return Ok(data.to_vec());
+        }
+        self.require(codec_name(algorithm))?.decompress(data)
+    }
+}
+
+impl Default for CodecRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The process-wide registry.
+pub fn codecs() -> &'static CodecRegistry {
+    static CODECS: OnceLock<CodecRegistry> = OnceLock::new();
+    CODECS.get_or_init(CodecRegistry::new)
+}
+
+/// Register `codec` with the process-wide registry.
+pub fn register_codec(codec: impl Codec + 'static) {
+    codecs().register(Arc::new(codec));
+}
+
+/// Decode bytes stored under `algorithm` with the process-wide registry.
+pub fn decompress(algorithm: &str, data: &[u8]) -> CodecResult<Vec<u8>> {
+    codecs().decompress(algorithm, data)
+}
+
+pub struct Lz4Codec;
+
+impl Codec for Lz4Codec {
+    fn name(&self) -> &'static str {
+        "lz4"
+    }
+
+    fn levels(&self) -> RangeInclusive<i32> {
+        1..=16
+    }
+
+    fn compress(&self, data: &[u8], level: i32) -> CodecResult<Vec<u8>> {
+        crate::compress_lz4(data, level)
+    }
+
+    fn decompress(&self, data: &[u8]) -> CodecResult<Vec<u8>> {
+        crate::decompress_lz4(data)
+    }
+}
+
+pub struct ZstdCodec;
+
+impl Codec for ZstdCodec {
+    fn name(&self) -> &'static str {
+        "zstd"
+    }
+
+    fn levels(&self) -> RangeInclusive<i32> {
+        -5..=22
+    }
+
+    fn compress(&self, data: &[u8], level: i32) -> CodecResult<Vec<u8>> {
+        crate::compress_zstd(data, level)
+    }
+
+    fn decompress(&self, data: &[u8]) -> CodecResult<Vec<u8>> {
+        crate::decompress_zstd(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress_segment;
+    use common::CompressionPolicy;
+
+    /// Byte-oriented run-length coding: (run, byte) pairs.
+    struct RunLength;
+
+    impl Codec for RunLength {
+        fn name(&self) -> &'static str {
+            "rle-test"
+        }
+
+        fn compress(&self, data: &[u8], _level: i32) -> CodecResult<Vec<u8>> {
+            let mut out = Vec::new();
+            for run in data.chunk_by(|a, b| a == b) {
+                for part in run.chunks(u8::MAX as usize) {
+                    out.extend_from_slice(&[part.len() as u8, part[0]]);
+                }
+            }
+            Ok(out)
+        }
+
+        fn decompress(&self, data: &[u8]) -> CodecResult<Vec<u8>> {
+            if !data.len().is_multiple_of(2) {
+                return Err(CompressionError::codec("rle-test", "odd length"));
+            }
+            Ok(data
+                .chunks(2)
+                .flat_map(|pair| std::iter::repeat_n(pair[1], pair[0] as usize))
+                .collect())
+        }
+    }
+
+    #[test]
+    fn registered_codecs_compress_by_name_and_decode_by_record() {
+        register_codec(RunLength);
+        let data = [vec![b'a'; 3000], vec![b'b'; 700]].concat();
+        let policy = CompressionPolicy::Codec {
+            name: "rle-test".into(),
+            level: 0,
+        };
+
+        let (compressed, result) = compress_segment(&data, &policy).unwrap();
+        assert!(result.compressed);
+        assert_eq!(result.algorithm, "rle-test:0");
+        assert_eq!(decompress(&result.algorithm, &compressed).unwrap(), data);
+        assert!(codecs().names().contains(&"rle-test"));
+    }
+
+    #[test]
+    fn unknown_codecs_are_errors() {
+        let policy = CompressionPolicy::Codec {
+            name: "missing".into(),
+            level: 1,
+        };
+        assert!(compress_segment(&[b'x'; 4096], &policy).is_err());
+        assert!(matches!(
+            decompress("missing:1", b"payload"),
+            Err(CompressionError::UnknownCodec { .. })
+        ));
+        assert_eq!(decompress("identity", b"payload").unwrap(), b"payload");
+    }
+}
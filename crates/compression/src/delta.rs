@@ -0,0 +1,253 @@
+//! Copy/insert deltas between similar byte strings.
+//!
+//! A delta rebuilds a target from a base it resembles: a sequence of copies of
+//! base ranges and inserts of literal bytes. Matches are seeded from a table
+//! of base windows sampled every [`STRIDE`] bytes, so any run the two share
+//! that is longer than `BLOCK + STRIDE` bytes is found wherever it sits in
+//! either input, and then extended byte by byte in both directions.
+
+use std::collections::HashMap;
+
+use crate::error::CompressionError;
+
+type DeltaResult<T> = std::result::Result<T, CompressionError>;
+
+const FORMAT_VERSION: u8 = 1;
+const OP_INSERT: u8 = 0;
+const OP_COPY: u8 = 1;
+
+/// Bytes hashed per seed window.
+const BLOCK: usize = 32;
+/// Distance between indexed base windows.
+const STRIDE: usize = 16;
+const HASH_BASE: u64 = 0x0000_0100_0000_01b3;
+/// Upper bound on the output reserved up front, so a corrupt length can't
+/// force a huge allocation before it is rejected.
+const MAX_RESERVE: usize = 64 * 1024 * 1024;
+
+fn window_hash(window: &[u8]) -> u64 {
+    window.iter().fold(0u64, |hash, byte| {
+        hash.wrapping_mul(HASH_BASE).wrapping_add(*byte as u64)
+    })
+}
+
+/// Factor by which the outgoing byte of a window was multiplied.
+fn leading_factor() -> u64 {
+    (1..BLOCK).fold(1u64, |factor, _| factor.wrapping_mul(HASH_BASE))
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(delta: &[u8], pos: &mut usize) -> DeltaResult<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *delta
+            .get(*pos)
+            .ok_or_else(|| CompressionError::codec("delta", "truncated varint"))?;
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(CompressionError::codec("delta", "varint overflows 64 bits"))
+}
+
+fn push_insert(out: &mut Vec<u8>, literal: &[u8]) {
+    if literal.is_empty() {
+        return;
+    }
+    out.push(OP_INSERT);
+    write_varint(out, literal.len() as u64);
+    out.extend_from_slice(literal);
+}
+
+fn push_copy(out: &mut Vec<u8>, offset: usize, len: usize) {
+    out.push(OP_COPY);
+    write_varint(out, offset as u64);
+    write_varint(out, len as u64);
+}
+
+/// Delta that rebuilds `target` from `base`, or `None` if it would take more
+/// than half as many bytes as `target` itself.
+pub fn encode_delta(base: &[u8], target: &[u8]) -> Option<Vec<u8>> {
+    if base.len() < BLOCK || target.len() < BLOCK {
+        return None;
+    }
+    let limit = target.len() / 2;
+
+    let mut seeds: HashMap<u64, u32> = HashMap::with_capacity(base.len() / STRIDE + 1);
+    for start in (0..=base.len() - BLOCK).step_by(STRIDE) {
+        seeds
+            .entry(window_hash(&base[start..start + BLOCK]))
+            .or_insert(start as u32);
+    }
+    let leading = leading_factor();
+
+    let mut out = vec![FORMAT_VERSION];
+    write_varint(&mut out, target.len() as u64);
+    let mut literal_start = 0;
+    let mut pos = 0;
+    let mut hash = window_hash(&target[..BLOCK]);
+    while pos + BLOCK <= target.len() {
+        if out.len() + (pos - literal_start) > limit {
+            return None;
+        }
+        let seed = seeds.get(&hash).map(|offset| *offset as usize);
+        if let Some(offset) = seed.filter(|o| base[*o..*o + BLOCK] == target[pos..pos + BLOCK]) {
+            let (mut start, mut base_start) = (pos, offset);
+            while start > literal_start
+                && base_start > 0
+                && base[base_start - 1] == target[start - 1]
+            {
+                start -= 1;
+                base_start -= 1;
+            }
+            let (mut end, mut base_end) = (pos + BLOCK, offset + BLOCK);
+            while end < target.len() && base_end < base.len() && base[base_end] == target[end] {
+                end += 1;
+                base_end += 1;
+            }
+
+            push_insert(&mut out, &target[literal_start..start]);
+            push_copy(&mut out, base_start, end - start);
+            literal_start = end;
+            pos = end;
+            if pos + BLOCK <= target.len() {
+                hash = window_hash(&target[pos..pos + BLOCK]);
+            }
+            continue;
+        }
+
+        if pos + BLOCK < target.len() {
+            hash = hash
+                .wrapping_sub((target[pos] as u64).wrapping_mul(leading))
+                .wrapping_mul(HASH_BASE)
+                .wrapping_add(target[pos + BLOCK] as u64);
+        }
+        pos += 1;
+    }
+    push_insert(&mut out, &target[literal_start..]);
+
+    (out.len() <= limit).then_some(out)
+}
+
+/// Rebuild the target a delta from [`encode_delta`] was made for.
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> DeltaResult<Vec<u8>> {
+    match delta.first() {
+        Some(&FORMAT_VERSION) => {}
+        Some(version) => {
+            return Err(CompressionError::codec(
+                "delta",
+                format!("unsupported delta format {}", version),
+            ))
+        }
+        None => return Err(CompressionError::codec("delta", "empty delta")),
+    }
+    let mut pos = 1;
+    let target_len = read_varint(delta, &mut pos)? as usize;
+    let mut output = Vec::with_capacity(target_len.min(MAX_RESERVE));
+
+    while pos < delta.len() {
+        let op = delta[pos];
+        pos += 1;
+        match op {
+            OP_INSERT => {
+                let len = read_varint(delta, &mut pos)? as usize;
+                let literal = pos
+                    .checked_add(len)
+                    .and_then(|end| delta.get(pos..end))
+                    .ok_or_else(|| CompressionError::codec("delta", "insert runs past the end"))?;
+                output.extend_from_slice(literal);
+                pos += len;
+            }
+            OP_COPY => {
+                let offset = read_varint(delta, &mut pos)? as usize;
+                let len = read_varint(delta, &mut pos)? as usize;
+                let range = offset
+                    .checked_add(len)
+                    .and_then(|end| base.get(offset..end))
+                    .ok_or_else(|| {
+                        CompressionError::codec("delta", "copy runs past the end of the base")
+                    })?;
+                output.extend_from_slice(range);
+            }
+            other => {
+                return Err(CompressionError::codec(
+                    "delta",
+                    format!("unknown delta op {}", other),
+                ))
+            }
+        }
+        if output.len() > target_len {
+            break;
+        }
+    }
+
+    if output.len() != target_len {
+        return Err(CompressionError::codec(
+            "delta",
+            format!(
+                "delta rebuilt {} bytes, expected {}",
+                output.len(),
+                target_len
+            ),
+        ));
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_data(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6_364_136_223_846_793_005)
+                    .wrapping_add(1_442_695_040_888_963_407);
+                (state >> 56) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn edited_copies_roundtrip_in_a_small_delta() {
+        let base = sample_data(200_000, 1);
+        let mut target = base.clone();
+        target[5_000..5_008].copy_from_slice(b"modified");
+        target.splice(120_000..120_000, b"inserted bytes".iter().copied());
+        target.drain(150_000..150_100);
+
+        let delta = encode_delta(&base, &target).unwrap();
+        assert!(delta.len() < 200, "delta was {} bytes", delta.len());
+        assert_eq!(apply_delta(&base, &delta).unwrap(), target);
+    }
+
+    #[test]
+    fn unrelated_data_is_not_worth_a_delta() {
+        let base = sample_data(64 * 1024, 1);
+        assert!(encode_delta(&base, &sample_data(64 * 1024, 2)).is_none());
+        assert!(encode_delta(&base, b"short").is_none());
+    }
+
+    #[test]
+    fn damaged_deltas_are_rejected() {
+        let base = sample_data(64 * 1024, 3);
+        let mut target = base.clone();
+        target[100] ^= 0xff;
+        let delta = encode_delta(&base, &target).unwrap();
+
+        assert!(apply_delta(&base[..1024], &delta).is_err());
+        assert!(apply_delta(&base, &delta[..delta.len() - 1]).is_err());
+        assert!(apply_delta(&base, &[]).is_err());
+    }
+}
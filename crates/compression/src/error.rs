@@ -15,6 +15,10 @@ pub enum CompressionError {
     #[error("Invalid compression policy: {reason}")]
     InvalidPolicy { reason: String },
 
+    /// No codec is registered under the name a policy or segment uses.
+    #[error("No codec registered as {name:?}")]
+    UnknownCodec { name: String },
+
     /// Compression backend produced an IO error.
     #[error("IO error while using {algorithm}: {source}")]
     Io {
@@ -42,6 +46,10 @@ impl CompressionError {
         }
     }
 
+    pub fn unknown_codec(name: impl Into<String>) -> Self {
+        CompressionError::UnknownCodec { name: name.into() }
+    }
+
     pub fn codec(algorithm: &'static str, message: impl Into<String>) -> Self {
         CompressionError::Codec {
             algorithm,
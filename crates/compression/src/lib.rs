@@ -1,7 +1,12 @@
+pub mod codec;
+pub mod delta;
+pub mod dictionary;
 mod error;
+pub mod selection;
 
 use std::borrow::Cow;
 use std::io::Write;
+use std::sync::Arc;
 
 use anyhow::{Context, Result};
 use common::{
@@ -11,8 +16,11 @@ use common::{
 use subtle::ConstantTimeEq;
 use tracing::{debug, info, instrument, warn};
 
+pub use codec::{codecs, register_codec, Codec, CodecRegistry};
 pub use error::CompressionError;
 
+use codec::{ZstdCodec, IDENTITY};
+
 type CompressionOpResult<T> = std::result::Result<T, CompressionError>;
 
 fn constant_time_equal(a: &[u8], b: &[u8]) -> bool {
@@ -52,6 +60,9 @@ pub struct CompressionResult {
     pub reused_original: bool,
     pub algorithm: String,
     pub reason: Option<CompressionSkipReason>,
+    /// The output was compressed against the dictionary passed in and needs it
+    /// to decompress.
+    pub dictionary: bool,
 }
 
 impl CompressionResult {
@@ -153,74 +164,80 @@ pub fn decompress_zstd(data: &[u8]) -> CompressionOpResult<Vec<u8>> {
     Ok(decompressed)
 }
 
-/// Adjust compression level based on policy
-fn adjusted_level(level: i32, algorithm: &'static str) -> CompressionOpResult<i32> {
-    let clamped = match algorithm {
-        "lz4" => level.clamp(1, 16),
-        "zstd" => level.clamp(-5, 22),
-        _ => level,
-    };
+/// Clamp `level` to the range `codec` accepts.
+fn adjusted_level(level: i32, codec: &dyn Codec) -> i32 {
+    let levels = codec.levels();
+    let clamped = level.clamp(*levels.start(), *levels.end());
 
     if clamped != level {
         warn!(
             original_level = level,
             clamped_level = clamped,
-            algorithm,
+            algorithm = codec.name(),
             "Compression level clamped to supported range"
         );
     }
 
-    Ok(clamped)
+    clamped
+}
+
+/// The registered codec and level `policy` selects, or `None` to store data as-is.
+fn resolve_codec(policy: &CompressionPolicy) -> CompressionOpResult<Option<(Arc<dyn Codec>, i32)>> {
+    let (name, level) = match policy {
+        CompressionPolicy::None => return Ok(None),
+        CompressionPolicy::LZ4 { level } => ("lz4", *level),
+        CompressionPolicy::Zstd { level } => ("zstd", *level),
+        CompressionPolicy::Codec { name, level } => (name.as_str(), *level),
+        CompressionPolicy::Adaptive { .. } => {
+            return Err(CompressionError::invalid_policy(
+                "adaptive compression must be resolved to a codec first",
+            ))
+        }
+    };
+    let codec = codecs().require(name)?;
+    let level = adjusted_level(level, codec.as_ref());
+    Ok(Some((codec, level)))
 }
 
 /// Attempt compression and return compressed data with metadata.
 fn attempt_compress(
     data: &[u8],
     policy: &CompressionPolicy,
+    dictionary: Option<&[u8]>,
 ) -> CompressionOpResult<(Vec<u8>, CompressionResult)> {
-    match policy {
-        CompressionPolicy::None => Ok((
+    let Some((codec, level)) = resolve_codec(policy)? else {
+        return Ok((
             data.to_vec(),
             CompressionResult {
                 original_size: data.len(),
                 compressed_size: data.len(),
                 compressed: false,
                 reused_original: true,
-                algorithm: "identity".into(),
+                algorithm: IDENTITY.into(),
                 reason: None,
+                dictionary: false,
             },
-        )),
-        CompressionPolicy::LZ4 { level } => {
-            let level = adjusted_level(*level, "lz4")?;
-            let compressed = compress_lz4(data, level)?;
-            Ok((
-                compressed,
-                CompressionResult {
-                    original_size: data.len(),
-                    compressed_size: data.len(),
-                    compressed: true,
-                    reused_original: false,
-                    algorithm: format!("lz4:{level}"),
-                    reason: None,
-                },
-            ))
-        }
-        CompressionPolicy::Zstd { level } => {
-            let level = adjusted_level(*level, "zstd")?;
-            let compressed = compress_zstd(data, level)?;
-            Ok((
-                compressed,
-                CompressionResult {
-                    original_size: data.len(),
-                    compressed_size: data.len(),
-                    compressed: true,
-                    reused_original: false,
-                    algorithm: format!("zstd:{level}"),
-                    reason: None,
-                },
-            ))
-        }
-    }
+        ));
+    };
+
+    // Only Zstd frames can be written against a dictionary.
+    let dictionary = dictionary.filter(|_| codec.name() == ZstdCodec.name());
+    let compressed = match dictionary {
+        Some(dictionary) => dictionary::compress_zstd_with_dictionary(data, level, dictionary)?,
+        None => codec.compress(data, level)?,
+    };
+    Ok((
+        compressed,
+        CompressionResult {
+            original_size: data.len(),
+            compressed_size: data.len(),
+            compressed: true,
+            reused_original: false,
+            algorithm: format!("{}:{level}", codec.name()),
+            reason: None,
+            dictionary: dictionary.is_some(),
+        },
+    ))
 }
 
 /// Verify integrity by comparing recompressed output with original.
@@ -229,30 +246,35 @@ fn verify_integrity(
     compressed: &[u8],
     original: &[u8],
 ) -> CompressionOpResult<()> {
-    match policy {
-        CompressionPolicy::LZ4 { .. } => {
-            let decompressed = decompress_lz4(compressed)?;
-            if !constant_time_equal(&decompressed, original) {
-                return Err(CompressionError::integrity("lz4"));
-            }
+    if let Some((codec, _)) = resolve_codec(policy)? {
+        let decompressed = codec.decompress(compressed)?;
+        if !constant_time_equal(&decompressed, original) {
+            return Err(CompressionError::integrity(codec.name()));
         }
-        CompressionPolicy::Zstd { .. } => {
-            let decompressed = decompress_zstd(compressed)?;
-            if !constant_time_equal(&decompressed, original) {
-                return Err(CompressionError::integrity("zstd"));
-            }
-        }
-        CompressionPolicy::None => {}
     }
     Ok(())
 }
 
 /// Adaptive compression that skips high-entropy or ineffective compressions.
-#[instrument(skip(data, policy), fields(input_len = data.len()))]
+/// `CompressionPolicy::Adaptive` is first resolved to a codec through the
+/// process-wide [`selection::selector`].
 pub fn adaptive_compress<'a>(
     data: &'a [u8],
     policy: &CompressionPolicy,
 ) -> Result<(Cow<'a, [u8]>, CompressionResult)> {
+    adaptive_compress_with_dictionary(data, policy, None)
+}
+
+/// [`adaptive_compress`], compressing Zstd policies against `dictionary` when
+/// one is given. Other codecs ignore it.
+#[instrument(skip(data, policy, dictionary), fields(input_len = data.len()))]
+pub fn adaptive_compress_with_dictionary<'a>(
+    data: &'a [u8],
+    policy: &CompressionPolicy,
+    dictionary: Option<&[u8]>,
+) -> Result<(Cow<'a, [u8]>, CompressionResult)> {
+    let policy = selection::selector().resolve(policy, data);
+    let policy = policy.as_ref();
     if matches!(policy, CompressionPolicy::None) {
         return Ok((
             Cow::Borrowed(data),
@@ -261,8 +283,9 @@ pub fn adaptive_compress<'a>(
                 compressed_size: data.len(),
                 compressed: false,
                 reused_original: true,
-                algorithm: "identity".into(),
+                algorithm: IDENTITY.into(),
                 reason: Some(CompressionSkipReason::Ineffective { ratio: 1.0 }),
+                dictionary: false,
             },
         ));
     }
@@ -279,14 +302,15 @@ pub fn adaptive_compress<'a>(
                 compressed_size: data.len(),
                 compressed: false,
                 reused_original: true,
-                algorithm: "identity".into(),
+                algorithm: IDENTITY.into(),
                 reason: Some(reason),
+                dictionary: false,
             },
         ));
     }
 
     let (compressed, mut result) =
-        attempt_compress(data, policy).context("compression backend failure")?;
+        attempt_compress(data, policy, dictionary).context("compression backend failure")?;
 
     if compressed.len() >= data.len() {
         let ratio = compressed.len() as f32 / data.len() as f32;
@@ -299,12 +323,25 @@ pub fn adaptive_compress<'a>(
         result.compressed = false;
         result.compressed_size = data.len();
         result.reused_original = true;
-        result.algorithm = "identity".into();
+        result.algorithm = IDENTITY.into();
         result.reason = Some(CompressionSkipReason::Ineffective { ratio });
+        result.dictionary = false;
         return Ok((Cow::Borrowed(data), result));
     }
 
-    verify_integrity(policy, &compressed, data).context("integrity verification failed")?;
+    match dictionary.filter(|_| result.dictionary) {
+        Some(dictionary) => {
+            let decompressed = dictionary::decompress_zstd_with_dictionary(&compressed, dictionary)
+                .context("integrity verification failed")?;
+            if !constant_time_equal(&decompressed, data) {
+                return Err(CompressionError::integrity("zstd"))
+                    .context("integrity verification failed");
+            }
+        }
+        None => {
+            verify_integrity(policy, &compressed, data).context("integrity verification failed")?
+        }
+    }
 
     Ok((Cow::Owned(compressed), result))
 }
@@ -317,21 +354,33 @@ pub fn compress_segment<'a>(
     adaptive_compress(data, policy)
 }
 
-pub struct Lz4ZstdCompressor;
+/// [`compress_segment`] against an optional trained Zstd dictionary; check
+/// [`CompressionResult::dictionary`] for whether the output depends on it.
+pub fn compress_segment_with_dictionary<'a>(
+    data: &'a [u8],
+    policy: &CompressionPolicy,
+    dictionary: Option<&[u8]>,
+) -> Result<(Cow<'a, [u8]>, CompressionResult)> {
+    adaptive_compress_with_dictionary(data, policy, dictionary)
+}
+
+/// [`Compressor`] backed by the process-wide codec registry, so it compresses
+/// with any registered codec and decodes whatever algorithm a segment records.
+pub struct CodecCompressor;
 
-impl Lz4ZstdCompressor {
+impl CodecCompressor {
     pub fn new() -> Self {
         Self
     }
 }
 
-impl Default for Lz4ZstdCompressor {
+impl Default for CodecCompressor {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl Compressor for Lz4ZstdCompressor {
+impl Compressor for CodecCompressor {
     fn compress<'a>(
         &'a self,
         data: &'a [u8],
@@ -350,19 +399,11 @@ impl Compressor for Lz4ZstdCompressor {
     }
 
     fn decompress(&self, data: &[u8], algorithm: &str) -> Result<Vec<u8>> {
-        match algorithm {
-            "identity" => Ok(data.to_vec()),
-            algo if algo.starts_with("lz4") => decompress_lz4(data).map_err(Into::into),
-            algo if algo.starts_with("zstd") => decompress_zstd(data).map_err(Into::into),
-            other => Err(CompressionError::invalid_policy(format!(
-                "unsupported algorithm {other}"
-            ))
-            .into()),
-        }
+        codec::decompress(algorithm, data).map_err(Into::into)
     }
 
     fn supports_algorithm(&self, algorithm: &str) -> bool {
-        algorithm == "identity" || algorithm.starts_with("lz4") || algorithm.starts_with("zstd")
+        codecs().supports(algorithm)
     }
 }
 
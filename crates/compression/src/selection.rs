@@ -0,0 +1,311 @@
+//! Codec choice by trial compression.
+//!
+//! `CompressionPolicy::Adaptive` names a throughput floor rather than a codec.
+//! [`CodecSelector`] compresses a sample of the data with each candidate
+//! codec and level, drops those slower than the floor, and picks the best
+//! ratio among the rest. Storing data as-is meets any floor, so when no
+//! candidate does, or none shrinks the sample, the data stays uncompressed.
+//!
+//! Each candidate is timed over a few runs and keeps its fastest, so one
+//! stalled run doesn't rule it out. Decisions are cached per [`ContentClass`]
+//! and floor, and re-evaluated once they are [`DECISION_TTL`] old, so trials
+//! run the first time a kind of data is seen and again as load shifts.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+
+use common::CompressionPolicy;
+use tracing::{info, warn};
+
+use crate::{estimate_entropy, resolve_codec};
+
+/// Bytes of the data that trials compress.
+pub const SAMPLE_LEN: usize = 64 * 1024;
+/// Timed runs per candidate; the fastest counts.
+const TRIAL_RUNS: usize = 3;
+/// How long a cached decision stands before the next write re-runs the trials.
+pub const DECISION_TTL: Duration = Duration::from_secs(10 * 60);
+/// Share of printable bytes above which a sample counts as text.
+const TEXT_THRESHOLD: f32 = 0.95;
+
+/// Coarse description of a sample that codec decisions are cached under.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ContentClass {
+    pub text: bool,
+    /// Shannon entropy in whole bits per byte.
+    pub entropy: u8,
+}
+
+impl ContentClass {
+    pub fn of(sample: &[u8]) -> Self {
+        if sample.is_empty() {
+            return Self {
+                text: false,
+                entropy: 0,
+            };
+        }
+        let printable = sample
+            .iter()
+            .filter(|byte| matches!(byte, b'\t' | b'\n' | b'\r' | 0x20..=0x7e | 0x80..))
+            .count();
+        Self {
+            text: printable as f32 / sample.len() as f32 >= TEXT_THRESHOLD,
+            entropy: estimate_entropy(sample).floor() as u8,
+        }
+    }
+}
+
+/// How one candidate did on a sample.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trial {
+    pub policy: CompressionPolicy,
+    pub ratio: f32,
+    pub bytes_per_sec: f64,
+}
+
+/// Best-ratio trial at least `min_bytes_per_sec` fast, preferring the faster
+/// of equal ratios; [`CompressionPolicy::None`] if none qualifies or shrinks
+/// the data.
+pub fn choose(trials: &[Trial], min_bytes_per_sec: u64) -> CompressionPolicy {
+    trials
+        .iter()
+        .filter(|trial| trial.bytes_per_sec >= min_bytes_per_sec as f64 && trial.ratio > 1.0)
+        .max_by(|a, b| {
+            a.ratio
+                .total_cmp(&b.ratio)
+                .then(a.bytes_per_sec.total_cmp(&b.bytes_per_sec))
+        })
+        .map(|trial| trial.policy.clone())
+        .unwrap_or(CompressionPolicy::None)
+}
+
+/// Codecs and levels tried when nothing else is configured.
+fn default_candidates() -> Vec<CompressionPolicy> {
+    vec![
+        CompressionPolicy::LZ4 { level: 1 },
+        CompressionPolicy::Zstd { level: 1 },
+        CompressionPolicy::Zstd { level: 3 },
+        CompressionPolicy::Zstd { level: 9 },
+        CompressionPolicy::Zstd { level: 19 },
+    ]
+}
+
+/// Resolves adaptive policies to a concrete codec by trial compression.
+pub struct CodecSelector {
+    candidates: RwLock<Vec<CompressionPolicy>>,
+    decisions: Mutex<HashMap<(ContentClass, u64), (CompressionPolicy, Instant)>>,
+    decision_ttl: Duration,
+}
+
+impl CodecSelector {
+    /// A selector trying LZ4 and a spread of Zstd levels.
+    pub fn new() -> Self {
+        Self::with_candidates(default_candidates())
+    }
+
+    pub fn with_candidates(candidates: Vec<CompressionPolicy>) -> Self {
+        Self {
+            candidates: RwLock::new(candidates),
+            decisions: Mutex::new(HashMap::new()),
+            decision_ttl: DECISION_TTL,
+        }
+    }
+
+    /// Re-evaluate cached decisions once they are `ttl` old.
+    pub fn with_decision_ttl(mut self, ttl: Duration) -> Self {
+        self.decision_ttl = ttl;
+        self
+    }
+
+    /// Replace the candidates, e.g. to try codecs registered since, and
+    /// forget decisions made among the old ones.
+    pub fn set_candidates(&self, candidates: Vec<CompressionPolicy>) {
+        *self.candidates.write().unwrap() = candidates;
+        self.decisions.lock().unwrap().clear();
+    }
+
+    /// Compress `sample` with every candidate, keeping each one's fastest of
+    /// [`TRIAL_RUNS`] runs. Candidates naming a codec that isn't registered
+    /// are skipped.
+    pub fn trial(&self, sample: &[u8]) -> Vec<Trial> {
+        let sample = &sample[..sample.len().min(SAMPLE_LEN)];
+        let candidates = self.candidates.read().unwrap().clone();
+        candidates
+            .into_iter()
+            .filter_map(|policy| {
+                let (codec, level) = match resolve_codec(&policy) {
+                    Ok(Some(resolved)) => resolved,
+                    Ok(None) => return None,
+                    Err(err) => {
+                        warn!(policy = ?policy, error = %err, "skipping codec candidate");
+                        return None;
+                    }
+                };
+                let mut compressed = Vec::new();
+                let mut elapsed = f64::MAX;
+                for _ in 0..TRIAL_RUNS {
+                    let started = Instant::now();
+                    compressed = codec.compress(sample, level).ok()?;
+                    elapsed = elapsed.min(started.elapsed().as_secs_f64());
+                }
+                let elapsed = elapsed.max(1e-9);
+                Some(Trial {
+                    policy,
+                    ratio: sample.len() as f32 / compressed.len().max(1) as f32,
+                    bytes_per_sec: sample.len() as f64 / elapsed,
+                })
+            })
+            .collect()
+    }
+
+    /// The codec to compress data like `sample` with at `min_bytes_per_sec`.
+    pub fn select(&self, sample: &[u8], min_bytes_per_sec: u64) -> CompressionPolicy {
+        let sample = &sample[..sample.len().min(SAMPLE_LEN)];
+        let class = ContentClass::of(sample);
+        if let Some(policy) = self.decision(class, min_bytes_per_sec) {
+            return policy;
+        }
+
+        let trials = self.trial(sample);
+        let policy = choose(&trials, min_bytes_per_sec);
+        info!(
+            class = ?class,
+            min_bytes_per_sec,
+            trials = trials.len(),
+            chosen = ?policy,
+            "adaptive compression chose a codec"
+        );
+        self.decisions
+            .lock()
+            .unwrap()
+            .insert((class, min_bytes_per_sec), (policy.clone(), Instant::now()));
+        policy
+    }
+
+    /// The cached decision for `class` at `min_bytes_per_sec`, if any and
+    /// not yet due for re-evaluation.
+    pub fn decision(
+        &self,
+        class: ContentClass,
+        min_bytes_per_sec: u64,
+    ) -> Option<CompressionPolicy> {
+        self.decisions
+            .lock()
+            .unwrap()
+            .get(&(class, min_bytes_per_sec))
+            .filter(|(_, decided_at)| decided_at.elapsed() < self.decision_ttl)
+            .map(|(policy, _)| policy.clone())
+    }
+
+    /// `policy`, with an adaptive policy replaced by the codec chosen for
+    /// data like `sample`.
+    pub fn resolve<'a>(
+        &self,
+        policy: &'a CompressionPolicy,
+        sample: &[u8],
+    ) -> Cow<'a, CompressionPolicy> {
+        match policy {
+            CompressionPolicy::Adaptive { min_bytes_per_sec } => {
+                Cow::Owned(self.select(sample, *min_bytes_per_sec))
+            }
+            other => Cow::Borrowed(other),
+        }
+    }
+}
+
+impl Default for CodecSelector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The process-wide selector adaptive policies resolve through.
+pub fn selector() -> &'static CodecSelector {
+    static SELECTOR: OnceLock<CodecSelector> = OnceLock::new();
+    SELECTOR.get_or_init(CodecSelector::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trial(policy: CompressionPolicy, ratio: f32, bytes_per_sec: f64) -> Trial {
+        Trial {
+            policy,
+            ratio,
+            bytes_per_sec,
+        }
+    }
+
+    #[test]
+    fn best_ratio_within_the_throughput_floor_wins() {
+        let trials = [
+            trial(CompressionPolicy::LZ4 { level: 1 }, 2.0, 900e6),
+            trial(CompressionPolicy::Zstd { level: 3 }, 3.0, 400e6),
+            trial(CompressionPolicy::Zstd { level: 19 }, 3.5, 10e6),
+        ];
+        assert_eq!(choose(&trials, 0), CompressionPolicy::Zstd { level: 19 });
+        assert_eq!(
+            choose(&trials, 300_000_000),
+            CompressionPolicy::Zstd { level: 3 }
+        );
+        assert_eq!(
+            choose(&trials, 500_000_000),
+            CompressionPolicy::LZ4 { level: 1 }
+        );
+        assert_eq!(choose(&trials, 1_000_000_000), CompressionPolicy::None);
+        assert_eq!(
+            choose(&[trial(CompressionPolicy::LZ4 { level: 1 }, 0.99, 1e9)], 0),
+            CompressionPolicy::None
+        );
+    }
+
+    #[test]
+    fn decisions_are_cached_per_content_class() {
+        let selector = CodecSelector::new();
+        let text = b"adaptive codec selection caches by content class\n".repeat(2_000);
+        let binary: Vec<u8> = (0..100_000u32).map(|i| (i * 7 % 32) as u8).collect();
+        assert!(ContentClass::of(&text).text);
+        assert!(!ContentClass::of(&binary).text);
+
+        let chosen = selector.select(&text, 0);
+        assert!(!matches!(chosen, CompressionPolicy::None));
+        assert_eq!(
+            selector.decision(ContentClass::of(&text), 0),
+            Some(chosen.clone())
+        );
+        assert_eq!(selector.decision(ContentClass::of(&binary), 0), None);
+        assert_eq!(
+            selector.resolve(
+                &CompressionPolicy::Adaptive {
+                    min_bytes_per_sec: 0
+                },
+                &text
+            ),
+            Cow::<CompressionPolicy>::Owned(chosen)
+        );
+
+        selector.set_candidates(vec![CompressionPolicy::LZ4 { level: 1 }]);
+        assert_eq!(selector.decision(ContentClass::of(&text), 0), None);
+        assert_eq!(
+            selector.select(&text, 0),
+            CompressionPolicy::LZ4 { level: 1 }
+        );
+    }
+
+    #[test]
+    fn stale_decisions_are_re_evaluated() {
+        let text = b"cached decisions age out and are tried again\n".repeat(2_000);
+        let class = ContentClass::of(&text);
+
+        let expiring = CodecSelector::new().with_decision_ttl(Duration::ZERO);
+        assert_ne!(expiring.select(&text, 0), CompressionPolicy::None);
+        assert_eq!(expiring.decision(class, 0), None);
+
+        let lasting = CodecSelector::new();
+        let chosen = lasting.select(&text, 0);
+        assert_eq!(lasting.decision(class, 0), Some(chosen));
+    }
+}
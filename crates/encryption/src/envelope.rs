@@ -0,0 +1,189 @@
+//! Envelope encryption of per-capsule data keys
+//!
+//! Each capsule's segments are sealed under its own random data encryption key
+//! (DEK). The DEK is kept in the capsule's metadata wrapped by a key encryption
+//! key (KEK), which is a [`KeyManager`](crate::KeyManager) version key. Rotating
+//! the master key then only means rewrapping DEKs; no segment is re-encrypted.
+//!
+//! ## Wrapping
+//!
+//! - The 64-byte DEK is XTS-encrypted under the KEK as a single sector
+//! - The tweak is derived from a caller-supplied context naming the key, so a
+//!   wrapped key only unwraps under the identity it was wrapped for
+//! - A keyed BLAKE3 tag over context and wrapped bytes detects tampering and
+//!   unwrapping under the wrong KEK
+
+use crate::error::{EncryptionError, Result};
+use crate::keymanager::{XtsKeyPair, XTS_KEY_SIZE};
+use crate::mac::MAC_TAG_SIZE;
+use crate::xts::{decrypt, encrypt};
+use subtle::ConstantTimeEq;
+use zeroize::Zeroizing;
+
+/// Context strings domain-separating the derivations below
+const WRAP_TWEAK_CONTEXT: &str = "SPACE-DEK-WRAP-TWEAK-V1";
+const WRAP_MAC_CONTEXT: &str = "SPACE-DEK-WRAP-MAC-V1";
+const SEGMENT_KEY_CONTEXT: &str = "SPACE-DEK-SEGMENT-KEY-V1";
+const SEGMENT_PAIR_CONTEXT: &str = "SPACE-SEGMENT-XTS-PAIR-V1";
+
+/// Generate a random data key from the operating system's CSPRNG
+pub fn generate_data_key() -> Result<XtsKeyPair> {
+    let mut bytes = Zeroizing::new([0u8; XTS_KEY_SIZE]);
+    getrandom::fill(bytes.as_mut()).map_err(|err| {
+        EncryptionError::KeyDerivationFailed(format!("no randomness for data key: {err}"))
+    })?;
+    Ok(XtsKeyPair::from_bytes(*bytes))
+}
+
+/// Wrap `dek` under `kek` for storage alongside the data it protects
+///
+/// # Arguments
+///
+/// * `dek` - Data key to wrap
+/// * `kek` - Key encryption key (a master-derived version key)
+/// * `context` - Identity of the data key; the same bytes must be given to unwrap it
+///
+/// # Returns
+///
+/// Tuple of (wrapped key bytes, integrity tag)
+pub fn wrap_data_key(
+    dek: &XtsKeyPair,
+    kek: &XtsKeyPair,
+    context: &[u8],
+) -> Result<([u8; XTS_KEY_SIZE], [u8; MAC_TAG_SIZE])> {
+    let plain = Zeroizing::new(dek.to_bytes());
+    let ciphertext = encrypt(plain.as_ref(), kek, &wrap_tweak(context))?;
+
+    let mut wrapped = [0u8; XTS_KEY_SIZE];
+    wrapped.copy_from_slice(&ciphertext);
+    let tag = wrap_tag(kek, context, &wrapped);
+    Ok((wrapped, tag))
+}
+
+/// Recover a data key wrapped by [`wrap_data_key`]
+///
+/// The tag is checked in constant time before anything is decrypted, so a
+/// wrong KEK or context fails with [`EncryptionError::IntegrityFailure`].
+pub fn unwrap_data_key(
+    wrapped: &[u8],
+    tag: &[u8; MAC_TAG_SIZE],
+    kek: &XtsKeyPair,
+    context: &[u8],
+) -> Result<XtsKeyPair> {
+    if wrapped.len() != XTS_KEY_SIZE {
+        return Err(EncryptionError::InvalidKeyLength {
+            expected: XTS_KEY_SIZE,
+            actual: wrapped.len(),
+        });
+    }
+    if !bool::from(wrap_tag(kek, context, wrapped).ct_eq(tag)) {
+        return Err(EncryptionError::IntegrityFailure);
+    }
+
+    let plain = Zeroizing::new(decrypt(wrapped, kek, &wrap_tweak(context))?);
+    let mut bytes = Zeroizing::new([0u8; XTS_KEY_SIZE]);
+    bytes.copy_from_slice(&plain);
+    Ok(XtsKeyPair::from_bytes(*bytes))
+}
+
+/// Derive the 256-bit key for one segment from a capsule's data key
+///
+/// Distinct segments get independent keys, so a keyring can hand out
+/// per-segment keys without exposing the data key itself.
+pub fn derive_segment_key(dek: &XtsKeyPair, segment: u64) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new_derive_key(SEGMENT_KEY_CONTEXT);
+    hasher.update(dek.key1());
+    hasher.update(dek.key2());
+    hasher.update(&segment.to_le_bytes());
+    *hasher.finalize().as_bytes()
+}
+
+/// Expand a 256-bit segment key into the XTS key pair segments are sealed with
+pub fn segment_key_pair(key: &[u8; 32]) -> XtsKeyPair {
+    let mut bytes = Zeroizing::new([0u8; XTS_KEY_SIZE]);
+    let mut hasher = blake3::Hasher::new_derive_key(SEGMENT_PAIR_CONTEXT);
+    hasher.update(key);
+    hasher.finalize_xof().fill(bytes.as_mut());
+    XtsKeyPair::from_bytes(*bytes)
+}
+
+fn wrap_tweak(context: &[u8]) -> [u8; 16] {
+    let hash = blake3::Hasher::new_derive_key(WRAP_TWEAK_CONTEXT)
+        .update(context)
+        .finalize();
+    let mut tweak = [0u8; 16];
+    tweak.copy_from_slice(&hash.as_bytes()[..16]);
+    tweak
+}
+
+fn wrap_tag(kek: &XtsKeyPair, context: &[u8], wrapped: &[u8]) -> [u8; MAC_TAG_SIZE] {
+    let mut key_hasher = blake3::Hasher::new_derive_key(WRAP_MAC_CONTEXT);
+    key_hasher.update(kek.key1());
+    key_hasher.update(kek.key2());
+    let mac_key = Zeroizing::new(*key_hasher.finalize().as_bytes());
+
+    let mut hasher = blake3::Hasher::new_keyed(&mac_key);
+    hasher.update(&(context.len() as u64).to_le_bytes());
+    hasher.update(context);
+    hasher.update(wrapped);
+    let mut tag = [0u8; MAC_TAG_SIZE];
+    tag.copy_from_slice(&hasher.finalize().as_bytes()[..MAC_TAG_SIZE]);
+    tag
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keymanager::KeyManager;
+
+    #[test]
+    fn test_wrap_roundtrip() {
+        let mut manager = KeyManager::new([7u8; 32]);
+        let kek = manager.get_key(1).unwrap().clone();
+        let dek = generate_data_key().unwrap();
+
+        let (wrapped, tag) = wrap_data_key(&dek, &kek, b"capsule-a:1").unwrap();
+        assert_ne!(wrapped, dek.to_bytes());
+
+        let unwrapped = unwrap_data_key(&wrapped, &tag, &kek, b"capsule-a:1").unwrap();
+        assert_eq!(unwrapped.to_bytes(), dek.to_bytes());
+    }
+
+    #[test]
+    fn test_unwrap_rejects_wrong_kek_or_context() {
+        let mut manager = KeyManager::new([7u8; 32]);
+        let kek = manager.get_key(1).unwrap().clone();
+        let other_kek = manager.get_key(2).unwrap().clone();
+        let dek = generate_data_key().unwrap();
+        let (mut wrapped, tag) = wrap_data_key(&dek, &kek, b"capsule-a:1").unwrap();
+
+        assert!(matches!(
+            unwrap_data_key(&wrapped, &tag, &other_kek, b"capsule-a:1"),
+            Err(EncryptionError::IntegrityFailure)
+        ));
+        assert!(matches!(
+            unwrap_data_key(&wrapped, &tag, &kek, b"capsule-b:1"),
+            Err(EncryptionError::IntegrityFailure)
+        ));
+        wrapped[0] ^= 1;
+        assert!(unwrap_data_key(&wrapped, &tag, &kek, b"capsule-a:1").is_err());
+    }
+
+    #[test]
+    fn test_data_and_segment_keys_are_distinct() {
+        let first = generate_data_key().unwrap();
+        let second = generate_data_key().unwrap();
+        assert_ne!(first.to_bytes(), second.to_bytes());
+
+        assert_ne!(derive_segment_key(&first, 1), derive_segment_key(&first, 2));
+        assert_ne!(
+            derive_segment_key(&first, 1),
+            derive_segment_key(&second, 1)
+        );
+        let key = derive_segment_key(&first, 1);
+        assert_eq!(
+            segment_key_pair(&key).to_bytes(),
+            segment_key_pair(&key).to_bytes()
+        );
+    }
+}
@@ -79,9 +79,8 @@ impl XtsKeyPair {
         &self.key2
     }
 
-    /// Convert to 64-byte array (for testing)
-    #[cfg(test)]
-    pub fn to_bytes(&self) -> [u8; XTS_KEY_SIZE] {
+    /// Convert to 64-byte array, e.g. to wrap the pair as a data key
+    pub(crate) fn to_bytes(&self) -> [u8; XTS_KEY_SIZE] {
         let mut bytes = [0u8; XTS_KEY_SIZE];
         bytes[0..32].copy_from_slice(&self.key1);
         bytes[32..64].copy_from_slice(&self.key2);
@@ -330,6 +329,19 @@ impl KeyManager {
         self.rotating = false;
     }
 
+    /// Abandon an in-progress rotation
+    ///
+    /// Reverts to the previous version for new writes. Anything already wrapped
+    /// under the abandoned version stays readable, since versions re-derive.
+    pub fn abort_rotation(&mut self) {
+        if !self.rotating {
+            return;
+        }
+        self.key_cache.remove(&self.current_version);
+        self.current_version -= 1;
+        self.rotating = false;
+    }
+
     /// Get list of available key versions (for admin/debugging)
     pub fn available_versions(&self) -> Vec<u32> {
         let mut versions: Vec<u32> = self.key_cache.keys().copied().collect();
@@ -475,6 +487,22 @@ mod tests {
         assert!(manager.get_key(2).is_ok());
     }
 
+    #[test]
+    fn test_aborted_rotation_keeps_previous_version() {
+        let mut manager = KeyManager::new([42u8; MASTER_KEY_SIZE]);
+        let rotated = *manager.get_key(2).unwrap().key1();
+
+        assert_eq!(manager.rotate().unwrap(), 2);
+        manager.abort_rotation();
+        assert_eq!(manager.current_version(), 1);
+        assert!(!manager.is_rotating());
+        // Anything wrapped under the abandoned version still unwraps.
+        assert_eq!(manager.get_key(2).unwrap().key1(), &rotated);
+
+        // A retried rotation lands on the same version.
+        assert_eq!(manager.rotate().unwrap(), 2);
+    }
+
     #[test]
     #[serial]
     fn test_available_versions() {
@@ -8,6 +8,7 @@
 //! - **XTS-AES-256**: Disk encryption mode with deterministic tweaks
 //! - **Poly1305 MAC**: Integrity verification for encrypted segments
 //! - **Key Management**: Versioned keys with rotation support
+//! - **Envelope Keys**: Per-capsule data keys wrapped by the versioned keys
 //! - **Dedup Preservation**: Identical plaintext → identical ciphertext
 //! - **Hardware Acceleration**: AES-NI support when available
 //!
@@ -58,6 +59,7 @@
 //! ```
 
 // Module declarations
+pub mod envelope;
 pub mod error;
 pub mod keymanager;
 pub mod mac;
@@ -65,6 +67,9 @@ pub mod policy;
 pub mod xts;
 
 // Re-exports for convenience
+pub use envelope::{
+    derive_segment_key, generate_data_key, segment_key_pair, unwrap_data_key, wrap_data_key,
+};
 pub use error::{EncryptionError, Result};
 pub use keymanager::{KeyManager, XtsKeyPair};
 pub use mac::{compute_mac, verify_mac, MAC_TAG_SIZE};
@@ -1,4 +1,5 @@
 use crate::{offload, LayoutOffload};
+use common::chunking::ContentDefinedChunker;
 use common::{LayoutStrategy, Policy};
 
 #[cfg(feature = "ml")]
@@ -41,5 +42,14 @@ pub fn compile(policy: &Policy) -> Box<dyn LayoutOffload + Send + Sync> {
             policy.clone(),
             merkle_algo.clone(),
         )),
+        LayoutStrategy::ContentDefined {
+            min_size,
+            avg_size,
+            max_size,
+        } => Box::new(offload::CpuContentDefined::new(ContentDefinedChunker::new(
+            *min_size as usize,
+            *avg_size as usize,
+            *max_size as usize,
+        ))),
     }
 }
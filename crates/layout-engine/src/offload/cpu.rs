@@ -1,5 +1,6 @@
 use anyhow::Result;
 use blake3;
+use common::chunking::ContentDefinedChunker;
 use common::{CapsuleId, ContentHash, MerkleAlgo, Policy};
 
 use crate::{LayoutOffload, SegmentRef, Zone, ZonePlan};
@@ -26,56 +27,67 @@ impl CpuFixed {
 
     fn build_plan(&self, capsules: &[CapsuleId], data_slices: &[&[u8]]) -> ZonePlan {
         let segment_size = self.policy.layout.strategy.default_segment_size();
-        let mut zones = Vec::new();
-        let mut zone_id = 0u64;
-        let mut current_zone = Zone {
-            id: zone_id,
-            iv_seed: zone_id,
-            segments: Vec::new(),
-        };
-        let mut zone_usage = 0usize;
-        let mut cursor = 0usize;
-        let capsule = capsule_id_for(capsules);
-
-        for slice in data_slices {
-            let mut start = 0usize;
-            while start < slice.len() {
-                let take = (slice.len() - start).min(segment_size);
-                let chunk = &slice[start..start + take];
-                let segment = SegmentRef {
-                    capsule_id: capsule,
-                    offset: (cursor + start) as u64,
-                    length: take as u64,
-                    compressed_hash: hash_chunk(chunk),
-                };
-                current_zone.segments.push(segment);
-                start += take;
-                zone_usage += take;
+        plan_segments(capsules, data_slices, segment_size, |rest| {
+            rest.len().min(segment_size)
+        })
+    }
+}
 
-                if zone_usage >= self.policy.layout.strategy.default_segment_size()
-                    && !current_zone.segments.is_empty()
-                {
-                    zones.push(current_zone);
-                    zone_id += 1;
-                    current_zone = Zone {
-                        id: zone_id,
-                        iv_seed: zone_id,
-                        segments: Vec::new(),
-                    };
-                    zone_usage = 0;
-                }
+/// Cut each slice into segments of `cut(remaining bytes)` bytes and group them
+/// into zones of at least `zone_size` bytes.
+fn plan_segments(
+    capsules: &[CapsuleId],
+    data_slices: &[&[u8]],
+    zone_size: usize,
+    cut: impl Fn(&[u8]) -> usize,
+) -> ZonePlan {
+    let mut zones = Vec::new();
+    let mut zone_id = 0u64;
+    let mut current_zone = Zone {
+        id: zone_id,
+        iv_seed: zone_id,
+        segments: Vec::new(),
+    };
+    let mut zone_usage = 0usize;
+    let mut cursor = 0usize;
+    let capsule = capsule_id_for(capsules);
+
+    for slice in data_slices {
+        let mut start = 0usize;
+        while start < slice.len() {
+            let take = cut(&slice[start..]);
+            let chunk = &slice[start..start + take];
+            let segment = SegmentRef {
+                capsule_id: capsule,
+                offset: (cursor + start) as u64,
+                length: take as u64,
+                compressed_hash: hash_chunk(chunk),
+            };
+            current_zone.segments.push(segment);
+            start += take;
+            zone_usage += take;
+
+            if zone_usage >= zone_size && !current_zone.segments.is_empty() {
+                zones.push(current_zone);
+                zone_id += 1;
+                current_zone = Zone {
+                    id: zone_id,
+                    iv_seed: zone_id,
+                    segments: Vec::new(),
+                };
+                zone_usage = 0;
             }
-            cursor += slice.len();
         }
+        cursor += slice.len();
+    }
 
-        if !current_zone.segments.is_empty() {
-            zones.push(current_zone);
-        }
+    if !current_zone.segments.is_empty() {
+        zones.push(current_zone);
+    }
 
-        ZonePlan {
-            zones,
-            merkle_root: None,
-        }
+    ZonePlan {
+        zones,
+        merkle_root: None,
     }
 }
 
@@ -112,6 +124,36 @@ impl LayoutOffload for CpuEntropy {
     }
 }
 
+/// FastCDC segmentation for [`LayoutStrategy::ContentDefined`](common::LayoutStrategy::ContentDefined).
+///
+/// Each slice is chunked on its own, so callers should pass a capsule as one
+/// slice for boundaries to depend only on content.
+pub struct CpuContentDefined {
+    chunker: ContentDefinedChunker,
+}
+
+impl CpuContentDefined {
+    pub fn new(chunker: ContentDefinedChunker) -> Self {
+        Self { chunker }
+    }
+}
+
+impl LayoutOffload for CpuContentDefined {
+    fn synthesize(
+        &self,
+        capsules: &[CapsuleId],
+        data_slices: &[&[u8]],
+        _policy: &Policy,
+    ) -> Result<ZonePlan> {
+        Ok(plan_segments(
+            capsules,
+            data_slices,
+            self.chunker.avg_size(),
+            |rest| self.chunker.cut(rest),
+        ))
+    }
+}
+
 pub struct CpuQuantumReady {
     policy: Policy,
     merkle_algo: MerkleAlgo,
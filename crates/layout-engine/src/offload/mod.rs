@@ -1,3 +1,3 @@
 pub mod cpu;
 
-pub use cpu::{CpuEntropy, CpuFixed, CpuQuantumReady};
+pub use cpu::{CpuContentDefined, CpuEntropy, CpuFixed, CpuQuantumReady};
@@ -16,3 +16,37 @@ fn fixed_fallback_compatible() {
 
     assert_eq!(plan.zones.len(), 2);
 }
+
+#[test]
+fn content_defined_segments_cover_the_data() {
+    let mut policy = Policy::default();
+    policy.layout.strategy = LayoutStrategy::ContentDefined {
+        min_size: 4 * 1024,
+        avg_size: 16 * 1024,
+        max_size: 64 * 1024,
+    };
+
+    let engine = LayoutEngine::new(&policy);
+    let mut state = 1u64;
+    let data: Vec<u8> = (0..1024 * 1024)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (state >> 56) as u8
+        })
+        .collect();
+    let plan = engine
+        .synthesize(&[], &[&data[..]], &policy)
+        .expect("content-defined plan should succeed");
+
+    let segments: Vec<_> = plan.zones.iter().flat_map(|zone| &zone.segments).collect();
+    assert!(segments.len() > 16);
+    let mut cursor = 0;
+    for segment in &segments {
+        assert_eq!(segment.offset, cursor);
+        assert!(segment.length <= 64 * 1024);
+        cursor += segment.length;
+    }
+    assert_eq!(cursor, data.len() as u64);
+}
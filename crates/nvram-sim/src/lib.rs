@@ -6,6 +6,7 @@ use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, RwLock};
 #[cfg(feature = "advanced-security")]
 use tracing::warn;
@@ -14,16 +15,80 @@ pub struct NvramLog {
     file: Arc<RwLock<File>>,
     segment_map: Arc<RwLock<HashMap<SegmentId, Segment>>>,
     next_offset: Arc<RwLock<u64>>,
+    log_path: String,
     metadata_path: String,
+    // Transactions reserve offsets up front, so compaction must wait for them.
+    active_txns: Arc<AtomicUsize>,
     #[cfg(feature = "advanced-security")]
     audit_log: Option<AuditLog>,
 }
 
+/// Outcome of a single [`NvramLog::compact`] pass.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompactionStats {
+    /// Live segments copied into the compacted log.
+    pub segments_moved: usize,
+    /// Size of the backing file before compaction.
+    pub bytes_before: u64,
+    /// Size of the backing file after compaction.
+    pub bytes_after: u64,
+}
+
+impl CompactionStats {
+    pub fn reclaimed_bytes(&self) -> u64 {
+        self.bytes_before.saturating_sub(self.bytes_after)
+    }
+}
+
+/// BLAKE3 digest of a segment's stored bytes, as recorded in `Segment::checksum`.
+pub fn segment_checksum(data: &[u8]) -> String {
+    blake3::hash(data).to_hex().to_string()
+}
+
+fn read_segment_bytes(file: &mut File, offset: u64, len: u32) -> Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buffer = vec![0u8; len as usize];
+    file.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
+
+fn compaction_paths(log_path: &str, metadata_path: &str) -> (String, String) {
+    (
+        format!("{}.compact", log_path),
+        format!("{}.compact", metadata_path),
+    )
+}
+
+/// Finish or discard a compaction that was interrupted by a crash.
+///
+/// The segment map temp file is written before the data file is renamed, so a
+/// missing data temp next to a present map temp means the data swap already
+/// happened and only the map rename is outstanding.
+fn recover_compaction(log_path: &str, metadata_path: &str) -> Result<()> {
+    let (data_tmp, map_tmp) = compaction_paths(log_path, metadata_path);
+    let data_pending = Path::new(&data_tmp).exists();
+    let map_pending = Path::new(&map_tmp).exists();
+
+    if map_pending && !data_pending {
+        std::fs::rename(&map_tmp, metadata_path)?;
+    } else {
+        if data_pending {
+            std::fs::remove_file(&data_tmp)?;
+        }
+        if map_pending {
+            std::fs::remove_file(&map_tmp)?;
+        }
+    }
+    Ok(())
+}
+
 impl NvramLog {
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
         let path_str = path.as_ref().to_string_lossy().to_string();
         let metadata_path = format!("{}.segments", path_str);
 
+        recover_compaction(&path_str, &metadata_path)?;
+
         let file = OpenOptions::new()
             .read(true)
             .write(true)
@@ -46,7 +111,9 @@ impl NvramLog {
             file: Arc::new(RwLock::new(file)),
             segment_map: Arc::new(RwLock::new(segment_map)),
             next_offset: Arc::new(RwLock::new(file_len)),
+            log_path: path_str,
             metadata_path,
+            active_txns: Arc::new(AtomicUsize::new(0)),
             #[cfg(feature = "advanced-security")]
             audit_log: None,
         })
@@ -65,6 +132,134 @@ impl NvramLog {
         Ok(())
     }
 
+    /// Path of the backing log file.
+    pub fn path(&self) -> &str {
+        &self.log_path
+    }
+
+    /// Current size of the backing log file, including bytes of reclaimed segments.
+    pub fn log_size(&self) -> Result<u64> {
+        Ok(self.file.read().unwrap().metadata()?.len())
+    }
+
+    /// Sum of the on-disk lengths of all live segments.
+    pub fn live_bytes(&self) -> u64 {
+        self.segment_map
+            .read()
+            .unwrap()
+            .values()
+            .map(|segment| segment.len as u64)
+            .sum()
+    }
+
+    /// Copy live segments into a fresh log and swap it in place of the current one.
+    ///
+    /// Live data is copied while readers and writers keep running; only the final
+    /// catch-up of segments appended during the copy and the file swap happen under
+    /// the log locks. Compaction is refused while an [`NvramTransaction`] is open
+    /// because pending segments already hold offsets in the old file.
+    pub fn compact(&self) -> Result<CompactionStats> {
+        if self.active_txns.load(Ordering::SeqCst) > 0 {
+            bail!("compaction deferred: transactions in flight");
+        }
+
+        let (data_tmp, map_tmp) = compaction_paths(&self.log_path, &self.metadata_path);
+        let snapshot: HashMap<SegmentId, Segment> = self.segment_map.read().unwrap().clone();
+        let mut live: Vec<Segment> = snapshot.values().cloned().collect();
+        live.sort_by_key(|segment| segment.offset);
+
+        let mut tmp = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&data_tmp)?;
+
+        // Phase 1: copy the snapshot without holding the log locks between segments.
+        let mut relocated: HashMap<SegmentId, u64> = HashMap::with_capacity(live.len());
+        let mut cursor = 0u64;
+        let copy_result: Result<()> = (|| {
+            for segment in &live {
+                let data = self.read_at(segment.offset, segment.len)?;
+                tmp.write_all(&data)?;
+                relocated.insert(segment.id, cursor);
+                cursor += segment.len as u64;
+            }
+            Ok(())
+        })();
+        if let Err(err) = copy_result {
+            drop(tmp);
+            let _ = std::fs::remove_file(&data_tmp);
+            return Err(err);
+        }
+
+        // Phase 2: catch up with concurrent appends and swap files.
+        let mut file = self.file.write().unwrap();
+        let mut next_offset = self.next_offset.write().unwrap();
+        let mut map = self.segment_map.write().unwrap();
+
+        if self.active_txns.load(Ordering::SeqCst) > 0 {
+            drop(tmp);
+            let _ = std::fs::remove_file(&data_tmp);
+            bail!("compaction deferred: transactions in flight");
+        }
+
+        let bytes_before = file.metadata()?.len();
+        let swap_result: Result<HashMap<SegmentId, Segment>> = (|| {
+            let mut compacted = HashMap::with_capacity(map.len());
+            for (seg_id, segment) in map.iter() {
+                let mut moved = segment.clone();
+                let unchanged = snapshot
+                    .get(seg_id)
+                    .map(|old| old.offset == segment.offset)
+                    .unwrap_or(false);
+                moved.offset = match relocated.get(seg_id) {
+                    Some(offset) if unchanged => *offset,
+                    _ => {
+                        let mut buffer = vec![0u8; segment.len as usize];
+                        file.seek(SeekFrom::Start(segment.offset))?;
+                        file.read_exact(&mut buffer)?;
+                        tmp.write_all(&buffer)?;
+                        let offset = cursor;
+                        cursor += segment.len as u64;
+                        offset
+                    }
+                };
+                compacted.insert(*seg_id, moved);
+            }
+            tmp.sync_all()?;
+
+            std::fs::write(&map_tmp, serde_json::to_string_pretty(&compacted)?)?;
+            std::fs::rename(&data_tmp, &self.log_path)?;
+            std::fs::rename(&map_tmp, &self.metadata_path)?;
+            Ok(compacted)
+        })();
+
+        let compacted = match swap_result {
+            Ok(compacted) => compacted,
+            Err(err) => {
+                let _ = recover_compaction(&self.log_path, &self.metadata_path);
+                return Err(err);
+            }
+        };
+
+        let segments_moved = compacted.len();
+        *file = tmp;
+        *map = compacted;
+        *next_offset = cursor;
+
+        Ok(CompactionStats {
+            segments_moved,
+            bytes_before,
+            bytes_after: cursor,
+        })
+    }
+
+    fn read_at(&self, offset: u64, len: u32) -> Result<Vec<u8>> {
+        let mut file = self.file.write().unwrap();
+        read_segment_bytes(&mut file, offset, len)
+    }
+
     #[cfg(feature = "advanced-security")]
     fn log_segment(&self, segment: &Segment) {
         if let Some(audit) = &self.audit_log {
@@ -100,6 +295,7 @@ impl NvramLog {
             id: seg_id,
             offset,
             len: data.len() as u32,
+            logical_len: data.len() as u32,
             // Phase 2.1: Compression fields
             compressed: false,
             compression_algo: "none".to_string(),
@@ -116,6 +312,13 @@ impl NvramLog {
             encrypted: false,
             pq_ciphertext: None,
             pq_nonce: None,
+            checksum: Some(segment_checksum(data)),
+            tier: StorageTier::Hot,
+            erasure: None,
+            sketch: None,
+            delta_base: None,
+            dictionary: None,
+            data_key: None,
         };
 
         *next_offset += data.len() as u64;
@@ -188,21 +391,18 @@ impl NvramLog {
     }
 
     pub fn read(&self, seg_id: SegmentId) -> Result<Vec<u8>> {
-        let segment = self
+        // Resolve the offset under the file lock so a compaction cannot swap the
+        // log between the lookup and the read.
+        let mut file = self.file.write().unwrap();
+        let (offset, len) = self
             .segment_map
             .read()
             .unwrap()
             .get(&seg_id)
-            .cloned()
+            .map(|segment| (segment.offset, segment.len))
             .ok_or_else(|| anyhow::anyhow!("Segment not found"))?;
 
-        let mut file = self.file.write().unwrap();
-        file.seek(SeekFrom::Start(segment.offset))?;
-
-        let mut buffer = vec![0u8; segment.len as usize];
-        file.read_exact(&mut buffer)?;
-
-        Ok(buffer)
+        read_segment_bytes(&mut file, offset, len)
     }
 
     /// NEW: Get segment metadata without reading data
@@ -221,15 +421,24 @@ impl NvramLog {
     /// NEW: Update segment metadata after encryption
     ///
     /// Called by the write pipeline to update encryption fields after
-    /// the segment has been written to disk.
-    pub fn update_segment_metadata(&self, seg_id: SegmentId, segment: Segment) -> Result<()> {
-        self.segment_map.write().unwrap().insert(seg_id, segment);
+    /// the segment has been written to disk. The stored offset is kept, since
+    /// a compaction may have moved the segment after `segment` was read.
+    pub fn update_segment_metadata(&self, seg_id: SegmentId, mut segment: Segment) -> Result<()> {
+        let mut map = self.segment_map.write().unwrap();
+        if let Some(current) = map.get(&seg_id) {
+            segment.offset = current.offset;
+        }
+        map.insert(seg_id, segment);
+        drop(map);
         self.save_segment_map()?;
         Ok(())
     }
 
     pub fn begin_transaction(&self) -> Result<NvramTransaction> {
-        let base_offset = *self.next_offset.read().unwrap();
+        let next_offset = self.next_offset.read().unwrap();
+        self.active_txns.fetch_add(1, Ordering::SeqCst);
+        let base_offset = *next_offset;
+        drop(next_offset);
         Ok(NvramTransaction::new(self.clone(), base_offset))
     }
 
@@ -244,7 +453,9 @@ impl Clone for NvramLog {
             file: Arc::clone(&self.file),
             segment_map: Arc::clone(&self.segment_map),
             next_offset: Arc::clone(&self.next_offset),
+            log_path: self.log_path.clone(),
             metadata_path: self.metadata_path.clone(),
+            active_txns: Arc::clone(&self.active_txns),
             #[cfg(feature = "advanced-security")]
             audit_log: self.audit_log.clone(),
         }
@@ -261,6 +472,8 @@ pub struct NvramTransaction {
     pending: Vec<PendingSegment>,
     base_offset: u64,
     current_offset: u64,
+    // False once another writer reserved space between our reservations.
+    contiguous: bool,
     finalized: bool,
 }
 
@@ -271,6 +484,7 @@ impl NvramTransaction {
             pending: Vec::new(),
             base_offset,
             current_offset: base_offset,
+            contiguous: true,
             finalized: false,
         }
     }
@@ -282,17 +496,36 @@ impl NvramTransaction {
         Ok(())
     }
 
+    fn finalize(&mut self) {
+        if !self.finalized {
+            self.finalized = true;
+            self.log.active_txns.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
     pub fn append_segment(&mut self, seg_id: SegmentId, data: &[u8]) -> Result<Segment> {
         self.ensure_active()?;
 
-        let offset = self.current_offset;
         let data_vec = data.to_vec();
         let len = data_vec.len() as u32;
 
+        // Reserve from the shared tail so concurrent transactions and plain
+        // appends never hand out overlapping ranges.
+        let offset = {
+            let mut next_offset = self.log.next_offset.write().unwrap();
+            if *next_offset != self.current_offset {
+                self.contiguous = false;
+            }
+            let offset = *next_offset;
+            *next_offset += len as u64;
+            offset
+        };
+
         let segment = Segment {
             id: seg_id,
             offset,
             len,
+            logical_len: len,
             compressed: false,
             compression_algo: "none".to_string(),
             content_hash: None,
@@ -306,13 +539,16 @@ impl NvramTransaction {
             encrypted: false,
             pq_ciphertext: None,
             pq_nonce: None,
+            checksum: Some(segment_checksum(&data_vec)),
+            tier: StorageTier::Hot,
+            erasure: None,
+            sketch: None,
+            delta_base: None,
+            dictionary: None,
+            data_key: None,
         };
 
         self.current_offset = offset + data_vec.len() as u64;
-        {
-            let mut next_offset = self.log.next_offset.write().unwrap();
-            *next_offset = self.current_offset;
-        }
 
         self.pending.push(PendingSegment {
             segment: segment.clone(),
@@ -347,7 +583,16 @@ impl NvramTransaction {
             .find(|entry| entry.segment.id == seg_id)
             .ok_or_else(|| anyhow!("pending segment {:?} not found", seg_id))?;
 
-        pending.segment = segment;
+        // Placement and checksum describe the staged bytes, so callers cannot override them.
+        let Segment {
+            offset,
+            len,
+            checksum,
+            ..
+        } = std::mem::replace(&mut pending.segment, segment);
+        pending.segment.offset = offset;
+        pending.segment.len = len;
+        pending.segment.checksum = checksum;
         Ok(())
     }
 
@@ -368,7 +613,7 @@ impl NvramTransaction {
         }
 
         if self.pending.is_empty() {
-            self.finalized = true;
+            self.finalize();
             return Ok(());
         }
 
@@ -385,12 +630,17 @@ impl NvramTransaction {
         })();
 
         if let Err(err) = write_result {
-            *next_offset = self.base_offset;
-            self.finalized = true;
+            if self.contiguous && *next_offset == self.current_offset {
+                *next_offset = self.base_offset;
+            }
+            drop(file);
+            drop(next_offset);
+            self.finalize();
             return Err(err);
         }
 
-        *next_offset = self.current_offset;
+        // Later transactions may already have reserved space past ours.
+        *next_offset = (*next_offset).max(self.current_offset);
         drop(file);
         drop(next_offset);
 
@@ -402,11 +652,11 @@ impl NvramTransaction {
                 self.log.log_segment(&entry.segment);
             }
         }
-        self.log.save_segment_map()?;
+        let saved = self.log.save_segment_map();
 
         self.pending.clear();
-        self.finalized = true;
-        Ok(())
+        self.finalize();
+        saved
     }
 
     pub fn rollback(&mut self) -> Result<()> {
@@ -414,11 +664,16 @@ impl NvramTransaction {
             return Ok(());
         }
 
-        let mut next_offset = self.log.next_offset.write().unwrap();
-        *next_offset = self.base_offset;
+        {
+            // Only give the reservation back if nothing was reserved after it.
+            let mut next_offset = self.log.next_offset.write().unwrap();
+            if self.contiguous && *next_offset == self.current_offset {
+                *next_offset = self.base_offset;
+            }
+        }
         self.pending.clear();
         self.current_offset = self.base_offset;
-        self.finalized = true;
+        self.finalize();
         Ok(())
     }
 }
@@ -1,23 +1,29 @@
 use std::borrow::Cow;
 
 use anyhow::{anyhow, Context, Result};
+use common::similarity::Sketch;
 use common::{
+    covering_segments,
     traits::{
         CapsuleCatalog, Compressor, DedupStats, Deduper, EncryptionSummary, Encryptor, Keyring,
         PolicyEvaluator, StorageBackend, StorageTransaction,
     },
-    Capsule, CapsuleId, CompressionPolicy, ContentHash, EncryptionPolicy, Policy, Segment,
-    SegmentId,
+    Capsule, CapsuleId, CompressionPolicy, ContentHash, DataKeyRef, EncryptionPolicy, Policy,
+    Segment, SegmentId, StorageTier, WrappedDataKey,
 };
-use compression::Lz4ZstdCompressor;
+use compression::delta::{apply_delta, encode_delta};
+use compression::selection::{selector, SAMPLE_LEN};
+use compression::CodecCompressor;
 use dedup::Blake3Deduper;
 use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
-use storage::{InMemoryBackend, NvramBackend};
+use storage::{ErasureBackend, FileBackend, InMemoryBackend, NvramBackend, TieredBackend};
 use tracing::instrument;
 
 use encryption::{
-    compute_mac, derive_tweak_from_hash, encrypt_segment, keymanager::MASTER_KEY_SIZE, KeyManager,
+    compute_mac, decrypt_segment, derive_segment_key, derive_tweak_from_hash, encrypt_segment,
+    generate_data_key, keymanager::MASTER_KEY_SIZE, segment_key_pair, unwrap_data_key, verify_mac,
+    wrap_data_key, EncryptionMetadata, KeyManager, XtsKeyPair,
 };
 use layout_engine::LayoutEngine;
 
@@ -101,30 +107,7 @@ impl Encryptor for XtsEncryptor {
         }
 
         let (key_version, key_pair) = self.acquire_key(policy.key_version())?;
-        let hash = blake3::hash(data.as_ref());
-        let tweak = derive_tweak_from_hash(hash.as_bytes());
-
-        let (ciphertext, mut metadata) =
-            encrypt_segment(data.as_ref(), &key_pair, key_version, tweak)
-                .context("segment encryption failed")?;
-
-        let mac = compute_mac(&ciphertext, &metadata, key_pair.key1(), key_pair.key2())
-            .context("failed to compute MAC")?;
-        metadata.set_integrity_tag(mac);
-
-        let algorithm = if policy.is_enabled() {
-            "xts-aes-256"
-        } else {
-            "none"
-        };
-        let mut summary = EncryptionSummary::new(algorithm);
-        summary.key_version = metadata.key_version;
-        summary.encryption_version = metadata.encryption_version;
-        summary.tweak_nonce = metadata.tweak_nonce;
-        summary.integrity_tag = metadata.integrity_tag;
-        summary.mac = metadata.integrity_tag.map(|tag| tag.to_vec());
-
-        Ok((ciphertext, summary))
+        seal(data.as_ref(), &key_pair, key_version)
     }
 
     fn decrypt(
@@ -144,15 +127,78 @@ impl Encryptor for XtsEncryptor {
     fn verify_mac(&self, _data: &[u8], _mac: &[u8], _segment: SegmentId) -> Result<()> {
         Ok(())
     }
+
+    fn encrypt_with_key(
+        &self,
+        data: Cow<'_, [u8]>,
+        policy: &EncryptionPolicy,
+        _segment: SegmentId,
+        key: &[u8; 32],
+    ) -> Result<(Vec<u8>, EncryptionSummary)> {
+        if !policy.is_enabled() {
+            let mut summary = EncryptionSummary::new("none");
+            summary.encryption_version = None;
+            return Ok((data.into_owned(), summary));
+        }
+        // Keyring keys are named by the segment's data key, not a master version.
+        seal(data.as_ref(), &segment_key_pair(key), 0)
+    }
+
+    fn decrypt_with_key(
+        &self,
+        data: &[u8],
+        _policy: &EncryptionPolicy,
+        segment: &Segment,
+        key: &[u8; 32],
+    ) -> Result<Vec<u8>> {
+        let key_pair = segment_key_pair(key);
+        let metadata = EncryptionMetadata {
+            encryption_version: segment.encryption_version,
+            key_version: segment.key_version,
+            tweak_nonce: segment.tweak_nonce,
+            integrity_tag: segment.integrity_tag,
+            ciphertext_len: Some(data.len() as u32),
+        };
+        verify_mac(data, &metadata, key_pair.key1(), key_pair.key2())
+            .context("segment integrity check failed")?;
+        decrypt_segment(data, &key_pair, &metadata).context("segment decryption failed")
+    }
 }
 
-/// Basic policy evaluator that mirrors incoming policy decisions.
+/// XTS-encrypt `data` under `key_pair` and tag it with a MAC.
+fn seal(
+    data: &[u8],
+    key_pair: &XtsKeyPair,
+    key_version: u32,
+) -> Result<(Vec<u8>, EncryptionSummary)> {
+    let hash = blake3::hash(data);
+    let tweak = derive_tweak_from_hash(hash.as_bytes());
+
+    let (ciphertext, mut metadata) =
+        encrypt_segment(data, key_pair, key_version, tweak).context("segment encryption failed")?;
+
+    let mac = compute_mac(&ciphertext, &metadata, key_pair.key1(), key_pair.key2())
+        .context("failed to compute MAC")?;
+    metadata.set_integrity_tag(mac);
+
+    let mut summary = EncryptionSummary::new("xts-aes-256");
+    summary.key_version = metadata.key_version;
+    summary.encryption_version = metadata.encryption_version;
+    summary.tweak_nonce = metadata.tweak_nonce;
+    summary.integrity_tag = metadata.integrity_tag;
+    summary.mac = metadata.integrity_tag.map(|tag| tag.to_vec());
+
+    Ok((ciphertext, summary))
+}
+
+/// Basic policy evaluator that mirrors incoming policy decisions, resolving
+/// adaptive compression by trial-compressing the sample.
 #[derive(Default, Clone)]
 pub struct DefaultPolicyEvaluator;
 
 impl PolicyEvaluator for DefaultPolicyEvaluator {
-    fn evaluate_compression(&self, policy: &Policy, _sample: &[u8]) -> Result<CompressionPolicy> {
-        Ok(policy.compression.clone())
+    fn evaluate_compression(&self, policy: &Policy, sample: &[u8]) -> Result<CompressionPolicy> {
+        Ok(selector().resolve(&policy.compression, sample).into_owned())
     }
 
     fn evaluate_dedup(&self, policy: &Policy) -> Result<bool> {
@@ -185,15 +231,71 @@ impl Keyring for NullKeyring {
     }
 }
 
-/// Keyring backed by the encryption key manager.
+/// Keyring giving each capsule its own random data key, wrapped by the key
+/// manager's current version key. Rotating the master key leaves data keys,
+/// and so every segment, as they are.
 #[derive(Clone)]
 pub struct KeyManagerKeyring {
     manager: Arc<Mutex<KeyManager>>,
+    // Wrapped data keys by capsule, oldest generation first.
+    data_keys: Arc<Mutex<HashMap<CapsuleId, Vec<WrappedDataKey>>>>,
 }
 
 impl KeyManagerKeyring {
     pub fn new(manager: Arc<Mutex<KeyManager>>) -> Self {
-        Self { manager }
+        Self {
+            manager,
+            data_keys: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Generate data key `id` and wrap it under the current master version.
+    fn generate(&self, id: DataKeyRef) -> Result<WrappedDataKey> {
+        let dek = generate_data_key().context("failed to generate data key")?;
+        let mut manager = self
+            .manager
+            .lock()
+            .map_err(|_| anyhow!("key manager mutex poisoned"))?;
+        let kek_version = manager.current_version();
+        let kek = manager
+            .get_key(kek_version)
+            .context("failed to load key encryption key")?;
+        let (wrapped, tag) =
+            wrap_data_key(&dek, kek, &id.wrap_context()).context("failed to wrap data key")?;
+        Ok(WrappedDataKey {
+            id,
+            kek_version,
+            wrapped: wrapped.to_vec(),
+            tag,
+        })
+    }
+
+    fn unwrap(&self, key: &WrappedDataKey) -> Result<XtsKeyPair> {
+        let mut manager = self
+            .manager
+            .lock()
+            .map_err(|_| anyhow!("key manager mutex poisoned"))?;
+        let kek = manager
+            .get_key(key.kek_version)
+            .context("failed to load key encryption key")?;
+        unwrap_data_key(&key.wrapped, &key.tag, kek, &key.id.wrap_context())
+            .with_context(|| format!("failed to unwrap data key {:?}", key.id))
+    }
+
+    /// `capsule`'s current data key, generated on first use.
+    fn current_key(&self, capsule: CapsuleId) -> Result<WrappedDataKey> {
+        let mut data_keys = self
+            .data_keys
+            .lock()
+            .map_err(|_| anyhow!("data key mutex poisoned"))?;
+        let keys = data_keys.entry(capsule).or_default();
+        if keys.is_empty() {
+            keys.push(self.generate(DataKeyRef {
+                capsule,
+                generation: 1,
+            })?);
+        }
+        Ok(keys[keys.len() - 1].clone())
     }
 }
 
@@ -205,26 +307,51 @@ impl Default for KeyManagerKeyring {
 }
 
 impl Keyring for KeyManagerKeyring {
-    fn derive_key(&self, _capsule: CapsuleId, _segment: SegmentId) -> Result<[u8; 32]> {
-        let mut manager = self
-            .manager
+    fn derive_key(&self, capsule: CapsuleId, segment: SegmentId) -> Result<[u8; 32]> {
+        let key = self.current_key(capsule)?;
+        Ok(derive_segment_key(&self.unwrap(&key)?, segment.0))
+    }
+
+    fn rotate_key(&mut self, capsule: CapsuleId) -> Result<()> {
+        let mut data_keys = self
+            .data_keys
             .lock()
-            .map_err(|_| anyhow!("key manager mutex poisoned"))?;
-        let version = manager.current_version();
-        let key_pair = manager
-            .get_key(version)
-            .context("failed to load key for derivation")?;
-        Ok(*key_pair.key1())
+            .map_err(|_| anyhow!("data key mutex poisoned"))?;
+        let keys = data_keys.entry(capsule).or_default();
+        let generation = keys.last().map_or(1, |key| key.id.generation + 1);
+        keys.push(self.generate(DataKeyRef {
+            capsule,
+            generation,
+        })?);
+        Ok(())
     }
 
-    fn rotate_key(&mut self, _capsule: CapsuleId) -> Result<()> {
-        let mut manager = self
-            .manager
+    fn restore_keys(&mut self, capsule: CapsuleId, keys: &[WrappedDataKey]) -> Result<()> {
+        let mut data_keys = self
+            .data_keys
             .lock()
-            .map_err(|_| anyhow!("key manager mutex poisoned"))?;
-        manager.rotate().context("key rotation failed")?;
+            .map_err(|_| anyhow!("data key mutex poisoned"))?;
+        let known = data_keys.entry(capsule).or_default();
+        for key in keys.iter().filter(|key| key.id.capsule == capsule) {
+            if known.iter().all(|existing| existing.id != key.id) {
+                known.push(key.clone());
+            }
+        }
+        known.sort_by_key(|key| key.id.generation);
         Ok(())
     }
+
+    fn wrapped_keys(&self, capsule: CapsuleId) -> Result<Vec<WrappedDataKey>> {
+        let data_keys = self
+            .data_keys
+            .lock()
+            .map_err(|_| anyhow!("data key mutex poisoned"))?;
+        Ok(data_keys.get(&capsule).cloned().unwrap_or_default())
+    }
+
+    fn unwrap_key(&self, key: &WrappedDataKey, segment: SegmentId) -> Result<[u8; 32]> {
+        Ok(derive_segment_key(&self.unwrap(key)?, segment.0))
+    }
 }
 
 /// Simple in-memory catalog for tests and defaults.
@@ -238,6 +365,7 @@ struct CatalogInner {
     next_segment: u64,
     capsules: HashMap<CapsuleId, Capsule>,
     content: HashMap<ContentHash, SegmentId>,
+    similar: HashMap<ContentHash, SegmentId>,
 }
 
 impl InMemoryCatalog {
@@ -271,22 +399,61 @@ impl CapsuleCatalog for InMemoryCatalog {
         policy: &Policy,
         segments: Vec<SegmentId>,
         stats: &DedupStats,
+        data_keys: Vec<WrappedDataKey>,
     ) -> Result<()> {
         let mut inner = self.inner.lock().unwrap();
+        let created_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs();
         let capsule = Capsule {
             id,
             size,
             segments,
-            created_at: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)?
-                .as_secs(),
+            created_at,
             policy: policy.clone(),
             deduped_bytes: stats.bytes_saved,
+            version: 1,
+            read_only: false,
+            origin: None,
+            metadata: Default::default(),
+            tags: Default::default(),
+            retain_until: policy.retention.map(|rules| rules.deadline(created_at)),
+            legal_hold: false,
+            lifecycle_step: 0,
+            tenant: None,
+            data_keys,
         };
         inner.capsules.insert(id, capsule);
         Ok(())
     }
 
+    fn set_data_keys(&self, id: CapsuleId, keys: Vec<WrappedDataKey>) -> Result<()> {
+        self.inner
+            .lock()
+            .unwrap()
+            .capsules
+            .get_mut(&id)
+            .ok_or_else(|| anyhow!("capsule {:?} not found", id))?
+            .data_keys = keys;
+        Ok(())
+    }
+
+    fn replace_segments(
+        &self,
+        id: CapsuleId,
+        segments: Vec<SegmentId>,
+        data_keys: Vec<WrappedDataKey>,
+    ) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let capsule = inner
+            .capsules
+            .get_mut(&id)
+            .ok_or_else(|| anyhow!("capsule {:?} not found", id))?;
+        capsule.segments = segments;
+        capsule.data_keys = data_keys;
+        Ok(())
+    }
+
     fn delete_capsule(&self, id: CapsuleId) -> Result<Capsule> {
         self.inner
             .lock()
@@ -316,6 +483,33 @@ impl CapsuleCatalog for InMemoryCatalog {
         Ok(false)
     }
 
+    fn lookup_similar(&self, sketch: &Sketch) -> Option<SegmentId> {
+        let inner = self.inner.lock().unwrap();
+        sketch
+            .keys()
+            .find_map(|key| inner.similar.get(&key).copied())
+    }
+
+    fn register_similar(&self, sketch: &Sketch, segment: SegmentId) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        for key in sketch.keys() {
+            inner.similar.insert(key, segment);
+        }
+        Ok(())
+    }
+
+    fn deregister_similar(&self, sketch: &Sketch, segment: SegmentId) -> Result<bool> {
+        let mut inner = self.inner.lock().unwrap();
+        let mut removed = false;
+        for key in sketch.keys() {
+            if inner.similar.get(&key) == Some(&segment) {
+                inner.similar.remove(&key);
+                removed = true;
+            }
+        }
+        Ok(removed)
+    }
+
     fn capsules(&self) -> Vec<Capsule> {
         self.inner
             .lock()
@@ -337,6 +531,13 @@ impl CapsuleCatalog for InMemoryCatalog {
     }
 }
 
+/// Segments moved by one [`Pipeline::rebalance_tiers`] pass.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TierReport {
+    pub promoted: usize,
+    pub demoted: usize,
+}
+
 /// Pipeline orchestrator that composes the modular traits.
 pub struct Pipeline<C, D, E, S, Eval, K, R>
 where
@@ -356,6 +557,8 @@ where
     keyring: Option<K>,
     stats: DedupStats,
     catalog: R,
+    // Reads per capsule since the last tier rebalance, decayed by each pass.
+    reads: Mutex<HashMap<CapsuleId, u64>>,
 }
 
 impl<C, D, E, S, Eval, K, R> Pipeline<C, D, E, S, Eval, K, R>
@@ -386,23 +589,36 @@ where
             keyring,
             stats: DedupStats::default(),
             catalog,
+            reads: Mutex::new(HashMap::new()),
         }
     }
 
+    /// Catalog holding the capsule records this pipeline writes.
+    pub fn catalog(&self) -> &R {
+        &self.catalog
+    }
+
+    /// Backend holding the segments this pipeline writes.
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
     #[instrument(skip_all)]
     pub async fn write_capsule(&mut self, data: &[u8], policy: &Policy) -> Result<CapsuleId> {
         let capsule_id = CapsuleId::new();
         let compression_policy = self
             .evaluator
-            .evaluate_compression(policy, &data[..data.len().min(1024)])?;
-        let chunk_size = policy.layout.strategy.default_segment_size();
-        let data_slices: Vec<&[u8]> = data.chunks(chunk_size).collect();
+            .evaluate_compression(policy, &data[..data.len().min(SAMPLE_LEN)])?;
+        // Segmenting is the layout engine's job; give it the capsule whole so
+        // content-defined cuts are not pinned to slice boundaries.
         let layout_engine = LayoutEngine::new(policy);
-        let zone_plan = layout_engine.synthesize(&[capsule_id], &data_slices, policy)?;
+        let zone_plan = layout_engine.synthesize(&[capsule_id], &[data], policy)?;
         let encryption_policy = self.evaluator.evaluate_encryption(policy)?;
 
         let mut segment_ids = Vec::new();
         let mut dedup_stats = DedupStats::new();
+        // Data keys of other capsules that shared segments are sealed under.
+        let mut borrowed_keys: Vec<DataKeyRef> = Vec::new();
 
         for zone in zone_plan.zones {
             for seg in zone.segments {
@@ -417,10 +633,12 @@ where
                 }
                 let chunk = &data[start..end];
                 let (view, summary) = self.compressor.compress(chunk, &compression_policy)?;
-                let hash = self.deduper.hash_content(view.as_ref());
+                // Keyed on the plaintext, so the same data dedupes under any codec.
+                let hash = self.deduper.hash_content(chunk);
 
                 if let Some(existing) = self.catalog.lookup_content(&hash) {
                     let mut metadata = self.storage.metadata(existing).await?;
+                    borrowed_keys.extend(metadata.data_key);
                     metadata.ref_count += 1;
                     metadata.deduplicated = metadata.ref_count > 1;
                     let mut txn = self.storage.begin_txn().await?;
@@ -431,23 +649,45 @@ where
                     dedup_stats.record(summary.output_size as u64, true);
                     segment_ids.push(existing);
                 } else {
+                    // New content resembling a stored segment is kept as a delta against it.
+                    let sketch = policy
+                        .delta_compression
+                        .then(|| Sketch::of(chunk))
+                        .flatten();
+                    let delta = match &sketch {
+                        Some(sketch) => {
+                            self.delta_against_similar(sketch, chunk, &encryption_policy)
+                                .await
+                        }
+                        None => None,
+                    };
+                    let sketch = sketch.filter(|_| delta.is_none());
+                    let (view, summary) = match &delta {
+                        Some((_, delta)) => self.compressor.compress(delta, &compression_policy)?,
+                        None => (view, summary),
+                    };
+
                     let mut txn = self.storage.begin_txn().await?;
                     let seg_id = self.catalog.allocate_segment()?;
 
-                    let (payload, encryption_summary) = if encryption_policy.is_enabled() {
-                        let _key = self
-                            .keyring
-                            .as_ref()
-                            .map(|keyring| keyring.derive_key(capsule_id, seg_id))
-                            .transpose()?;
-                        let (encrypted, summary) = self.encryptor.encrypt(
+                    let mut data_key = None;
+                    let (payload, encryption_summary) = match &self.keyring {
+                        Some(keyring) if encryption_policy.is_enabled() => {
+                            let key = keyring.derive_key(capsule_id, seg_id)?;
+                            data_key = keyring.wrapped_keys(capsule_id)?.last().map(|key| key.id);
+                            self.encryptor.encrypt_with_key(
+                                Cow::Borrowed(view.as_ref()),
+                                &encryption_policy,
+                                seg_id,
+                                &key,
+                            )?
+                        }
+                        None if encryption_policy.is_enabled() => self.encryptor.encrypt(
                             Cow::Borrowed(view.as_ref()),
                             &encryption_policy,
                             seg_id,
-                        )?;
-                        (encrypted, summary)
-                    } else {
-                        (view.into_owned(), EncryptionSummary::new("none"))
+                        )?,
+                        _ => (view.into_owned(), EncryptionSummary::new("none")),
                     };
 
                     txn.append(seg_id, &payload).await?;
@@ -455,6 +695,7 @@ where
                         id: seg_id,
                         offset: 0,
                         len: payload.len() as u32,
+                        logical_len: chunk.len() as u32,
                         compressed: summary.compressed,
                         compression_algo: summary.algorithm.clone(),
                         content_hash: Some(hash.clone()),
@@ -468,10 +709,28 @@ where
                         encrypted: encryption_policy.is_enabled(),
                         pq_ciphertext: None,
                         pq_nonce: None,
+                        checksum: None,
+                        tier: StorageTier::Hot,
+                        erasure: None,
+                        sketch,
+                        delta_base: delta.as_ref().map(|(base, _)| *base),
+                        dictionary: None,
+                        data_key,
                     };
                     txn.set_segment_metadata(seg_id, metadata).await?;
+                    // The delta holds a reference on its base for as long as it exists.
+                    if let Some((base, _)) = &delta {
+                        let mut base_metadata = self.storage.metadata(*base).await?;
+                        borrowed_keys.extend(base_metadata.data_key);
+                        base_metadata.ref_count += 1;
+                        base_metadata.deduplicated = base_metadata.ref_count > 1;
+                        txn.set_segment_metadata(*base, base_metadata).await?;
+                    }
                     txn.commit().await?;
 
+                    if let Some(sketch) = &sketch {
+                        self.catalog.register_similar(sketch, seg_id)?;
+                    }
                     self.catalog.register_content(hash.clone(), seg_id)?;
                     self.deduper.register_content(hash, seg_id)?;
                     self.deduper.update_stats(summary.output_size as u64, false);
@@ -482,71 +741,441 @@ where
             }
         }
 
+        // The record carries every data key its segments need, including those
+        // of other capsules, so reads never depend on another record.
+        let mut data_keys = match &self.keyring {
+            Some(keyring) => keyring.wrapped_keys(capsule_id)?,
+            None => Vec::new(),
+        };
+        for id in borrowed_keys {
+            if data_keys.iter().all(|key| key.id != id) {
+                data_keys.push(self.find_data_key(&[], id)?);
+            }
+        }
+
         self.catalog.create_capsule(
             capsule_id,
             data.len() as u64,
             policy,
             segment_ids,
             &dedup_stats,
+            data_keys,
         )?;
 
         Ok(capsule_id)
     }
 
+    /// Wrapped data key `id`, from `keys` or else wherever it is known.
+    fn find_data_key(&self, keys: &[WrappedDataKey], id: DataKeyRef) -> Result<WrappedDataKey> {
+        if let Some(key) = keys.iter().find(|key| key.id == id) {
+            return Ok(key.clone());
+        }
+        if let Some(keyring) = &self.keyring {
+            if let Some(key) = keyring
+                .wrapped_keys(id.capsule)?
+                .into_iter()
+                .find(|key| key.id == id)
+            {
+                return Ok(key);
+            }
+        }
+        self.catalog
+            .lookup_capsule(id.capsule)
+            .ok()
+            .and_then(|capsule| capsule.data_key(id).cloned())
+            .ok_or_else(|| anyhow!("data key {:?} is unavailable", id))
+    }
+
     pub fn stats(&self) -> DedupStats {
         self.stats.clone()
     }
 
     pub async fn read_capsule(&self, id: CapsuleId) -> Result<Vec<u8>> {
         let capsule = self.catalog.lookup_capsule(id)?;
+        self.record_read(id);
+        self.decode_capsule(&capsule).await
+    }
+
+    async fn decode_capsule(&self, capsule: &Capsule) -> Result<Vec<u8>> {
         let mut output = Vec::with_capacity(capsule.size as usize);
 
         for seg_id in &capsule.segments {
+            if let Some(len) = seg_id.hole_len() {
+                output.resize(output.len() + len as usize, 0);
+                continue;
+            }
             let metadata = self.storage.metadata(*seg_id).await?;
-            let raw = self.storage.read(*seg_id).await?;
-            let decrypted = if metadata.encrypted {
+            output.extend_from_slice(&self.decode_segment(capsule, &metadata).await?);
+        }
+
+        Ok(output)
+    }
+
+    /// Read `len` bytes at `offset`, decoding only the segments that cover the range.
+    pub async fn read_range(&self, id: CapsuleId, offset: u64, len: usize) -> Result<Vec<u8>> {
+        let capsule = self.catalog.lookup_capsule(id)?;
+        if offset + len as u64 > capsule.size {
+            return Err(anyhow!(
+                "read of {} bytes at {} exceeds capsule size {}",
+                len,
+                offset,
+                capsule.size
+            ));
+        }
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+        self.record_read(id);
+
+        let mut segments = Vec::with_capacity(capsule.segments.len());
+        for seg_id in &capsule.segments {
+            segments.push(match seg_id.hole_len() {
+                Some(_) => None,
+                None => Some(self.storage.metadata(*seg_id).await?),
+            });
+        }
+        let logical_lens: Vec<u64> = capsule
+            .segments
+            .iter()
+            .zip(&segments)
+            .map(|(seg_id, metadata)| match metadata {
+                Some(metadata) => metadata.logical_len as u64,
+                None => seg_id.hole_len().unwrap_or(0),
+            })
+            .collect();
+
+        let Some(extents) = covering_segments(&logical_lens, offset, len) else {
+            let full = self.decode_capsule(&capsule).await?;
+            return Ok(full[offset as usize..offset as usize + len].to_vec());
+        };
+
+        let mut output = Vec::with_capacity(len);
+        for extent in extents {
+            let Some(metadata) = &segments[extent.index] else {
+                output.resize(output.len() + (extent.end - extent.start), 0);
+                continue;
+            };
+            let data = self.decode_segment(&capsule, metadata).await?;
+            let slice = data.get(extent.start..extent.end).ok_or_else(|| {
+                anyhow!(
+                    "segment {} decoded to {} bytes, expected at least {}",
+                    metadata.id.0,
+                    data.len(),
+                    extent.end
+                )
+            })?;
+            output.extend_from_slice(slice);
+        }
+
+        Ok(output)
+    }
+
+    async fn decode_segment(&self, capsule: &Capsule, metadata: &Segment) -> Result<Vec<u8>> {
+        let (encryption, keys) = (&capsule.policy.encryption, &capsule.data_keys);
+        let payload = self.decode_payload(encryption, keys, metadata).await?;
+        let Some(base_id) = metadata.delta_base else {
+            return Ok(payload);
+        };
+        // Bases are never deltas themselves.
+        let base = self.storage.metadata(base_id).await?;
+        let base_data = self.decode_payload(encryption, keys, &base).await?;
+        Ok(apply_delta(&base_data, &payload)?)
+    }
+
+    /// Decrypt and decompress the bytes stored for a segment, looking its
+    /// data key up in `keys` first.
+    async fn decode_payload(
+        &self,
+        encryption: &EncryptionPolicy,
+        keys: &[WrappedDataKey],
+        metadata: &Segment,
+    ) -> Result<Vec<u8>> {
+        let raw = self.storage.read(metadata.id).await?;
+        let decrypted = self.decrypt_payload(raw, encryption, keys, metadata)?;
+        if metadata.compressed {
+            self.compressor
+                .decompress(&decrypted, metadata.compression_algo.as_str())
+        } else {
+            Ok(decrypted)
+        }
+    }
+
+    /// Decrypt a segment's stored bytes, looking its data key up in `keys` first.
+    fn decrypt_payload(
+        &self,
+        raw: Vec<u8>,
+        encryption: &EncryptionPolicy,
+        keys: &[WrappedDataKey],
+        metadata: &Segment,
+    ) -> Result<Vec<u8>> {
+        match (metadata.data_key, &self.keyring) {
+            _ if !metadata.encrypted => Ok(raw),
+            (Some(id), Some(keyring)) => {
+                let wrapped = self.find_data_key(keys, id)?;
+                let key = keyring.unwrap_key(&wrapped, metadata.id)?;
                 self.encryptor
-                    .decrypt(&raw, &capsule.policy.encryption, *seg_id)?
+                    .decrypt_with_key(&raw, encryption, metadata, &key)
+            }
+            _ => self.encryptor.decrypt(&raw, encryption, metadata.id),
+        }
+    }
+
+    /// A stored segment resembling `chunk`, and the delta rebuilding `chunk` from it.
+    async fn delta_against_similar(
+        &self,
+        sketch: &Sketch,
+        chunk: &[u8],
+        encryption: &EncryptionPolicy,
+    ) -> Option<(SegmentId, Vec<u8>)> {
+        let base_id = self.catalog.lookup_similar(sketch)?;
+        let base = self.storage.metadata(base_id).await.ok()?;
+        if base.delta_base.is_some() {
+            return None;
+        }
+        let base_data = self.decode_payload(encryption, &[], &base).await.ok()?;
+        encode_delta(&base_data, chunk).map(|delta| (base_id, delta))
+    }
+
+    fn record_read(&self, id: CapsuleId) {
+        if let Ok(mut reads) = self.reads.lock() {
+            *reads.entry(id).or_default() += 1;
+        }
+    }
+
+    /// Move segments between storage tiers to match how hot their capsules are.
+    ///
+    /// A capsule's heat is its reads since the previous pass relative to the
+    /// most-read capsule, from 0.0 to 1.0. Capsules at or above their policy's
+    /// `layout.heat_threshold` belong on the hot tier and the rest on the
+    /// capacity tier; a segment shared by several capsules stays hot if any of
+    /// them is hot. Read counts are halved after each pass so that recent
+    /// reads count for more than old ones.
+    ///
+    /// Single-tier backends never move anything.
+    pub async fn rebalance_tiers(&mut self) -> Result<TierReport> {
+        let reads = {
+            let mut reads = self
+                .reads
+                .lock()
+                .map_err(|_| anyhow!("read statistics mutex poisoned"))?;
+            let snapshot = reads.clone();
+            reads.values_mut().for_each(|count| *count /= 2);
+            reads.retain(|_, count| *count > 0);
+            snapshot
+        };
+        let hottest = reads.values().copied().max().unwrap_or(0);
+
+        let mut targets: HashMap<SegmentId, StorageTier> = HashMap::new();
+        for capsule in self.catalog.capsules() {
+            let count = reads.get(&capsule.id).copied().unwrap_or(0);
+            let heat = if hottest == 0 {
+                0.0
             } else {
-                raw
+                count as f32 / hottest as f32
             };
-            let decompressed = if metadata.compressed {
-                self.compressor
-                    .decompress(&decrypted, metadata.compression_algo.as_str())?
+            let tier = if heat >= capsule.policy.layout.heat_threshold {
+                StorageTier::Hot
             } else {
-                decrypted
+                StorageTier::Capacity
             };
-            output.extend_from_slice(&decompressed);
+            for seg_id in capsule.segments.iter().filter(|seg_id| !seg_id.is_hole()) {
+                let target = targets.entry(*seg_id).or_insert(tier);
+                if tier == StorageTier::Hot {
+                    *target = StorageTier::Hot;
+                }
+            }
         }
 
-        Ok(output)
+        let mut targets: Vec<_> = targets.into_iter().collect();
+        targets.sort_by_key(|(seg_id, _)| seg_id.0);
+        let mut report = TierReport::default();
+        for (seg_id, tier) in targets {
+            if self.storage.relocate(seg_id, tier).await? {
+                match tier {
+                    StorageTier::Hot => report.promoted += 1,
+                    StorageTier::Capacity => report.demoted += 1,
+                }
+            }
+        }
+        Ok(report)
     }
 
-    pub async fn delete_capsule(&mut self, id: CapsuleId) -> Result<()> {
+    /// Re-seal capsule `id` under a new data key of its own.
+    ///
+    /// The generation follows on from the keys in the capsule's record, and
+    /// the new key is recorded before any segment is sealed under it. Segments
+    /// sealed under the capsule's older keys, or directly under the master
+    /// key, are copied under the new key and the originals released; segments
+    /// it shares under other capsules' keys stay as they are. Returns the
+    /// updated record.
+    pub async fn rotate_data_key(&mut self, id: CapsuleId) -> Result<Capsule> {
         let capsule = self.catalog.lookup_capsule(id)?;
+        let encryption = self.evaluator.evaluate_encryption(&capsule.policy)?;
+        let keyring = match self.keyring.as_mut() {
+            Some(keyring) if encryption.is_enabled() => keyring,
+            _ => return Err(anyhow!("capsule {:?} is not encrypted", id)),
+        };
+        keyring.restore_keys(id, &capsule.data_keys)?;
+        keyring.rotate_key(id)?;
+        let current = keyring
+            .wrapped_keys(id)?
+            .pop()
+            .ok_or_else(|| anyhow!("keyring gave capsule {:?} no data key", id))?;
+        let mut data_keys = capsule.data_keys.clone();
+        data_keys.push(current.clone());
+        self.catalog.set_data_keys(id, data_keys.clone())?;
+
+        // Slots per segment to re-seal, so a segment repeated within the
+        // capsule is copied once and released once per slot.
+        let mut stale: HashMap<SegmentId, u32> = HashMap::new();
+        for seg_id in capsule.segments.iter().filter(|seg_id| !seg_id.is_hole()) {
+            let metadata = self.storage.metadata(*seg_id).await?;
+            let own = metadata.data_key.is_none_or(|key| key.capsule == id);
+            if metadata.encrypted && own {
+                *stale.entry(*seg_id).or_default() += 1;
+            }
+        }
 
-        for seg_id in &capsule.segments {
+        let mut resealed: HashMap<SegmentId, Segment> = HashMap::new();
+        for (&seg_id, &slots) in &stale {
+            let metadata = self.storage.metadata(seg_id).await?;
+            let raw = self.storage.read(seg_id).await?;
+            let plain =
+                self.decrypt_payload(raw, &capsule.policy.encryption, &data_keys, &metadata)?;
+
+            let new_id = self.catalog.allocate_segment()?;
+            let keyring = self
+                .keyring
+                .as_ref()
+                .ok_or_else(|| anyhow!("keyring disappeared during rotation"))?;
+            let key = keyring.derive_key(id, new_id)?;
+            let (payload, summary) =
+                self.encryptor
+                    .encrypt_with_key(Cow::Owned(plain), &encryption, new_id, &key)?;
+            let sealed = Segment {
+                id: new_id,
+                offset: 0,
+                len: payload.len() as u32,
+                ref_count: slots,
+                deduplicated: slots > 1,
+                access_count: 0,
+                encryption_version: summary.encryption_version,
+                key_version: summary.key_version,
+                tweak_nonce: summary.tweak_nonce,
+                integrity_tag: summary.integrity_tag,
+                checksum: None,
+                data_key: Some(current.id),
+                ..metadata.clone()
+            };
+
+            let mut txn = self.storage.begin_txn().await?;
+            txn.append(new_id, &payload).await?;
+            txn.set_segment_metadata(new_id, sealed.clone()).await?;
+            // The copy holds its own reference on a delta base.
+            if let Some(base) = metadata.delta_base {
+                let mut base_metadata = self.storage.metadata(base).await?;
+                base_metadata.ref_count += 1;
+                base_metadata.deduplicated = base_metadata.ref_count > 1;
+                txn.set_segment_metadata(base, base_metadata).await?;
+            }
+            txn.commit().await?;
+            resealed.insert(seg_id, sealed);
+        }
+
+        let segments: Vec<SegmentId> = capsule
+            .segments
+            .iter()
+            .map(|seg_id| resealed.get(seg_id).map_or(*seg_id, |sealed| sealed.id))
+            .collect();
+        // Keep only the keys the new segments, and any delta bases, are sealed under.
+        let mut needed: HashSet<DataKeyRef> = HashSet::new();
+        for seg_id in segments.iter().filter(|seg_id| !seg_id.is_hole()) {
             let metadata = self.storage.metadata(*seg_id).await?;
-            let mut updated = metadata.clone();
+            needed.extend(metadata.data_key);
+            if let Some(base) = metadata.delta_base {
+                needed.extend(self.storage.metadata(base).await?.data_key);
+            }
+        }
+        data_keys.retain(|key| key.id == current.id || needed.contains(&key.id));
+        self.catalog.replace_segments(id, segments, data_keys)?;
 
-            if updated.ref_count > 1 {
-                updated.ref_count -= 1;
-                updated.deduplicated = updated.ref_count > 1;
-                let mut txn = self.storage.begin_txn().await?;
-                txn.set_segment_metadata(*seg_id, updated).await?;
-                txn.commit().await?;
-            } else {
-                self.storage.delete(*seg_id).await?;
-                if let Some(hash) = metadata.content_hash {
-                    let _ = self.catalog.deregister_content(&hash, *seg_id)?;
+        for (seg_id, slots) in stale {
+            for _ in 0..slots {
+                self.release_reference(seg_id).await?;
+            }
+            // The copy takes over dedup and similarity lookups the original gave up.
+            let sealed = &resealed[&seg_id];
+            if let Some(hash) = &sealed.content_hash {
+                if self.catalog.lookup_content(hash).is_none() {
+                    self.catalog.register_content(hash.clone(), sealed.id)?;
+                }
+            }
+            if let Some(sketch) = &sealed.sketch {
+                if self.catalog.lookup_similar(sketch).is_none() {
+                    self.catalog.register_similar(sketch, sealed.id)?;
                 }
             }
         }
 
+        self.catalog.lookup_capsule(id)
+    }
+
+    pub async fn delete_capsule(&mut self, id: CapsuleId) -> Result<()> {
+        let capsule = self.catalog.lookup_capsule(id)?;
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs();
+        if capsule.is_immutable_at(now) {
+            return Err(anyhow!("capsule {:?} is under retention or legal hold", id));
+        }
+
+        for seg_id in capsule.segments.iter().filter(|seg_id| !seg_id.is_hole()) {
+            self.release_reference(*seg_id).await?;
+        }
+
         self.catalog.delete_capsule(id)?;
+        if let Ok(mut reads) = self.reads.lock() {
+            reads.remove(&id);
+        }
+        Ok(())
+    }
+
+    /// Drop one capsule slot's reference to `seg_id`, and the reference a
+    /// delta deleted as a result held on its base.
+    async fn release_reference(&mut self, seg_id: SegmentId) -> Result<()> {
+        let metadata = self.storage.metadata(seg_id).await?;
+        if let Some(base) = self.release_segment(metadata).await? {
+            // Bases are never deltas, so releasing one stops here.
+            let base = self.storage.metadata(base).await?;
+            self.release_segment(base).await?;
+        }
         Ok(())
     }
 
+    /// Drop one reference to a segment, deleting it when it was the last.
+    /// Returns the base a deleted delta segment held a reference on.
+    async fn release_segment(&mut self, metadata: Segment) -> Result<Option<SegmentId>> {
+        let seg_id = metadata.id;
+        if metadata.ref_count > 1 {
+            let mut updated = metadata;
+            updated.ref_count -= 1;
+            updated.deduplicated = updated.ref_count > 1;
+            let mut txn = self.storage.begin_txn().await?;
+            txn.set_segment_metadata(seg_id, updated).await?;
+            txn.commit().await?;
+            return Ok(None);
+        }
+
+        self.storage.delete(seg_id).await?;
+        if let Some(hash) = &metadata.content_hash {
+            let _ = self.catalog.deregister_content(hash, seg_id)?;
+        }
+        if let Some(sketch) = &metadata.sketch {
+            let _ = self.catalog.deregister_similar(sketch, seg_id)?;
+        }
+        Ok(metadata.delta_base)
+    }
+
     pub async fn garbage_collect(&mut self) -> Result<usize> {
         let referenced: HashSet<SegmentId> = self
             .catalog
@@ -562,27 +1191,47 @@ where
             .map(|(hash, seg)| (seg, hash))
             .collect();
 
-        let mut reclaimed = 0usize;
+        let mut segments = Vec::new();
+        for seg_id in self.storage.segment_ids().await? {
+            if let Ok(metadata) = self.storage.metadata(seg_id).await {
+                segments.push(metadata);
+            }
+        }
+        let live = |metadata: &Segment| referenced.contains(&metadata.id) && metadata.ref_count > 0;
+        // A live delta keeps its base even when no capsule references the base.
+        let pinned: HashSet<SegmentId> = segments
+            .iter()
+            .filter(|metadata| live(metadata))
+            .filter_map(|metadata| metadata.delta_base)
+            .collect();
+        let kept = |metadata: &Segment| live(metadata) || pinned.contains(&metadata.id);
 
-        let orphan_segments = self.storage.segment_ids().await?;
+        let mut reclaimed = 0usize;
+        let mut released: HashMap<SegmentId, u32> = HashMap::new();
         let mut txn = self.storage.begin_txn().await?;
 
-        for seg_id in orphan_segments {
-            let metadata = match self.storage.metadata(seg_id).await {
-                Ok(meta) => meta,
-                Err(_) => continue,
-            };
-
-            if referenced.contains(&seg_id) && metadata.ref_count > 0 {
-                continue;
+        for metadata in segments.iter().filter(|metadata| !kept(metadata)) {
+            txn.delete(metadata.id).await?;
+            if let Some(hash) = content_map.get(&metadata.id) {
+                let _ = self.catalog.deregister_content(hash, metadata.id)?;
             }
-
-            txn.delete(seg_id).await?;
-            if let Some(hash) = content_map.get(&seg_id) {
-                let _ = self.catalog.deregister_content(hash, seg_id)?;
+            if let Some(sketch) = &metadata.sketch {
+                let _ = self.catalog.deregister_similar(sketch, metadata.id)?;
+            }
+            if let Some(base) = metadata.delta_base {
+                *released.entry(base).or_default() += 1;
             }
             reclaimed += 1;
         }
+        // Bases that stay lose the references reclaimed deltas held on them.
+        for metadata in segments.iter().filter(|metadata| kept(metadata)) {
+            if let Some(count) = released.get(&metadata.id) {
+                let mut updated = metadata.clone();
+                updated.ref_count = updated.ref_count.saturating_sub(*count);
+                updated.deduplicated = updated.ref_count > 1;
+                txn.set_segment_metadata(updated.id, updated).await?;
+            }
+        }
         txn.commit().await?;
 
         Ok(reclaimed)
@@ -591,7 +1240,7 @@ where
 
 /// Builder used to assemble pipelines with optional overrides.
 pub struct PipelineBuilder<
-    C = Lz4ZstdCompressor,
+    C = CodecCompressor,
     D = Blake3Deduper,
     E = NoopEncryptor,
     S = InMemoryBackend,
@@ -702,7 +1351,7 @@ where
 }
 
 pub type InMemoryPipeline = Pipeline<
-    Lz4ZstdCompressor,
+    CodecCompressor,
     Blake3Deduper,
     NoopEncryptor,
     InMemoryBackend,
@@ -714,7 +1363,7 @@ pub type InMemoryPipeline = Pipeline<
 pub type DefaultPipeline = InMemoryPipeline;
 
 pub type NvramPipeline = Pipeline<
-    Lz4ZstdCompressor,
+    CodecCompressor,
     Blake3Deduper,
     NoopEncryptor,
     NvramBackend,
@@ -724,7 +1373,7 @@ pub type NvramPipeline = Pipeline<
 >;
 
 pub type NvramPipelineWithEncryption = Pipeline<
-    Lz4ZstdCompressor,
+    CodecCompressor,
     Blake3Deduper,
     XtsEncryptor,
     NvramBackend,
@@ -733,10 +1382,32 @@ pub type NvramPipelineWithEncryption = Pipeline<
     InMemoryCatalog,
 >;
 
+/// NVRAM hot tier in front of a file-backed capacity tier.
+pub type TieredPipeline = Pipeline<
+    CodecCompressor,
+    Blake3Deduper,
+    NoopEncryptor,
+    TieredBackend<NvramBackend, FileBackend>,
+    DefaultPolicyEvaluator,
+    NullKeyring,
+    InMemoryCatalog,
+>;
+
+/// Segments erasure-coded across file-backed stores, one per directory.
+pub type ErasurePipeline = Pipeline<
+    CodecCompressor,
+    Blake3Deduper,
+    NoopEncryptor,
+    ErasureBackend<FileBackend>,
+    DefaultPolicyEvaluator,
+    NullKeyring,
+    InMemoryCatalog,
+>;
+
 pub fn pipeline_with_nvram<P: AsRef<std::path::Path>>(path: P) -> Result<NvramPipeline> {
     let storage = NvramBackend::open(path)?;
     Ok(Pipeline::new(
-        Lz4ZstdCompressor,
+        CodecCompressor,
         Blake3Deduper::default(),
         NoopEncryptor,
         storage,
@@ -752,7 +1423,7 @@ pub fn pipeline_with_nvram_xts<P: AsRef<std::path::Path>>(
 ) -> Result<NvramPipelineWithEncryption> {
     let storage = NvramBackend::open(path)?;
     Ok(Pipeline::new(
-        Lz4ZstdCompressor,
+        CodecCompressor,
         Blake3Deduper::default(),
         XtsEncryptor::new(Arc::clone(&key_manager)),
         storage,
@@ -761,3 +1432,44 @@ pub fn pipeline_with_nvram_xts<P: AsRef<std::path::Path>>(
         InMemoryCatalog::default(),
     ))
 }
+
+pub fn pipeline_with_tiers<P, Q>(nvram_path: P, capacity_dir: Q) -> Result<TieredPipeline>
+where
+    P: AsRef<std::path::Path>,
+    Q: AsRef<std::path::Path>,
+{
+    let storage = TieredBackend::new(
+        NvramBackend::open(nvram_path)?,
+        FileBackend::open(capacity_dir)?,
+    );
+    Ok(Pipeline::new(
+        CodecCompressor,
+        Blake3Deduper::default(),
+        NoopEncryptor,
+        storage,
+        DefaultPolicyEvaluator,
+        None,
+        InMemoryCatalog::default(),
+    ))
+}
+
+/// Erasure-code segments across `dirs` with the profile from `policy`; `dirs`
+/// should sit on distinct disks, and there must be one per fragment.
+pub fn pipeline_with_erasure<P: AsRef<std::path::Path>>(
+    dirs: &[P],
+    policy: &Policy,
+) -> Result<ErasurePipeline> {
+    let backends = dirs
+        .iter()
+        .map(FileBackend::open)
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Pipeline::new(
+        CodecCompressor,
+        Blake3Deduper::default(),
+        NoopEncryptor,
+        ErasureBackend::for_policy(backends, policy)?,
+        DefaultPolicyEvaluator,
+        None,
+        InMemoryCatalog::default(),
+    ))
+}
@@ -0,0 +1,51 @@
+use common::traits::{CapsuleCatalog, StorageBackend};
+use common::{LayoutStrategy, Policy};
+use futures::executor::block_on;
+use pipeline::{InMemoryPipeline, PipelineBuilder};
+
+fn sample_data(len: usize, seed: u64) -> Vec<u8> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (state >> 56) as u8
+        })
+        .collect()
+}
+
+#[test]
+fn content_defined_layout_survives_insertions() {
+    let mut pipeline: InMemoryPipeline = PipelineBuilder::new().build();
+    let mut policy = Policy::default();
+    policy.layout.strategy = LayoutStrategy::ContentDefined {
+        min_size: 16 * 1024,
+        avg_size: 64 * 1024,
+        max_size: 256 * 1024,
+    };
+
+    let data = sample_data(2 * 1024 * 1024, 1);
+    let original = block_on(pipeline.write_capsule(&data, &policy)).unwrap();
+    let stored = block_on(pipeline.storage().segment_ids()).unwrap().len();
+    assert_eq!(
+        pipeline
+            .catalog()
+            .lookup_capsule(original)
+            .unwrap()
+            .segments
+            .len(),
+        stored
+    );
+
+    let mut edited = b"prefix".to_vec();
+    edited.extend_from_slice(&data);
+    let id = block_on(pipeline.write_capsule(&edited, &policy)).unwrap();
+    let added = block_on(pipeline.storage().segment_ids()).unwrap().len() - stored;
+    assert!(added <= 2, "an insertion stored {} new segments", added);
+    assert_eq!(block_on(pipeline.read_capsule(id)).unwrap(), edited);
+    assert_eq!(
+        block_on(pipeline.read_range(id, 1_000_000, 64)).unwrap(),
+        edited[1_000_000..1_000_064]
+    );
+}
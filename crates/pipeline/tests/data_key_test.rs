@@ -0,0 +1,182 @@
+use anyhow::{anyhow, Result};
+use common::similarity::Sketch;
+use common::traits::{CapsuleCatalog, DedupStats, StorageBackend};
+use common::{Capsule, CapsuleId, ContentHash, Policy, SegmentId, WrappedDataKey};
+use compression::CodecCompressor;
+use dedup::Blake3Deduper;
+use encryption::keymanager::{KeyManager, MASTER_KEY_SIZE};
+use futures::executor::block_on;
+use pipeline::{
+    DefaultPolicyEvaluator, InMemoryCatalog, KeyManagerKeyring, Pipeline, XtsEncryptor,
+};
+use std::sync::{Arc, Mutex};
+use storage::InMemoryBackend;
+
+const MASTER_KEY: [u8; MASTER_KEY_SIZE] = [0x6B; MASTER_KEY_SIZE];
+
+type KeyedPipeline<R> = Pipeline<
+    CodecCompressor,
+    Blake3Deduper,
+    XtsEncryptor,
+    InMemoryBackend,
+    DefaultPolicyEvaluator,
+    KeyManagerKeyring,
+    R,
+>;
+
+/// Catalog that "crashes" on any update to a capsule after it is created,
+/// standing in for a process that dies right after `create_capsule` returns.
+#[derive(Clone, Default)]
+struct CrashAfterCreate(InMemoryCatalog);
+
+impl CapsuleCatalog for CrashAfterCreate {
+    fn allocate_segment(&self) -> Result<SegmentId> {
+        self.0.allocate_segment()
+    }
+
+    fn lookup_capsule(&self, id: CapsuleId) -> Result<Capsule> {
+        self.0.lookup_capsule(id)
+    }
+
+    fn create_capsule(
+        &self,
+        id: CapsuleId,
+        size: u64,
+        policy: &Policy,
+        segments: Vec<SegmentId>,
+        stats: &DedupStats,
+        data_keys: Vec<WrappedDataKey>,
+    ) -> Result<()> {
+        self.0
+            .create_capsule(id, size, policy, segments, stats, data_keys)
+    }
+
+    fn delete_capsule(&self, id: CapsuleId) -> Result<Capsule> {
+        self.0.delete_capsule(id)
+    }
+
+    fn set_data_keys(&self, _id: CapsuleId, _keys: Vec<WrappedDataKey>) -> Result<()> {
+        Err(anyhow!("crashed before the data keys were recorded"))
+    }
+
+    fn replace_segments(
+        &self,
+        _id: CapsuleId,
+        _segments: Vec<SegmentId>,
+        _data_keys: Vec<WrappedDataKey>,
+    ) -> Result<()> {
+        Err(anyhow!("crashed before the segments were replaced"))
+    }
+
+    fn lookup_content(&self, hash: &ContentHash) -> Option<SegmentId> {
+        self.0.lookup_content(hash)
+    }
+
+    fn register_content(&self, hash: ContentHash, segment: SegmentId) -> Result<()> {
+        self.0.register_content(hash, segment)
+    }
+
+    fn deregister_content(&self, hash: &ContentHash, segment: SegmentId) -> Result<bool> {
+        self.0.deregister_content(hash, segment)
+    }
+
+    fn lookup_similar(&self, sketch: &Sketch) -> Option<SegmentId> {
+        self.0.lookup_similar(sketch)
+    }
+
+    fn register_similar(&self, sketch: &Sketch, segment: SegmentId) -> Result<()> {
+        self.0.register_similar(sketch, segment)
+    }
+
+    fn deregister_similar(&self, sketch: &Sketch, segment: SegmentId) -> Result<bool> {
+        self.0.deregister_similar(sketch, segment)
+    }
+
+    fn capsules(&self) -> Vec<Capsule> {
+        self.0.capsules()
+    }
+
+    fn content_entries(&self) -> Vec<(ContentHash, SegmentId)> {
+        self.0.content_entries()
+    }
+}
+
+/// A pipeline over `storage` and `catalog` with a fresh keyring, as after a
+/// restart: only the master key and what the catalog recorded survive.
+fn keyed_pipeline<R: CapsuleCatalog>(storage: InMemoryBackend, catalog: R) -> KeyedPipeline<R> {
+    let manager = Arc::new(Mutex::new(KeyManager::new(MASTER_KEY)));
+    Pipeline::new(
+        CodecCompressor,
+        Blake3Deduper::default(),
+        XtsEncryptor::new(Arc::clone(&manager)),
+        storage,
+        DefaultPolicyEvaluator,
+        Some(KeyManagerKeyring::new(manager)),
+        catalog,
+    )
+}
+
+#[test]
+fn data_keys_are_published_with_the_capsule() {
+    let storage = InMemoryBackend::new();
+    let catalog = CrashAfterCreate::default();
+    let data = b"sealed and published in one catalog update ".repeat(4_000);
+
+    let id = {
+        let mut pipeline = keyed_pipeline(storage.clone(), catalog.clone());
+        block_on(pipeline.write_capsule(&data, &Policy::encrypted())).unwrap()
+    };
+
+    let record = catalog.lookup_capsule(id).unwrap();
+    assert_eq!(record.own_data_key().unwrap().id.generation, 1);
+    let restarted = keyed_pipeline(storage, catalog);
+    assert_eq!(block_on(restarted.read_capsule(id)).unwrap(), data);
+}
+
+#[test]
+fn rotation_after_a_restart_continues_the_recorded_generations() {
+    let storage = InMemoryBackend::new();
+    let catalog = InMemoryCatalog::default();
+    let data = b"re-sealed under the next recorded generation ".repeat(4_000);
+
+    let (id, copy) = {
+        let mut pipeline = keyed_pipeline(storage.clone(), catalog.clone());
+        let id = block_on(pipeline.write_capsule(&data, &Policy::encrypted())).unwrap();
+        let copy = block_on(pipeline.write_capsule(&data, &Policy::encrypted())).unwrap();
+        (id, copy)
+    };
+    let original = catalog.lookup_capsule(id).unwrap();
+    assert_eq!(
+        catalog.lookup_capsule(copy).unwrap().segments,
+        original.segments
+    );
+
+    // A restarted keyring knows nothing of generation 1 but the record does.
+    let mut pipeline = keyed_pipeline(storage.clone(), catalog.clone());
+    let rotated = block_on(pipeline.rotate_data_key(id)).unwrap();
+    let key = rotated.own_data_key().unwrap().clone();
+    assert_eq!(key.id.generation, 2);
+    assert_eq!(rotated.data_keys, vec![key.clone()]);
+    for seg_id in &rotated.segments {
+        assert!(!original.segments.contains(seg_id));
+        let segment = block_on(pipeline.storage().metadata(*seg_id)).unwrap();
+        assert_eq!(segment.data_key, Some(key.id));
+        assert_eq!(segment.ref_count, 1);
+    }
+    // The copy still holds the originals under the key its record carries.
+    for seg_id in &original.segments {
+        let segment = block_on(pipeline.storage().metadata(*seg_id)).unwrap();
+        assert_eq!(segment.ref_count, 1);
+    }
+    assert_eq!(block_on(pipeline.read_capsule(id)).unwrap(), data);
+    assert_eq!(block_on(pipeline.read_capsule(copy)).unwrap(), data);
+
+    let mut restarted = keyed_pipeline(storage, catalog.clone());
+    assert_eq!(block_on(restarted.read_capsule(id)).unwrap(), data);
+    let again = block_on(restarted.rotate_data_key(id)).unwrap();
+    assert_eq!(again.own_data_key().unwrap().id.generation, 3);
+    assert_eq!(block_on(restarted.read_capsule(id)).unwrap(), data);
+
+    let plain = block_on(restarted.write_capsule(b"not encrypted", &Policy::default())).unwrap();
+    assert!(block_on(restarted.rotate_data_key(plain)).is_err());
+}
@@ -0,0 +1,60 @@
+use common::traits::{CapsuleCatalog, StorageBackend};
+use common::Policy;
+use futures::executor::block_on;
+use pipeline::{InMemoryPipeline, PipelineBuilder};
+
+fn sample_data(len: usize, seed: u64) -> Vec<u8> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (state >> 56) as u8
+        })
+        .collect()
+}
+
+#[test]
+fn near_duplicates_are_rebuilt_from_their_base() {
+    let mut pipeline: InMemoryPipeline = PipelineBuilder::new().build();
+    let policy = Policy {
+        delta_compression: true,
+        ..Policy::default()
+    };
+
+    let original = sample_data(512 * 1024, 1);
+    let mut changed = original.clone();
+    changed[10_000..10_010].copy_from_slice(b"0123456789");
+    changed.truncate(500 * 1024);
+
+    let first = block_on(pipeline.write_capsule(&original, &policy)).unwrap();
+    let second = block_on(pipeline.write_capsule(&changed, &policy)).unwrap();
+
+    let base = pipeline.catalog().lookup_capsule(first).unwrap().segments[0];
+    let delta_id = pipeline.catalog().lookup_capsule(second).unwrap().segments[0];
+    let delta = block_on(pipeline.storage().metadata(delta_id)).unwrap();
+    assert_eq!(delta.delta_base, Some(base));
+    assert!(delta.len < 1024, "delta stored {} bytes", delta.len);
+    assert_eq!(
+        block_on(pipeline.storage().metadata(base))
+            .unwrap()
+            .ref_count,
+        2
+    );
+    assert_eq!(block_on(pipeline.read_capsule(second)).unwrap(), changed);
+    assert_eq!(
+        block_on(pipeline.read_range(second, 9_995, 20)).unwrap(),
+        changed[9_995..10_015]
+    );
+
+    // Neither deleting the base's capsule nor collecting garbage drops the base.
+    block_on(pipeline.delete_capsule(first)).unwrap();
+    assert_eq!(block_on(pipeline.garbage_collect()).unwrap(), 0);
+    assert_eq!(block_on(pipeline.read_capsule(second)).unwrap(), changed);
+
+    block_on(pipeline.delete_capsule(second)).unwrap();
+    assert!(block_on(pipeline.storage().segment_ids())
+        .unwrap()
+        .is_empty());
+}
@@ -0,0 +1,68 @@
+use common::traits::CapsuleCatalog;
+use common::Policy;
+use futures::executor::block_on;
+use pipeline::pipeline_with_erasure;
+use std::fs;
+use std::path::PathBuf;
+
+fn disk_dirs(prefix: &str, count: usize) -> Vec<PathBuf> {
+    (0..count)
+        .map(|i| {
+            let dir = PathBuf::from(format!("{}_pipeline_erasure_disk{}", prefix, i));
+            let _ = fs::remove_dir_all(&dir);
+            dir
+        })
+        .collect()
+}
+
+fn cleanup(dirs: &[PathBuf]) {
+    for dir in dirs {
+        let _ = fs::remove_dir_all(dir);
+    }
+}
+
+fn sample_data(len: usize, seed: u32) -> Vec<u8> {
+    (0..len)
+        .map(|i| {
+            ((i / 3) as u32 ^ seed)
+                .wrapping_mul(2_654_435_761)
+                .to_le_bytes()[1]
+        })
+        .collect()
+}
+
+#[test]
+fn capsules_read_back_after_disk_loss() {
+    let dirs = disk_dirs("disk_loss", 3);
+    let policy = Policy {
+        erasure_profile: Some("2+1".into()),
+        ..Policy::default()
+    };
+    let mut pipeline = pipeline_with_erasure(&dirs, &policy).unwrap();
+
+    let data = sample_data(5 * 1024 * 1024, 1);
+    let id = block_on(pipeline.write_capsule(&data, &policy)).unwrap();
+    // A second copy dedupes, updating the metadata on every disk.
+    let copy = block_on(pipeline.write_capsule(&data, &policy)).unwrap();
+    assert_eq!(
+        pipeline.catalog().lookup_capsule(id).unwrap().segments,
+        pipeline.catalog().lookup_capsule(copy).unwrap().segments
+    );
+
+    fs::remove_dir_all(&dirs[0]).unwrap();
+    assert_eq!(block_on(pipeline.read_capsule(id)).unwrap(), data);
+    assert_eq!(
+        block_on(pipeline.read_range(copy, 4 * 1024 * 1024, 100)).unwrap(),
+        data[4 * 1024 * 1024..4 * 1024 * 1024 + 100]
+    );
+
+    cleanup(&dirs);
+}
+
+#[test]
+fn directory_count_must_match_profile() {
+    let dirs = disk_dirs("mismatch", 3);
+    // The default layout profile is 6+2.
+    assert!(pipeline_with_erasure(&dirs, &Policy::default()).is_err());
+    cleanup(&dirs);
+}
@@ -0,0 +1,39 @@
+use common::traits::{CapsuleCatalog, StorageBackend};
+use common::{CompressionPolicy, Policy};
+use futures::executor::block_on;
+use pipeline::{InMemoryPipeline, PipelineBuilder};
+
+#[test]
+fn codecs_share_segments_for_identical_data() {
+    let mut pipeline: InMemoryPipeline = PipelineBuilder::new().build();
+    let data = b"one fingerprint, many codecs ".repeat(100_000);
+
+    let ids: Vec<_> = [
+        CompressionPolicy::LZ4 { level: 1 },
+        CompressionPolicy::Zstd { level: 3 },
+        CompressionPolicy::None,
+    ]
+    .into_iter()
+    .map(|compression| {
+        let policy = Policy {
+            compression,
+            ..Policy::default()
+        };
+        block_on(pipeline.write_capsule(&data, &policy)).unwrap()
+    })
+    .collect();
+
+    let segments = pipeline.catalog().lookup_capsule(ids[0]).unwrap().segments;
+    let stored = block_on(pipeline.storage().segment_ids()).unwrap();
+    assert_eq!(stored.len(), segments.len());
+    for &id in &ids {
+        assert_eq!(
+            pipeline.catalog().lookup_capsule(id).unwrap().segments,
+            segments
+        );
+        assert_eq!(block_on(pipeline.read_capsule(id)).unwrap(), data);
+    }
+    let metadata = block_on(pipeline.storage().metadata(segments[0])).unwrap();
+    assert!(metadata.compression_algo.starts_with("lz4"));
+    assert_eq!(metadata.ref_count, 3);
+}
@@ -0,0 +1,97 @@
+use common::traits::{CapsuleCatalog, StorageBackend};
+use common::{CapsuleId, Policy, StorageTier};
+use futures::executor::block_on;
+use pipeline::{pipeline_with_tiers, TierReport, TieredPipeline};
+use std::fs;
+
+fn setup(prefix: &str) -> (String, String) {
+    let log_path = format!("{}_tiering.log", prefix);
+    let capacity_dir = format!("{}_tiering_capacity", prefix);
+    cleanup(&log_path, &capacity_dir);
+    (log_path, capacity_dir)
+}
+
+fn cleanup(log_path: &str, capacity_dir: &str) {
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_dir_all(capacity_dir);
+}
+
+fn sample_data(len: usize, seed: u32) -> Vec<u8> {
+    (0..len)
+        .map(|i| {
+            ((i / 3) as u32 ^ seed)
+                .wrapping_mul(2_654_435_761)
+                .to_le_bytes()[1]
+        })
+        .collect()
+}
+
+fn tier_of(pipeline: &TieredPipeline, id: CapsuleId) -> StorageTier {
+    let capsule = pipeline.catalog().lookup_capsule(id).unwrap();
+    block_on(pipeline.storage().metadata(capsule.segments[0]))
+        .unwrap()
+        .tier
+}
+
+#[test]
+fn capsules_follow_their_heat() {
+    let (log_path, capacity_dir) = setup("heat");
+    let mut pipeline = pipeline_with_tiers(&log_path, &capacity_dir).unwrap();
+    let policy = Policy::default();
+
+    let hot_data = sample_data(64 * 1024, 1);
+    let cold_data = sample_data(64 * 1024, 2);
+    let hot = block_on(pipeline.write_capsule(&hot_data, &policy)).unwrap();
+    let cold = block_on(pipeline.write_capsule(&cold_data, &policy)).unwrap();
+    assert_eq!(tier_of(&pipeline, hot), StorageTier::Hot);
+
+    // Nothing has been read, so everything cools to the capacity tier.
+    let report = block_on(pipeline.rebalance_tiers()).unwrap();
+    assert_eq!(report.demoted, 2);
+    assert_eq!(tier_of(&pipeline, cold), StorageTier::Capacity);
+    assert_eq!(block_on(pipeline.read_capsule(cold)).unwrap(), cold_data);
+
+    for _ in 0..4 {
+        block_on(pipeline.read_capsule(hot)).unwrap();
+    }
+    let report = block_on(pipeline.rebalance_tiers()).unwrap();
+    assert_eq!(
+        report,
+        TierReport {
+            promoted: 1,
+            demoted: 0
+        }
+    );
+    assert_eq!(tier_of(&pipeline, hot), StorageTier::Hot);
+    assert_eq!(tier_of(&pipeline, cold), StorageTier::Capacity);
+    assert_eq!(
+        block_on(pipeline.read_range(hot, 10, 100)).unwrap(),
+        hot_data[10..110]
+    );
+
+    // Without further reads the counts decay and the capsule cools again.
+    for _ in 0..4 {
+        block_on(pipeline.rebalance_tiers()).unwrap();
+    }
+    assert_eq!(tier_of(&pipeline, hot), StorageTier::Capacity);
+
+    cleanup(&log_path, &capacity_dir);
+}
+
+#[test]
+fn zero_threshold_keeps_capsules_hot() {
+    let (log_path, capacity_dir) = setup("always_hot");
+    let mut pipeline = pipeline_with_tiers(&log_path, &capacity_dir).unwrap();
+    let mut policy = Policy::default();
+    policy.layout.heat_threshold = 0.0;
+
+    let id = block_on(pipeline.write_capsule(&sample_data(64 * 1024, 3), &policy)).unwrap();
+    assert_eq!(
+        block_on(pipeline.rebalance_tiers()).unwrap(),
+        TierReport::default()
+    );
+    assert_eq!(tier_of(&pipeline, id), StorageTier::Hot);
+
+    cleanup(&log_path, &capacity_dir);
+}
@@ -1,14 +1,14 @@
 //! Block protocol façade – exposes capsule-backed logical volumes.
 //!
 //! The contract mimics a very small subset of what an NVMe/NBD target would need:
-//! create logical volumes, read ranges, and write ranges.  Each write produces a
-//! brand-new capsule so that the immutable data-plane invariants still hold.
-//! We eagerly delete superseded capsules via the [`WritePipeline`] helper so that
-//! deduplicated segments are correctly reference-counted.
+//! create logical volumes, read ranges, and write ranges.  Writes go through the
+//! pipeline's copy-on-write [`WritePipeline::write_range`], so a volume keeps its
+//! backing capsule and only the touched segments are replaced; superseded
+//! segments are released with their refcounts so dedupe stays accurate.
 
 use anyhow::{anyhow, bail, Result};
 use capsule_registry::{pipeline::WritePipeline, CapsuleRegistry};
-use common::CapsuleId;
+use common::{CapsuleId, Policy};
 use nvram_sim::NvramLog;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
@@ -28,6 +28,8 @@ pub struct BlockVolume {
     created_at: u64,
     updated_at: u64,
     version: u64,
+    #[serde(default)]
+    read_only: bool,
 }
 
 impl BlockVolume {
@@ -58,6 +60,11 @@ impl BlockVolume {
     pub fn version(&self) -> u64 {
         self.version
     }
+
+    /// Snapshot volumes reject writes.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
 }
 
 pub struct BlockView {
@@ -114,9 +121,9 @@ impl BlockView {
 
     /// Create a new logical volume.
     ///
-    /// Trade-off: we eagerly zero-initialise the backing capsule so all reads
-    /// return deterministic data.  In a production path we would lazily
-    /// materialise blocks or use sparse extents.
+    /// The backing capsule is thin-provisioned: it starts out as a single hole,
+    /// so unwritten blocks read back as zeroes and a volume of any size costs
+    /// nothing until it is written.
     pub fn create_volume_with_block_size(
         &self,
         name: &str,
@@ -133,10 +140,6 @@ impl BlockView {
         if !size.is_multiple_of(block_size) {
             bail!("Volume size must be a multiple of block size");
         }
-        if size > usize::MAX as u64 {
-            bail!("Volume size exceeds addressable memory for initialisation");
-        }
-
         {
             let volumes = self.volumes.read().unwrap();
             if volumes.contains_key(name) {
@@ -144,8 +147,9 @@ impl BlockView {
             }
         }
 
-        let buffer = vec![0u8; size as usize];
-        let capsule_id = self.pipeline.write_capsule(&buffer)?;
+        let capsule_id = self
+            .pipeline
+            .create_sparse_capsule(size, &Policy::default())?;
         let now = unix_timestamp();
 
         let volume = BlockVolume {
@@ -156,16 +160,58 @@ impl BlockView {
             created_at: now,
             updated_at: now,
             version: 1,
+            read_only: false,
+        };
+        self.insert_volume(volume)
+    }
+
+    /// Take a read-only, point-in-time snapshot of `source` as volume `name`.
+    ///
+    /// The snapshot shares the source's segments, so it costs metadata only
+    /// until the source is overwritten.
+    pub fn snapshot_volume(&self, source: &str, name: &str) -> Result<BlockVolume> {
+        self.derive_volume(source, name, true)
+    }
+
+    /// Create a writable copy of `source` as volume `name` without copying data.
+    pub fn clone_volume(&self, source: &str, name: &str) -> Result<BlockVolume> {
+        self.derive_volume(source, name, false)
+    }
+
+    fn derive_volume(&self, source: &str, name: &str, read_only: bool) -> Result<BlockVolume> {
+        validate_volume_name(name)?;
+        let source = self.volume(source)?;
+        if self.volumes.read().unwrap().contains_key(name) {
+            bail!("Volume already exists: {}", name);
+        }
+
+        let capsule_id = if read_only {
+            self.pipeline.snapshot_capsule(source.capsule_id)?
+        } else {
+            self.pipeline.clone_capsule(source.capsule_id)?
         };
+        let now = unix_timestamp();
+
+        self.insert_volume(BlockVolume {
+            name: name.to_string(),
+            capsule_id,
+            created_at: now,
+            updated_at: now,
+            version: 1,
+            read_only,
+            ..source
+        })
+    }
 
+    fn insert_volume(&self, volume: BlockVolume) -> Result<BlockVolume> {
         let mut volumes = self.volumes.write().unwrap();
-        if volumes.contains_key(name) {
+        if volumes.contains_key(&volume.name) {
             // Another thread raced us – drop the new capsule to avoid leakage.
             drop(volumes);
-            let _ = self.pipeline.delete_capsule(capsule_id);
-            bail!("Volume already exists: {}", name);
+            let _ = self.pipeline.delete_capsule(volume.capsule_id);
+            bail!("Volume already exists: {}", volume.name);
         }
-        volumes.insert(name.to_string(), volume.clone());
+        volumes.insert(volume.name.clone(), volume.clone());
         drop(volumes);
         self.persist()?;
         Ok(volume)
@@ -191,9 +237,13 @@ impl BlockView {
         {
             let mut volumes = self.volumes.write().unwrap();
             let volume = volumes
-                .remove(name)
+                .get(name)
                 .ok_or_else(|| anyhow!("Volume not found: {}", name))?;
             capsule_id = volume.capsule_id;
+            self.pipeline
+                .registry()
+                .ensure_mutable(capsule_id, "delete")?;
+            volumes.remove(name);
         }
 
         self.persist()?;
@@ -212,9 +262,14 @@ impl BlockView {
 
     /// Overwrite a range within the logical volume.
     ///
-    /// We rewrite the whole backing capsule.  The code performs a basic
-    /// optimistic concurrency check by verifying that metadata wasn't updated
-    /// while we were copying.
+    /// Only the backing segments the range touches are rewritten; the volume
+    /// keeps its capsule, whose version the pipeline bumps.  Writes to one
+    /// capsule serialize in the pipeline, so once the range is durable the
+    /// write has happened and the volume merely records it, unless the volume
+    /// was deleted meanwhile.
+    ///
+    /// The modular pipeline cannot rewrite a capsule in place; there the whole
+    /// volume is rewritten into a new capsule instead.
     pub fn write(&self, name: &str, offset: u64, data: &[u8]) -> Result<()> {
         if data.is_empty() {
             return Ok(());
@@ -225,32 +280,63 @@ impl BlockView {
             let volume = volumes
                 .get(name)
                 .ok_or_else(|| anyhow!("Volume not found: {}", name))?;
+            if volume.read_only {
+                bail!("Volume is a read-only snapshot: {}", name);
+            }
             if offset + data.len() as u64 > volume.size {
                 bail!("Write beyond end of volume");
             }
             (volume.capsule_id, volume.version)
         };
 
+        if self.pipeline.uses_modular() {
+            return self.rewrite_volume(name, capsule_id, version, offset, data);
+        }
+
+        self.pipeline.write_range(capsule_id, offset, data)?;
+        let now = unix_timestamp();
+
+        let mut volumes = self.volumes.write().unwrap();
+        match volumes.get_mut(name) {
+            Some(volume) if volume.capsule_id == capsule_id => {
+                volume.updated_at = now;
+                volume.version = volume.version.saturating_add(1);
+            }
+            // delete_volume reclaims the capsule, and the write with it.
+            _ => bail!("Volume deleted concurrently: {}", name),
+        }
+
+        drop(volumes);
+        self.persist()
+    }
+
+    /// Copy-on-write of the whole volume into a new capsule, for pipelines
+    /// that cannot patch one in place. Nothing is published unless the volume
+    /// is unchanged, so a failed rewrite can simply be retried.
+    fn rewrite_volume(
+        &self,
+        name: &str,
+        capsule_id: CapsuleId,
+        version: u64,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<()> {
         let mut buffer = self.pipeline.read_capsule(capsule_id)?;
         let start = offset as usize;
-        let end = start + data.len();
-        buffer[start..end].copy_from_slice(data);
+        buffer[start..start + data.len()].copy_from_slice(data);
 
         let new_capsule = self.pipeline.write_capsule(&buffer)?;
         let now = unix_timestamp();
 
         let mut volumes = self.volumes.write().unwrap();
-        let volume = volumes
-            .get_mut(name)
-            .ok_or_else(|| anyhow!("Volume not found: {}", name))?;
-
-        if volume.version != version || volume.capsule_id != capsule_id {
-            // Somebody mutated the volume while we were rewriting; drop the new capsule
-            // and ask the caller to retry.
-            drop(volumes);
-            let _ = self.pipeline.delete_capsule(new_capsule);
-            bail!("Volume modified concurrently");
-        }
+        let volume = match volumes.get_mut(name) {
+            Some(volume) if volume.version == version && volume.capsule_id == capsule_id => volume,
+            _ => {
+                drop(volumes);
+                let _ = self.pipeline.delete_capsule(new_capsule);
+                bail!("Volume modified concurrently");
+            }
+        };
 
         volume.capsule_id = new_capsule;
         volume.updated_at = now;
@@ -7,6 +7,7 @@ fn teardown(prefix: &str) {
     let _ = fs::remove_file(format!("{}.nvram", prefix));
     let _ = fs::remove_file(format!("{}.nvram.segments", prefix));
     let _ = fs::remove_file(format!("{}.metadata", prefix));
+    let _ = fs::remove_dir_all(format!("{}.metadata.dedup", prefix));
     let _ = fs::remove_file(format!("{}.block.json", prefix));
 }
 
@@ -26,6 +27,7 @@ fn block_volume_lifecycle() {
     let block = setup(prefix);
 
     let volume = block.create_volume("vol0", 16 * 1024).unwrap();
+    let capsule_id = volume.capsule_id();
     assert_eq!(volume.name(), "vol0");
     assert_eq!(volume.size(), 16 * 1024);
     assert_eq!(volume.block_size(), 4096);
@@ -44,6 +46,8 @@ fn block_volume_lifecycle() {
 
     let info = block.volume("vol0").unwrap();
     assert!(info.version() >= 3);
+    // Range writes patch the backing capsule in place.
+    assert_eq!(info.capsule_id(), capsule_id);
 
     let listed = block.list_volumes();
     assert_eq!(listed.len(), 1);
@@ -97,3 +101,95 @@ fn block_persists_volumes_across_reopen() {
 
     teardown(prefix);
 }
+
+#[test]
+fn block_snapshots_and_clones_share_data() {
+    let prefix = "test_block_snapshot";
+    let block = setup(prefix);
+
+    block.create_volume("prod", 8192).unwrap();
+    block.write("prod", 0, &[0x11; 4096]).unwrap();
+
+    let snap = block.snapshot_volume("prod", "prod-snap").unwrap();
+    assert!(snap.is_read_only());
+    assert_eq!(snap.size(), 8192);
+    let dev = block.clone_volume("prod", "prod-dev").unwrap();
+    assert!(!dev.is_read_only());
+
+    block.write("prod", 0, &[0x22; 4096]).unwrap();
+    block.write("prod-dev", 4096, &[0x33; 4096]).unwrap();
+    assert!(block.write("prod-snap", 0, &[0x44; 16]).is_err());
+    assert!(block.clone_volume("prod", "prod-dev").is_err());
+
+    assert_eq!(block.read("prod-snap", 0, 4096).unwrap(), vec![0x11; 4096]);
+    assert_eq!(block.read("prod", 0, 4096).unwrap(), vec![0x22; 4096]);
+    let dev_data = block.read("prod-dev", 0, 8192).unwrap();
+    assert_eq!(&dev_data[..4096], &[0x11; 4096]);
+    assert_eq!(&dev_data[4096..], &[0x33; 4096]);
+
+    block.delete_volume("prod").unwrap();
+    assert_eq!(block.read("prod-snap", 0, 4096).unwrap(), vec![0x11; 4096]);
+
+    drop(block);
+    teardown(prefix);
+}
+
+#[test]
+fn block_volumes_are_thin_provisioned() {
+    let prefix = "test_block_thin";
+    let block = setup(prefix);
+
+    let tib = 1u64 << 40;
+    block.create_volume("huge", tib).unwrap();
+    assert_eq!(block.read("huge", tib - 4096, 4096).unwrap(), vec![0; 4096]);
+
+    block.write("huge", tib / 2, &[0x7E; 4096]).unwrap();
+    assert_eq!(block.read("huge", tib / 2, 4096).unwrap(), vec![0x7E; 4096]);
+    assert_eq!(block.read("huge", tib / 2 + 4096, 16).unwrap(), vec![0; 16]);
+
+    drop(block);
+    teardown(prefix);
+}
+
+#[test]
+fn block_concurrent_writes_fail_only_when_not_applied() {
+    let prefix = "test_block_concurrent";
+    teardown(prefix);
+    let registry = CapsuleRegistry::open(format!("{}.metadata", prefix)).unwrap();
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(format!("{}.nvram", prefix)).unwrap();
+    let block = BlockView::open(registry, nvram, format!("{}.block.json", prefix)).unwrap();
+    let block = std::sync::Arc::new(block);
+    let capsule_id = block.create_volume("vol0", 4 * 4096).unwrap().capsule_id();
+    let base_version = registry_view.lookup(capsule_id).unwrap().version;
+
+    let writers: Vec<_> = (0..4u64)
+        .map(|sector| {
+            let block = std::sync::Arc::clone(&block);
+            std::thread::spawn(move || {
+                for round in 1..=20u8 {
+                    // Callers retry failed writes, so one that was applied anyway
+                    // would be applied twice.
+                    while block.write("vol0", sector * 4096, &[round; 4096]).is_err() {}
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    assert_eq!(
+        block.read("vol0", 0, 4 * 4096).unwrap(),
+        vec![20u8; 4 * 4096]
+    );
+    let volume = block.volume("vol0").unwrap();
+    assert_eq!(volume.version(), 1 + 80);
+    assert_eq!(
+        registry_view.lookup(capsule_id).unwrap().version,
+        base_version + 80
+    );
+
+    drop(block);
+    teardown(prefix);
+}
@@ -10,11 +10,12 @@
 //!   * provide rich doc comments / inline rationale so that future protocol teams
 //!     understand the trade-offs made here.
 //!     The implementation is intentionally conservative: it serialises namespace
-//!     mutations through an `RwLock` and rewrites whole files on every modification.
+//!     mutations through an `RwLock` and rewrites whole files on `write_file`;
+//!     `write_range` patches only the segments a write touches.
 
 use anyhow::{anyhow, bail, Result};
 use capsule_registry::{pipeline::WritePipeline, CapsuleRegistry};
-use common::CapsuleId;
+use common::{CapsuleId, Policy};
 use nvram_sim::NvramLog;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
@@ -243,6 +244,21 @@ impl NfsView {
     /// immutable.  If a previous file existed, we ask the pipeline to delete the
     /// superseded capsule once the new data is durable.
     pub fn write_file(&self, path: &str, data: Vec<u8>) -> Result<CapsuleId> {
+        self.write_file_with_policy(path, data, &Policy::default())
+    }
+
+    /// Write a file at `path`, creating its capsule under `policy`.
+    ///
+    /// Overwriting a file whose capsule keeps version history replaces the
+    /// capsule in place instead, so earlier contents stay reachable through
+    /// [`read_file_at`](Self::read_file_at). The existing capsule's policy wins
+    /// in that case.
+    pub fn write_file_with_policy(
+        &self,
+        path: &str,
+        data: Vec<u8>,
+        policy: &Policy,
+    ) -> Result<CapsuleId> {
         let path_info = normalize_path(path)?;
         if path_info.is_root() {
             bail!("Cannot write file at root");
@@ -257,16 +273,40 @@ impl NfsView {
         let now = unix_timestamp();
 
         // Pre-flight check for directory collisions while avoiding holding the write lock.
-        {
+        let existing = {
             let nodes = self.nodes.read().unwrap();
-            if let Some(existing) = nodes.get(path_info.full()) {
-                if existing.is_directory() {
-                    bail!("Cannot overwrite directory with file");
-                }
+            match nodes.get(path_info.full()).map(|node| &node.kind) {
+                Some(NfsNodeKind::Directory) => bail!("Cannot overwrite directory with file"),
+                Some(NfsNodeKind::File { capsule_id, .. }) => Some(*capsule_id),
+                None => None,
+            }
+        };
+
+        let previous = match existing {
+            Some(capsule_id) => Some(self.pipeline.lookup_capsule(capsule_id)?),
+            None => None,
+        };
+        if let Some(capsule) = previous.as_ref() {
+            if capsule.policy.versioning.is_some() {
+                return self.overwrite_versioned(&path_info, capsule.id, &data);
             }
+            // The old capsule is dropped below, so it must be free to go.
+            self.pipeline
+                .registry()
+                .ensure_mutable(capsule.id, "overwrite")?;
         }
 
-        let capsule_id = self.pipeline.write_capsule(&data)?;
+        let capsule_id = self.pipeline.write_capsule_with_policy(&data, policy)?;
+        // Extended attributes and tags belong to the file, not its contents.
+        if let Some(previous) = previous {
+            let registry = self.pipeline.registry();
+            if !previous.metadata.is_empty() {
+                registry.update_metadata(capsule_id, |metadata| *metadata = previous.metadata)?;
+            }
+            if !previous.tags.is_empty() {
+                registry.update_tags(capsule_id, |tags| *tags = previous.tags)?;
+            }
+        }
         let mut nodes = self.nodes.write().unwrap();
         ensure_directory(&mut nodes, &parent_info, now)?;
 
@@ -310,6 +350,46 @@ impl NfsView {
         Ok(capsule_id)
     }
 
+    fn overwrite_versioned(
+        &self,
+        path_info: &NormalizedPath,
+        capsule_id: CapsuleId,
+        data: &[u8],
+    ) -> Result<CapsuleId> {
+        let capsule = self.pipeline.overwrite_capsule(capsule_id, data)?;
+        self.touch_file(path_info, capsule_id, capsule.size)?;
+        self.persist()?;
+        Ok(capsule_id)
+    }
+
+    /// Record a new size for the file at `path_info`, provided it still points
+    /// at `capsule_id`.
+    fn touch_file(
+        &self,
+        path_info: &NormalizedPath,
+        capsule_id: CapsuleId,
+        new_size: u64,
+    ) -> Result<()> {
+        let now = unix_timestamp();
+        let mut nodes = self.nodes.write().unwrap();
+        match nodes.get_mut(path_info.full()) {
+            Some(NfsNode {
+                kind:
+                    NfsNodeKind::File {
+                        capsule_id: current,
+                        size,
+                    },
+                modified_at,
+                ..
+            }) if *current == capsule_id => {
+                *size = new_size;
+                *modified_at = now;
+                Ok(())
+            }
+            _ => bail!("File modified concurrently: {}", path_info.full()),
+        }
+    }
+
     /// Read the full file contents for `path`.
     pub fn read_file(&self, path: &str) -> Result<Vec<u8>> {
         let path_info = normalize_path(path)?;
@@ -349,6 +429,160 @@ impl NfsView {
         }
     }
 
+    /// Overwrite `data` at `offset` within the file at `path`, extending it if
+    /// the write runs past the current end.
+    ///
+    /// Unlike [`write_file`](Self::write_file) the file keeps its capsule; the
+    /// pipeline rewrites just the overlapping segments.
+    ///
+    /// The modular pipeline cannot patch a capsule in place, so under it this
+    /// fails and files must be replaced whole with `write_file`.
+    pub fn write_range(&self, path: &str, offset: u64, data: &[u8]) -> Result<()> {
+        let path_info = normalize_path(path)?;
+        let capsule_id = {
+            let nodes = self.nodes.read().unwrap();
+            let node = nodes
+                .get(path_info.full())
+                .ok_or_else(|| anyhow!("No such file: {}", path_info.full()))?;
+            match node.kind {
+                NfsNodeKind::File { capsule_id, size } => {
+                    if offset > size {
+                        bail!("Write starts beyond end of file");
+                    }
+                    capsule_id
+                }
+                NfsNodeKind::Directory => bail!("Path is a directory: {}", node.path),
+            }
+        };
+
+        let capsule = self.pipeline.write_range(capsule_id, offset, data)?;
+        self.touch_file(&path_info, capsule_id, capsule.size)?;
+        self.persist()
+    }
+
+    /// Read the file at `path` as it stood at capsule `version`.
+    pub fn read_file_at(&self, path: &str, version: u64) -> Result<Vec<u8>> {
+        let capsule_id = self.file_capsule(path)?;
+        self.pipeline.read_capsule_at(capsule_id, version)
+    }
+
+    /// Capsule versions of the file at `path` that can still be read, oldest first.
+    pub fn file_versions(&self, path: &str) -> Result<Vec<u64>> {
+        let capsule_id = self.file_capsule(path)?;
+        self.pipeline.list_versions(capsule_id)
+    }
+
+    /// Set extended attribute `name` on the file at `path`.
+    pub fn set_xattr(&self, path: &str, name: &str, value: &str) -> Result<()> {
+        let capsule_id = self.file_capsule(path)?;
+        self.pipeline
+            .registry()
+            .update_metadata(capsule_id, |metadata| {
+                metadata.insert(name.to_string(), value.to_string());
+            })
+    }
+
+    pub fn get_xattr(&self, path: &str, name: &str) -> Result<Option<String>> {
+        let capsule_id = self.file_capsule(path)?;
+        let capsule = self.pipeline.lookup_capsule(capsule_id)?;
+        Ok(capsule.metadata.get(name).cloned())
+    }
+
+    /// All extended attributes of the file at `path`.
+    pub fn list_xattrs(&self, path: &str) -> Result<BTreeMap<String, String>> {
+        let capsule_id = self.file_capsule(path)?;
+        Ok(self.pipeline.lookup_capsule(capsule_id)?.metadata)
+    }
+
+    /// Remove extended attribute `name`, returning whether it was set.
+    pub fn remove_xattr(&self, path: &str, name: &str) -> Result<bool> {
+        let capsule_id = self.file_capsule(path)?;
+        let mut removed = false;
+        self.pipeline
+            .registry()
+            .update_metadata(capsule_id, |metadata| {
+                removed = metadata.remove(name).is_some();
+            })?;
+        Ok(removed)
+    }
+
+    /// Replace the tags on the file at `path`.
+    pub fn set_tags(&self, path: &str, tags: BTreeMap<String, String>) -> Result<()> {
+        let capsule_id = self.file_capsule(path)?;
+        self.pipeline
+            .registry()
+            .update_tags(capsule_id, |current| *current = tags)
+    }
+
+    pub fn tags(&self, path: &str) -> Result<BTreeMap<String, String>> {
+        let capsule_id = self.file_capsule(path)?;
+        Ok(self.pipeline.lookup_capsule(capsule_id)?.tags)
+    }
+
+    fn file_capsule(&self, path: &str) -> Result<CapsuleId> {
+        let path_info = normalize_path(path)?;
+        let nodes = self.nodes.read().unwrap();
+        let node = nodes
+            .get(path_info.full())
+            .ok_or_else(|| anyhow!("No such file: {}", path_info.full()))?;
+        match node.kind {
+            NfsNodeKind::File { capsule_id, .. } => Ok(capsule_id),
+            NfsNodeKind::Directory => bail!("Path is a directory: {}", node.path),
+        }
+    }
+
+    /// Copy the file at `source` to a new file at `dest` without copying data.
+    ///
+    /// The copy is a writable capsule clone: both files share segments until
+    /// one of them is overwritten.
+    pub fn clone_file(&self, source: &str, dest: &str) -> Result<CapsuleId> {
+        let source_info = normalize_path(source)?;
+        let dest_info = normalize_path(dest)?;
+        let parent_info = normalize_path(
+            &dest_info
+                .parent_path()
+                .ok_or_else(|| anyhow!("Cannot clone onto root"))?,
+        )?;
+        let file_name = dest_info
+            .name()
+            .ok_or_else(|| anyhow!("Invalid file path"))?
+            .to_string();
+
+        let (source_capsule, size) = {
+            let nodes = self.nodes.read().unwrap();
+            if nodes.contains_key(dest_info.full()) {
+                bail!("Path already exists: {}", dest_info.full());
+            }
+            let node = nodes
+                .get(source_info.full())
+                .ok_or_else(|| anyhow!("No such file: {}", source_info.full()))?;
+            match node.kind {
+                NfsNodeKind::File { capsule_id, size } => (capsule_id, size),
+                NfsNodeKind::Directory => bail!("Path is a directory: {}", node.path),
+            }
+        };
+
+        let capsule_id = self.pipeline.clone_capsule(source_capsule)?;
+        let now = unix_timestamp();
+
+        let mut nodes = self.nodes.write().unwrap();
+        let inserted = !nodes.contains_key(dest_info.full())
+            && ensure_directory(&mut nodes, &parent_info, now).is_ok();
+        if !inserted {
+            drop(nodes);
+            let _ = self.pipeline.delete_capsule(capsule_id);
+            bail!("Cannot create clone at {}", dest_info.full());
+        }
+        nodes.insert(
+            dest_info.full().to_string(),
+            NfsNode::file(dest_info.full(), &file_name, capsule_id, size, now),
+        );
+        drop(nodes);
+
+        self.persist()?;
+        Ok(capsule_id)
+    }
+
     /// Explicitly create a directory and its parents.
     pub fn mkdir(&self, path: &str) -> Result<()> {
         let path_info = normalize_path(path)?;
@@ -386,6 +620,9 @@ impl NfsView {
                 nodes.remove(path_info.full());
             }
             NfsNodeKind::File { capsule_id, .. } => {
+                self.pipeline
+                    .registry()
+                    .ensure_mutable(capsule_id, "delete")?;
                 nodes.remove(path_info.full());
                 removed_capsule = Some(capsule_id);
             }
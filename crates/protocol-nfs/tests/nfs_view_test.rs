@@ -1,12 +1,15 @@
 use capsule_registry::CapsuleRegistry;
+use common::{Policy, RetentionPolicy, VersioningPolicy};
 use nvram_sim::NvramLog;
 use protocol_nfs::NfsView;
+use std::collections::BTreeMap;
 use std::fs;
 
 fn teardown(prefix: &str) {
     let _ = fs::remove_file(format!("{}.nvram", prefix));
     let _ = fs::remove_file(format!("{}.nvram.segments", prefix));
     let _ = fs::remove_file(format!("{}.metadata", prefix));
+    let _ = fs::remove_dir_all(format!("{}.metadata.dedup", prefix));
     let _ = fs::remove_file(format!("{}.nfs.json", prefix));
 }
 
@@ -112,3 +115,143 @@ fn nfs_persists_namespace_state() {
 
     teardown(prefix);
 }
+
+#[test]
+fn nfs_write_range_patches_in_place() {
+    let prefix = "test_nfs_write_range";
+    let nfs = setup(prefix);
+
+    let capsule_id = nfs
+        .write_file("/notes.txt", b"hello, world".to_vec())
+        .unwrap();
+
+    nfs.write_range("/notes.txt", 7, b"there!").unwrap();
+    assert_eq!(nfs.read_file("/notes.txt").unwrap(), b"hello, there!");
+
+    let meta = nfs.metadata("/notes.txt").unwrap();
+    assert_eq!(meta.size(), 13);
+    assert_eq!(meta.capsule_id(), Some(capsule_id));
+
+    assert!(nfs.write_range("/notes.txt", 20, b"gap").is_err());
+    assert!(nfs.write_range("/", 0, b"dir").is_err());
+
+    drop(nfs);
+    teardown(prefix);
+}
+
+#[test]
+fn nfs_clone_file_is_independent() {
+    let prefix = "test_nfs_clone";
+    let nfs = setup(prefix);
+
+    let original = nfs
+        .write_file("/src/config.toml", b"mode = prod".to_vec())
+        .unwrap();
+    let copy = nfs
+        .clone_file("/src/config.toml", "/dev/config.toml")
+        .unwrap();
+    assert_ne!(original, copy);
+    assert_eq!(nfs.read_file("/dev/config.toml").unwrap(), b"mode = prod");
+
+    nfs.write_range("/dev/config.toml", 7, b"test").unwrap();
+    assert_eq!(nfs.read_file("/dev/config.toml").unwrap(), b"mode = test");
+    assert_eq!(nfs.read_file("/src/config.toml").unwrap(), b"mode = prod");
+
+    assert!(nfs
+        .clone_file("/src/config.toml", "/dev/config.toml")
+        .is_err());
+    assert!(nfs.clone_file("/src", "/dev/dir-copy").is_err());
+
+    drop(nfs);
+    teardown(prefix);
+}
+
+#[test]
+fn nfs_versioned_file_keeps_history() {
+    let prefix = "test_nfs_versions";
+    let nfs = setup(prefix);
+
+    let policy = Policy {
+        versioning: Some(VersioningPolicy::keep_last(2)),
+        ..Policy::default()
+    };
+    let capsule_id = nfs
+        .write_file_with_policy("/doc.txt", b"draft one".to_vec(), &policy)
+        .unwrap();
+    assert_eq!(
+        nfs.write_file("/doc.txt", b"draft two".to_vec()).unwrap(),
+        capsule_id
+    );
+    nfs.write_range("/doc.txt", 6, b"three").unwrap();
+
+    assert_eq!(nfs.read_file("/doc.txt").unwrap(), b"draft three");
+    assert_eq!(nfs.file_versions("/doc.txt").unwrap(), vec![1, 2, 3]);
+    assert_eq!(nfs.read_file_at("/doc.txt", 1).unwrap(), b"draft one");
+    assert_eq!(nfs.read_file_at("/doc.txt", 2).unwrap(), b"draft two");
+    assert_eq!(nfs.metadata("/doc.txt").unwrap().size(), 11);
+
+    // Only the two most recent superseded versions are kept.
+    nfs.write_file("/doc.txt", b"final".to_vec()).unwrap();
+    assert_eq!(nfs.file_versions("/doc.txt").unwrap(), vec![2, 3, 4]);
+    assert!(nfs.read_file_at("/doc.txt", 1).is_err());
+
+    drop(nfs);
+    teardown(prefix);
+}
+
+#[test]
+fn nfs_xattrs_follow_the_file() {
+    let prefix = "test_nfs_xattrs";
+    let nfs = setup(prefix);
+
+    nfs.write_file("/photo.jpg", b"pixels".to_vec()).unwrap();
+    nfs.set_xattr("/photo.jpg", "user.camera", "x100").unwrap();
+    nfs.set_xattr("/photo.jpg", "user.iso", "400").unwrap();
+    nfs.set_tags(
+        "/photo.jpg",
+        BTreeMap::from([("album".to_string(), "summer".to_string())]),
+    )
+    .unwrap();
+
+    assert_eq!(
+        nfs.get_xattr("/photo.jpg", "user.camera").unwrap(),
+        Some("x100".to_string())
+    );
+    assert!(nfs.remove_xattr("/photo.jpg", "user.iso").unwrap());
+    assert!(!nfs.remove_xattr("/photo.jpg", "user.iso").unwrap());
+
+    // Rewriting the contents keeps the file's attributes.
+    nfs.write_file("/photo.jpg", b"retouched".to_vec()).unwrap();
+    let xattrs = nfs.list_xattrs("/photo.jpg").unwrap();
+    assert_eq!(xattrs.len(), 1);
+    assert_eq!(xattrs["user.camera"], "x100");
+    assert_eq!(nfs.tags("/photo.jpg").unwrap()["album"], "summer");
+
+    assert!(nfs.set_xattr("/", "user.bad", "dir").is_err());
+
+    drop(nfs);
+    teardown(prefix);
+}
+
+#[test]
+fn nfs_retained_file_is_immutable() {
+    let prefix = "test_nfs_retained";
+    let nfs = setup(prefix);
+
+    let policy = Policy {
+        retention: Some(RetentionPolicy::for_secs(3_600)),
+        ..Policy::default()
+    };
+    nfs.write_file_with_policy("/audit/2026.csv", b"q1,q2,q3,q4".to_vec(), &policy)
+        .unwrap();
+
+    assert!(nfs
+        .write_file("/audit/2026.csv", b"rewritten".to_vec())
+        .is_err());
+    assert!(nfs.write_range("/audit/2026.csv", 0, b"xx").is_err());
+    assert!(nfs.delete("/audit/2026.csv").is_err());
+    assert_eq!(nfs.read_file("/audit/2026.csv").unwrap(), b"q1,q2,q3,q4");
+
+    drop(nfs);
+    teardown(prefix);
+}
@@ -1,15 +1,19 @@
 use axum::{
-    body::Bytes,
+    body::Body,
     extract::{Path, State},
-    http::StatusCode,
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
 use serde::Serialize;
+use std::collections::BTreeMap;
 use std::sync::Arc;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
-use crate::S3View;
+use crate::{KeyMapping, ObjectAttributes, S3View};
+use capsule_registry::RetentionError;
+
+const META_PREFIX: &str = "x-amz-meta-";
 
 pub type AppState = Arc<S3View>;
 
@@ -35,11 +39,23 @@ pub struct ListResponse {
 pub async fn put_object(
     State(s3): State<AppState>,
     Path((bucket, key)): Path<(String, String)>,
-    body: Bytes,
+    headers: HeaderMap,
+    body: Body,
 ) -> Response {
-    info!("PUT /{}/{} ({} bytes)", bucket, key, body.len());
+    info!("PUT /{}/{}", bucket, key);
+
+    let attributes = match object_attributes(&headers) {
+        Ok(attributes) => attributes,
+        Err(e) => {
+            error!("❌ PUT rejected: {}", e);
+            return (StatusCode::BAD_REQUEST, e).into_response();
+        }
+    };
 
-    match s3.put_object(&bucket, &key, body.to_vec()).await {
+    match s3
+        .put_object_stream_with_attributes(&bucket, &key, body.into_data_stream(), attributes)
+        .await
+    {
         Ok(capsule_id) => {
             info!(
                 "✅ Created capsule {} for {}/{}",
@@ -55,7 +71,11 @@ pub async fn put_object(
         }
         Err(e) => {
             error!("❌ PUT failed: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+            (
+                failure_status(&e, StatusCode::INTERNAL_SERVER_ERROR),
+                e.to_string(),
+            )
+                .into_response()
         }
     }
 }
@@ -67,17 +87,32 @@ pub async fn get_object(
 ) -> Response {
     info!("GET /{}/{}", bucket, key);
 
-    match s3.get_object(&bucket, &key).await {
-        Ok(data) => {
-            info!("✅ Retrieved {} bytes from {}/{}", data.len(), bucket, key);
-
-            // Get metadata for Content-Type
-            let content_type = s3
-                .head_object(&bucket, &key)
-                .map(|m| m.content_type)
-                .unwrap_or_else(|_| "application/octet-stream".to_string());
+    let mapping = match s3.head_object(&bucket, &key) {
+        Ok(mapping) => mapping,
+        Err(e) => {
+            error!("❌ GET failed: {}", e);
+            return (StatusCode::NOT_FOUND, e.to_string()).into_response();
+        }
+    };
 
-            (StatusCode::OK, [("Content-Type", content_type)], data).into_response()
+    match s3.get_object_stream(&bucket, &key).await {
+        Ok(stream) => {
+            info!(
+                "✅ Streaming {} bytes from {}/{}",
+                mapping.size(),
+                bucket,
+                key
+            );
+            (
+                StatusCode::OK,
+                [
+                    ("Content-Type", mapping.content_type().to_string()),
+                    ("Content-Length", mapping.size().to_string()),
+                ],
+                attribute_headers(&mapping),
+                Body::from_stream(stream),
+            )
+                .into_response()
         }
         Err(e) => {
             error!("❌ GET failed: {}", e);
@@ -104,6 +139,7 @@ pub async fn head_object(
                     ("ETag", format!("\"{}\"", mapping.capsule_id.as_uuid())),
                     ("Last-Modified", format_http_date(mapping.created_at)),
                 ],
+                attribute_headers(&mapping),
             )
                 .into_response()
         }
@@ -161,11 +197,20 @@ pub async fn delete_object(
         }
         Err(e) => {
             error!("❌ DELETE failed: {}", e);
-            (StatusCode::NOT_FOUND, e.to_string()).into_response()
+            (failure_status(&e, StatusCode::NOT_FOUND), e.to_string()).into_response()
         }
     }
 }
 
+/// Retention refusals are access denials; everything else keeps `fallback`.
+fn failure_status(err: &anyhow::Error, fallback: StatusCode) -> StatusCode {
+    if err.downcast_ref::<RetentionError>().is_some() {
+        StatusCode::FORBIDDEN
+    } else {
+        fallback
+    }
+}
+
 /// Health check endpoint
 pub async fn health_check() -> Response {
     Json(serde_json::json!({
@@ -175,6 +220,88 @@ pub async fn health_check() -> Response {
     .into_response()
 }
 
+/// Collect `x-amz-meta-*` headers and the `x-amz-tagging` set from a PUT.
+fn object_attributes(headers: &HeaderMap) -> Result<ObjectAttributes, String> {
+    let mut attributes = ObjectAttributes::default();
+    for (name, value) in headers {
+        let Some(key) = name.as_str().strip_prefix(META_PREFIX) else {
+            continue;
+        };
+        let value = value
+            .to_str()
+            .map_err(|_| format!("metadata header {} is not valid text", name))?;
+        attributes
+            .metadata
+            .insert(key.to_string(), value.to_string());
+    }
+    if let Some(tagging) = headers.get("x-amz-tagging") {
+        let tagging = tagging
+            .to_str()
+            .map_err(|_| "x-amz-tagging is not valid text".to_string())?;
+        attributes.tags = parse_tagging(tagging)?;
+    }
+    Ok(attributes)
+}
+
+/// Parse an `x-amz-tagging` value, a URL-encoded `key=value&...` list.
+fn parse_tagging(value: &str) -> Result<BTreeMap<String, String>, String> {
+    value
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let key = percent_decode(key)?;
+            if key.is_empty() {
+                return Err("empty tag key".to_string());
+            }
+            Ok((key, percent_decode(value)?))
+        })
+        .collect()
+}
+
+fn percent_decode(input: &str) -> Result<String, String> {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' => {
+                let byte = input
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| format!("bad percent-escape in {:?}", input))?;
+                out.push(byte);
+                i += 2;
+            }
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8(out).map_err(|_| format!("tag {:?} is not valid UTF-8", input))
+}
+
+/// Response headers echoing an object's metadata and tag count.
+fn attribute_headers(mapping: &KeyMapping) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for (key, value) in mapping.metadata() {
+        let name = HeaderName::try_from(format!("{}{}", META_PREFIX, key));
+        match (name, HeaderValue::from_str(value)) {
+            (Ok(name), Ok(value)) => {
+                headers.insert(name, value);
+            }
+            _ => warn!("skipping unrepresentable metadata header {}", key),
+        }
+    }
+    if !mapping.tags().is_empty() {
+        headers.insert(
+            HeaderName::from_static("x-amz-tagging-count"),
+            HeaderValue::from(mapping.tags().len()),
+        );
+    }
+    headers
+}
+
 /// Format Unix timestamp as HTTP date
 fn format_http_date(timestamp: u64) -> String {
     use std::time::{Duration, UNIX_EPOCH};
@@ -1,20 +1,34 @@
 use anyhow::Result;
+use bytes::Bytes;
 #[cfg(feature = "modular_pipeline")]
 use capsule_registry::modular_pipeline::RegistryPipelineHandle;
+#[cfg(feature = "modular_pipeline")]
+use capsule_registry::stream::MODULAR_BUFFER_LIMIT;
 use capsule_registry::{pipeline::WritePipeline, CapsuleRegistry};
-use common::CapsuleId;
 #[cfg(feature = "modular_pipeline")]
-use common::Policy;
+use common::SEGMENT_SIZE;
+use common::{CapsuleId, Policy};
+use futures::stream::{self, BoxStream, Stream, StreamExt};
 use nvram_sim::NvramLog;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::{Arc, RwLock};
+use tokio::sync::mpsc;
 #[cfg(feature = "modular_pipeline")]
 use tokio::sync::Mutex as TokioMutex;
 use tokio::task;
+use tracing::warn;
 
 pub mod handlers;
 pub mod server;
 
+/// User metadata and tags of an object, stored on its capsule.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ObjectAttributes {
+    /// `x-amz-meta-*` values keyed without the prefix.
+    pub metadata: BTreeMap<String, String>,
+    pub tags: BTreeMap<String, String>,
+}
+
 /// Maps S3 keys to Capsule IDs
 #[derive(Debug, Clone)]
 pub struct KeyMapping {
@@ -23,6 +37,7 @@ pub struct KeyMapping {
     size: u64,
     created_at: u64,
     content_type: String,
+    attributes: ObjectAttributes,
 }
 
 impl KeyMapping {
@@ -45,6 +60,14 @@ impl KeyMapping {
     pub fn content_type(&self) -> &str {
         &self.content_type
     }
+
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.attributes.metadata
+    }
+
+    pub fn tags(&self) -> &BTreeMap<String, String> {
+        &self.attributes.tags
+    }
 }
 
 /// S3 Protocol View - provides S3-compatible access to capsules
@@ -56,59 +79,338 @@ enum PipelineBackend {
 
 pub struct S3View {
     pipeline: PipelineBackend,
+    // Shared with the pipeline; checked synchronously before keys are dropped.
+    registry: CapsuleRegistry,
     // Maps "bucket/key" -> CapsuleId
     key_map: Arc<RwLock<HashMap<String, KeyMapping>>>,
+    // Policy of the capsules new keys are stored in.
+    policy: Policy,
 }
 
 impl S3View {
     pub fn new(registry: CapsuleRegistry, nvram: NvramLog) -> Self {
+        let pipeline = WritePipeline::new(registry, nvram);
         Self {
-            pipeline: PipelineBackend::Legacy(Arc::new(WritePipeline::new(registry, nvram))),
+            registry: pipeline.registry().clone(),
+            pipeline: PipelineBackend::Legacy(Arc::new(pipeline)),
             key_map: Arc::new(RwLock::new(HashMap::new())),
+            policy: Policy::default(),
         }
     }
 
     #[cfg(feature = "modular_pipeline")]
     pub fn new_modular(handle: RegistryPipelineHandle) -> Self {
         Self {
+            registry: handle.registry().clone(),
             pipeline: PipelineBackend::Modular(Arc::new(TokioMutex::new(handle))),
             key_map: Arc::new(RwLock::new(HashMap::new())),
+            policy: Policy::default(),
         }
     }
 
+    /// Store objects under new keys with `policy`, e.g. one with versioning so
+    /// that a re-PUT keeps the contents it replaces readable.
+    pub fn with_policy(mut self, policy: Policy) -> Self {
+        self.policy = policy;
+        self
+    }
+
     /// PUT object - create new capsule from data
     pub async fn put_object(&self, bucket: &str, key: &str, data: Vec<u8>) -> Result<CapsuleId> {
+        self.put_object_with_attributes(bucket, key, data, ObjectAttributes::default())
+            .await
+    }
+
+    /// PUT object carrying user metadata and tags.
+    ///
+    /// Re-putting a key writes the next version of its capsule, so under a
+    /// versioning policy the previous contents stay readable through
+    /// [`get_object_version`](Self::get_object_version). The modular pipeline
+    /// keeps no history; there a re-PUT stores a new capsule and deletes the
+    /// old one.
+    pub async fn put_object_with_attributes(
+        &self,
+        bucket: &str,
+        key: &str,
+        data: Vec<u8>,
+        attributes: ObjectAttributes,
+    ) -> Result<CapsuleId> {
+        self.ensure_replaceable(bucket, key, "overwrite")?;
+        let existing = self.mapped_capsule(bucket, key);
         let data_len = data.len();
         let capsule_id = match &self.pipeline {
             PipelineBackend::Legacy(pipeline) => {
                 let pipeline = Arc::clone(pipeline);
-                let payload = data.clone();
-                task::spawn_blocking(move || pipeline.write_capsule(&payload))
+                let policy = self.policy.clone();
+                task::spawn_blocking(move || match existing {
+                    Some(capsule_id) => pipeline
+                        .overwrite_capsule(capsule_id, &data)
+                        .map(|capsule| capsule.id),
+                    None => pipeline.write_capsule_with_policy(&data, &policy),
+                })
+                .await
+                .map_err(|err| anyhow::anyhow!(err.to_string()))??
+            }
+            #[cfg(feature = "modular_pipeline")]
+            PipelineBackend::Modular(pipeline) => {
+                let mut handle = pipeline.lock().await;
+                handle.write_capsule(&data, &self.policy).await?
+            }
+        };
+
+        self.publish(
+            bucket,
+            key,
+            capsule_id,
+            data_len as u64,
+            attributes,
+            existing,
+        )
+        .await?;
+        Ok(capsule_id)
+    }
+
+    /// PUT object from a stream of body chunks without buffering the whole object.
+    ///
+    /// The modular pipeline only writes whole capsules, so there the body is
+    /// buffered and refused once it grows past `MODULAR_BUFFER_LIMIT`.
+    pub async fn put_object_stream<S, E>(
+        &self,
+        bucket: &str,
+        key: &str,
+        body: S,
+    ) -> Result<CapsuleId>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Unpin,
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        self.put_object_stream_with_attributes(bucket, key, body, ObjectAttributes::default())
+            .await
+    }
+
+    /// Streaming PUT carrying user metadata and tags.
+    pub async fn put_object_stream_with_attributes<S, E>(
+        &self,
+        bucket: &str,
+        key: &str,
+        mut body: S,
+        attributes: ObjectAttributes,
+    ) -> Result<CapsuleId>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Unpin,
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        self.ensure_replaceable(bucket, key, "overwrite")?;
+        let existing = self.mapped_capsule(bucket, key);
+        let (capsule_id, size) = match &self.pipeline {
+            PipelineBackend::Legacy(pipeline) => {
+                let pipeline = Arc::clone(pipeline);
+                let policy = self.policy.clone();
+                let (tx, mut rx) = mpsc::channel::<Result<Bytes>>(4);
+                let writer = task::spawn_blocking(move || -> Result<(CapsuleId, u64)> {
+                    let mut writer = match existing {
+                        Some(capsule_id) => pipeline.capsule_overwriter(capsule_id)?,
+                        None => pipeline.capsule_writer(&policy)?,
+                    };
+                    while let Some(chunk) = rx.blocking_recv() {
+                        writer.write_chunk(&chunk?)?;
+                    }
+                    let size = writer.bytes_written();
+                    Ok((writer.finish()?, size))
+                });
+
+                while let Some(chunk) = body.next().await {
+                    let failed = chunk.is_err();
+                    // A closed channel means the writer already failed; its error is reported below.
+                    if tx.send(chunk.map_err(anyhow::Error::from)).await.is_err() || failed {
+                        break;
+                    }
+                }
+                drop(tx);
+
+                writer
                     .await
                     .map_err(|err| anyhow::anyhow!(err.to_string()))??
             }
             #[cfg(feature = "modular_pipeline")]
             PipelineBackend::Modular(pipeline) => {
+                // The modular pipeline only takes whole capsules, so the body is
+                // buffered up to a limit rather than without bound.
+                let mut data = Vec::new();
+                while let Some(chunk) = body.next().await {
+                    let chunk = chunk?;
+                    if data.len() + chunk.len() > MODULAR_BUFFER_LIMIT {
+                        anyhow::bail!(
+                            "object exceeds the {} byte streaming limit of the modular pipeline",
+                            MODULAR_BUFFER_LIMIT
+                        );
+                    }
+                    data.extend_from_slice(&chunk);
+                }
                 let mut handle = pipeline.lock().await;
-                handle.write_capsule(&data, &Policy::default()).await?
+                let capsule_id = handle.write_capsule(&data, &self.policy).await?;
+                (capsule_id, data.len() as u64)
             }
         };
 
+        self.publish(bucket, key, capsule_id, size, attributes, existing)
+            .await?;
+        Ok(capsule_id)
+    }
+
+    /// Point `bucket/key` at the freshly written `capsule_id` and delete any
+    /// other capsule the key held, which a concurrent PUT or the modular
+    /// pipeline may have left behind.
+    async fn publish(
+        &self,
+        bucket: &str,
+        key: &str,
+        capsule_id: CapsuleId,
+        size: u64,
+        attributes: ObjectAttributes,
+        existing: Option<CapsuleId>,
+    ) -> Result<()> {
+        // Fresh capsules start without attributes, so plain PUTs skip the update;
+        // a new version still carries the old ones and must be cleared.
+        if existing == Some(capsule_id) || attributes != ObjectAttributes::default() {
+            self.store_attributes(capsule_id, &attributes).await?;
+        }
+        let displaced = self.record_mapping(bucket, key, capsule_id, size, attributes)?;
+
+        if let Some(old) = displaced.filter(|old| old.capsule_id != capsule_id) {
+            let deleted = match &self.pipeline {
+                PipelineBackend::Legacy(pipeline) => {
+                    let pipeline = Arc::clone(pipeline);
+                    task::spawn_blocking(move || pipeline.delete_capsule(old.capsule_id))
+                        .await
+                        .map_err(|err| anyhow::anyhow!(err.to_string()))
+                        .and_then(|deleted| deleted)
+                }
+                #[cfg(feature = "modular_pipeline")]
+                PipelineBackend::Modular(pipeline) => {
+                    pipeline.lock().await.delete_capsule(old.capsule_id).await
+                }
+            };
+            if let Err(err) = deleted {
+                warn!(key = %old.key, error = %err, "failed to delete replaced object capsule");
+            }
+        }
+        Ok(())
+    }
+
+    /// GET an earlier version of an object, as listed by
+    /// [`list_object_versions`](Self::list_object_versions).
+    pub async fn get_object_version(
+        &self,
+        bucket: &str,
+        key: &str,
+        version: u64,
+    ) -> Result<Vec<u8>> {
+        let mapping = self.head_object(bucket, key)?;
+        match &self.pipeline {
+            PipelineBackend::Legacy(pipeline) => {
+                let pipeline = Arc::clone(pipeline);
+                task::spawn_blocking(move || pipeline.read_capsule_at(mapping.capsule_id, version))
+                    .await
+                    .map_err(|err| anyhow::anyhow!(err.to_string()))?
+            }
+            #[cfg(feature = "modular_pipeline")]
+            PipelineBackend::Modular(pipeline) => {
+                // Nothing is retained under the modular pipeline, so only the live version reads.
+                self.registry.capsule_at(mapping.capsule_id, version)?;
+                let handle = pipeline.lock().await;
+                handle.read_capsule(mapping.capsule_id).await
+            }
+        }
+    }
+
+    /// Versions of an object that can be read, oldest first.
+    pub fn list_object_versions(&self, bucket: &str, key: &str) -> Result<Vec<u64>> {
+        let mapping = self.head_object(bucket, key)?;
+        self.registry.list_versions(mapping.capsule_id)
+    }
+
+    /// Replace the tags of an existing object.
+    pub async fn put_object_tagging(
+        &self,
+        bucket: &str,
+        key: &str,
+        tags: BTreeMap<String, String>,
+    ) -> Result<()> {
+        let mapping = self.head_object(bucket, key)?;
+        let attributes = ObjectAttributes {
+            metadata: mapping.attributes.metadata,
+            tags,
+        };
+        self.store_attributes(mapping.capsule_id, &attributes)
+            .await?;
+
+        if let Some(current) = self.key_map.write().unwrap().get_mut(&mapping.key) {
+            if current.capsule_id == mapping.capsule_id {
+                current.attributes = attributes;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write `attributes` onto the capsule record so every view sees them.
+    async fn store_attributes(
+        &self,
+        capsule_id: CapsuleId,
+        attributes: &ObjectAttributes,
+    ) -> Result<()> {
+        let store = |registry: &CapsuleRegistry| -> Result<()> {
+            registry.update_metadata(capsule_id, |metadata| {
+                metadata.clone_from(&attributes.metadata)
+            })?;
+            registry.update_tags(capsule_id, |tags| tags.clone_from(&attributes.tags))
+        };
+        match &self.pipeline {
+            PipelineBackend::Legacy(pipeline) => store(pipeline.registry()),
+            #[cfg(feature = "modular_pipeline")]
+            PipelineBackend::Modular(pipeline) => store(pipeline.lock().await.registry()),
+        }
+    }
+
+    /// Fail if `bucket/key` maps to a capsule under retention or legal hold.
+    fn ensure_replaceable(&self, bucket: &str, key: &str, action: &'static str) -> Result<()> {
+        match self.mapped_capsule(bucket, key) {
+            Some(capsule_id) => self.registry.ensure_mutable(capsule_id, action),
+            None => Ok(()),
+        }
+    }
+
+    fn mapped_capsule(&self, bucket: &str, key: &str) -> Option<CapsuleId> {
+        let full_key = format!("{}/{}", bucket, key);
+        self.key_map
+            .read()
+            .unwrap()
+            .get(&full_key)
+            .map(|mapping| mapping.capsule_id)
+    }
+
+    fn record_mapping(
+        &self,
+        bucket: &str,
+        key: &str,
+        capsule_id: CapsuleId,
+        size: u64,
+        attributes: ObjectAttributes,
+    ) -> Result<Option<KeyMapping>> {
         // Map S3 key to capsule
         let full_key = format!("{}/{}", bucket, key);
         let mapping = KeyMapping {
             key: full_key.clone(),
             capsule_id,
-            size: data_len as u64,
+            size,
             created_at: std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)?
                 .as_secs(),
             content_type: detect_content_type(key),
+            attributes,
         };
 
-        self.key_map.write().unwrap().insert(full_key, mapping);
-
-        Ok(capsule_id)
+        Ok(self.key_map.write().unwrap().insert(full_key, mapping))
     }
 
     /// GET object - read capsule data
@@ -138,6 +440,61 @@ impl S3View {
         }
     }
 
+    /// GET object as a stream of decoded segments.
+    pub async fn get_object_stream(
+        &self,
+        bucket: &str,
+        key: &str,
+    ) -> Result<BoxStream<'static, Result<Bytes>>> {
+        let mapping = self.head_object(bucket, key)?;
+
+        match &self.pipeline {
+            PipelineBackend::Legacy(pipeline) => {
+                let pipeline = Arc::clone(pipeline);
+                let (tx, rx) = mpsc::channel::<Result<Bytes>>(2);
+                task::spawn_blocking(move || {
+                    let reader = match pipeline.capsule_reader(mapping.capsule_id) {
+                        Ok(reader) => reader,
+                        Err(err) => {
+                            let _ = tx.blocking_send(Err(err));
+                            return;
+                        }
+                    };
+                    for chunk in reader {
+                        // Stop reading once the client has gone away.
+                        if tx.blocking_send(chunk).is_err() {
+                            break;
+                        }
+                    }
+                });
+
+                Ok(stream::unfold(rx, |mut rx| async move {
+                    rx.recv().await.map(|chunk| (chunk, rx))
+                })
+                .boxed())
+            }
+            #[cfg(feature = "modular_pipeline")]
+            PipelineBackend::Modular(pipeline) => {
+                // Read a segment's worth at a time, releasing the pipeline between reads.
+                let pipeline = Arc::clone(pipeline);
+                let (capsule_id, size) = (mapping.capsule_id, mapping.size);
+                Ok(stream::try_unfold(0u64, move |offset| {
+                    let pipeline = Arc::clone(&pipeline);
+                    async move {
+                        if offset >= size {
+                            return Ok(None);
+                        }
+                        let len = (size - offset).min(SEGMENT_SIZE as u64) as usize;
+                        let handle = pipeline.lock().await;
+                        let data = handle.read_range(capsule_id, offset, len).await?;
+                        Ok(Some((Bytes::from(data), offset + len as u64)))
+                    }
+                })
+                .boxed())
+            }
+        }
+    }
+
     /// HEAD object - get metadata without reading data
     pub fn head_object(&self, bucket: &str, key: &str) -> Result<KeyMapping> {
         let full_key = format!("{}/{}", bucket, key);
@@ -168,11 +525,12 @@ impl S3View {
     pub fn delete_object(&self, bucket: &str, key: &str) -> Result<()> {
         let full_key = format!("{}/{}", bucket, key);
 
-        self.key_map
-            .write()
-            .unwrap()
-            .remove(&full_key)
+        let mut key_map = self.key_map.write().unwrap();
+        let mapping = key_map
+            .get(&full_key)
             .ok_or_else(|| anyhow::anyhow!("Key not found: {}", full_key))?;
+        self.registry.ensure_mutable(mapping.capsule_id, "delete")?;
+        key_map.remove(&full_key);
 
         // Note: We're not deleting the capsule itself yet - that's for Phase 3
         // For now, capsules are only deleted when explicitly removed via spacectl
@@ -1,6 +1,8 @@
-use capsule_registry::CapsuleRegistry;
+use capsule_registry::{CapsuleQuery, CapsuleRegistry, RetentionError};
+use common::{Policy, VersioningPolicy};
 use nvram_sim::NvramLog;
-use protocol_s3::S3View;
+use protocol_s3::{ObjectAttributes, S3View};
+use std::collections::BTreeMap;
 use std::fs;
 use std::sync::Once;
 
@@ -20,6 +22,7 @@ async fn test_s3_put_and_get() {
     let _ = fs::remove_file(log_path);
     let _ = fs::remove_file(format!("{}.segments", log_path));
     let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
 
     let registry = CapsuleRegistry::open(meta_path).unwrap();
     let nvram = NvramLog::open(log_path).unwrap();
@@ -63,6 +66,7 @@ async fn test_s3_put_and_get() {
     fs::remove_file(log_path).unwrap();
     fs::remove_file(format!("{}.segments", log_path)).unwrap();
     fs::remove_file(meta_path).unwrap();
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
 
     println!("\n🎉 All S3 view tests passed!");
 }
@@ -75,6 +79,7 @@ async fn test_s3_multiple_objects() {
     let _ = fs::remove_file(log_path);
     let _ = fs::remove_file(format!("{}.segments", log_path));
     let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
 
     let registry = CapsuleRegistry::open(meta_path).unwrap();
     let nvram = NvramLog::open(log_path).unwrap();
@@ -109,6 +114,7 @@ async fn test_s3_multiple_objects() {
     fs::remove_file(log_path).unwrap();
     fs::remove_file(format!("{}.segments", log_path)).unwrap();
     fs::remove_file(meta_path).unwrap();
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
 
     println!("🎉 Multi-object test passed!");
 }
@@ -121,6 +127,7 @@ async fn test_s3_large_object() {
     let _ = fs::remove_file(log_path);
     let _ = fs::remove_file(format!("{}.segments", log_path));
     let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
 
     let registry = CapsuleRegistry::open(meta_path).unwrap();
     let nvram = NvramLog::open(log_path).unwrap();
@@ -145,6 +152,292 @@ async fn test_s3_large_object() {
     fs::remove_file(log_path).unwrap();
     fs::remove_file(format!("{}.segments", log_path)).unwrap();
     fs::remove_file(meta_path).unwrap();
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
 
     println!("🎉 Large object test passed!");
 }
+
+#[tokio::test]
+async fn test_s3_streaming_put_and_get() {
+    use bytes::Bytes;
+    use futures::{stream, StreamExt};
+
+    init_native_pipeline();
+    let log_path = "test_s3_stream.nvram";
+    let meta_path = "test_s3_stream.metadata";
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
+
+    let registry = CapsuleRegistry::open(meta_path).unwrap();
+    let nvram = NvramLog::open(log_path).unwrap();
+    let s3 = S3View::new(registry, nvram);
+
+    let large_data: Vec<u8> = (0..10_000_000).map(|i| (i % 251) as u8).collect();
+    let body = stream::iter(
+        large_data
+            .chunks(64 * 1024)
+            .map(|chunk| Ok::<_, std::io::Error>(Bytes::copy_from_slice(chunk)))
+            .collect::<Vec<_>>(),
+    );
+
+    s3.put_object_stream("test", "streamed.bin", body)
+        .await
+        .unwrap();
+    assert_eq!(
+        s3.head_object("test", "streamed.bin").unwrap().size(),
+        large_data.len() as u64
+    );
+
+    // Each 4MB segment arrives as its own chunk.
+    let chunks: Vec<Bytes> = s3
+        .get_object_stream("test", "streamed.bin")
+        .await
+        .unwrap()
+        .map(|chunk| chunk.unwrap())
+        .collect()
+        .await;
+    assert_eq!(chunks.len(), 3);
+    assert_eq!(chunks.concat(), large_data);
+
+    // A failing body must not publish a truncated object.
+    let broken = stream::iter(vec![
+        Ok(Bytes::from_static(b"partial")),
+        Err(std::io::Error::other("client disconnected")),
+    ]);
+    assert!(s3
+        .put_object_stream("test", "broken.bin", broken)
+        .await
+        .is_err());
+    assert!(s3.head_object("test", "broken.bin").is_err());
+
+    fs::remove_file(log_path).unwrap();
+    fs::remove_file(format!("{}.segments", log_path)).unwrap();
+    fs::remove_file(meta_path).unwrap();
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
+}
+
+#[tokio::test]
+async fn test_s3_object_metadata_and_tags() {
+    init_native_pipeline();
+    let log_path = "test_s3_attrs.nvram";
+    let meta_path = "test_s3_attrs.metadata";
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
+
+    let registry = CapsuleRegistry::open(meta_path).unwrap();
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path).unwrap();
+    let s3 = S3View::new(registry, nvram);
+
+    let attributes = ObjectAttributes {
+        metadata: BTreeMap::from([("owner".to_string(), "alice".to_string())]),
+        tags: BTreeMap::from([("project".to_string(), "apollo".to_string())]),
+    };
+    let capsule_id = s3
+        .put_object_with_attributes("docs", "plan.txt", b"launch plan".to_vec(), attributes)
+        .await
+        .unwrap();
+
+    let mapping = s3.head_object("docs", "plan.txt").unwrap();
+    assert_eq!(mapping.metadata()["owner"], "alice");
+    assert_eq!(mapping.tags()["project"], "apollo");
+
+    // Attributes live on the capsule, where the registry index can find them.
+    let capsule = registry_view.lookup(capsule_id).unwrap();
+    assert_eq!(capsule.metadata["owner"], "alice");
+    assert_eq!(
+        registry_view.find_capsules(&CapsuleQuery::default().tag_value("project", "apollo")),
+        vec![capsule_id]
+    );
+
+    s3.put_object_tagging(
+        "docs",
+        "plan.txt",
+        BTreeMap::from([("project".to_string(), "gemini".to_string())]),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        s3.head_object("docs", "plan.txt").unwrap().tags()["project"],
+        "gemini"
+    );
+    assert!(registry_view
+        .find_capsules(&CapsuleQuery::default().tag_value("project", "apollo"))
+        .is_empty());
+    assert_eq!(
+        registry_view.lookup(capsule_id).unwrap().metadata["owner"],
+        "alice"
+    );
+
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
+}
+
+#[tokio::test]
+async fn test_s3_held_object_cannot_be_replaced_or_deleted() {
+    init_native_pipeline();
+    let log_path = "test_s3_hold.nvram";
+    let meta_path = "test_s3_hold.metadata";
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
+
+    let registry = CapsuleRegistry::open(meta_path).unwrap();
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path).unwrap();
+    let s3 = S3View::new(registry, nvram);
+
+    let capsule_id = s3
+        .put_object("records", "ledger.txt", b"fiscal year ledger".to_vec())
+        .await
+        .unwrap();
+    registry_view.set_legal_hold(capsule_id, true).unwrap();
+
+    let err = s3
+        .put_object("records", "ledger.txt", b"rewritten".to_vec())
+        .await
+        .unwrap_err();
+    assert!(err.downcast_ref::<RetentionError>().is_some());
+    assert!(s3.delete_object("records", "ledger.txt").is_err());
+    assert_eq!(
+        s3.head_object("records", "ledger.txt")
+            .unwrap()
+            .capsule_id(),
+        capsule_id
+    );
+
+    registry_view.set_legal_hold(capsule_id, false).unwrap();
+    s3.delete_object("records", "ledger.txt").unwrap();
+    assert!(s3.head_object("records", "ledger.txt").is_err());
+
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
+}
+
+#[tokio::test]
+async fn test_s3_reput_keeps_earlier_versions_readable() {
+    use bytes::Bytes;
+    use futures::stream;
+
+    init_native_pipeline();
+    let log_path = "test_s3_versions.nvram";
+    let meta_path = "test_s3_versions.metadata";
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
+
+    let registry = CapsuleRegistry::open(meta_path).unwrap();
+    let registry_view = registry.clone();
+    let nvram = NvramLog::open(log_path).unwrap();
+    let policy = Policy {
+        versioning: Some(VersioningPolicy::keep_last(4)),
+        ..Policy::default()
+    };
+    let s3 = S3View::new(registry, nvram).with_policy(policy);
+
+    let attributes = ObjectAttributes {
+        metadata: BTreeMap::from([("draft".to_string(), "1".to_string())]),
+        tags: BTreeMap::new(),
+    };
+    let first = s3
+        .put_object_with_attributes("docs", "notes.txt", b"first draft".to_vec(), attributes)
+        .await
+        .unwrap();
+    let second = s3
+        .put_object("docs", "notes.txt", b"second draft".to_vec())
+        .await
+        .unwrap();
+    assert_eq!(second, first);
+    assert_eq!(registry_view.list_capsules(), vec![first]);
+    assert!(s3
+        .head_object("docs", "notes.txt")
+        .unwrap()
+        .metadata()
+        .is_empty());
+
+    let body = stream::iter(vec![Ok::<_, std::io::Error>(Bytes::from_static(
+        b"third draft",
+    ))]);
+    s3.put_object_stream("docs", "notes.txt", body)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        s3.list_object_versions("docs", "notes.txt").unwrap(),
+        vec![1, 2, 3]
+    );
+    assert_eq!(
+        s3.get_object_version("docs", "notes.txt", 1).await.unwrap(),
+        b"first draft"
+    );
+    assert_eq!(
+        s3.get_object_version("docs", "notes.txt", 2).await.unwrap(),
+        b"second draft"
+    );
+    assert_eq!(
+        s3.get_object("docs", "notes.txt").await.unwrap(),
+        b"third draft"
+    );
+    assert_eq!(s3.head_object("docs", "notes.txt").unwrap().size(), 11);
+
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
+}
+
+#[cfg(feature = "modular_pipeline")]
+#[tokio::test]
+async fn test_s3_modular_streams_segment_by_segment() {
+    use bytes::Bytes;
+    use capsule_registry::modular_pipeline::registry_pipeline_from_log;
+    use futures::{stream, StreamExt};
+
+    let log_path = "test_s3_modular_stream.nvram";
+    let meta_path = "test_s3_modular_stream.metadata";
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
+
+    let registry = CapsuleRegistry::open(meta_path).unwrap();
+    let nvram = NvramLog::open(log_path).unwrap();
+    let s3 = S3View::new_modular(registry_pipeline_from_log(nvram, registry).unwrap());
+
+    let large_data: Vec<u8> = (0..10_000_000).map(|i| (i % 253) as u8).collect();
+    let body = stream::iter(
+        large_data
+            .chunks(64 * 1024)
+            .map(|chunk| Ok::<_, std::io::Error>(Bytes::copy_from_slice(chunk)))
+            .collect::<Vec<_>>(),
+    );
+    s3.put_object_stream("test", "streamed.bin", body)
+        .await
+        .unwrap();
+
+    // Reads are ranged, so the object never has to fit in memory at once.
+    let chunks: Vec<Bytes> = s3
+        .get_object_stream("test", "streamed.bin")
+        .await
+        .unwrap()
+        .map(|chunk| chunk.unwrap())
+        .collect()
+        .await;
+    assert_eq!(chunks.len(), 3);
+    assert_eq!(chunks.concat(), large_data);
+
+    let _ = fs::remove_file(log_path);
+    let _ = fs::remove_file(format!("{}.segments", log_path));
+    let _ = fs::remove_file(meta_path);
+    let _ = fs::remove_dir_all(format!("{}.dedup", meta_path));
+}
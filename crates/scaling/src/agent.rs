@@ -74,7 +74,7 @@ impl ScalingAgent {
     async fn handle_telemetry_event(&self, event: Telemetry) -> Result<()> {
         // Extract policy from event (use default if not specified)
         let policy = match &event {
-            Telemetry::NewCapsule { policy, .. } => policy.clone(),
+            Telemetry::NewCapsule { policy, .. } => policy.as_ref().clone(),
             _ => Policy::metro_sync(), // Default for non-capsule events
         };
 
@@ -360,7 +360,7 @@ mod tests {
         let policy = Policy::default();
         tx.send(Telemetry::NewCapsule {
             id: capsule_id,
-            policy,
+            policy: Box::new(policy),
             node_id: None,
         })
         .unwrap();
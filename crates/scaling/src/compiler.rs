@@ -560,11 +560,7 @@ impl MeshState {
             .find(|(id, _)| *id == node_id)
             .map(|(_, info)| {
                 let total = info.available_bytes + info.used_bytes;
-                if total == 0 {
-                    0
-                } else {
-                    (info.used_bytes * 100) / total
-                }
+                (info.used_bytes * 100).checked_div(total).unwrap_or(0)
             })
             .unwrap_or(100) // Treat unknown nodes as fully utilized
     }
@@ -621,7 +617,7 @@ mod tests {
         let capsule_id = CapsuleId::new();
         let event = Telemetry::NewCapsule {
             id: capsule_id,
-            policy: policy.clone(),
+            policy: Box::new(policy.clone()),
             node_id: None,
         };
 
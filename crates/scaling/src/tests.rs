@@ -124,7 +124,7 @@ mod agent_tests {
         let policy = Policy::metro_sync();
         tx.send(Telemetry::NewCapsule {
             id: capsule_id,
-            policy: policy.clone(),
+            policy: Box::new(policy.clone()),
             node_id: None,
         })
         .unwrap();
@@ -3,19 +3,22 @@ use anyhow::anyhow;
 use anyhow::Result;
 #[cfg(feature = "modular_pipeline")]
 use capsule_registry::modular_pipeline;
-use capsule_registry::{pipeline::WritePipeline, CapsuleRegistry};
+use capsule_registry::{
+    dictionary::DictionaryTrainer, fsck::Fsck, lifecycle::LifecycleManager,
+    pipeline::WritePipeline, tenant::TenantQuota, CapsuleQuery, CapsuleRegistry,
+};
 #[cfg(feature = "phase4")]
 use clap::{Args, ValueEnum};
 use clap::{Parser, Subcommand};
 #[cfg(feature = "phase4")]
 use common::podms::ZoneId;
-use common::CapsuleId;
 #[cfg(any(
     feature = "pipeline_async",
     feature = "modular_pipeline",
     feature = "phase4"
 ))]
 use common::Policy;
+use common::{CapsuleId, TenantId};
 #[cfg(feature = "phase4")]
 use csi_driver_rs::ProvisionRequest;
 use nvram_sim::NvramLog;
@@ -174,6 +177,18 @@ enum BlockCommands {
     },
 }
 
+#[derive(Subcommand)]
+enum DictionaryCommands {
+    /// Train a new dictionary version from recently written segments
+    Train {
+        /// Tenant to train for; capsules without a tenant when omitted
+        #[arg(long)]
+        tenant: Option<String>,
+    },
+    /// List trained dictionaries and their versions
+    List,
+}
+
 fn open_registry_and_nvram() -> Result<(CapsuleRegistry, NvramLog)> {
     let registry = CapsuleRegistry::new();
     let nvram = NvramLog::open(NVRAM_PATH)?;
@@ -349,6 +364,251 @@ fn run_block_command(command: BlockCommands) -> Result<()> {
     Ok(())
 }
 
+fn run_fsck(repair: bool) -> Result<()> {
+    let (registry, nvram) = open_registry_and_nvram()?;
+    let fsck = Fsck::new(&registry, &nvram);
+    let report = if repair {
+        fsck.repair()?
+    } else {
+        fsck.check()?
+    };
+
+    println!(
+        "Checked {} capsules and {} segments",
+        report.capsules_checked, report.segments_checked
+    );
+    if report.is_clean() {
+        println!("No issues found");
+        return Ok(());
+    }
+
+    for issue in &report.issues {
+        let status = if repair && issue.is_repairable() {
+            "repaired"
+        } else if issue.is_repairable() {
+            "repairable"
+        } else {
+            "manual"
+        };
+        println!("[{}] {}", status, issue);
+    }
+    println!(
+        "{} issues, {} repaired, {} unresolved",
+        report.issues.len(),
+        report.repaired,
+        report.unresolved()
+    );
+
+    if report.unresolved() > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn run_lifecycle() -> Result<()> {
+    let (registry, nvram) = open_registry_and_nvram()?;
+    let pipeline = WritePipeline::new(registry, nvram);
+    let report = LifecycleManager::new(&pipeline).run()?;
+    println!(
+        "Expired {}, transitioned {}, deferred by retention {}, unsupported {}, failed {}",
+        report.expired, report.transitioned, report.deferred, report.unsupported, report.failed
+    );
+    Ok(())
+}
+
+fn run_tag(capsule_id: &str, tags: &[String]) -> Result<()> {
+    let id = CapsuleId::from_uuid(capsule_id.parse()?);
+    let registry = CapsuleRegistry::new();
+    let parsed: Vec<(String, String)> = tags
+        .iter()
+        .map(|tag| match tag.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+            _ => Err(anyhow::anyhow!("tag {:?} is not key=value", tag)),
+        })
+        .collect::<Result<_>>()?;
+    registry.update_tags(id, |current| current.extend(parsed))?;
+    println!("Tagged {}", id.as_uuid());
+    Ok(())
+}
+
+fn run_retain(capsule_id: &str, secs: u64) -> Result<()> {
+    let id = CapsuleId::from_uuid(capsule_id.parse()?);
+    let until = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs()
+        .saturating_add(secs);
+    CapsuleRegistry::new().set_retention(id, until)?;
+    println!("Retaining {} until {}", id.as_uuid(), until);
+    Ok(())
+}
+
+fn run_hold(capsule_id: &str, hold: bool) -> Result<()> {
+    let id = CapsuleId::from_uuid(capsule_id.parse()?);
+    CapsuleRegistry::new().set_legal_hold(id, hold)?;
+    let state = if hold { "placed on" } else { "released from" };
+    println!("Legal hold {} {}", state, id.as_uuid());
+    Ok(())
+}
+
+fn run_quota(tenant: &str, soft: Option<u64>, hard: Option<u64>) -> Result<()> {
+    let tenant = TenantId::new(tenant);
+    let quota = TenantQuota {
+        soft_bytes: soft,
+        hard_bytes: hard,
+    };
+    CapsuleRegistry::new().set_quota(&tenant, quota)?;
+    println!(
+        "Quota for {}: soft {}, hard {}",
+        tenant,
+        format_limit(quota.soft_bytes),
+        format_limit(quota.hard_bytes)
+    );
+    Ok(())
+}
+
+fn run_usage(tenant: Option<String>) -> Result<()> {
+    let (registry, nvram) = open_registry_and_nvram()?;
+    let pipeline = WritePipeline::new(registry, nvram);
+    let tenants = match tenant {
+        Some(tenant) => vec![TenantId::new(tenant)],
+        None => pipeline.registry().tenants(),
+    };
+    if tenants.is_empty() {
+        println!("(no tenants)");
+        return Ok(());
+    }
+
+    println!(
+        "{:<24} {:>8} {:>14} {:>14} {:>14} {:>14}",
+        "TENANT", "CAPSULES", "LOGICAL", "PHYSICAL", "SOFT", "HARD"
+    );
+    for tenant in tenants {
+        let usage = pipeline.tenant_usage(&tenant)?;
+        let quota = pipeline.registry().quota(&tenant);
+        let over_soft = quota
+            .soft_bytes
+            .is_some_and(|soft| usage.logical_bytes > soft);
+        println!(
+            "{:<24} {:>8} {:>14} {:>14} {:>14} {:>14}{}",
+            tenant.as_str(),
+            usage.capsules,
+            usage.logical_bytes,
+            usage.physical_bytes,
+            format_limit(quota.soft_bytes),
+            format_limit(quota.hard_bytes),
+            if over_soft { "  over soft limit" } else { "" }
+        );
+    }
+    Ok(())
+}
+
+fn run_dictionary_command(command: DictionaryCommands) -> Result<()> {
+    match command {
+        DictionaryCommands::Train { tenant } => {
+            let (registry, nvram) = open_registry_and_nvram()?;
+            let pipeline = WritePipeline::new(registry, nvram);
+            let tenant = tenant.map(TenantId::new);
+            let report = DictionaryTrainer::new(&pipeline).train(tenant.as_ref())?;
+            let gain = report.gain;
+            println!(
+                "Trained dictionary for {}: version {} of {}, {} bytes from {} samples",
+                format_scope(report.tenant.as_ref()),
+                report.dictionary.version,
+                report.dictionary.capsule.as_uuid(),
+                report.dictionary_len,
+                report.samples
+            );
+            println!(
+                "Held-out ratio {:.2}x without, {:.2}x with ({:+.1}%)",
+                gain.plain_ratio(),
+                gain.dictionary_ratio(),
+                (gain.dictionary_ratio() / gain.plain_ratio() - 1.0) * 100.0
+            );
+        }
+        DictionaryCommands::List => {
+            let registry = CapsuleRegistry::new();
+            let dictionaries = registry.dictionaries();
+            if dictionaries.is_empty() {
+                println!("(no dictionaries)");
+                return Ok(());
+            }
+            println!("{:<24} {:<36} VERSIONS", "SCOPE", "CAPSULE");
+            for (tenant, capsule) in dictionaries {
+                let versions: Vec<String> = registry
+                    .list_versions(capsule)?
+                    .iter()
+                    .map(u64::to_string)
+                    .collect();
+                println!(
+                    "{:<24} {:<36} {}",
+                    format_scope(tenant.as_ref()),
+                    capsule.as_uuid().to_string(),
+                    versions.join(",")
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn format_scope(tenant: Option<&TenantId>) -> String {
+    tenant.map_or_else(|| "(shared)".to_string(), TenantId::to_string)
+}
+
+fn format_limit(limit: Option<u64>) -> String {
+    limit.map_or_else(|| "-".to_string(), |bytes| bytes.to_string())
+}
+
+fn run_find(
+    tags: Vec<String>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    older_than: Option<u64>,
+    newer_than: Option<u64>,
+) -> Result<()> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+    let mut query = CapsuleQuery {
+        min_size,
+        max_size,
+        created_before: older_than.map(|age| now.saturating_sub(age)),
+        created_after: newer_than.map(|age| now.saturating_sub(age)),
+        ..CapsuleQuery::default()
+    };
+    for tag in tags {
+        query = match tag.split_once('=') {
+            Some((key, value)) => query.tag_value(key, value),
+            None => query.tagged(tag),
+        };
+    }
+
+    let registry = CapsuleRegistry::new();
+    let matches = registry.find_capsules(&query);
+    if matches.is_empty() {
+        println!("(no matching capsules)");
+        return Ok(());
+    }
+
+    println!("Capsule ID	Size (bytes)	Created	Tags");
+    for id in matches {
+        let capsule = registry.lookup(id)?;
+        let tags: Vec<String> = capsule
+            .tags
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        println!(
+            "{}	{:>12}	{}	{}",
+            capsule.id.as_uuid(),
+            capsule.size,
+            capsule.created_at,
+            tags.join(",")
+        );
+    }
+    Ok(())
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Create a new capsule from data
@@ -356,6 +616,9 @@ enum Commands {
         /// Input file path
         #[arg(short, long)]
         file: String,
+        /// Tenant that owns the capsule
+        #[arg(long)]
+        tenant: Option<String>,
         #[cfg(feature = "modular_pipeline")]
         #[arg(long)]
         modular: bool,
@@ -391,6 +654,74 @@ enum Commands {
         #[command(subcommand)]
         command: BlockCommands,
     },
+    /// Check registry and NVRAM log consistency
+    Fsck {
+        /// Fix orphans, refcounts and stale dedup entries
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Apply due lifecycle rules (expiry and policy transitions) once
+    Lifecycle,
+    /// Set tags on a capsule
+    Tag {
+        /// Capsule UUID
+        capsule_id: String,
+        /// Tags as key=value
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+    /// Find capsules by tag, size or age
+    Find {
+        /// Required tag, as key or key=value (repeatable)
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+        /// Minimum size in bytes
+        #[arg(long)]
+        min_size: Option<u64>,
+        /// Maximum size in bytes
+        #[arg(long)]
+        max_size: Option<u64>,
+        /// Only capsules created at least this many seconds ago
+        #[arg(long)]
+        older_than: Option<u64>,
+        /// Only capsules created within this many seconds
+        #[arg(long)]
+        newer_than: Option<u64>,
+    },
+    /// Retain a capsule for at least the given number of seconds from now
+    Retain {
+        /// Capsule UUID
+        capsule_id: String,
+        /// Retention period in seconds
+        #[arg(long)]
+        secs: u64,
+    },
+    /// Place or release a legal hold on a capsule
+    Hold {
+        /// Capsule UUID
+        capsule_id: String,
+        /// Release the hold instead of placing it
+        #[arg(long)]
+        release: bool,
+    },
+    /// Set a tenant's soft and hard quotas in bytes; omitted limits are removed
+    Quota {
+        tenant: String,
+        #[arg(long)]
+        soft: Option<u64>,
+        #[arg(long)]
+        hard: Option<u64>,
+    },
+    /// Report per-tenant usage against quotas
+    Usage {
+        /// Only report this tenant
+        tenant: Option<String>,
+    },
+    /// Train and list the Zstd dictionaries small segments compress against
+    Dictionary {
+        #[command(subcommand)]
+        command: DictionaryCommands,
+    },
 }
 
 #[cfg(feature = "phase4")]
@@ -466,10 +797,23 @@ fn main() -> Result<()> {
     match cli.command {
         Commands::Create {
             file,
+            tenant,
             #[cfg(feature = "modular_pipeline")]
             modular,
         } => {
             let data = fs::read(&file)?;
+            if let Some(tenant) = tenant {
+                let (registry, nvram) = open_registry_and_nvram()?;
+                let pipeline = WritePipeline::new(registry, nvram);
+                let id = pipeline.write_capsule_for_tenant(
+                    &TenantId::new(tenant),
+                    &data,
+                    &Default::default(),
+                )?;
+                println!("Capsule created: {}", id.as_uuid());
+                println!("Size: {} bytes", data.len());
+                return Ok(());
+            }
             #[cfg(feature = "modular_pipeline")]
             if modular {
                 let id = modular_write_capsule(&data)?;
@@ -578,6 +922,42 @@ fn main() -> Result<()> {
         Commands::Block { command } => {
             run_block_command(command)?;
         }
+        Commands::Fsck { repair } => {
+            run_fsck(repair)?;
+        }
+        Commands::Lifecycle => {
+            run_lifecycle()?;
+        }
+        Commands::Tag { capsule_id, tags } => {
+            run_tag(&capsule_id, &tags)?;
+        }
+        Commands::Find {
+            tags,
+            min_size,
+            max_size,
+            older_than,
+            newer_than,
+        } => {
+            run_find(tags, min_size, max_size, older_than, newer_than)?;
+        }
+        Commands::Retain { capsule_id, secs } => {
+            run_retain(&capsule_id, secs)?;
+        }
+        Commands::Hold {
+            capsule_id,
+            release,
+        } => {
+            run_hold(&capsule_id, !release)?;
+        }
+        Commands::Quota { tenant, soft, hard } => {
+            run_quota(&tenant, soft, hard)?;
+        }
+        Commands::Usage { tenant } => {
+            run_usage(tenant)?;
+        }
+        Commands::Dictionary { command } => {
+            run_dictionary_command(command)?;
+        }
     }
 
     Ok(())
@@ -0,0 +1,445 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Result};
+use common::{
+    traits::{StorageBackend, StorageTransaction},
+    ErasureLayout, Policy, Segment, SegmentId,
+};
+use futures::executor::block_on;
+use futures::future::{self, BoxFuture};
+use futures::lock::Mutex as AsyncMutex;
+use tracing::warn;
+
+use crate::install;
+use crate::reed_solomon::ReedSolomon;
+
+/// How often the rebuild worker checks whether it should stop.
+const REBUILDER_TICK: Duration = Duration::from_millis(50);
+
+/// Storage that spreads each segment over several backends with Reed-Solomon coding.
+///
+/// A segment is encoded into `k` data and `m` parity fragments, and fragment
+/// `i` is stored on backend `i` under the segment's own id. Every backend also
+/// keeps a copy of the segment metadata, whose [`ErasureLayout`] lists the
+/// fragment checksums. A read reassembles the segment from any `k` fragments
+/// that match their checksum, so up to `m` backends can be lost or corrupted.
+/// [`rebuild`](Self::rebuild) rewrites missing fragments, and an
+/// [`ErasureRebuilder`] runs it in the background.
+#[derive(Clone)]
+pub struct ErasureBackend<B> {
+    backends: Vec<B>,
+    codec: Arc<ReedSolomon>,
+    /// Held by commits, deletes and the rebuild of each segment, and shared by
+    /// clones, so a rebuild never installs over a change made under it.
+    writes: Arc<AsyncMutex<()>>,
+}
+
+/// Outcome of an [`ErasureBackend::rebuild`] pass.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RebuildReport {
+    pub segments_checked: usize,
+    pub fragments_rebuilt: usize,
+    /// Segments left with fewer than `k` intact fragments.
+    pub unrecoverable: usize,
+}
+
+impl RebuildReport {
+    fn absorb(&mut self, other: RebuildReport) {
+        self.segments_checked += other.segments_checked;
+        self.fragments_rebuilt += other.fragments_rebuilt;
+        self.unrecoverable += other.unrecoverable;
+    }
+}
+
+impl<B> ErasureBackend<B>
+where
+    B: StorageBackend,
+    B::Transaction: 'static,
+{
+    /// Code over `backends` with `data_fragments` data fragments per segment;
+    /// the remaining backends hold parity.
+    pub fn new(backends: Vec<B>, data_fragments: usize) -> Result<Self> {
+        let parity_fragments = backends.len().saturating_sub(data_fragments);
+        let codec = ReedSolomon::new(data_fragments, parity_fragments)?;
+        Ok(Self {
+            backends,
+            codec: Arc::new(codec),
+            writes: Arc::new(AsyncMutex::new(())),
+        })
+    }
+
+    /// Code over `backends` with the profile from [`Policy::ec_profile`], which
+    /// must match the number of backends.
+    pub fn for_policy(backends: Vec<B>, policy: &Policy) -> Result<Self> {
+        let (data, parity) = policy.ec_profile()?;
+        if backends.len() != data + parity {
+            bail!(
+                "erasure profile {}+{} needs {} backends, got {}",
+                data,
+                parity,
+                data + parity,
+                backends.len()
+            );
+        }
+        Self::new(backends, data)
+    }
+
+    pub fn backends(&self) -> &[B] {
+        &self.backends
+    }
+
+    /// Data and parity fragments per segment.
+    pub fn profile(&self) -> (usize, usize) {
+        (self.codec.data_fragments(), self.codec.parity_fragments())
+    }
+
+    fn layout<'a>(&self, segment: SegmentId, metadata: &'a Segment) -> Result<&'a ErasureLayout> {
+        let layout = metadata
+            .erasure
+            .as_ref()
+            .ok_or_else(|| anyhow!("segment {:?} has no erasure layout", segment))?;
+        if (layout.data_fragments, layout.parity_fragments) != self.profile() {
+            bail!(
+                "segment {:?} was coded {}+{} but the store is {}+{}",
+                segment,
+                layout.data_fragments,
+                layout.parity_fragments,
+                self.codec.data_fragments(),
+                self.codec.parity_fragments()
+            );
+        }
+        Ok(layout)
+    }
+
+    /// Each backend's fragment of `segment`, or `None` where it is missing or corrupt.
+    async fn fragments(&self, segment: SegmentId, layout: &ErasureLayout) -> Vec<Option<Vec<u8>>> {
+        let mut fragments = Vec::with_capacity(self.backends.len());
+        for (backend, checksum) in self.backends.iter().zip(&layout.fragment_checksums) {
+            let fragment = match backend.read(segment).await {
+                Ok(fragment) if fragment_checksum(&fragment) == *checksum => Some(fragment),
+                _ => None,
+            };
+            fragments.push(fragment);
+        }
+        fragments
+    }
+
+    async fn decode(&self, segment: SegmentId) -> Result<Vec<u8>> {
+        let metadata = self.metadata(segment).await?;
+        let layout = self.layout(segment, &metadata)?;
+        let mut fragments = self.fragments(segment, layout).await;
+        if fragments[..self.codec.data_fragments()]
+            .iter()
+            .any(Option::is_none)
+        {
+            self.codec
+                .reconstruct(&mut fragments)
+                .map_err(|err| anyhow!("segment {:?} cannot be rebuilt: {}", segment, err))?;
+        }
+        let fragments: Vec<Vec<u8>> = fragments.into_iter().flatten().collect();
+        self.codec.join(&fragments, layout.payload_len as usize)
+    }
+
+    /// Rewrite every missing or corrupt fragment from the surviving ones.
+    ///
+    /// A backend that lost a segment's metadata counts as missing the fragment
+    /// too. Segments with fewer than `k` intact fragments are reported as
+    /// unrecoverable and left untouched. Each segment is read and repaired
+    /// without a commit or delete in between, so one deleted meanwhile is
+    /// skipped and a backend keeps its own metadata where it still has it.
+    pub async fn rebuild(&mut self) -> Result<RebuildReport> {
+        let mut report = RebuildReport::default();
+        let writes = Arc::clone(&self.writes);
+        for segment in self.segment_ids().await? {
+            report.segments_checked += 1;
+            let _guard = writes.lock().await;
+            let Ok(metadata) = self.metadata(segment).await else {
+                continue;
+            };
+            let Ok(layout) = self.layout(segment, &metadata).cloned() else {
+                report.unrecoverable += 1;
+                continue;
+            };
+            let mut fragments = self.fragments(segment, &layout).await;
+            for (index, backend) in self.backends.iter().enumerate() {
+                if backend.metadata(segment).await.is_err() {
+                    fragments[index] = None;
+                }
+            }
+            let missing: Vec<usize> = (0..fragments.len())
+                .filter(|&index| fragments[index].is_none())
+                .collect();
+            if missing.is_empty() {
+                continue;
+            }
+            if self.codec.reconstruct(&mut fragments).is_err() {
+                report.unrecoverable += 1;
+                continue;
+            }
+            for index in missing {
+                let fragment = fragments[index].as_deref().unwrap_or_default();
+                let backend = &mut self.backends[index];
+                let target = match backend.metadata(segment).await {
+                    Ok(existing) if existing.erasure == metadata.erasure => existing,
+                    _ => metadata.clone(),
+                };
+                install(backend, segment, fragment, target).await?;
+                report.fragments_rebuilt += 1;
+            }
+        }
+        Ok(report)
+    }
+}
+
+fn fragment_checksum(fragment: &[u8]) -> String {
+    blake3::hash(fragment).to_hex().to_string()
+}
+
+pub struct ErasureTransaction<T> {
+    fragments: Vec<T>,
+    codec: Arc<ReedSolomon>,
+    writes: Arc<AsyncMutex<()>>,
+    /// Layouts of segments appended in this transaction, stamped onto their
+    /// metadata when it is set.
+    layouts: HashMap<SegmentId, (ErasureLayout, bool)>,
+}
+
+impl<T> StorageTransaction for ErasureTransaction<T>
+where
+    T: StorageTransaction + 'static,
+{
+    fn append<'a>(&'a mut self, segment: SegmentId, data: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let fragments = self.codec.encode(data);
+            let layout = ErasureLayout {
+                data_fragments: self.codec.data_fragments(),
+                parity_fragments: self.codec.parity_fragments(),
+                payload_len: data.len() as u64,
+                fragment_checksums: fragments.iter().map(|f| fragment_checksum(f)).collect(),
+            };
+            for (txn, fragment) in self.fragments.iter_mut().zip(&fragments) {
+                txn.append(segment, fragment).await?;
+            }
+            self.layouts.insert(segment, (layout, false));
+            Ok(())
+        })
+    }
+
+    fn set_segment_metadata<'a>(
+        &'a mut self,
+        segment: SegmentId,
+        mut metadata: Segment,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            if let Some((layout, described)) = self.layouts.get_mut(&segment) {
+                metadata.erasure = Some(layout.clone());
+                *described = true;
+            } else if metadata.erasure.is_none() {
+                bail!("segment {:?} has no erasure layout", segment);
+            }
+            for txn in &mut self.fragments {
+                txn.set_segment_metadata(segment, metadata.clone()).await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(&'a mut self, segment: SegmentId) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.layouts.remove(&segment);
+            for txn in &mut self.fragments {
+                txn.delete(segment).await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn commit(self) -> BoxFuture<'static, Result<()>> {
+        Box::pin(async move {
+            // Without metadata nobody could find the layout to decode the fragments.
+            if let Some((segment, _)) = self.layouts.iter().find(|(_, (_, described))| !described) {
+                let segment = *segment;
+                for txn in self.fragments {
+                    let _ = txn.rollback().await;
+                }
+                bail!("segment {:?} was appended without metadata", segment);
+            }
+            // No rebuild may see some backends committed and others not.
+            let _guard = self.writes.lock().await;
+            let mut result = Ok(());
+            for txn in self.fragments {
+                let committed = txn.commit().await;
+                result = result.and(committed);
+            }
+            result
+        })
+    }
+
+    fn rollback(self) -> BoxFuture<'static, Result<()>> {
+        Box::pin(async move {
+            let mut result = Ok(());
+            for txn in self.fragments {
+                let rolled_back = txn.rollback().await;
+                result = result.and(rolled_back);
+            }
+            result
+        })
+    }
+}
+
+impl<B> StorageBackend for ErasureBackend<B>
+where
+    B: StorageBackend,
+    B::Transaction: 'static,
+{
+    type Transaction = ErasureTransaction<B::Transaction>;
+
+    fn append<'a>(&'a mut self, segment: SegmentId, _data: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+        // The fragment layout lives in the metadata, so data alone can't be decoded.
+        Box::pin(future::ready(Err(anyhow!(
+            "segment {:?}: erasure-coded segments are written in a transaction with their metadata",
+            segment
+        ))))
+    }
+
+    fn read(&self, segment: SegmentId) -> BoxFuture<'_, Result<Vec<u8>>> {
+        Box::pin(self.decode(segment))
+    }
+
+    fn metadata(&self, segment: SegmentId) -> BoxFuture<'_, Result<Segment>> {
+        Box::pin(async move {
+            for backend in &self.backends {
+                if let Ok(metadata) = backend.metadata(segment).await {
+                    return Ok(metadata);
+                }
+            }
+            Err(anyhow!("segment {:?} metadata not found", segment))
+        })
+    }
+
+    fn delete<'a>(&'a mut self, segment: SegmentId) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let _guard = self.writes.lock().await;
+            // Keep going past a failed backend so no other fragment is left behind.
+            let mut result = Ok(());
+            for backend in &mut self.backends {
+                let deleted = backend.delete(segment).await;
+                result = result.and(deleted);
+            }
+            result
+        })
+    }
+
+    fn segment_ids(&self) -> BoxFuture<'_, Result<Vec<SegmentId>>> {
+        Box::pin(async move {
+            let mut ids = Vec::new();
+            let mut last_err = None;
+            let mut listed = 0;
+            for backend in &self.backends {
+                match backend.segment_ids().await {
+                    Ok(found) => {
+                        ids.extend(found);
+                        listed += 1;
+                    }
+                    Err(err) => last_err = Some(err),
+                }
+            }
+            if listed == 0 {
+                if let Some(err) = last_err {
+                    return Err(err);
+                }
+            }
+            ids.sort_by_key(|id| id.0);
+            ids.dedup();
+            Ok(ids)
+        })
+    }
+
+    fn begin_txn(&mut self) -> BoxFuture<'_, Result<Self::Transaction>> {
+        Box::pin(async move {
+            let mut fragments = Vec::with_capacity(self.backends.len());
+            for backend in &mut self.backends {
+                fragments.push(backend.begin_txn().await?);
+            }
+            Ok(ErasureTransaction {
+                fragments,
+                codec: Arc::clone(&self.codec),
+                writes: Arc::clone(&self.writes),
+                layouts: HashMap::new(),
+            })
+        })
+    }
+}
+
+/// Background worker that runs [`ErasureBackend::rebuild`] on an interval.
+pub struct ErasureRebuilder {
+    stop: Arc<AtomicBool>,
+    totals: Arc<Mutex<RebuildReport>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ErasureRebuilder {
+    /// Rebuild through `backend`, a clone sharing its stores with the one in use.
+    pub fn spawn<B>(mut backend: ErasureBackend<B>, interval: Duration) -> Self
+    where
+        B: StorageBackend + Send + 'static,
+        B::Transaction: 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_flag = Arc::clone(&stop);
+        let totals = Arc::new(Mutex::new(RebuildReport::default()));
+        let running_totals = Arc::clone(&totals);
+
+        let handle = thread::spawn(move || {
+            let mut next_run = Instant::now();
+            while !stop_flag.load(Ordering::SeqCst) {
+                if Instant::now() < next_run {
+                    thread::sleep(REBUILDER_TICK);
+                    continue;
+                }
+                match block_on(backend.rebuild()) {
+                    Ok(report) => {
+                        if let Ok(mut totals) = running_totals.lock() {
+                            totals.absorb(report);
+                        }
+                    }
+                    Err(err) => warn!(error = %err, "erasure rebuild pass failed"),
+                }
+                next_run = Instant::now() + interval;
+            }
+        });
+
+        Self {
+            stop,
+            totals,
+            handle: Some(handle),
+        }
+    }
+
+    /// Sum of the reports of every pass so far.
+    pub fn totals(&self) -> RebuildReport {
+        self.totals.lock().map(|totals| *totals).unwrap_or_default()
+    }
+
+    /// Signal the worker to exit and wait for it.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for ErasureRebuilder {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
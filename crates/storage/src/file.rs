@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use common::{
+    traits::{StorageBackend, StorageTransaction},
+    Segment, SegmentId,
+};
+use futures::future::{self, BoxFuture};
+
+const DATA_EXT: &str = "seg";
+const META_EXT: &str = "meta";
+
+/// File-backed storage used as the capacity tier of a [`TieredBackend`](crate::TieredBackend).
+///
+/// Each segment is kept as `<id>.seg` with its metadata as JSON in `<id>.meta`.
+/// Files are written under a temporary name and renamed into place, so a crash
+/// leaves either the previous or the new version of each.
+#[derive(Clone)]
+pub struct FileBackend {
+    root: Arc<PathBuf>,
+}
+
+impl FileBackend {
+    pub fn open<P: AsRef<Path>>(root: P) -> Result<Self> {
+        fs::create_dir_all(root.as_ref())?;
+        Ok(Self {
+            root: Arc::new(root.as_ref().to_path_buf()),
+        })
+    }
+
+    /// Directory holding the segment files.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn path(&self, segment: SegmentId, ext: &str) -> PathBuf {
+        self.root.join(format!("{}.{}", segment.0, ext))
+    }
+
+    fn write_file(&self, segment: SegmentId, ext: &str, bytes: &[u8]) -> Result<()> {
+        let path = self.path(segment, ext);
+        let staging = path.with_extension(format!("{}.tmp", ext));
+        let mut file = fs::File::create(&staging)?;
+        file.write_all(bytes)?;
+        file.sync_data()?;
+        fs::rename(&staging, &path)?;
+        Ok(())
+    }
+
+    fn remove_file(&self, segment: SegmentId, ext: &str) -> Result<()> {
+        match fs::remove_file(self.path(segment, ext)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn put_data(&self, segment: SegmentId, data: &[u8]) -> Result<()> {
+        self.write_file(segment, DATA_EXT, data)
+    }
+
+    fn put_metadata(&self, segment: SegmentId, metadata: &Segment) -> Result<()> {
+        self.write_file(segment, META_EXT, &serde_json::to_vec(metadata)?)
+    }
+
+    fn remove(&self, segment: SegmentId) -> Result<()> {
+        // Metadata goes first: a segment without it is no longer listed.
+        self.remove_file(segment, META_EXT)?;
+        self.remove_file(segment, DATA_EXT)
+    }
+
+    fn read_data(&self, segment: SegmentId) -> Result<Vec<u8>> {
+        fs::read(self.path(segment, DATA_EXT))
+            .map_err(|err| anyhow!("segment {:?} not found: {}", segment, err))
+    }
+
+    fn read_metadata(&self, segment: SegmentId) -> Result<Segment> {
+        let raw = fs::read(self.path(segment, META_EXT))
+            .map_err(|err| anyhow!("segment {:?} metadata not found: {}", segment, err))?;
+        Ok(serde_json::from_slice(&raw)?)
+    }
+
+    fn list(&self) -> Result<Vec<SegmentId>> {
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(self.root.as_path())? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(META_EXT) {
+                continue;
+            }
+            if let Some(id) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse().ok())
+            {
+                ids.push(SegmentId(id));
+            }
+        }
+        Ok(ids)
+    }
+}
+
+pub struct FileTransaction {
+    backend: FileBackend,
+    staged_segments: HashMap<SegmentId, Vec<u8>>,
+    staged_metadata: HashMap<SegmentId, Segment>,
+    deleted: Vec<SegmentId>,
+}
+
+impl StorageTransaction for FileTransaction {
+    fn append<'a>(&'a mut self, segment: SegmentId, data: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+        self.staged_segments.insert(segment, data.to_vec());
+        Box::pin(async { Ok(()) })
+    }
+
+    fn set_segment_metadata<'a>(
+        &'a mut self,
+        segment: SegmentId,
+        metadata: Segment,
+    ) -> BoxFuture<'a, Result<()>> {
+        self.staged_metadata.insert(segment, metadata);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn delete<'a>(&'a mut self, segment: SegmentId) -> BoxFuture<'a, Result<()>> {
+        self.deleted.push(segment);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn commit(self) -> BoxFuture<'static, Result<()>> {
+        Box::pin(async move {
+            // Data before metadata, so a listed segment always has its bytes.
+            for (segment, data) in &self.staged_segments {
+                self.backend.put_data(*segment, data)?;
+            }
+            for (segment, metadata) in &self.staged_metadata {
+                self.backend.put_metadata(*segment, metadata)?;
+            }
+            for segment in self.deleted {
+                self.backend.remove(segment)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn rollback(self) -> BoxFuture<'static, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+impl StorageBackend for FileBackend {
+    type Transaction = FileTransaction;
+
+    fn append<'a>(&'a mut self, segment: SegmentId, data: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+        Box::pin(future::ready(self.put_data(segment, data)))
+    }
+
+    fn read(&self, segment: SegmentId) -> BoxFuture<'_, Result<Vec<u8>>> {
+        Box::pin(future::ready(self.read_data(segment)))
+    }
+
+    fn metadata(&self, segment: SegmentId) -> BoxFuture<'_, Result<Segment>> {
+        Box::pin(future::ready(self.read_metadata(segment)))
+    }
+
+    fn delete<'a>(&'a mut self, segment: SegmentId) -> BoxFuture<'a, Result<()>> {
+        Box::pin(future::ready(self.remove(segment)))
+    }
+
+    fn segment_ids(&self) -> BoxFuture<'_, Result<Vec<SegmentId>>> {
+        Box::pin(future::ready(self.list()))
+    }
+
+    fn begin_txn(&mut self) -> BoxFuture<'_, Result<Self::Transaction>> {
+        Box::pin(future::ready(Ok(FileTransaction {
+            backend: self.clone(),
+            staged_segments: HashMap::new(),
+            staged_metadata: HashMap::new(),
+            deleted: Vec::new(),
+        })))
+    }
+}
@@ -10,6 +10,29 @@ use common::{
 use futures::future::{self, BoxFuture};
 use nvram_sim::{NvramLog, NvramTransaction};
 
+mod erasure;
+mod file;
+mod reed_solomon;
+mod tiered;
+
+pub use erasure::{ErasureBackend, ErasureRebuilder, ErasureTransaction, RebuildReport};
+pub use file::{FileBackend, FileTransaction};
+pub use reed_solomon::ReedSolomon;
+pub use tiered::{TieredBackend, TieredTransaction};
+
+/// Write `data` and `metadata` for `segment` to `backend` in one transaction.
+pub(crate) async fn install<B: StorageBackend>(
+    backend: &mut B,
+    segment: SegmentId,
+    data: &[u8],
+    metadata: Segment,
+) -> Result<()> {
+    let mut txn = backend.begin_txn().await?;
+    txn.append(segment, data).await?;
+    txn.set_segment_metadata(segment, metadata).await?;
+    txn.commit().await
+}
+
 #[derive(Default)]
 struct Inner {
     segments: HashMap<SegmentId, Vec<u8>>,
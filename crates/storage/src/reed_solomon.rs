@@ -0,0 +1,268 @@
+use anyhow::{anyhow, bail, Result};
+
+/// Arithmetic in GF(2^8) modulo x^8 + x^4 + x^3 + x^2 + 1 (0x11d).
+mod gf {
+    struct Tables {
+        exp: [u8; 512],
+        log: [u8; 256],
+    }
+
+    static TABLES: Tables = build();
+
+    const fn build() -> Tables {
+        let mut exp = [0u8; 512];
+        let mut log = [0u8; 256];
+        let mut x: u16 = 1;
+        let mut i = 0;
+        while i < 255 {
+            exp[i] = x as u8;
+            exp[i + 255] = x as u8;
+            log[x as usize] = i as u8;
+            x <<= 1;
+            if x & 0x100 != 0 {
+                x ^= 0x11d;
+            }
+            i += 1;
+        }
+        Tables { exp, log }
+    }
+
+    pub fn mul(a: u8, b: u8) -> u8 {
+        if a == 0 || b == 0 {
+            return 0;
+        }
+        TABLES.exp[TABLES.log[a as usize] as usize + TABLES.log[b as usize] as usize]
+    }
+
+    /// Multiplicative inverse; `a` must be non-zero.
+    pub fn inv(a: u8) -> u8 {
+        debug_assert!(a != 0, "zero has no inverse");
+        TABLES.exp[255 - TABLES.log[a as usize] as usize]
+    }
+
+    /// `c * x` for every byte `x`, so a fragment is scaled with one lookup per byte.
+    pub fn mul_table(c: u8) -> [u8; 256] {
+        let mut table = [0u8; 256];
+        for (x, product) in table.iter_mut().enumerate() {
+            *product = mul(c, x as u8);
+        }
+        table
+    }
+}
+
+/// Systematic Reed-Solomon code over GF(2^8).
+///
+/// The first `data` fragments are the payload itself; parity fragment `i` is
+/// the combination of the data fragments weighted by row `i` of a Cauchy
+/// matrix. Every square submatrix of `[I; C]` is invertible, so any `data`
+/// of the `data + parity` fragments determine the rest.
+#[derive(Debug, Clone)]
+pub struct ReedSolomon {
+    data: usize,
+    parity: usize,
+    parity_rows: Vec<Vec<u8>>,
+}
+
+impl ReedSolomon {
+    pub fn new(data: usize, parity: usize) -> Result<Self> {
+        if data == 0 || parity == 0 || data + parity > 255 {
+            bail!(
+                "unsupported erasure profile {}+{}: need k >= 1, m >= 1 and k + m <= 255",
+                data,
+                parity
+            );
+        }
+        // x_i = data + i and y_j = j are distinct, so x_i ^ y_j is never zero.
+        let parity_rows = (0..parity)
+            .map(|i| (0..data).map(|j| gf::inv(((data + i) ^ j) as u8)).collect())
+            .collect();
+        Ok(Self {
+            data,
+            parity,
+            parity_rows,
+        })
+    }
+
+    pub fn data_fragments(&self) -> usize {
+        self.data
+    }
+
+    pub fn parity_fragments(&self) -> usize {
+        self.parity
+    }
+
+    pub fn total_fragments(&self) -> usize {
+        self.data + self.parity
+    }
+
+    /// Split `payload` into zero-padded data fragments followed by parity fragments.
+    pub fn encode(&self, payload: &[u8]) -> Vec<Vec<u8>> {
+        let fragment_len = payload.len().div_ceil(self.data);
+        let mut fragments: Vec<Vec<u8>> = (0..self.data)
+            .map(|i| {
+                let start = (i * fragment_len).min(payload.len());
+                let end = (start + fragment_len).min(payload.len());
+                let mut fragment = payload[start..end].to_vec();
+                fragment.resize(fragment_len, 0);
+                fragment
+            })
+            .collect();
+        let data: Vec<&[u8]> = fragments.iter().map(Vec::as_slice).collect();
+        let parity: Vec<Vec<u8>> = self
+            .parity_rows
+            .iter()
+            .map(|row| combine(row, &data))
+            .collect();
+        fragments.extend(parity);
+        fragments
+    }
+
+    /// Fill in every `None` in `fragments` from the fragments that are present.
+    ///
+    /// Fails when fewer than `data` fragments are present or their lengths differ.
+    pub fn reconstruct(&self, fragments: &mut [Option<Vec<u8>>]) -> Result<()> {
+        if fragments.len() != self.total_fragments() {
+            bail!(
+                "expected {} fragments, got {}",
+                self.total_fragments(),
+                fragments.len()
+            );
+        }
+        let present: Vec<usize> = (0..fragments.len())
+            .filter(|&i| fragments[i].is_some())
+            .take(self.data)
+            .collect();
+        if present.len() < self.data {
+            bail!(
+                "only {} of the {} fragments needed are available",
+                present.len(),
+                self.data
+            );
+        }
+        let fragment_len = fragments[present[0]].as_ref().map_or(0, Vec::len);
+        if fragments
+            .iter()
+            .flatten()
+            .any(|fragment| fragment.len() != fragment_len)
+        {
+            bail!("fragments differ in length");
+        }
+
+        if fragments[..self.data].iter().any(Option::is_none) {
+            let rows: Vec<Vec<u8>> = present.iter().map(|&i| self.row(i)).collect();
+            let decode = invert(rows)?;
+            let recovered: Vec<(usize, Vec<u8>)> = {
+                let inputs: Vec<&[u8]> = present
+                    .iter()
+                    .filter_map(|&i| fragments[i].as_deref())
+                    .collect();
+                (0..self.data)
+                    .filter(|&j| fragments[j].is_none())
+                    .map(|j| (j, combine(&decode[j], &inputs)))
+                    .collect()
+            };
+            for (j, fragment) in recovered {
+                fragments[j] = Some(fragment);
+            }
+        }
+
+        let parity: Vec<(usize, Vec<u8>)> = {
+            let data: Vec<&[u8]> = fragments[..self.data]
+                .iter()
+                .filter_map(|fragment| fragment.as_deref())
+                .collect();
+            (0..self.parity)
+                .filter(|&i| fragments[self.data + i].is_none())
+                .map(|i| (self.data + i, combine(&self.parity_rows[i], &data)))
+                .collect()
+        };
+        for (i, fragment) in parity {
+            fragments[i] = Some(fragment);
+        }
+        Ok(())
+    }
+
+    /// Concatenate the data fragments and strip the padding added by [`encode`](Self::encode).
+    pub fn join(&self, fragments: &[Vec<u8>], payload_len: usize) -> Result<Vec<u8>> {
+        let mut payload: Vec<u8> = fragments
+            .iter()
+            .take(self.data)
+            .flat_map(|fragment| fragment.iter().copied())
+            .collect();
+        if fragments.len() < self.data || payload.len() < payload_len {
+            bail!("fragments are too short for a {} byte payload", payload_len);
+        }
+        payload.truncate(payload_len);
+        Ok(payload)
+    }
+
+    /// Row `index` of the generator matrix `[I; C]`.
+    fn row(&self, index: usize) -> Vec<u8> {
+        if index < self.data {
+            let mut row = vec![0u8; self.data];
+            row[index] = 1;
+            row
+        } else {
+            self.parity_rows[index - self.data].clone()
+        }
+    }
+}
+
+/// `sum(coefficients[i] * inputs[i])`, byte by byte.
+fn combine(coefficients: &[u8], inputs: &[&[u8]]) -> Vec<u8> {
+    let mut output = vec![0u8; inputs.first().map_or(0, |input| input.len())];
+    for (&coefficient, input) in coefficients.iter().zip(inputs) {
+        match coefficient {
+            0 => {}
+            1 => output
+                .iter_mut()
+                .zip(input.iter())
+                .for_each(|(out, &byte)| *out ^= byte),
+            _ => {
+                let table = gf::mul_table(coefficient);
+                output
+                    .iter_mut()
+                    .zip(input.iter())
+                    .for_each(|(out, &byte)| *out ^= table[byte as usize]);
+            }
+        }
+    }
+    output
+}
+
+/// Gauss-Jordan inversion of a square matrix.
+fn invert(mut matrix: Vec<Vec<u8>>) -> Result<Vec<Vec<u8>>> {
+    let n = matrix.len();
+    let mut inverse: Vec<Vec<u8>> = (0..n)
+        .map(|i| {
+            let mut row = vec![0u8; n];
+            row[i] = 1;
+            row
+        })
+        .collect();
+
+    for col in 0..n {
+        let pivot = (col..n)
+            .find(|&row| matrix[row][col] != 0)
+            .ok_or_else(|| anyhow!("fragment matrix is singular"))?;
+        matrix.swap(col, pivot);
+        inverse.swap(col, pivot);
+
+        let scale = gf::inv(matrix[col][col]);
+        for value in matrix[col].iter_mut().chain(inverse[col].iter_mut()) {
+            *value = gf::mul(*value, scale);
+        }
+
+        for row in 0..n {
+            let factor = matrix[row][col];
+            if row == col || factor == 0 {
+                continue;
+            }
+            for k in 0..n {
+                matrix[row][k] ^= gf::mul(factor, matrix[col][k]);
+                inverse[row][k] ^= gf::mul(factor, inverse[col][k]);
+            }
+        }
+    }
+    Ok(inverse)
+}
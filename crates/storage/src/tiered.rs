@@ -0,0 +1,171 @@
+use anyhow::Result;
+use common::{
+    traits::{StorageBackend, StorageTransaction},
+    Segment, SegmentId, StorageTier,
+};
+use futures::future::BoxFuture;
+
+use crate::install;
+
+/// Storage composed of a hot tier and a capacity tier.
+///
+/// New segments are written to the hot tier. [`relocate`](StorageBackend::relocate)
+/// moves a segment between tiers by copying it to the target tier before
+/// deleting the source copy, so a reader always finds one; should a move be
+/// interrupted, the hot copy wins. Metadata reports the tier a segment was
+/// found on, and metadata updates are routed back to that tier.
+#[derive(Clone, Default)]
+pub struct TieredBackend<H, C> {
+    hot: H,
+    capacity: C,
+}
+
+impl<H, C> TieredBackend<H, C>
+where
+    H: StorageBackend,
+    C: StorageBackend,
+{
+    pub fn new(hot: H, capacity: C) -> Self {
+        Self { hot, capacity }
+    }
+
+    pub fn hot(&self) -> &H {
+        &self.hot
+    }
+
+    pub fn capacity(&self) -> &C {
+        &self.capacity
+    }
+}
+
+pub struct TieredTransaction<H, C> {
+    hot: H,
+    capacity: C,
+}
+
+impl<H, C> StorageTransaction for TieredTransaction<H, C>
+where
+    H: StorageTransaction + 'static,
+    C: StorageTransaction + 'static,
+{
+    fn append<'a>(&'a mut self, segment: SegmentId, data: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+        self.hot.append(segment, data)
+    }
+
+    fn set_segment_metadata<'a>(
+        &'a mut self,
+        segment: SegmentId,
+        metadata: Segment,
+    ) -> BoxFuture<'a, Result<()>> {
+        match metadata.tier {
+            StorageTier::Hot => self.hot.set_segment_metadata(segment, metadata),
+            StorageTier::Capacity => self.capacity.set_segment_metadata(segment, metadata),
+        }
+    }
+
+    fn delete<'a>(&'a mut self, segment: SegmentId) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.hot.delete(segment).await?;
+            self.capacity.delete(segment).await
+        })
+    }
+
+    fn commit(self) -> BoxFuture<'static, Result<()>> {
+        Box::pin(async move {
+            self.hot.commit().await?;
+            self.capacity.commit().await
+        })
+    }
+
+    fn rollback(self) -> BoxFuture<'static, Result<()>> {
+        Box::pin(async move {
+            let hot = self.hot.rollback().await;
+            let capacity = self.capacity.rollback().await;
+            hot.and(capacity)
+        })
+    }
+}
+
+impl<H, C> StorageBackend for TieredBackend<H, C>
+where
+    H: StorageBackend,
+    C: StorageBackend,
+    H::Transaction: 'static,
+    C::Transaction: 'static,
+{
+    type Transaction = TieredTransaction<H::Transaction, C::Transaction>;
+
+    fn append<'a>(&'a mut self, segment: SegmentId, data: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+        self.hot.append(segment, data)
+    }
+
+    fn read(&self, segment: SegmentId) -> BoxFuture<'_, Result<Vec<u8>>> {
+        Box::pin(async move {
+            match self.hot.read(segment).await {
+                Ok(data) => Ok(data),
+                Err(_) => self.capacity.read(segment).await,
+            }
+        })
+    }
+
+    fn metadata(&self, segment: SegmentId) -> BoxFuture<'_, Result<Segment>> {
+        Box::pin(async move {
+            let (mut metadata, tier) = match self.hot.metadata(segment).await {
+                Ok(metadata) => (metadata, StorageTier::Hot),
+                Err(_) => (
+                    self.capacity.metadata(segment).await?,
+                    StorageTier::Capacity,
+                ),
+            };
+            metadata.tier = tier;
+            Ok(metadata)
+        })
+    }
+
+    fn delete<'a>(&'a mut self, segment: SegmentId) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.hot.delete(segment).await?;
+            self.capacity.delete(segment).await
+        })
+    }
+
+    fn segment_ids(&self) -> BoxFuture<'_, Result<Vec<SegmentId>>> {
+        Box::pin(async move {
+            let mut ids = self.hot.segment_ids().await?;
+            ids.extend(self.capacity.segment_ids().await?);
+            ids.sort_by_key(|id| id.0);
+            ids.dedup();
+            Ok(ids)
+        })
+    }
+
+    fn begin_txn(&mut self) -> BoxFuture<'_, Result<Self::Transaction>> {
+        Box::pin(async move {
+            let hot = self.hot.begin_txn().await?;
+            let capacity = self.capacity.begin_txn().await?;
+            Ok(TieredTransaction { hot, capacity })
+        })
+    }
+
+    fn relocate(&mut self, segment: SegmentId, tier: StorageTier) -> BoxFuture<'_, Result<bool>> {
+        Box::pin(async move {
+            let mut metadata = self.metadata(segment).await?;
+            if metadata.tier == tier {
+                return Ok(false);
+            }
+            let data = self.read(segment).await?;
+            metadata.tier = tier;
+            match tier {
+                StorageTier::Hot => {
+                    install(&mut self.hot, segment, &data, metadata).await?;
+                    self.capacity.delete(segment).await?;
+                }
+                StorageTier::Capacity => {
+                    install(&mut self.capacity, segment, &data, metadata).await?;
+                    self.hot.delete(segment).await?;
+                }
+            }
+            Ok(true)
+        })
+    }
+}
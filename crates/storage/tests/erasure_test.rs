@@ -0,0 +1,277 @@
+use anyhow::Result;
+use common::traits::{StorageBackend, StorageTransaction};
+use common::{Policy, Segment, SegmentId};
+use futures::executor::block_on;
+use futures::future::BoxFuture;
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+use storage::{
+    ErasureBackend, ErasureRebuilder, FileBackend, InMemoryBackend, InMemoryTransaction,
+    RebuildReport, ReedSolomon,
+};
+
+fn disk_dirs(prefix: &str, count: usize) -> Vec<PathBuf> {
+    (0..count)
+        .map(|i| {
+            let dir = PathBuf::from(format!("{}_erasure_disk{}", prefix, i));
+            let _ = fs::remove_dir_all(&dir);
+            dir
+        })
+        .collect()
+}
+
+fn cleanup(dirs: &[PathBuf]) {
+    for dir in dirs {
+        let _ = fs::remove_dir_all(dir);
+    }
+}
+
+fn sample_data(len: usize, seed: u32) -> Vec<u8> {
+    (0..len)
+        .map(|i| {
+            ((i / 3) as u32 ^ seed)
+                .wrapping_mul(2_654_435_761)
+                .to_le_bytes()[1]
+        })
+        .collect()
+}
+
+fn metadata(id: SegmentId, len: usize) -> Segment {
+    serde_json::from_value(serde_json::json!({
+        "id": id,
+        "offset": 0,
+        "len": len,
+        "ref_count": 1,
+    }))
+    .unwrap()
+}
+
+fn write_segment<B: StorageBackend>(backend: &mut B, id: SegmentId, data: &[u8]) {
+    block_on(async {
+        let mut txn = backend.begin_txn().await.unwrap();
+        txn.append(id, data).await.unwrap();
+        txn.set_segment_metadata(id, metadata(id, data.len()))
+            .await
+            .unwrap();
+        txn.commit().await.unwrap();
+    });
+}
+
+#[test]
+fn any_k_fragments_reconstruct_the_rest() {
+    let codec = ReedSolomon::new(4, 2).unwrap();
+    let payload = sample_data(10_001, 1);
+    let encoded = codec.encode(&payload);
+    assert_eq!(encoded.len(), 6);
+    assert_eq!(codec.join(&encoded, payload.len()).unwrap(), payload);
+
+    for lost_a in 0..6 {
+        for lost_b in lost_a + 1..6 {
+            let mut fragments: Vec<_> = encoded.iter().cloned().map(Some).collect();
+            fragments[lost_a] = None;
+            fragments[lost_b] = None;
+            codec.reconstruct(&mut fragments).unwrap();
+            let fragments: Vec<_> = fragments.into_iter().flatten().collect();
+            assert_eq!(fragments, encoded, "lost {} and {}", lost_a, lost_b);
+        }
+    }
+
+    let mut fragments: Vec<_> = encoded.into_iter().map(Some).collect();
+    for lost in [0, 2, 5] {
+        fragments[lost] = None;
+    }
+    assert!(codec.reconstruct(&mut fragments).is_err());
+}
+
+#[test]
+fn segments_survive_losing_parity_count_disks() {
+    let dirs = disk_dirs("loss", 6);
+    let policy = Policy {
+        erasure_profile: Some("4+2".into()),
+        ..Policy::default()
+    };
+    let backends = dirs
+        .iter()
+        .map(|dir| FileBackend::open(dir).unwrap())
+        .collect();
+    let mut store = ErasureBackend::for_policy(backends, &policy).unwrap();
+    assert_eq!(store.profile(), (4, 2));
+
+    let id = SegmentId(11);
+    let data = sample_data(256 * 1024 + 7, 2);
+    write_segment(&mut store, id, &data);
+    let layout = block_on(store.metadata(id)).unwrap().erasure.unwrap();
+    assert_eq!(layout.payload_len, data.len() as u64);
+    // Each disk holds a quarter of the payload rather than a full copy.
+    assert_eq!(
+        fs::metadata(dirs[0].join("11.seg")).unwrap().len(),
+        data.len().div_ceil(4) as u64
+    );
+
+    // One disk is replaced with an empty one and another fragment rots.
+    fs::remove_dir_all(&dirs[1]).unwrap();
+    fs::create_dir_all(&dirs[1]).unwrap();
+    fs::write(dirs[4].join("11.seg"), b"bit rot").unwrap();
+    assert_eq!(block_on(store.read(id)).unwrap(), data);
+
+    let report = block_on(store.rebuild()).unwrap();
+    assert_eq!(
+        report,
+        RebuildReport {
+            segments_checked: 1,
+            fragments_rebuilt: 2,
+            unrecoverable: 0,
+        }
+    );
+    assert_eq!(
+        block_on(store.rebuild()).unwrap().fragments_rebuilt,
+        0,
+        "a second pass finds nothing to do"
+    );
+
+    // With the rebuilt fragments back, two other disks can go.
+    fs::remove_dir_all(&dirs[0]).unwrap();
+    fs::remove_dir_all(&dirs[3]).unwrap();
+    assert_eq!(block_on(store.read(id)).unwrap(), data);
+    assert_eq!(block_on(store.segment_ids()).unwrap(), vec![id]);
+
+    fs::remove_dir_all(&dirs[5]).unwrap();
+    assert!(block_on(store.read(id)).is_err());
+
+    cleanup(&dirs);
+}
+
+#[test]
+fn metadata_updates_reach_every_fragment() {
+    let backends: Vec<_> = (0..3).map(|_| InMemoryBackend::new()).collect();
+    let mut store = ErasureBackend::new(backends, 2).unwrap();
+    let id = SegmentId(5);
+    write_segment(&mut store, id, &sample_data(4_096, 3));
+
+    let mut meta = block_on(store.metadata(id)).unwrap();
+    meta.ref_count = 3;
+    block_on(async {
+        let mut txn = store.begin_txn().await.unwrap();
+        txn.set_segment_metadata(id, meta).await.unwrap();
+        txn.commit().await.unwrap();
+    });
+    for backend in store.backends() {
+        assert_eq!(block_on(backend.metadata(id)).unwrap().ref_count, 3);
+    }
+
+    // Fragments can't be decoded without their layout, so bare appends are refused.
+    assert!(block_on(store.append(SegmentId(6), b"orphan")).is_err());
+    let err = block_on(async {
+        let mut txn = store.begin_txn().await.unwrap();
+        txn.append(SegmentId(6), b"orphan").await.unwrap();
+        txn.commit().await
+    });
+    assert!(err.is_err());
+    assert_eq!(block_on(store.segment_ids()).unwrap(), vec![id]);
+
+    block_on(store.delete(id)).unwrap();
+    for backend in store.backends() {
+        assert!(block_on(backend.read(id)).is_err());
+    }
+}
+
+#[test]
+fn rebuilder_restores_lost_fragments_in_background() {
+    let backends: Vec<_> = (0..3).map(|_| InMemoryBackend::new()).collect();
+    let mut store = ErasureBackend::new(backends, 2).unwrap();
+    let id = SegmentId(9);
+    let data = sample_data(64 * 1024, 4);
+    write_segment(&mut store, id, &data);
+
+    let mut lost = store.backends()[2].clone();
+    block_on(lost.delete(id)).unwrap();
+
+    let rebuilder = ErasureRebuilder::spawn(store.clone(), Duration::from_millis(20));
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while rebuilder.totals().fragments_rebuilt == 0 {
+        assert!(Instant::now() < deadline, "rebuilder never ran");
+        thread::sleep(Duration::from_millis(10));
+    }
+    rebuilder.stop();
+
+    assert!(block_on(store.backends()[2].read(id)).is_ok());
+    let mut first = store.backends()[0].clone();
+    block_on(first.delete(id)).unwrap();
+    assert_eq!(block_on(store.read(id)).unwrap(), data);
+}
+
+/// In-memory backend whose fragment reads stall, widening the window between a
+/// rebuild reading a segment and installing its repaired fragments.
+#[derive(Clone, Default)]
+struct SlowReads(InMemoryBackend);
+
+impl StorageBackend for SlowReads {
+    type Transaction = InMemoryTransaction;
+
+    fn append<'a>(&'a mut self, segment: SegmentId, data: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+        self.0.append(segment, data)
+    }
+
+    fn read(&self, segment: SegmentId) -> BoxFuture<'_, Result<Vec<u8>>> {
+        thread::sleep(Duration::from_millis(1));
+        self.0.read(segment)
+    }
+
+    fn metadata(&self, segment: SegmentId) -> BoxFuture<'_, Result<Segment>> {
+        self.0.metadata(segment)
+    }
+
+    fn delete<'a>(&'a mut self, segment: SegmentId) -> BoxFuture<'a, Result<()>> {
+        self.0.delete(segment)
+    }
+
+    fn segment_ids(&self) -> BoxFuture<'_, Result<Vec<SegmentId>>> {
+        self.0.segment_ids()
+    }
+
+    fn begin_txn(&mut self) -> BoxFuture<'_, Result<Self::Transaction>> {
+        self.0.begin_txn()
+    }
+}
+
+#[test]
+fn rebuild_never_resurrects_or_rewinds_concurrent_changes() {
+    let backends: Vec<_> = (0..3).map(|_| SlowReads::default()).collect();
+    let mut store = ErasureBackend::new(backends, 2).unwrap();
+    let rebuilder = ErasureRebuilder::spawn(store.clone(), Duration::ZERO);
+
+    for round in 0..200u64 {
+        let id = SegmentId(round);
+        write_segment(&mut store, id, &sample_data(4_096, round as u32));
+        let mut lost = store.backends()[2].clone();
+        block_on(lost.delete(id)).unwrap();
+
+        if round % 2 == 0 {
+            block_on(store.delete(id)).unwrap();
+        } else {
+            let mut meta = block_on(store.metadata(id)).unwrap();
+            meta.ref_count = 7;
+            block_on(async {
+                let mut txn = store.begin_txn().await.unwrap();
+                txn.set_segment_metadata(id, meta).await.unwrap();
+                txn.commit().await.unwrap();
+            });
+        }
+    }
+    rebuilder.stop();
+    block_on(store.rebuild()).unwrap();
+
+    for round in 0..200u64 {
+        let id = SegmentId(round);
+        for backend in store.backends() {
+            let metadata = block_on(backend.metadata(id));
+            if round % 2 == 0 {
+                assert!(metadata.is_err(), "segment {} was resurrected", round);
+            } else {
+                assert_eq!(metadata.unwrap().ref_count, 7, "segment {}", round);
+            }
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use common::traits::{StorageBackend, StorageTransaction};
+use common::{Segment, SegmentId, StorageTier};
+use futures::executor::block_on;
+use std::fs;
+use storage::{FileBackend, InMemoryBackend, TieredBackend};
+
+fn capacity_dir(prefix: &str) -> String {
+    let dir = format!("{}_capacity_tier", prefix);
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn metadata(id: SegmentId, len: usize) -> Segment {
+    serde_json::from_value(serde_json::json!({
+        "id": id,
+        "offset": 0,
+        "len": len,
+        "ref_count": 1,
+    }))
+    .unwrap()
+}
+
+fn write_segment<B: StorageBackend>(backend: &mut B, id: SegmentId, data: &[u8]) {
+    block_on(async {
+        let mut txn = backend.begin_txn().await.unwrap();
+        txn.append(id, data).await.unwrap();
+        txn.set_segment_metadata(id, metadata(id, data.len()))
+            .await
+            .unwrap();
+        txn.commit().await.unwrap();
+    });
+}
+
+#[test]
+fn segments_move_between_tiers_transparently() {
+    let dir = capacity_dir("move");
+    let mut tiered = TieredBackend::new(InMemoryBackend::new(), FileBackend::open(&dir).unwrap());
+    let id = SegmentId(7);
+    let data = b"capacity tier payload".to_vec();
+    write_segment(&mut tiered, id, &data);
+
+    let meta = block_on(tiered.metadata(id)).unwrap();
+    assert_eq!(meta.tier, StorageTier::Hot);
+
+    assert!(block_on(tiered.relocate(id, StorageTier::Capacity)).unwrap());
+    assert!(!block_on(tiered.relocate(id, StorageTier::Capacity)).unwrap());
+    assert!(block_on(tiered.hot().read(id)).is_err());
+    assert_eq!(block_on(tiered.read(id)).unwrap(), data);
+    let mut meta = block_on(tiered.metadata(id)).unwrap();
+    assert_eq!(meta.tier, StorageTier::Capacity);
+
+    // Metadata updates land on the tier the segment was found on.
+    meta.ref_count = 2;
+    block_on(async {
+        let mut txn = tiered.begin_txn().await.unwrap();
+        txn.set_segment_metadata(id, meta).await.unwrap();
+        txn.commit().await.unwrap();
+    });
+    assert_eq!(
+        block_on(tiered.capacity().metadata(id)).unwrap().ref_count,
+        2
+    );
+    assert_eq!(block_on(tiered.segment_ids()).unwrap(), vec![id]);
+
+    assert!(block_on(tiered.relocate(id, StorageTier::Hot)).unwrap());
+    assert!(block_on(tiered.capacity().metadata(id)).is_err());
+    let meta = block_on(tiered.metadata(id)).unwrap();
+    assert_eq!((meta.tier, meta.ref_count), (StorageTier::Hot, 2));
+
+    block_on(tiered.delete(id)).unwrap();
+    assert!(block_on(tiered.read(id)).is_err());
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn file_backend_persists_segments() {
+    let dir = capacity_dir("persist");
+    let id = SegmentId(3);
+    {
+        let mut backend = FileBackend::open(&dir).unwrap();
+        write_segment(&mut backend, id, b"durable bytes");
+    }
+
+    let mut backend = FileBackend::open(&dir).unwrap();
+    assert_eq!(block_on(backend.segment_ids()).unwrap(), vec![id]);
+    assert_eq!(block_on(backend.read(id)).unwrap(), b"durable bytes");
+    assert_eq!(block_on(backend.metadata(id)).unwrap().len, 13);
+
+    block_on(backend.delete(id)).unwrap();
+    assert!(block_on(backend.segment_ids()).unwrap().is_empty());
+    // Deleting a missing segment is not an error.
+    block_on(backend.delete(id)).unwrap();
+
+    let _ = fs::remove_dir_all(&dir);
+}